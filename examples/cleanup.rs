@@ -4,7 +4,7 @@ use std::io::ErrorKind;
 use std::thread;
 use std::time::Duration;
 
-const DEVICE_ADDRESS: &'static str = "0A:0A:0A:0A:0A:A0";
+const DEVICE_ADDRESS: &str = "0A:0A:0A:0A:0A:A0";
 
 //TODO debug!
 
@@ -1,6 +1,6 @@
 use bluebus::BleBuilder;
 
-const DEVICE_ADDRESS: &'static str = "0A:0A:0A:0A:0A:0A";
+const DEVICE_ADDRESS: &str = "0A:0A:0A:0A:0A:0A";
 
 fn main() {
     let mut ble = BleBuilder::default().build().unwrap();
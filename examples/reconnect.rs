@@ -0,0 +1,20 @@
+use bluebus::{Backoff, BleBuilder, ConnectionSupervisor};
+use std::time::Duration;
+
+const DEVICE_ADDRESS: &str = "0A:0A:0A:0A:0A:0A";
+
+fn main() {
+    let mut ble = BleBuilder::default().build().unwrap();
+    let backoff = Backoff {
+        initial: Duration::from_secs(1),
+        max: Duration::from_secs(30),
+        factor: 2,
+    };
+    let mut supervisor = ConnectionSupervisor::new(DEVICE_ADDRESS.parse().unwrap())
+        .with_backoff(backoff)
+        .discover_when_unknown(true);
+
+    supervisor
+        .run(&mut ble, |state| println!("{}: {:?}", DEVICE_ADDRESS, state))
+        .unwrap();
+}
@@ -1,40 +1,40 @@
-use bluebus::BleBuilder;
-use nix::poll::{poll, ppoll, PollFd, PollFlags};
-use nix::sys::signal::SigSet;
-use std::fs::File;
-use std::io::Read;
-use std::os::unix::io::FromRawFd;
+use bluebus::{BleBuilder, Error, Event, Timeout};
+use std::thread;
+use std::time::Duration;
 
-//use epoll
-
-const DEVICE_ADDRESS: &'static str = "0A:0A:0A:0A:0A:0A";
+const DEVICE_ADDRESS: &str = "0A:0A:0A:0A:0A:0A";
+const CHARACTERISTIC: &str = "93700001-1bb7-1599-985b-f5e7dc991483";
 
 fn main() {
-    let mut ble = BleBuilder::default().build().unwrap();
+    let mut ble = BleBuilder::default().with_auto_reacquire().build().unwrap();
     ble.connect(DEVICE_ADDRESS).unwrap();
     dbg!(ble.is_connected(DEVICE_ADDRESS).unwrap());
 
-    let mut fd = ble
-        .notify(DEVICE_ADDRESS, "93700001-1bb7-1599-985b-f5e7dc991483")
-        .unwrap();
-    //ble.listen_dbus();
-
-    let mut buffer = [0u8; 4];
-    let pollfd = PollFd::new(fd, PollFlags::all());
-    let mut file = unsafe { File::from_raw_fd(fd) };
+    let mut notification = ble.notify(DEVICE_ADDRESS, CHARACTERISTIC).unwrap();
     loop {
-        if ppoll(&mut [pollfd], None, SigSet::all()).unwrap() != 0 {
-            //if poll(&mut [pollfd], -1).unwrap() != 0 {
-
-            if let Some(event) = pollfd.revents() {
-                if event.is_empty() {
-                    continue;
+        match notification.recv(Timeout::Infinite) {
+            Ok(Some(value)) => println!("recieved: {:?}", value),
+            Ok(None) => (),
+            Err(Error::Disconnected(_)) => {
+                println!("device disconnected, reconnecting");
+                while ble.connect(DEVICE_ADDRESS).is_err() {
+                    thread::sleep(Duration::from_secs(1));
                 }
-
-                dbg!(event);
-                let nread = file.read(&mut buffer).unwrap();
-                println!("nread: {}", nread);
+                // notify is acquired again once the services are resolved
+                loop {
+                    match ble.wait_event(Timeout::Infinite).unwrap() {
+                        Event::NotificationReacquired(..) => break,
+                        Event::NotificationReacquireFailed(_, _, e) => {
+                            panic!("could not notify: {:?}", e)
+                        }
+                        _ => (),
+                    }
+                }
+                notification = ble
+                    .take_notification(DEVICE_ADDRESS, CHARACTERISTIC)
+                    .unwrap();
             }
+            Err(e) => panic!("{:?}", e),
         }
     }
 }
@@ -0,0 +1,163 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+use crate::error::Error;
+
+/// Whether a device uses its public (IEEE assigned) or a random address,
+/// bluez reports this as the AddressType property of a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AddressType {
+    Public,
+    Random,
+}
+
+impl Default for AddressType {
+    fn default() -> Self {
+        AddressType::Public
+    }
+}
+
+impl FromStr for AddressType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "public" => Ok(AddressType::Public),
+            "random" => Ok(AddressType::Random),
+            _ => Err(Error::InvalidAddress(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for AddressType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AddressType::Public => f.write_str("public"),
+            AddressType::Random => f.write_str("random"),
+        }
+    }
+}
+
+/// A bluetooth device address. Parses from the usual `0A:0A:0A:0A:0A:0A`
+/// notation in any case and is always formatted in upper case, which is
+/// what bluez uses. Two addresses are equal if their octets are equal, the
+/// address type is not compared.
+#[derive(Debug, Clone, Copy)]
+pub struct Address {
+    octets: [u8; 6],
+    kind: AddressType,
+}
+
+impl Address {
+    /// a public address, octets in the order they are written
+    pub const fn new(octets: [u8; 6]) -> Self {
+        Address {
+            octets,
+            kind: AddressType::Public,
+        }
+    }
+
+    pub fn with_type(mut self, kind: AddressType) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn octets(&self) -> [u8; 6] {
+        self.octets
+    }
+
+    pub fn kind(&self) -> AddressType {
+        self.kind
+    }
+
+    /// the form used in bluez object paths: `0A_0A_0A_0A_0A_0A`
+    pub(crate) fn dbus_fragment(&self) -> String {
+        self.to_string().replace(":", "_")
+    }
+}
+
+impl PartialEq for Address {
+    fn eq(&self, other: &Address) -> bool {
+        self.octets == other.octets
+    }
+}
+
+impl Eq for Address {}
+
+impl Hash for Address {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.octets.hash(state)
+    }
+}
+
+impl FromStr for Address {
+    type Err = Error;
+
+    /// accepts both `:` and `_` (as used in object paths) as separator
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidAddress(s.to_owned());
+        let parts: Vec<&str> = s.split(&[':', '_'][..]).collect();
+        if parts.len() != 6 {
+            return Err(invalid());
+        }
+
+        let mut octets = [0u8; 6];
+        for (octet, part) in octets.iter_mut().zip(parts) {
+            // from_str_radix would also take a sign
+            if part.len() != 2 || !part.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(invalid());
+            }
+            *octet = u8::from_str_radix(part, 16).map_err(|_| invalid())?;
+        }
+        Ok(Address::new(octets))
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.octets;
+        write!(
+            f,
+            "{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
+            a, b, c, d, e, g
+        )
+    }
+}
+
+/// Anything that can be turned into an Address, implemented for Address
+/// and for strings which are parsed.
+pub trait IntoAddress {
+    fn into_address(self) -> Result<Address, Error>;
+}
+
+impl IntoAddress for Address {
+    fn into_address(self) -> Result<Address, Error> {
+        Ok(self)
+    }
+}
+
+impl IntoAddress for &Address {
+    fn into_address(self) -> Result<Address, Error> {
+        Ok(*self)
+    }
+}
+
+impl IntoAddress for &str {
+    fn into_address(self) -> Result<Address, Error> {
+        self.parse()
+    }
+}
+
+impl IntoAddress for String {
+    fn into_address(self) -> Result<Address, Error> {
+        self.parse()
+    }
+}
+
+impl IntoAddress for &String {
+    fn into_address(self) -> Result<Address, Error> {
+        self.parse()
+    }
+}
@@ -0,0 +1,420 @@
+//! Advertise from the host using LEAdvertisingManager1. Build an
+//! `Advertisement` and pass it to `Ble::register_advertisement`. When bluez
+//! stops the advertisement on its own (for example because its timeout passed)
+//! `Event::AdvertisementReleased` is emitted.
+//!
+//! ```no_run
+//! use bluebus::advertising::{Advertisement, Include};
+//! use bluebus::{BleBuilder, Uuid};
+//!
+//! let mut ble = BleBuilder::default().build().unwrap();
+//! let advertisement = Advertisement::peripheral()
+//!     .with_service_uuid(Uuid::from_u16(0x180f))
+//!     .with_local_name("gateway")
+//!     .with_include(Include::TxPower);
+//! let id = ble.register_advertisement(advertisement).unwrap();
+//! ```
+
+use std::collections::HashMap;
+
+use rustbus::message_builder::MarshalledMessage;
+use rustbus::params::{self, Param};
+use rustbus::{signature, MessageBuilder};
+
+use crate::dbus_helpers::*;
+use crate::error::{Context, Error};
+use crate::uuid::Uuid;
+use crate::{Ble, Event};
+
+/// all advertisements are exported below this path
+pub(crate) const ROOT: &str = "/bluebus/advertisement";
+
+const MANAGER_IFACE: &str = "org.bluez.LEAdvertisingManager1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AdvertisementType {
+    /// connectable
+    Peripheral,
+    /// not connectable
+    Broadcast,
+}
+
+/// Data bluez can add to the advertisement for you
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Include {
+    TxPower,
+    Appearance,
+    LocalName,
+}
+
+impl Include {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Include::TxPower => "tx-power",
+            Include::Appearance => "appearance",
+            Include::LocalName => "local-name",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Advertisement {
+    typ: AdvertisementType,
+    service_uuids: Vec<Uuid>,
+    solicit_uuids: Vec<Uuid>,
+    manufacturer_data: HashMap<u16, Vec<u8>>,
+    service_data: HashMap<Uuid, Vec<u8>>,
+    local_name: Option<String>,
+    appearance: Option<u16>,
+    tx_power: Option<i16>,
+    includes: Vec<Include>,
+    discoverable: Option<bool>,
+    min_interval: Option<u32>,
+    max_interval: Option<u32>,
+    duration: Option<u16>,
+    timeout: Option<u16>,
+}
+
+impl Advertisement {
+    pub fn new(typ: AdvertisementType) -> Self {
+        Advertisement {
+            typ,
+            service_uuids: Vec::new(),
+            solicit_uuids: Vec::new(),
+            manufacturer_data: HashMap::new(),
+            service_data: HashMap::new(),
+            local_name: None,
+            appearance: None,
+            tx_power: None,
+            includes: Vec::new(),
+            discoverable: None,
+            min_interval: None,
+            max_interval: None,
+            duration: None,
+            timeout: None,
+        }
+    }
+
+    pub fn peripheral() -> Self {
+        Self::new(AdvertisementType::Peripheral)
+    }
+
+    pub fn broadcast() -> Self {
+        Self::new(AdvertisementType::Broadcast)
+    }
+
+    pub fn with_service_uuid(mut self, uuid: Uuid) -> Self {
+        self.service_uuids.push(uuid);
+        self
+    }
+
+    pub fn with_solicit_uuid(mut self, uuid: Uuid) -> Self {
+        self.solicit_uuids.push(uuid);
+        self
+    }
+
+    pub fn with_manufacturer_data(mut self, company_id: u16, data: impl Into<Vec<u8>>) -> Self {
+        self.manufacturer_data.insert(company_id, data.into());
+        self
+    }
+
+    pub fn with_service_data(mut self, uuid: Uuid, data: impl Into<Vec<u8>>) -> Self {
+        self.service_data.insert(uuid, data.into());
+        self
+    }
+
+    pub fn with_local_name(mut self, name: impl Into<String>) -> Self {
+        self.local_name = Some(name.into());
+        self
+    }
+
+    pub fn with_appearance(mut self, appearance: u16) -> Self {
+        self.appearance = Some(appearance);
+        self
+    }
+
+    /// in dBm
+    pub fn with_tx_power(mut self, tx_power: i16) -> Self {
+        self.tx_power = Some(tx_power);
+        self
+    }
+
+    pub fn with_include(mut self, include: Include) -> Self {
+        if !self.includes.contains(&include) {
+            self.includes.push(include);
+        }
+        self
+    }
+
+    pub fn discoverable(mut self, discoverable: bool) -> Self {
+        self.discoverable = Some(discoverable);
+        self
+    }
+
+    /// advertising interval in milliseconds
+    pub fn with_interval(mut self, min: u32, max: u32) -> Self {
+        self.min_interval = Some(min);
+        self.max_interval = Some(max);
+        self
+    }
+
+    /// how long, in seconds, this advertisement is shown when bluez has to
+    /// rotate between multiple advertisements
+    pub fn with_duration(mut self, seconds: u16) -> Self {
+        self.duration = Some(seconds);
+        self
+    }
+
+    /// stop advertising after this many seconds, bluez will then release
+    /// the advertisement
+    pub fn with_timeout(mut self, seconds: u16) -> Self {
+        self.timeout = Some(seconds);
+        self
+    }
+
+    fn properties(&self) -> Param<'static, 'static> {
+        let typ = match self.typ {
+            AdvertisementType::Peripheral => "peripheral",
+            AdvertisementType::Broadcast => "broadcast",
+        };
+        let uuids =
+            |uuids: &[Uuid]| string_array_param(uuids.iter().map(Uuid::to_string).collect());
+        let base = |b: params::Base<'static>| Param::Base(b);
+
+        let mut properties = vec![("Type", string_param(typ))];
+        if !self.service_uuids.is_empty() {
+            properties.push(("ServiceUUIDs", uuids(&self.service_uuids)));
+        }
+        if !self.solicit_uuids.is_empty() {
+            properties.push(("SolicitUUIDs", uuids(&self.solicit_uuids)));
+        }
+        if !self.manufacturer_data.is_empty() {
+            let map = self
+                .manufacturer_data
+                .iter()
+                .map(|(id, data)| {
+                    let data =
+                        Param::Container(params::Container::make_variant(byte_array_param(data)));
+                    (params::Base::Uint16(*id), data)
+                })
+                .collect();
+            properties.push((
+                "ManufacturerData",
+                dict_param(signature::Base::Uint16, "v", map),
+            ));
+        }
+        if !self.service_data.is_empty() {
+            let map = self
+                .service_data
+                .iter()
+                .map(|(uuid, data)| {
+                    let data =
+                        Param::Container(params::Container::make_variant(byte_array_param(data)));
+                    (params::Base::String(uuid.to_string()), data)
+                })
+                .collect();
+            properties.push(("ServiceData", dict_param(signature::Base::String, "v", map)));
+        }
+        if let Some(name) = &self.local_name {
+            properties.push(("LocalName", string_param(name.as_str())));
+        }
+        if let Some(appearance) = self.appearance {
+            properties.push(("Appearance", base(params::Base::Uint16(appearance))));
+        }
+        if let Some(tx_power) = self.tx_power {
+            properties.push(("TxPower", base(params::Base::Int16(tx_power))));
+        }
+        if !self.includes.is_empty() {
+            let includes = self
+                .includes
+                .iter()
+                .map(|i| i.as_str().to_owned())
+                .collect();
+            properties.push(("Includes", string_array_param(includes)));
+        }
+        if let Some(discoverable) = self.discoverable {
+            properties.push(("Discoverable", base(params::Base::Boolean(discoverable))));
+        }
+        if let Some(min) = self.min_interval {
+            properties.push(("MinInterval", base(params::Base::Uint32(min))));
+        }
+        if let Some(max) = self.max_interval {
+            properties.push(("MaxInterval", base(params::Base::Uint32(max))));
+        }
+        if let Some(duration) = self.duration {
+            properties.push(("Duration", base(params::Base::Uint16(duration))));
+        }
+        if let Some(timeout) = self.timeout {
+            properties.push(("Timeout", base(params::Base::Uint16(timeout))));
+        }
+        variant_dict_param(properties)
+    }
+}
+
+/// Identifies a registered advertisement
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AdvertisementId(u32);
+
+impl AdvertisementId {
+    /// the object path the advertisement is exported at
+    pub fn path(&self) -> String {
+        format!("{}{}", ROOT, self.0)
+    }
+}
+
+pub(crate) struct RegisteredAdvertisement {
+    id: AdvertisementId,
+    advertisement: Advertisement,
+}
+
+impl Ble {
+    /// export the advertisement and ask bluez to start advertising it
+    pub fn register_advertisement(
+        &mut self,
+        advertisement: Advertisement,
+    ) -> Result<AdvertisementId, Error> {
+        let id = AdvertisementId(self.next_object_id);
+        self.next_object_id += 1;
+        self.advertisements
+            .push(RegisteredAdvertisement { id, advertisement });
+
+        if let Err(e) = self.send_register_advertisement(id) {
+            self.advertisements.retain(|registerd| registerd.id != id);
+            return Err(e);
+        }
+        Ok(id)
+    }
+
+    fn send_register_advertisement(&mut self, id: AdvertisementId) -> Result<(), Error> {
+        let mut register = MessageBuilder::new()
+            .call("RegisterAdvertisement".into())
+            .at("org.bluez".into())
+            .on(self.adapter_path().into())
+            .with_interface(MANAGER_IFACE.into())
+            .build();
+        register.body.push_old_param(&objectpath_param(id.path()))?;
+        register
+            .body
+            .push_old_param(&variant_dict_param(Vec::new()))?;
+
+        let response_serial = self.connection.send_message(&mut register, self.timeout)?;
+        let msg = self.wait_response_serving_calls(response_serial, self.timeout)?;
+
+        match msg.typ {
+            rustbus::MessageType::Reply => Ok(()),
+            rustbus::MessageType::Error => Err(Error::from((msg, Context::RegisterAdvertisement))),
+            _ => Err(Error::UnexpectedDbusReply),
+        }
+    }
+
+    /// register all advertisements again, used after bluez restarted. Returns
+    /// the errors of the ones that failed.
+    pub(crate) fn reregister_advertisements(&mut self) -> Vec<Error> {
+        let ids: Vec<_> = self.advertisements.iter().map(|r| r.id).collect();
+        ids.into_iter()
+            .filter_map(|id| self.send_register_advertisement(id).err())
+            .collect()
+    }
+
+    /// stop advertising and stop exporting the advertisement
+    pub fn unregister_advertisement(&mut self, id: AdvertisementId) -> Result<(), Error> {
+        let mut unregister = MessageBuilder::new()
+            .call("UnregisterAdvertisement".into())
+            .at("org.bluez".into())
+            .on(self.adapter_path().into())
+            .with_interface(MANAGER_IFACE.into())
+            .build();
+        unregister
+            .body
+            .push_old_param(&objectpath_param(id.path()))?;
+
+        let response_serial = self
+            .connection
+            .send_message(&mut unregister, self.timeout)?;
+        let msg = self.wait_response_serving_calls(response_serial, self.timeout)?;
+        self.advertisements.retain(|registerd| registerd.id != id);
+
+        match msg.typ {
+            rustbus::MessageType::Reply => Ok(()),
+            rustbus::MessageType::Error => {
+                Err(Error::from((msg, Context::UnregisterAdvertisement)))
+            }
+            _ => Err(Error::UnexpectedDbusReply),
+        }
+    }
+
+    /// number of advertisements that can still be registered
+    pub fn supported_advertisement_instances(&mut self) -> Result<u8, Error> {
+        self.advertising_instances("SupportedInstances")
+    }
+
+    /// number of advertisements currently registered with bluez, this
+    /// includes those of other applications
+    pub fn active_advertisement_instances(&mut self) -> Result<u8, Error> {
+        self.advertising_instances("ActiveInstances")
+    }
+
+    fn advertising_instances(&mut self, property: &str) -> Result<u8, Error> {
+        let path = self.adapter_path();
+        let value =
+            self.get_property(path, MANAGER_IFACE, property, Context::AdvertisingInstances)?;
+        unwrap_base(value)
+            .and_then(|b| match b {
+                params::Base::Byte(n) => Some(n),
+                _ => None,
+            })
+            .ok_or(Error::UnexpectedDbusReply)
+    }
+
+    pub(crate) fn handle_advertisement_call(
+        &mut self,
+        call: MarshalledMessage,
+    ) -> Result<MarshalledMessage, Error> {
+        let path = call.dynheader.object.clone().unwrap_or_default();
+        let member = call.dynheader.member.clone().unwrap_or_default();
+        let id = path
+            .strip_prefix(ROOT)
+            .and_then(|id| id.parse().ok())
+            .map(AdvertisementId);
+        let registered = self
+            .advertisements
+            .iter()
+            .position(|registered| Some(registered.id) == id);
+
+        let index = match registered {
+            Some(index) => index,
+            None => {
+                return Ok(error_response(
+                    &call.dynheader,
+                    "org.freedesktop.DBus.Error.UnknownObject",
+                    "no such object",
+                ))
+            }
+        };
+
+        let mut reply = call.dynheader.make_response();
+        match member.as_str() {
+            "GetAll" => {
+                let properties = self.advertisements[index].advertisement.properties();
+                reply.body.push_old_param(&properties)?;
+            }
+            "Release" => {
+                let released = self.advertisements.remove(index);
+                self.events
+                    .push_back(Event::AdvertisementReleased(released.id));
+            }
+            _ => {
+                return Ok(error_response(
+                    &call.dynheader,
+                    "org.freedesktop.DBus.Error.UnknownMethod",
+                    "unknown method",
+                ))
+            }
+        }
+        Ok(reply)
+    }
+}
@@ -0,0 +1,292 @@
+//! Battery levels of devices. Bluez exposes the level of devices that
+//! report it through the battery service as `org.bluez.Battery1`, read it
+//! with `Ble::battery_percentage`, changes arrive as `Event::BatteryChanged`.
+//!
+//! Levels bluez can not read itself, for example ones decoded from a
+//! proprietary characteristic, can be published to the rest of the system
+//! by registering a battery provider:
+//!
+//! ```no_run
+//! use bluebus::BleBuilder;
+//!
+//! let mut ble = BleBuilder::default().build().unwrap();
+//! ble.register_battery_provider().unwrap();
+//! ble.provide_battery("0A:0A:0A:0A:0A:0A", 80, Some("proprietary")).unwrap();
+//! ```
+
+use rustbus::message_builder::MarshalledMessage;
+use rustbus::params::{self, Param};
+use rustbus::MessageBuilder;
+
+use crate::address::{Address, IntoAddress};
+use crate::dbus_helpers::*;
+use crate::error::{Context, Error};
+use crate::Ble;
+
+/// the provider and the batteries it provides are exported below this path
+pub(crate) const ROOT: &str = "/bluebus/battery";
+
+const BATTERY_IFACE: &str = "org.bluez.Battery1";
+const PROVIDER_IFACE: &str = "org.bluez.BatteryProvider1";
+const MANAGER_IFACE: &str = "org.bluez.BatteryProviderManager1";
+
+struct ProvidedBattery {
+    adress: Address,
+    percentage: u8,
+    source: Option<String>,
+}
+
+impl ProvidedBattery {
+    fn path(&self) -> String {
+        format!("{}/dev_{}", ROOT, self.adress.dbus_fragment())
+    }
+}
+
+/// the batteries we provide, they are kept while not registerd so they can
+/// be set before registering and survive a restart of bluez
+#[derive(Default)]
+pub(crate) struct BatteryProvider {
+    registerd: bool,
+    batteries: Vec<ProvidedBattery>,
+}
+
+impl Ble {
+    /// battery level of a connected device in percent
+    #[allow(dead_code)]
+    pub fn battery_percentage(&mut self, adress: impl IntoAddress) -> Result<u8, Error> {
+        let path = self.device_path(adress.into_address()?);
+        let value = self.get_property(
+            path,
+            BATTERY_IFACE,
+            "Percentage",
+            Context::BatteryPercentage,
+        )?;
+        match unwrap_base(value) {
+            Some(params::Base::Byte(percentage)) => Ok(percentage),
+            _ => Err(Error::UnexpectedDbusReply),
+        }
+    }
+
+    /// start publishing the batteries set with `provide_battery` to bluez
+    #[allow(dead_code)]
+    pub fn register_battery_provider(&mut self) -> Result<(), Error> {
+        self.send_register_battery_provider()?;
+        self.battery_provider.registerd = true;
+        Ok(())
+    }
+
+    fn send_register_battery_provider(&mut self) -> Result<(), Error> {
+        let mut register = MessageBuilder::new()
+            .call("RegisterBatteryProvider".into())
+            .at("org.bluez".into())
+            .on(self.adapter_path().into())
+            .with_interface(MANAGER_IFACE.into())
+            .build();
+        register.body.push_old_param(&objectpath_param(ROOT))?;
+
+        let response_serial = self.connection.send_message(&mut register, self.timeout)?;
+        let msg = self.wait_response_serving_calls(response_serial, self.timeout)?;
+
+        match msg.typ {
+            rustbus::MessageType::Reply => Ok(()),
+            rustbus::MessageType::Error => {
+                Err(Error::from((msg, Context::RegisterBatteryProvider)))
+            }
+            _ => Err(Error::UnexpectedDbusReply),
+        }
+    }
+
+    /// register the provider again, used after bluez restarted
+    pub(crate) fn reregister_battery_provider(&mut self) -> Option<Error> {
+        if !self.battery_provider.registerd {
+            return None;
+        }
+        self.send_register_battery_provider().err()
+    }
+
+    /// stop publishing batteries, the batteries set are kept
+    #[allow(dead_code)]
+    pub fn unregister_battery_provider(&mut self) -> Result<(), Error> {
+        let mut unregister = MessageBuilder::new()
+            .call("UnregisterBatteryProvider".into())
+            .at("org.bluez".into())
+            .on(self.adapter_path().into())
+            .with_interface(MANAGER_IFACE.into())
+            .build();
+        unregister.body.push_old_param(&objectpath_param(ROOT))?;
+
+        let response_serial = self
+            .connection
+            .send_message(&mut unregister, self.timeout)?;
+        let msg = self.wait_response_serving_calls(response_serial, self.timeout)?;
+        self.battery_provider.registerd = false;
+
+        match msg.typ {
+            rustbus::MessageType::Reply => Ok(()),
+            rustbus::MessageType::Error => {
+                Err(Error::from((msg, Context::UnregisterBatteryProvider)))
+            }
+            _ => Err(Error::UnexpectedDbusReply),
+        }
+    }
+
+    /// set the battery level of a device in percent, source describes where
+    /// the level comes from. Bluez shows it as the Battery1 of the device.
+    #[allow(dead_code)]
+    pub fn provide_battery(
+        &mut self,
+        adress: impl IntoAddress,
+        percentage: u8,
+        source: Option<&str>,
+    ) -> Result<(), Error> {
+        let adress = adress.into_address()?;
+        if percentage > 100 {
+            return Err(Error::InvalidValue(Context::ProvideBattery));
+        }
+        let source = source.map(str::to_owned);
+        let registerd = self.battery_provider.registerd;
+        let batteries = &mut self.battery_provider.batteries;
+        if let Some(battery) = batteries.iter_mut().find(|b| b.adress == adress) {
+            let mut changed = vec![("Percentage", Param::Base(params::Base::Byte(percentage)))];
+            if let Some(new) = source.filter(|new| Some(new) != battery.source.as_ref()) {
+                changed.push(("Source", string_param(new.as_str())));
+                battery.source = Some(new);
+            }
+            battery.percentage = percentage;
+            if registerd {
+                let changed = variant_dict_param(changed);
+                let mut signal = properties_changed(&battery.path(), PROVIDER_IFACE, changed)?;
+                self.connection.send_message(&mut signal, self.timeout)?;
+            }
+            return Ok(());
+        }
+
+        let battery = ProvidedBattery {
+            adress,
+            percentage,
+            source,
+        };
+        if registerd {
+            let properties = self.battery_properties(&battery);
+            let interfaces = interfaces_param(vec![(PROVIDER_IFACE, properties)]);
+            let mut signal = MessageBuilder::new()
+                .signal(
+                    "org.freedesktop.DBus.ObjectManager".into(),
+                    "InterfacesAdded".into(),
+                    ROOT.into(),
+                )
+                .build();
+            signal
+                .body
+                .push_old_params(&[objectpath_param(battery.path()), interfaces])?;
+            self.connection.send_message(&mut signal, self.timeout)?;
+        }
+        self.battery_provider.batteries.push(battery);
+        Ok(())
+    }
+
+    /// stop providing the battery level of this device
+    #[allow(dead_code)]
+    pub fn remove_provided_battery(&mut self, adress: impl IntoAddress) -> Result<(), Error> {
+        let adress = adress.into_address()?;
+        let batteries = &mut self.battery_provider.batteries;
+        let index = match batteries.iter().position(|b| b.adress == adress) {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+        let battery = batteries.remove(index);
+        if !self.battery_provider.registerd {
+            return Ok(());
+        }
+
+        let mut signal = MessageBuilder::new()
+            .signal(
+                "org.freedesktop.DBus.ObjectManager".into(),
+                "InterfacesRemoved".into(),
+                ROOT.into(),
+            )
+            .build();
+        let interfaces = string_array_param(vec![PROVIDER_IFACE.to_owned()]);
+        signal
+            .body
+            .push_old_params(&[objectpath_param(battery.path()), interfaces])?;
+        self.connection.send_message(&mut signal, self.timeout)?;
+        Ok(())
+    }
+
+    fn battery_properties(&self, battery: &ProvidedBattery) -> Param<'static, 'static> {
+        let device = self.device_path(battery.adress).to_string();
+        let mut properties = vec![
+            (
+                "Percentage",
+                Param::Base(params::Base::Byte(battery.percentage)),
+            ),
+            ("Device", objectpath_param(device)),
+        ];
+        if let Some(source) = &battery.source {
+            properties.push(("Source", string_param(source.as_str())));
+        }
+        variant_dict_param(properties)
+    }
+
+    pub(crate) fn handle_battery_call(
+        &mut self,
+        call: MarshalledMessage,
+    ) -> Result<MarshalledMessage, Error> {
+        let path = call.dynheader.object.clone().unwrap_or_default();
+        let member = call.dynheader.member.clone().unwrap_or_default();
+        let mut reply = call.dynheader.make_response();
+
+        if path == ROOT {
+            if member != "GetManagedObjects" {
+                return Ok(error_response(
+                    &call.dynheader,
+                    "org.freedesktop.DBus.Error.UnknownMethod",
+                    "unknown method",
+                ));
+            }
+            let objects = self
+                .battery_provider
+                .batteries
+                .iter()
+                .map(|battery| {
+                    let properties = self.battery_properties(battery);
+                    let interfaces = interfaces_param(vec![(PROVIDER_IFACE, properties)]);
+                    (battery.path(), interfaces)
+                })
+                .collect();
+            reply.body.push_old_param(&managed_objects_param(objects))?;
+            return Ok(reply);
+        }
+
+        let battery = self
+            .battery_provider
+            .batteries
+            .iter()
+            .find(|battery| battery.path() == path);
+        let battery = match battery {
+            Some(battery) => battery,
+            None => {
+                return Ok(error_response(
+                    &call.dynheader,
+                    "org.freedesktop.DBus.Error.UnknownObject",
+                    "no such object",
+                ))
+            }
+        };
+        match member.as_str() {
+            "GetAll" => {
+                let properties = self.battery_properties(battery);
+                reply.body.push_old_param(&properties)?;
+            }
+            _ => {
+                return Ok(error_response(
+                    &call.dynheader,
+                    "org.freedesktop.DBus.Error.UnknownMethod",
+                    "unknown method",
+                ))
+            }
+        }
+        Ok(reply)
+    }
+}
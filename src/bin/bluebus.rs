@@ -0,0 +1,520 @@
+use std::io::{self, BufRead, Read, Write};
+use std::process;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use bluebus::rustbus::client_conn::Error as ConnError;
+use bluebus::{
+    Address, Ble, BleBuilder, CallOptions, DeviceInfo, DiscoveryFilter, Error, Event, Timeout, Uuid,
+};
+
+const USAGE: &str = "\
+usage: bluebus [--adapter N] [--bus ADDRESS] <command> [arguments]
+
+commands:
+    adapters                           list the adapters
+    scan [--duration SECS] [--name TEXT] [--uuid UUID].. [--rssi DBM]
+                                       discover devices, prints signal strength
+                                       updates as they come in
+    info <adress>                      show what bluez knows about a device
+    connect <adress>
+    disconnect <adress>
+    pair <adress>                      asks for the passkey, or to compare it, if
+                                       the device needs one
+    remove <adress> [--clear-cache]    forget the device, optionally also remove
+                                       its attribute cache (needs root)
+    gatt <adress>                      list services, characteristics and descriptors
+    read <adress> <uuid> [--format F]
+    write <adress> <uuid> <data> [--format F]
+    notify <adress> <uuid> [--format F] [--count N]
+                                       print notifications with a timestamp
+
+F is one of hex (default), utf8 or base64";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = run(Args(args)) {
+        match e {
+            Failure::Usage(msg) => eprintln!("{}\n\n{}", msg, USAGE),
+            Failure::Ble(Error::PermissionDenied(path)) => {
+                eprintln!(
+                    "error: no permission to access {}, try as root",
+                    path.display()
+                )
+            }
+            Failure::Ble(e) => eprintln!("error: {:?}", e),
+            Failure::Io(e) => eprintln!("error: {}", e),
+        }
+        process::exit(1);
+    }
+}
+
+enum Failure {
+    Usage(String),
+    Ble(Error),
+    Io(io::Error),
+}
+
+impl From<Error> for Failure {
+    fn from(e: Error) -> Self {
+        Failure::Ble(e)
+    }
+}
+
+impl From<io::Error> for Failure {
+    fn from(e: io::Error) -> Self {
+        Failure::Io(e)
+    }
+}
+
+fn usage(msg: impl Into<String>) -> Failure {
+    Failure::Usage(msg.into())
+}
+
+/// the arguments not yet used, options are taken out before the positionals
+struct Args(Vec<String>);
+
+impl Args {
+    fn option(&mut self, name: &str) -> Result<Option<String>, Failure> {
+        let i = match self.0.iter().position(|a| a == name) {
+            Some(i) => i,
+            None => return Ok(None),
+        };
+        if i + 1 >= self.0.len() {
+            return Err(usage(format!("{} needs a value", name)));
+        }
+        let value = self.0.remove(i + 1);
+        self.0.remove(i);
+        Ok(Some(value))
+    }
+
+    fn options(&mut self, name: &str) -> Result<Vec<String>, Failure> {
+        let mut values = Vec::new();
+        while let Some(value) = self.option(name)? {
+            values.push(value);
+        }
+        Ok(values)
+    }
+
+    fn parsed_option<T>(&mut self, name: &str) -> Result<Option<T>, Failure>
+    where
+        T: std::str::FromStr,
+    {
+        match self.option(name)? {
+            Some(value) => match value.parse() {
+                Ok(value) => Ok(Some(value)),
+                Err(_) => Err(usage(format!("invalid value for {}: {}", name, value))),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn flag(&mut self, name: &str) -> bool {
+        match self.0.iter().position(|a| a == name) {
+            Some(i) => {
+                self.0.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn format(&mut self) -> Result<Format, Failure> {
+        match self.option("--format")?.as_deref() {
+            None | Some("hex") => Ok(Format::Hex),
+            Some("utf8") => Ok(Format::Utf8),
+            Some("base64") => Ok(Format::Base64),
+            Some(other) => Err(usage(format!("unknown format: {}", other))),
+        }
+    }
+
+    fn positional(&mut self, what: &str) -> Result<String, Failure> {
+        if self.0.is_empty() {
+            return Err(usage(format!("missing {}", what)));
+        }
+        Ok(self.0.remove(0))
+    }
+
+    fn adress(&mut self) -> Result<Address, Failure> {
+        Ok(self.positional("device adress")?.parse()?)
+    }
+
+    fn uuid(&mut self) -> Result<Uuid, Failure> {
+        Ok(self.positional("characteristic uuid")?.parse()?)
+    }
+
+    /// call once all options and positionals are taken
+    fn done(self) -> Result<(), Failure> {
+        if let Some(unknown) = self.0.iter().find(|a| a.starts_with("--")) {
+            return Err(usage(format!("unknown option: {}", unknown)));
+        }
+        match self.0.first() {
+            Some(extra) => Err(usage(format!("unexpected argument: {}", extra))),
+            None => Ok(()),
+        }
+    }
+}
+
+fn run(mut args: Args) -> Result<(), Failure> {
+    if args.flag("--help") || args.flag("-h") {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    let mut builder = BleBuilder::default();
+    if let Some(adapter) = args.parsed_option("--adapter")? {
+        builder = builder.with_adapter(adapter);
+    }
+    if let Some(bus) = args.option("--bus")? {
+        builder = builder.with_bus_address(bus);
+    }
+    let command = args.positional("command")?;
+    let mut ble = builder.build()?;
+
+    match command.as_str() {
+        "adapters" => adapters(&mut ble, args),
+        "scan" => scan(&mut ble, args),
+        "info" => info(&mut ble, args),
+        "connect" => {
+            let adress = args.adress()?;
+            args.done()?;
+            Ok(ble.connect(adress)?)
+        }
+        "disconnect" => {
+            let adress = args.adress()?;
+            args.done()?;
+            Ok(ble.disconnect(adress)?)
+        }
+        "pair" => pair(&mut ble, args),
+        "remove" => remove(&mut ble, args),
+        "gatt" => gatt(&mut ble, args),
+        "read" => read(&mut ble, args),
+        "write" => write(&mut ble, args),
+        "notify" => notify(&mut ble, args),
+        other => Err(usage(format!("unknown command: {}", other))),
+    }
+}
+
+fn adapters(ble: &mut Ble, args: Args) -> Result<(), Failure> {
+    args.done()?;
+    for adapter in ble.adapters()? {
+        println!(
+            "hci{} {} {}{}{}",
+            adapter.numb,
+            adapter.adress,
+            adapter.alias,
+            if adapter.powered { " [powered]" } else { "" },
+            if adapter.discovering {
+                " [discovering]"
+            } else {
+                ""
+            },
+        );
+    }
+    Ok(())
+}
+
+fn scan(ble: &mut Ble, mut args: Args) -> Result<(), Failure> {
+    let duration = args.parsed_option("--duration")?.unwrap_or(10);
+    let name = args.option("--name")?;
+    let mut filter = DiscoveryFilter::new().duplicate_data(true);
+    for uuid in args.options("--uuid")? {
+        filter = filter.with_uuid(uuid.parse()?);
+    }
+    if let Some(rssi) = args.parsed_option("--rssi")? {
+        filter = filter.with_rssi(rssi);
+    }
+    args.done()?;
+
+    // rssi updates only carry the adress, remember the names we have seen
+    let mut known = ble.devices()?;
+    let matches = |device: &DeviceInfo| match &name {
+        Some(name) => device.alias.contains(name.as_str()),
+        None => true,
+    };
+
+    ble.set_discovery_filter(&filter)?;
+    ble.start_discovery()?;
+    let deadline = Instant::now() + Duration::from_secs(duration);
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break;
+        }
+        let event = match ble.wait_event(Timeout::Duration(left)) {
+            Ok(event) => event,
+            Err(Error::DbusConnectionError(ConnError::TimedOut)) => break,
+            Err(e) => return Err(e.into()),
+        };
+        match event {
+            Event::DeviceAdded(device) => {
+                if matches(&device) {
+                    println!(
+                        "[new]  {} {} {}",
+                        device.adress,
+                        rssi(device.rssi),
+                        device.alias
+                    );
+                }
+                known.retain(|d| d.adress != device.adress);
+                known.push(device);
+            }
+            Event::Rssi(adress, dbm) => {
+                if let Some(device) = known.iter().find(|d| d.adress == adress) {
+                    if matches(device) {
+                        println!("[rssi] {} {} {}", adress, rssi(Some(dbm)), device.alias);
+                    }
+                }
+            }
+            Event::DeviceRemoved(adress) => {
+                if let Some(device) = known.iter().find(|d| d.adress == adress) {
+                    if matches(device) {
+                        println!("[gone] {} {}", adress, device.alias);
+                    }
+                }
+                known.retain(|d| d.adress != adress);
+            }
+            _ => (),
+        }
+    }
+    ble.stop_discovery()?;
+    Ok(())
+}
+
+fn rssi(rssi: Option<i16>) -> String {
+    match rssi {
+        Some(dbm) => format!("{:>4} dBm", dbm),
+        None => "   ? dBm".to_owned(),
+    }
+}
+
+fn info(ble: &mut Ble, mut args: Args) -> Result<(), Failure> {
+    let adress = args.adress()?;
+    args.done()?;
+    let info = ble.device_info(adress)?;
+    println!("adress: {} ({})", info.adress, info.adress.kind());
+    println!("name: {}", info.name.as_deref().unwrap_or("-"));
+    println!("alias: {}", info.alias);
+    println!("rssi: {}", rssi(info.rssi).trim());
+    if let Some(tx_power) = info.tx_power {
+        println!("tx power: {} dBm", tx_power);
+    }
+    if let Some(appearance) = info.appearance {
+        println!("appearance: {:#06x}", appearance);
+    }
+    println!("paired: {}", info.paired);
+    println!("trusted: {}", info.trusted);
+    println!("connected: {}", info.connected);
+    println!("services resolved: {}", info.services_resolved);
+    for uuid in info.uuids {
+        println!("uuid: {}", uuid);
+    }
+    Ok(())
+}
+
+fn pair(ble: &mut Ble, mut args: Args) -> Result<(), Failure> {
+    let adress = args.adress()?;
+    args.done()?;
+    let ask_passkey = || loop {
+        print!("passkey: ");
+        let _ = io::stdout().flush();
+        let mut line = String::new();
+        match io::stdin().lock().read_line(&mut line) {
+            Ok(0) | Err(_) => process::exit(1),
+            Ok(_) => (),
+        }
+        match line.trim().parse() {
+            Ok(passkey) if passkey <= 999_999 => return passkey,
+            _ => eprintln!("the passkey is a number of at most 6 digits"),
+        }
+    };
+    let confirm = |passkey: Option<u32>| loop {
+        match passkey {
+            Some(passkey) => print!("does the device show {:06}? [y/n] ", passkey),
+            None => print!("pair without a passkey? [y/n] "),
+        }
+        let _ = io::stdout().flush();
+        let mut line = String::new();
+        match io::stdin().lock().read_line(&mut line) {
+            Ok(0) | Err(_) => process::exit(1),
+            Ok(_) => (),
+        }
+        match line.trim() {
+            "y" => return true,
+            "n" => return false,
+            _ => eprintln!("answer y or n"),
+        }
+    };
+    let options = CallOptions::new().with_timeout(Duration::from_secs(60));
+    ble.pair_confirming(adress, ask_passkey, confirm, &options)?;
+    println!("paired");
+    Ok(())
+}
+
+fn remove(ble: &mut Ble, mut args: Args) -> Result<(), Failure> {
+    let clear_cache = args.flag("--clear-cache");
+    let adress = args.adress()?;
+    args.done()?;
+    if clear_cache {
+        ble.reset_device(adress)?;
+    } else {
+        ble.remove(adress)?;
+    }
+    Ok(())
+}
+
+fn gatt(ble: &mut Ble, mut args: Args) -> Result<(), Failure> {
+    let adress = args.adress()?;
+    args.done()?;
+    for service in ble.gatt_tree(adress)? {
+        let kind = if service.primary {
+            "primary"
+        } else {
+            "secondary"
+        };
+        println!("service {} ({})", service.uuid, kind);
+        for characteristic in service.characteristics {
+            let flags: Vec<_> = characteristic
+                .flags
+                .iter()
+                .map(|flag| format!("{:?}", flag))
+                .collect();
+            println!(
+                "    characteristic {} [{}]",
+                characteristic.uuid,
+                flags.join(", ")
+            );
+            for descriptor in characteristic.descriptors {
+                println!("        descriptor {}", descriptor.uuid);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read(ble: &mut Ble, mut args: Args) -> Result<(), Failure> {
+    let format = args.format()?;
+    let adress = args.adress()?;
+    let uuid = args.uuid()?;
+    args.done()?;
+    let value = ble.read(adress, uuid)?;
+    println!("{}", format.encode(&value));
+    Ok(())
+}
+
+fn write(ble: &mut Ble, mut args: Args) -> Result<(), Failure> {
+    let format = args.format()?;
+    let adress = args.adress()?;
+    let uuid = args.uuid()?;
+    let data = args.positional("data")?;
+    args.done()?;
+    let data = format
+        .decode(&data)
+        .ok_or_else(|| usage(format!("data is not valid {:?}", format)))?;
+    Ok(ble.write(adress, uuid, data)?)
+}
+
+fn notify(ble: &mut Ble, mut args: Args) -> Result<(), Failure> {
+    let format = args.format()?;
+    let count: Option<usize> = args.parsed_option("--count")?;
+    let adress = args.adress()?;
+    let uuid = args.uuid()?;
+    args.done()?;
+
+    let mut notification = ble.notify(adress, uuid)?;
+    let mut buffer = vec![0u8; notification.mtu() as usize];
+    let mut recieved = 0;
+    while count.map_or(true, |count| recieved < count) {
+        let n = notification.read(&mut buffer)?;
+        if n == 0 {
+            eprintln!("notifications stopped, the device disconnected");
+            process::exit(1);
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        println!(
+            "{}.{:03} {}",
+            now.as_secs(),
+            now.subsec_millis(),
+            format.encode(&buffer[..n])
+        );
+        recieved += 1;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    Hex,
+    Utf8,
+    Base64,
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+impl Format {
+    fn encode(&self, data: &[u8]) -> String {
+        match self {
+            Format::Hex => {
+                let bytes: Vec<_> = data.iter().map(|b| format!("{:02x}", b)).collect();
+                bytes.join(" ")
+            }
+            Format::Utf8 => String::from_utf8_lossy(data).into_owned(),
+            Format::Base64 => {
+                let mut out = String::new();
+                for chunk in data.chunks(3) {
+                    let b = [
+                        chunk[0],
+                        *chunk.get(1).unwrap_or(&0),
+                        *chunk.get(2).unwrap_or(&0),
+                    ];
+                    let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+                    for i in 0..4 {
+                        if i <= chunk.len() {
+                            out.push(BASE64[(n >> (18 - 6 * i) & 63) as usize] as char);
+                        } else {
+                            out.push('=');
+                        }
+                    }
+                }
+                out
+            }
+        }
+    }
+
+    /// hex may be seperated by spaces or colons
+    fn decode(&self, text: &str) -> Option<Vec<u8>> {
+        match self {
+            Format::Hex => {
+                let digits: String = text
+                    .chars()
+                    .filter(|c| !c.is_whitespace() && *c != ':')
+                    .collect();
+                if digits.len() % 2 != 0 || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return None;
+                }
+                (0..digits.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).ok())
+                    .collect()
+            }
+            Format::Utf8 => Some(text.as_bytes().to_vec()),
+            Format::Base64 => {
+                let text = text.trim_end_matches('=');
+                let mut out = Vec::new();
+                let mut bits = 0u32;
+                let mut numb_bits = 0;
+                for c in text.bytes() {
+                    let value = BASE64.iter().position(|b| *b == c)? as u32;
+                    bits = bits << 6 | value;
+                    numb_bits += 6;
+                    if numb_bits >= 8 {
+                        numb_bits -= 8;
+                        out.push((bits >> numb_bits) as u8);
+                        bits &= (1 << numb_bits) - 1;
+                    }
+                }
+                Some(out)
+            }
+        }
+    }
+}
@@ -0,0 +1,303 @@
+//! The connection to the bus Ble makes by default. `rustbus::Conn` 0.6 does
+//! not put the error name in the header of error replies, the bus drops
+//! those as invalid and disconnects the sender. Every error we answer a call
+//! to an exported object with would cost us our connection. Connecting
+//! through rustbus also aborts debug builds, see `connect_unix`.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Write};
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
+
+use nix::errno::Errno;
+use nix::libc;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::socket::{
+    sendmsg, socket, AddressFamily, ControlMessage, MsgFlags, SockFlag, SockType, UnixAddr,
+};
+use nix::sys::uio::IoVec;
+use rustbus::auth;
+use rustbus::client_conn::{Error as ConnError, Timeout};
+use rustbus::message_builder::{MarshalledMessage, MessageType};
+use rustbus::wire::{marshal, unmarshal, util, HeaderField};
+use rustbus::ByteOrder;
+
+use crate::connection::BusConnection;
+use crate::dbus_helpers::bus_path;
+use crate::error::Error;
+use crate::notification::millis_left;
+
+/// where the system bus is unless DBUS_SYSTEM_BUS_ADDRESS says otherwise
+pub(crate) const SYSTEM_BUS: &str = "unix:path=/run/dbus/system_bus_socket";
+
+/// connect and recvmsg of nix compute the offset of the path in a
+/// sockaddr_un through a null pointer, debug builds of recent compilers
+/// abort on that. This is connect without it.
+fn connect_unix(fd: RawFd, addr: &UnixAddr) -> nix::Result<()> {
+    let sockaddr = &addr.0;
+    let path_offset = sockaddr.sun_path.as_ptr() as usize - sockaddr as *const _ as usize;
+    let len = (path_offset + addr.1) as libc::socklen_t;
+    let sockaddr = sockaddr as *const libc::sockaddr_un;
+    Errno::result(unsafe { libc::connect(fd, sockaddr.cast(), len) }).map(drop)
+}
+
+/// recvmsg without the null pointer, see `connect_unix`. The file
+/// descriptors that come along go in fds.
+fn recv_with_fds(fd: RawFd, buf: &mut [u8], fds: &mut Vec<RawFd>) -> nix::Result<usize> {
+    // aligned for cmsghdr, room for more fds than the bus passes at once
+    let mut cmsg_buffer = [0u64; 64];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = cmsg_buffer.as_mut_ptr().cast();
+    msg.msg_controllen = mem::size_of_val(&cmsg_buffer) as _;
+
+    let received = Errno::result(unsafe { libc::recvmsg(fd, &mut msg, 0) })?;
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    while let Some(header) = unsafe { cmsg.as_ref() } {
+        if header.cmsg_level == libc::SOL_SOCKET && header.cmsg_type == libc::SCM_RIGHTS {
+            let data = unsafe { libc::CMSG_DATA(cmsg) };
+            // cmsg_len is not a size_t everywhere
+            #[allow(clippy::unnecessary_cast)]
+            let cmsg_len = header.cmsg_len as usize;
+            let len = cmsg_len.saturating_sub(data as usize - cmsg as usize);
+            let data = data as *const RawFd;
+            for i in 0..len / mem::size_of::<RawFd>() {
+                fds.push(unsafe { data.add(i).read_unaligned() });
+            }
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+    }
+    Ok(received as usize)
+}
+
+/// rustbus only converts the errors of the nix it uses itself
+fn nix_error(err: nix::Error) -> ConnError {
+    let errno = err.as_errno().unwrap_or(nix::errno::Errno::UnknownErrno);
+    io::Error::from(errno).into()
+}
+
+/// A minimal bus connection, messages that arrive are queued by kind like
+/// `rustbus::RpcConn` does. Also used by the fake bluez.
+pub(crate) struct BusSocket {
+    stream: UnixStream,
+    buf_in: Vec<u8>,
+    fds_in: Vec<RawFd>,
+    serial_counter: u32,
+    responses: HashMap<u32, MarshalledMessage>,
+    calls: VecDeque<MarshalledMessage>,
+    signals: VecDeque<MarshalledMessage>,
+}
+
+impl BusSocket {
+    /// connect to the bus at a dbus address such as `unix:path=/tmp/bus`
+    pub(crate) fn connect(address: &str) -> Result<Self, Error> {
+        let addr = bus_path(address)?;
+        let fd = socket(
+            AddressFamily::Unix,
+            SockType::Stream,
+            SockFlag::SOCK_CLOEXEC,
+            None,
+        )
+        .map_err(nix_error)?;
+        let mut stream = unsafe { UnixStream::from_raw_fd(fd) };
+        connect_unix(fd, &addr).map_err(nix_error)?;
+
+        let rejected = |e| Err(Error::DbusConnectionError(e));
+        match auth::do_auth(&mut stream).map_err(ConnError::from)? {
+            auth::AuthResult::Ok => (),
+            auth::AuthResult::Rejected => return rejected(ConnError::AuthFailed),
+        }
+        match auth::negotiate_unix_fds(&mut stream).map_err(ConnError::from)? {
+            auth::AuthResult::Ok => (),
+            auth::AuthResult::Rejected => return rejected(ConnError::UnixFdNegotiationFailed),
+        }
+        auth::send_begin(&mut stream).map_err(ConnError::from)?;
+
+        Ok(BusSocket {
+            stream,
+            buf_in: Vec::new(),
+            fds_in: Vec::new(),
+            serial_counter: 1,
+            responses: HashMap::new(),
+            calls: VecDeque::new(),
+            signals: VecDeque::new(),
+        })
+    }
+
+    /// send the message with the error name in its header if it has one,
+    /// returns the serial it was sent with
+    pub(crate) fn send(&mut self, msg: &mut MarshalledMessage) -> Result<u32, Error> {
+        let serial = match msg.dynheader.serial {
+            Some(serial) => serial,
+            None => {
+                let serial = self.serial_counter;
+                self.serial_counter += 1;
+                msg.dynheader.serial = Some(serial);
+                serial
+            }
+        };
+
+        let mut header_fields = Vec::new();
+        if let Some(name) = &msg.dynheader.error_name {
+            header_fields.push(HeaderField::ErrorName(name.clone()));
+        }
+        let mut buf = Vec::new();
+        marshal::marshal(msg, ByteOrder::LittleEndian, &header_fields, &mut buf)?;
+
+        let fds = [ControlMessage::ScmRights(&msg.raw_fds)];
+        let cmsgs: &[ControlMessage] = if msg.raw_fds.is_empty() { &[] } else { &fds };
+        let iov = [IoVec::from_slice(&buf)];
+        let sent = sendmsg(
+            self.stream.as_raw_fd(),
+            &iov,
+            cmsgs,
+            MsgFlags::empty(),
+            None,
+        )
+        .map_err(nix_error)?;
+        self.stream
+            .write_all(&buf[sent..])
+            .map_err(ConnError::from)?;
+        Ok(serial)
+    }
+
+    /// send a call and wait for its reply, other messages are dropped. Only
+    /// used while setting up, before anyone can call us.
+    pub(crate) fn call(
+        &mut self,
+        msg: &mut MarshalledMessage,
+        timeout: Duration,
+    ) -> Result<MarshalledMessage, Error> {
+        let serial = self.send(msg)?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match self.recv(Timeout::Duration(left))? {
+                Some(reply) if reply.dynheader.response_serial == Some(serial) => return Ok(reply),
+                Some(_) => (),
+                None => return Err(ConnError::TimedOut.into()),
+            }
+        }
+    }
+
+    /// number of bytes the message at the start of the buffer needs
+    fn bytes_needed(&self) -> Result<usize, Error> {
+        if self.buf_in.len() < unmarshal::HEADER_LEN + 4 {
+            return Ok(unmarshal::HEADER_LEN + 4);
+        }
+        let (_, header) = unmarshal::unmarshal_header(&self.buf_in, 0)?;
+        let (_, fields_len) =
+            util::parse_u32(&self.buf_in[unmarshal::HEADER_LEN..], header.byteorder)?;
+        let header_len = unmarshal::HEADER_LEN + 4 + fields_len as usize;
+        let padding = (8 - header_len % 8) % 8;
+        Ok(header_len + padding + header.body_len as usize)
+    }
+
+    /// wait for the next message, None if none arrived before the timeout
+    pub(crate) fn recv(&mut self, timeout: Timeout) -> Result<Option<MarshalledMessage>, Error> {
+        let start = Instant::now();
+        loop {
+            let needed = self.bytes_needed()?;
+            if self.buf_in.len() >= needed && needed > unmarshal::HEADER_LEN + 4 {
+                return self.take_message().map(Some);
+            }
+
+            let timeout_ms = millis_left(start, timeout);
+            let mut fds = [PollFd::new(self.stream.as_raw_fd(), PollFlags::POLLIN)];
+            match poll(&mut fds, timeout_ms as i32) {
+                Ok(0) => return Ok(None),
+                Ok(_) => (),
+                Err(nix::Error::Sys(Errno::EINTR)) => continue,
+                Err(e) => return Err(nix_error(e).into()),
+            }
+
+            // only read up to the end of this message, so file descriptors
+            // are not attributed to the wrong message
+            let mut tmp = vec![0u8; needed - self.buf_in.len()];
+            let fd = self.stream.as_raw_fd();
+            let bytes = match recv_with_fds(fd, &mut tmp, &mut self.fds_in) {
+                Ok(bytes) => bytes,
+                Err(nix::Error::Sys(Errno::EINTR)) => continue,
+                Err(e) => return Err(nix_error(e).into()),
+            };
+            if bytes == 0 {
+                return Err(Error::CouldNotConnectToBus(
+                    "bus closed the connection".into(),
+                ));
+            }
+            self.buf_in.extend_from_slice(&tmp[..bytes]);
+        }
+    }
+
+    fn take_message(&mut self) -> Result<MarshalledMessage, Error> {
+        let (header_bytes, header) = unmarshal::unmarshal_header(&self.buf_in, 0)?;
+        let (dynheader_bytes, dynheader) =
+            unmarshal::unmarshal_dynamic_header(&header, &self.buf_in, header_bytes)?;
+        let (_, mut msg) = unmarshal::unmarshal_next_message(
+            &header,
+            dynheader,
+            &self.buf_in,
+            header_bytes + dynheader_bytes,
+        )?;
+        msg.raw_fds = std::mem::take(&mut self.fds_in);
+        self.buf_in.clear();
+        Ok(msg)
+    }
+}
+
+impl BusConnection for BusSocket {
+    fn send_message(&mut self, msg: &mut MarshalledMessage, _: Timeout) -> Result<u32, Error> {
+        self.send(msg)
+    }
+
+    fn try_get_response(&mut self, serial: u32) -> Option<MarshalledMessage> {
+        self.responses.remove(&serial)
+    }
+
+    fn try_get_call(&mut self) -> Option<MarshalledMessage> {
+        self.calls.pop_front()
+    }
+
+    fn try_get_signal(&mut self) -> Option<MarshalledMessage> {
+        self.signals.pop_front()
+    }
+
+    fn refill_once(&mut self, timeout: Timeout) -> Result<(), Error> {
+        match self.recv(timeout)? {
+            Some(msg) => {
+                self.queue(msg);
+                Ok(())
+            }
+            None => Err(ConnError::TimedOut.into()),
+        }
+    }
+
+    fn refill_all(&mut self) -> Result<Vec<MarshalledMessage>, Error> {
+        while let Some(msg) = self.recv(Timeout::Nonblock)? {
+            self.queue(msg);
+        }
+        Ok(Vec::new())
+    }
+}
+
+impl BusSocket {
+    fn queue(&mut self, msg: MarshalledMessage) {
+        match msg.typ {
+            MessageType::Call => self.calls.push_back(msg),
+            MessageType::Signal => self.signals.push_back(msg),
+            MessageType::Reply | MessageType::Error => {
+                if let Some(serial) = msg.dynheader.response_serial {
+                    self.responses.insert(serial, msg);
+                }
+            }
+            MessageType::Invalid => (),
+        }
+    }
+}
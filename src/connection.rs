@@ -0,0 +1,323 @@
+//! The bus connection Ble uses. Ble talks to bluez through a
+//! `BusConnection`, by default its own connection to the system bus. Use
+//! `BleBuilder::with_bus_address` or `BleBuilder::with_session_bus` to
+//! connect to another bus or pass any `BusConnection`, for example a test
+//! double, to `BleBuilder::with_connection`.
+//!
+//! `rustbus::RpcConn` implements it too, but rustbus 0.6 leaves the error
+//! name out of error replies. The bus disconnects a sender of those, so
+//! with an `RpcConn` any call to an object we export that fails (an agent
+//! rejecting a passkey, a gatt server refusing a write) ends the connection.
+//! Debug builds of recent compilers also abort in nix 0.17, which rustbus
+//! uses, while it connects. To use one anyway add this to the Cargo.toml of
+//! your application:
+//!
+//! ```toml
+//! [profile.dev.package.nix]
+//! debug-assertions = false
+//! ```
+//!
+//! Every message passes through here, with the `tracing` feature each
+//! method call, reply, error and signal is emitted as an event with target
+//! `bluebus::dbus`. Replies carry the latency since their call was sent.
+//! Messages can also be recorded to a file, see `recording`.
+
+#[cfg(feature = "tracing")]
+use std::collections::HashMap;
+use std::collections::HashSet;
+#[cfg(feature = "tracing")]
+use std::time::{Duration, Instant};
+
+use rustbus::client_conn::{RpcConn, Timeout};
+use rustbus::message_builder::MarshalledMessage;
+#[cfg(feature = "tracing")]
+use rustbus::MessageType;
+
+use crate::error::Error;
+use crate::recording::{Direction, Recorder};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Moves messages between Ble and bluez. Messages that arrive are queued by
+/// kind: replies by the serial of their call, calls made to us and signals
+/// in order. This is how `rustbus::RpcConn` works.
+pub trait BusConnection {
+    /// send the message, if it has no serial yet one is assigned. Returns
+    /// the serial the message was sent with.
+    fn send_message(&mut self, msg: &mut MarshalledMessage, timeout: Timeout) -> Result<u32>;
+    fn try_get_response(&mut self, serial: u32) -> Option<MarshalledMessage>;
+    fn try_get_call(&mut self) -> Option<MarshalledMessage>;
+    fn try_get_signal(&mut self) -> Option<MarshalledMessage>;
+    /// block until a message is queued or the timeout passes
+    fn refill_once(&mut self, timeout: Timeout) -> Result<()>;
+    /// queue everything available without blocking, returns replies that
+    /// still need to be sent
+    fn refill_all(&mut self) -> Result<Vec<MarshalledMessage>>;
+}
+
+impl BusConnection for RpcConn {
+    fn send_message(&mut self, msg: &mut MarshalledMessage, timeout: Timeout) -> Result<u32> {
+        Ok(RpcConn::send_message(self, msg, timeout)?)
+    }
+    fn try_get_response(&mut self, serial: u32) -> Option<MarshalledMessage> {
+        RpcConn::try_get_response(self, serial)
+    }
+    fn try_get_call(&mut self) -> Option<MarshalledMessage> {
+        RpcConn::try_get_call(self)
+    }
+    fn try_get_signal(&mut self) -> Option<MarshalledMessage> {
+        RpcConn::try_get_signal(self)
+    }
+    fn refill_once(&mut self, timeout: Timeout) -> Result<()> {
+        RpcConn::refill_once(self, timeout)?;
+        Ok(())
+    }
+    fn refill_all(&mut self) -> Result<Vec<MarshalledMessage>> {
+        Ok(RpcConn::refill_all(self)?)
+    }
+}
+
+/// a call we sent and are waiting on
+#[cfg(feature = "tracing")]
+struct Pending {
+    sent: Instant,
+    path: String,
+    interface: String,
+    member: String,
+}
+
+pub(crate) struct Connection {
+    transport: Box<dyn BusConnection>,
+    recorder: Option<Recorder>,
+    /// calls we gave up waiting on, their replies are dropped as they arrive
+    abandoned: HashSet<u32>,
+    /// by serial, so replies can be matched to their call
+    #[cfg(feature = "tracing")]
+    pending: HashMap<u32, Pending>,
+}
+
+impl Connection {
+    pub(crate) fn new(transport: Box<dyn BusConnection>, recorder: Option<Recorder>) -> Self {
+        Connection {
+            transport,
+            recorder,
+            abandoned: HashSet::new(),
+            #[cfg(feature = "tracing")]
+            pending: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn send_message(
+        &mut self,
+        msg: &mut MarshalledMessage,
+        timeout: Timeout,
+    ) -> Result<u32> {
+        let serial = self.transport.send_message(msg, timeout)?;
+        #[cfg(feature = "tracing")]
+        self.trace_sent(msg, serial);
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(Direction::Sent, msg, serial);
+            recorder.check()?;
+        }
+        Ok(serial)
+    }
+
+    /// record a message we took from the transport, errors are returned by
+    /// the next send
+    fn received(&mut self, msg: &MarshalledMessage) {
+        if let Some(recorder) = &mut self.recorder {
+            let serial = msg.dynheader.serial.unwrap_or_default();
+            recorder.record(Direction::Received, msg, serial);
+        }
+    }
+
+    pub(crate) fn try_get_response(&mut self, serial: u32) -> Option<MarshalledMessage> {
+        let response = self.transport.try_get_response(serial)?;
+        #[cfg(feature = "tracing")]
+        self.trace_response(&response, serial);
+        self.received(&response);
+        Some(response)
+    }
+
+    /// stop waiting on the call with this serial, its reply is dropped
+    /// instead of staying queued forever
+    pub(crate) fn abandon(&mut self, serial: u32) {
+        match self.try_get_response(serial) {
+            Some(reply) => close_fds(reply),
+            None => {
+                self.abandoned.insert(serial);
+            }
+        }
+    }
+
+    fn drop_abandoned(&mut self) {
+        let abandoned: Vec<u32> = self.abandoned.iter().copied().collect();
+        for serial in abandoned {
+            if let Some(reply) = self.try_get_response(serial) {
+                close_fds(reply);
+                self.abandoned.remove(&serial);
+            }
+        }
+    }
+
+    pub(crate) fn try_get_call(&mut self) -> Option<MarshalledMessage> {
+        let call = self.transport.try_get_call()?;
+        #[cfg(feature = "tracing")]
+        trace_received(&call);
+        self.received(&call);
+        Some(call)
+    }
+
+    pub(crate) fn try_get_signal(&mut self) -> Option<MarshalledMessage> {
+        let signal = self.transport.try_get_signal()?;
+        #[cfg(feature = "tracing")]
+        trace_received(&signal);
+        self.received(&signal);
+        Some(signal)
+    }
+
+    pub(crate) fn wait_response(
+        &mut self,
+        serial: u32,
+        timeout: Timeout,
+    ) -> Result<MarshalledMessage> {
+        loop {
+            if let Some(response) = self.try_get_response(serial) {
+                return Ok(response);
+            }
+            self.refill_once(timeout)?;
+        }
+    }
+
+    pub(crate) fn wait_signal(&mut self, timeout: Timeout) -> Result<MarshalledMessage> {
+        loop {
+            if let Some(signal) = self.try_get_signal() {
+                return Ok(signal);
+            }
+            self.refill_once(timeout)?;
+        }
+    }
+
+    pub(crate) fn refill_once(&mut self, timeout: Timeout) -> Result<()> {
+        self.transport.refill_once(timeout)?;
+        self.drop_abandoned();
+        Ok(())
+    }
+
+    /// read everything available without blocking, returns error replies
+    /// for calls that were filtered out, these still need to be sent
+    pub(crate) fn refill_all(&mut self) -> Result<Vec<MarshalledMessage>> {
+        let replies = self.transport.refill_all()?;
+        self.drop_abandoned();
+        Ok(replies)
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl Connection {
+    fn trace_sent(&mut self, msg: &MarshalledMessage, serial: u32) {
+        let header = &msg.dynheader;
+        match msg.typ {
+            MessageType::Call => {
+                tracing::debug!(
+                    target: "bluebus::dbus",
+                    serial,
+                    destination = header.destination.as_deref().unwrap_or_default(),
+                    path = header.object.as_deref().unwrap_or_default(),
+                    interface = header.interface.as_deref().unwrap_or_default(),
+                    member = header.member.as_deref().unwrap_or_default(),
+                    "calling method"
+                );
+                let pending = Pending {
+                    sent: Instant::now(),
+                    path: header.object.clone().unwrap_or_default(),
+                    interface: header.interface.clone().unwrap_or_default(),
+                    member: header.member.clone().unwrap_or_default(),
+                };
+                // replies to calls that timed out are never taken
+                if self.pending.len() > 256 {
+                    let max_age = Duration::from_secs(60);
+                    self.pending.retain(|_, p| p.sent.elapsed() < max_age);
+                }
+                self.pending.insert(serial, pending);
+            }
+            MessageType::Reply => tracing::debug!(
+                target: "bluebus::dbus",
+                serial,
+                reply_to = header.response_serial,
+                "sending reply"
+            ),
+            MessageType::Error => tracing::debug!(
+                target: "bluebus::dbus",
+                serial,
+                reply_to = header.response_serial,
+                error = header.error_name.as_deref().unwrap_or_default(),
+                "sending error"
+            ),
+            MessageType::Signal | MessageType::Invalid => tracing::debug!(
+                target: "bluebus::dbus",
+                serial,
+                path = header.object.as_deref().unwrap_or_default(),
+                member = header.member.as_deref().unwrap_or_default(),
+                "sending message"
+            ),
+        }
+    }
+
+    fn trace_response(&mut self, response: &MarshalledMessage, serial: u32) {
+        let pending = match self.pending.remove(&serial) {
+            Some(pending) => pending,
+            None => return,
+        };
+        let latency_ms = pending.sent.elapsed().as_secs_f64() * 1000.0;
+        match response.typ {
+            MessageType::Error => tracing::debug!(
+                target: "bluebus::dbus",
+                serial,
+                path = pending.path.as_str(),
+                interface = pending.interface.as_str(),
+                member = pending.member.as_str(),
+                error = response.dynheader.error_name.as_deref().unwrap_or_default(),
+                latency_ms,
+                "method returned error"
+            ),
+            _ => tracing::debug!(
+                target: "bluebus::dbus",
+                serial,
+                path = pending.path.as_str(),
+                interface = pending.interface.as_str(),
+                member = pending.member.as_str(),
+                latency_ms,
+                "method returned"
+            ),
+        }
+    }
+}
+
+/// a dropped message does not close the file descriptors it carries, for
+/// one the socket of an AcquireNotify we gave up on
+fn close_fds(msg: MarshalledMessage) {
+    for fd in msg.raw_fds {
+        let _ = nix::unistd::close(fd);
+    }
+}
+
+#[cfg(feature = "tracing")]
+fn trace_received(msg: &MarshalledMessage) {
+    let header = &msg.dynheader;
+    let kind = match msg.typ {
+        MessageType::Call => "method called",
+        MessageType::Signal => "signal",
+        _ => "message",
+    };
+    tracing::debug!(
+        target: "bluebus::dbus",
+        serial = header.serial,
+        sender = header.sender.as_deref().unwrap_or_default(),
+        path = header.object.as_deref().unwrap_or_default(),
+        interface = header.interface.as_deref().unwrap_or_default(),
+        member = header.member.as_deref().unwrap_or_default(),
+        "{}",
+        kind
+    );
+}
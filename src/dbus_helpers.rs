@@ -1,5 +1,7 @@
 use crate::error::Error;
-use rustbus::{message_builder::MarshalledMessage, params, params::Param, MessageBuilder};
+use nix::sys::socket::UnixAddr;
+use rustbus::message_builder::{DynamicHeader, MarshalledMessage, MessageType};
+use rustbus::{params, params::Param, signature, MessageBuilder};
 
 pub fn unwrap_variant<'e, 'a>(
     container: params::Container<'e, 'a>,
@@ -83,6 +85,164 @@ pub fn get_name_owner(name: String) -> Result<MarshalledMessage, Error> {
     Ok(msg)
 }
 
+pub fn bluez_owner_changed_rule() -> String {
+    "type='signal',sender='org.freedesktop.DBus',interface='org.freedesktop.DBus',\
+    member='NameOwnerChanged',arg0='org.bluez'"
+        .to_owned()
+}
+
+pub fn bluez_properties_changed_rule() -> String {
+    "type='signal',sender='org.bluez',interface='org.freedesktop.DBus.Properties',\
+    member='PropertiesChanged'"
+        .to_owned()
+}
+
+pub fn bluez_objects_changed_rule() -> String {
+    "type='signal',sender='org.bluez',interface='org.freedesktop.DBus.ObjectManager'".to_owned()
+}
+
+/// remove a key from a a{sv} dict and return its value if it is a u16
+pub fn take_variant_u16(dict: &mut params::DictMap, key: &str) -> Option<u16> {
+    let param = dict.remove(&params::Base::String(key.to_owned()))?;
+    let container = unwrap_container(param)?;
+    let variant = unwrap_variant(container)?;
+    let base = unwrap_base(variant.value)?;
+    unwrap_u16(base)
+}
+
+pub fn take_variant_objectpath(dict: &mut params::DictMap, key: &str) -> Option<String> {
+    let param = dict.remove(&params::Base::String(key.to_owned()))?;
+    let container = unwrap_container(param)?;
+    let variant = unwrap_variant(container)?;
+    match unwrap_base(variant.value)? {
+        params::Base::ObjectPath(s) => Some(s),
+        _ => None,
+    }
+}
+
+/// signature of a single complete type, only use with valid constant signatures
+pub fn signature(sig: &str) -> signature::Type {
+    signature::Type::parse_description(sig)
+        .expect("invalid constant signature")
+        .remove(0)
+}
+
+pub fn string_param(s: impl Into<String>) -> Param<'static, 'static> {
+    Param::Base(params::Base::String(s.into()))
+}
+
+pub fn objectpath_param(s: impl Into<String>) -> Param<'static, 'static> {
+    Param::Base(params::Base::ObjectPath(s.into()))
+}
+
+pub fn array_param(
+    element_sig: &str,
+    values: Vec<Param<'static, 'static>>,
+) -> Param<'static, 'static> {
+    let array = params::Array {
+        element_sig: signature(element_sig),
+        values,
+    };
+    Param::Container(params::Container::Array(array))
+}
+
+pub fn string_array_param(strings: Vec<String>) -> Param<'static, 'static> {
+    array_param("s", strings.into_iter().map(string_param).collect())
+}
+
+pub fn byte_array_param(bytes: &[u8]) -> Param<'static, 'static> {
+    let bytes = bytes.iter().map(|b| Param::Base(params::Base::Byte(*b)));
+    array_param("y", bytes.collect())
+}
+
+pub fn dict_param(
+    key_sig: signature::Base,
+    value_sig: &str,
+    map: params::DictMap<'static, 'static>,
+) -> Param<'static, 'static> {
+    let dict = params::Dict {
+        key_sig,
+        value_sig: signature(value_sig),
+        map,
+    };
+    Param::Container(params::Container::Dict(dict))
+}
+
+/// a{sv}, the values are wrapped in variants
+pub fn variant_dict_param(
+    entries: Vec<(&str, Param<'static, 'static>)>,
+) -> Param<'static, 'static> {
+    let map = entries
+        .into_iter()
+        .map(|(key, value)| {
+            let value = Param::Container(params::Container::make_variant(value));
+            (params::Base::String(key.to_owned()), value)
+        })
+        .collect();
+    dict_param(signature::Base::String, "v", map)
+}
+
+/// a{sa{sv}}: interface name to its properties
+pub fn interfaces_param(
+    interfaces: Vec<(&str, Param<'static, 'static>)>,
+) -> Param<'static, 'static> {
+    let map = interfaces
+        .into_iter()
+        .map(|(name, props)| (params::Base::String(name.to_owned()), props))
+        .collect();
+    dict_param(signature::Base::String, "a{sv}", map)
+}
+
+/// a{oa{sa{sv}}}: the reply to ObjectManager.GetManagedObjects
+pub fn managed_objects_param(
+    objects: Vec<(String, Param<'static, 'static>)>,
+) -> Param<'static, 'static> {
+    let map = objects
+        .into_iter()
+        .map(|(path, interfaces)| (params::Base::ObjectPath(path), interfaces))
+        .collect();
+    dict_param(signature::Base::ObjectPath, "a{sa{sv}}", map)
+}
+
+/// parse a dbus address such as `unix:path=/tmp/bus,guid=..`, only unix
+/// sockets are supported
+pub fn bus_path(address: &str) -> Result<UnixAddr, Error> {
+    let unsupported = || Error::CouldNotConnectToBus(address.to_owned());
+    let location = address.split(',').next().ok_or_else(unsupported)?;
+    if let Some(path) = location.strip_prefix("unix:path=") {
+        UnixAddr::new(path).map_err(|_| unsupported())
+    } else if let Some(name) = location.strip_prefix("unix:abstract=") {
+        UnixAddr::new_abstract(name.as_bytes()).map_err(|_| unsupported())
+    } else {
+        Err(unsupported())
+    }
+}
+
+/// make_error_response from rustbus sets the wrong message type, this does not
+pub fn error_response(call: &DynamicHeader, name: &str, msg: &str) -> MarshalledMessage {
+    let mut response = call.make_error_response(name.to_owned(), Some(msg.to_owned()));
+    response.typ = MessageType::Error;
+    response
+}
+
+pub fn properties_changed(
+    path: &str,
+    interface: &str,
+    changed: Param<'static, 'static>,
+) -> Result<MarshalledMessage, Error> {
+    let mut signal = MessageBuilder::new()
+        .signal(
+            "org.freedesktop.DBus.Properties".into(),
+            "PropertiesChanged".into(),
+            path.to_owned(),
+        )
+        .build();
+    signal.body.push_param(interface)?;
+    signal.body.push_old_param(&changed)?;
+    signal.body.push_param(&[] as &[&str])?;
+    Ok(signal)
+}
+
 pub fn register_agent(obj_path: &str, capability: &str) -> Result<MarshalledMessage, Error> {
     let param1 = Param::Base(params::Base::ObjectPath(obj_path.to_owned()));
     let param2 = Param::Base(params::Base::String(capability.to_owned()));
@@ -0,0 +1,94 @@
+use rustbus::params::{self, Param};
+
+use crate::dbus_helpers::*;
+use crate::uuid::Uuid;
+
+/// Which kind of devices to look for during discovery
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// classic and low energy, bluez decides
+    Auto,
+    BrEdr,
+    Le,
+}
+
+impl Transport {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Transport::Auto => "auto",
+            Transport::BrEdr => "bredr",
+            Transport::Le => "le",
+        }
+    }
+}
+
+/// Limits the devices reported during discovery, pass it to
+/// `Ble::set_discovery_filter` before `Ble::start_discovery`. An empty
+/// filter removes any filter set before.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DiscoveryFilter {
+    uuids: Vec<Uuid>,
+    rssi: Option<i16>,
+    transport: Option<Transport>,
+    duplicate_data: Option<bool>,
+    pattern: Option<String>,
+}
+
+impl DiscoveryFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// only report devices advertising this service, can be called multiple times
+    pub fn with_uuid(mut self, uuid: Uuid) -> Self {
+        self.uuids.push(uuid);
+        self
+    }
+
+    /// only report devices recieved with at least this signal strength in dBm
+    pub fn with_rssi(mut self, rssi: i16) -> Self {
+        self.rssi = Some(rssi);
+        self
+    }
+
+    pub fn with_transport(mut self, transport: Transport) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    /// report every advertisement not just changes, needed to follow the rssi
+    pub fn duplicate_data(mut self, duplicates: bool) -> Self {
+        self.duplicate_data = Some(duplicates);
+        self
+    }
+
+    /// only report devices whose adress or name starts with this
+    pub fn with_pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.pattern = Some(pattern.into());
+        self
+    }
+
+    pub(crate) fn as_param(&self) -> Param<'static, 'static> {
+        let mut entries = Vec::new();
+        if !self.uuids.is_empty() {
+            let uuids = self.uuids.iter().map(Uuid::to_string).collect();
+            entries.push(("UUIDs", string_array_param(uuids)));
+        }
+        if let Some(rssi) = self.rssi {
+            entries.push(("RSSI", Param::Base(params::Base::Int16(rssi))));
+        }
+        if let Some(transport) = self.transport {
+            entries.push(("Transport", string_param(transport.as_str())));
+        }
+        if let Some(duplicates) = self.duplicate_data {
+            entries.push((
+                "DuplicateData",
+                Param::Base(params::Base::Boolean(duplicates)),
+            ));
+        }
+        if let Some(pattern) = &self.pattern {
+            entries.push(("Pattern", string_param(pattern.as_str())));
+        }
+        variant_dict_param(entries)
+    }
+}
@@ -1,27 +1,47 @@
 use rustbus::message_builder::MarshalledMessage;
 use rustbus::params::message::Message;
 
+use crate::uuid::Uuid;
+
 #[derive(Debug)]
 pub enum Error {
     RustbusError(rustbus::Error),
     DbusConnectionError(rustbus::client_conn::Error),
     DBusUnMashallError(rustbus::wire::unmarshal::Error),
-    CouldNotConnectToDevice,
     CouldNotConnectToBus(String),
+    InvalidAddress(String),
+    InvalidUuid(String),
+    InvalidPath(String),
     UuidNotFound,
     DoesNotExist(Context),
     CharacteristicNotFound(Context),
     NoFdReturned,
     UnexpectedDbusReply,
-    PairingTimeOut,
     CouldNotRemoveCache(std::io::Error),
+    /// no permission to access this file, most bluez storage needs root
+    PermissionDenied(std::path::PathBuf),
+    Io(std::io::Error),
+    /// a bluetooth socket call failed
+    Socket(std::io::Error),
+    /// the file is not a recording made by `BleBuilder::record_to`
+    InvalidRecording(String),
+    /// during replay a message was sent that differs from the recording
+    ReplayMismatch(String),
     OperationNotSupported(Context),
     InvalidLength(Context),
+    InvalidValue(Context),
     AuthenticationCanceled(Context),
     AuthenticationFailed(Context),
     BluezFailed(Context),
     NotPermitted(Context),
     InProgress(Context),
+    AlreadyExists(Context),
+    /// bluez did not answer within the timeout
+    TimedOut(Context),
+    /// the operation was aborted through its `CancelToken`
+    Cancelled(Context),
+    /// the device disconnected, no more notifications will arrive
+    Disconnected(Context),
     UnknownErrorMessage(String),
 }
 
@@ -34,7 +54,7 @@ impl PartialEq for Error {
 
 impl From<std::io::Error> for Error {
     fn from(err: std::io::Error) -> Error {
-        Error::CouldNotRemoveCache(err)
+        Error::Io(err)
     }
 }
 
@@ -71,17 +91,44 @@ impl<'a> From<(Message<'a, 'a>, Context)> for Error {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Context {
     Remove,
     Connect,
+    DeviceInfo,
+    AdapterStorage,
+    AttributeCache,
+    BondInfo,
+    GattTree,
+    SetDiscoveryFilter,
     Disconnect,
     Pair,
+    CancelPairing,
+    RegisterAgent,
+    RegisterApplication,
+    UnregisterApplication,
+    RegisterAdvertisement,
+    UnregisterAdvertisement,
+    AdvertisingInstances,
+    BatteryPercentage,
+    RegisterBatteryProvider,
+    UnregisterBatteryProvider,
+    ProvideBattery,
+    RegisterProfile,
+    UnregisterProfile,
+    ConnectProfile,
+    DisconnectProfile,
+    NotifyValue(Uuid),
     StartDiscovery,
     StopDiscovery,
-    AquireNotify(String),
-    ReadValue(String),
-    WriteValue(String),
+    AquireNotify(Uuid),
+    /// recieving from a `Notification`
+    Notification(Uuid),
+    ReadValue(Uuid),
+    WriteValue(Uuid),
+    Mtu(Uuid),
+    Decode(Uuid),
 }
 
 fn unpack_msg(msg: &mut Message) -> Option<String> {
@@ -94,6 +141,9 @@ pub fn error_from(mut msg: Message, context: Context) -> Error {
         match error_msg.as_str() {
             "Operation is not supported" => return Error::OperationNotSupported(context),
             "Invalid Length" => return Error::InvalidLength(context),
+            // Properties.Get on an interface the object does not (yet) have
+            msg if msg.starts_with("No such interface") => return Error::DoesNotExist(context),
+            msg if msg.starts_with("No such property") => return Error::DoesNotExist(context),
             _ => (),
         }
     }
@@ -101,10 +151,16 @@ pub fn error_from(mut msg: Message, context: Context) -> Error {
         match error_name.as_str() {
             "org.bluez.Error.AuthenticationCanceled" => return Error::AuthenticationCanceled(context),
             "org.bluez.Error.AuthenticationFailed" => return Error::AuthenticationFailed(context),
+            // the agent refused, for one a passkey that did not match
+            "org.bluez.Error.AuthenticationRejected" => {
+                return Error::AuthenticationFailed(context)
+            }
             "org.bluez.Error.DoesNotExist" => return Error::DoesNotExist(context),
             "org.bluez.Error.Failed" => return Error::BluezFailed(context),
             "org.bluez.Error.NotPermitted" => return Error::NotPermitted(context),
             "org.bluez.Error.InProgress" => return Error::InProgress(context),
+            "org.bluez.Error.AlreadyExists" => return Error::AlreadyExists(context),
+            "org.freedesktop.DBus.Error.UnknownObject" => return Error::DoesNotExist(context),
             _ => (),
         }
     }
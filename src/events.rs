@@ -0,0 +1,412 @@
+use std::time::Instant;
+
+use rustbus::client_conn::Timeout;
+use rustbus::message_builder::MarshalledMessage;
+use rustbus::standard_messages;
+
+use crate::address::{Address, IntoAddress};
+use crate::advertising::{self, AdvertisementId};
+use crate::battery;
+use crate::dbus_helpers::*;
+use crate::error::Error;
+use crate::gatt_server;
+use crate::info::{interfaces_from_param, DeviceInfo, Properties};
+use crate::notification::Notification;
+use crate::operations::CallOptions;
+use crate::path::{BluezPath, PathKind};
+use crate::profile::{self, ProfileId};
+use crate::uuid::{IntoUuid, Uuid};
+use crate::Ble;
+
+/// Things that happend on the bus that the user of Ble might need to act on
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    /// bluetoothd exited, all connections and notify file descriptors are gone
+    BluezStopped,
+    /// bluetoothd (re)appeared on the bus. The agent, any gatt applications,
+    /// advertisements and profiles have already been registered again, notifications can be
+    /// re-established using `reacquire_notifications` once the devices are
+    /// connected again. Holds the errors of everything that could not be
+    /// registered again, empty if all went well.
+    BluezRestarted(Vec<Error>),
+    /// the device with this adress connected
+    Connected(Address),
+    /// the device with this adress disconnected
+    Disconnected(Address),
+    /// all services of the device with this adress have been discoverd,
+    /// its characteristics can now be used
+    ServicesResolved(Address),
+    /// bluez stopped this advertisement, for example because its timeout
+    /// passed. It is no longer exported and does not need to be unregisterd.
+    AdvertisementReleased(AdvertisementId),
+    /// a device showed up on our adapter, for example during discovery
+    DeviceAdded(DeviceInfo),
+    /// bluez forgot this device, because it was removed or not seen for a while
+    DeviceRemoved(Address),
+    /// new signal strength in dBm for a device, reported during discovery
+    Rssi(Address, i16),
+    /// the battery level of the device changed, in percent
+    BatteryChanged(Address, u8),
+    /// a device connected to this profile, take the stream with
+    /// `take_profile_connection`
+    ProfileConnected(ProfileId, Address),
+    /// bluez wants the profile connection to this device closed, shut down
+    /// the stream
+    ProfileDisconnectRequested(ProfileId, Address),
+    /// bluez unregisterd this profile, it is no longer exported
+    ProfileReleased(ProfileId),
+    /// the device is back and notify was acquired again, take the new stream
+    /// with `take_notification`. Only with `BleBuilder::with_auto_reacquire`.
+    NotificationReacquired(Address, Uuid),
+    /// the device is back but acquiring notify again failed with this error
+    NotificationReacquireFailed(Address, Uuid, Error),
+}
+
+impl Ble {
+    /// block until an event arrives or the timeout passes. Calls to objects
+    /// we export (such as a gatt application) are answered while waiting,
+    /// notify is acquired again here for `BleBuilder::with_auto_reacquire`.
+    pub fn wait_event(&mut self, timeout: Timeout) -> Result<Event, Error> {
+        self.listen()?;
+        let start = Instant::now();
+        loop {
+            self.process_queued()?;
+            if let Some(event) = self.events.pop_front() {
+                return Ok(event);
+            }
+            let left = timeout_left(start, timeout)?;
+            if !self.pending_reacquires.is_empty() && !matches!(left, Timeout::Nonblock) {
+                self.reacquire_one(left);
+                continue;
+            }
+            self.connection.refill_once(left)?;
+        }
+    }
+
+    /// return an event if one is available without blocking
+    pub fn try_event(&mut self) -> Result<Option<Event>, Error> {
+        self.listen()?;
+        for mut reply in self.connection.refill_all()? {
+            self.connection.send_message(&mut reply, self.timeout)?;
+        }
+        self.process_queued()?;
+        Ok(self.events.pop_front())
+    }
+
+    /// ask the bus for the signals events are made from. Not done before
+    /// the user wants events (see `BleBuilder::with_events`) as they pile up
+    /// in the connection until they are handled.
+    pub(crate) fn listen(&mut self) -> Result<(), Error> {
+        if self.listening {
+            return Ok(());
+        }
+        for rule in &[
+            bluez_properties_changed_rule(),
+            bluez_objects_changed_rule(),
+        ] {
+            let mut message = standard_messages::add_match(rule.to_owned());
+            let response_serial = self.connection.send_message(&mut message, self.timeout)?;
+            self.connection
+                .wait_response(response_serial, self.timeout)?;
+        }
+        self.listening = true;
+        Ok(())
+    }
+
+    /// wait for the reply to a call we made while answering calls made to us.
+    /// Needed when bluez calls us before it replies, for example when
+    /// registering a gatt application.
+    pub(crate) fn wait_response_serving_calls(
+        &mut self,
+        serial: u32,
+        timeout: Timeout,
+    ) -> Result<MarshalledMessage, Error> {
+        let start = Instant::now();
+        loop {
+            if let Some(response) = self.connection.try_get_response(serial) {
+                return Ok(response);
+            }
+            while let Some(call) = self.connection.try_get_call() {
+                self.handle_call(call)?;
+            }
+            self.connection.refill_once(timeout_left(start, timeout)?)?;
+        }
+    }
+
+    /// answer all queued calls and turn all queued signals into events
+    fn process_queued(&mut self) -> Result<(), Error> {
+        while let Some(call) = self.connection.try_get_call() {
+            self.handle_call(call)?;
+        }
+        while let Some(signal) = self.connection.try_get_signal() {
+            self.handle_signal(signal);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn handle_call(&mut self, call: MarshalledMessage) -> Result<(), Error> {
+        let path = call.dynheader.object.clone().unwrap_or_default();
+        let mut reply = if path.starts_with(gatt_server::ROOT) {
+            self.handle_gatt_call(call)?
+        } else if path.starts_with(advertising::ROOT) {
+            self.handle_advertisement_call(call)?
+        } else if path.starts_with(battery::ROOT) {
+            self.handle_battery_call(call)?
+        } else if path.starts_with(profile::ROOT) {
+            self.handle_profile_call(call)?
+        } else {
+            error_response(
+                &call.dynheader,
+                "org.freedesktop.DBus.Error.UnknownObject",
+                "no such object",
+            )
+        };
+        self.connection.send_message(&mut reply, self.timeout)?;
+        Ok(())
+    }
+
+    /// turn the signal into events, a malformed signal is skipped
+    fn handle_signal(&mut self, signal: MarshalledMessage) {
+        let header = &signal.dynheader;
+        let _malformed = match (header.interface.as_deref(), header.member.as_deref()) {
+            (Some("org.freedesktop.DBus"), Some("NameOwnerChanged")) => {
+                self.handle_owner_changed(signal)
+            }
+            (Some("org.freedesktop.DBus.Properties"), Some("PropertiesChanged")) => {
+                self.handle_properties_changed(signal)
+            }
+            (Some("org.freedesktop.DBus.ObjectManager"), Some("InterfacesAdded")) => {
+                self.handle_interfaces_added(signal)
+            }
+            (Some("org.freedesktop.DBus.ObjectManager"), Some("InterfacesRemoved")) => {
+                self.handle_interfaces_removed(signal)
+            }
+            _ => Ok(()),
+        };
+    }
+
+    fn handle_owner_changed(&mut self, signal: MarshalledMessage) -> Result<(), Error> {
+        let (name, _old_owner, new_owner) = signal
+            .body
+            .parser()
+            .get3::<String, String, String>()
+            .map_err(|_| Error::UnexpectedDbusReply)?;
+        if name != "org.bluez" {
+            return Ok(());
+        }
+
+        if new_owner.is_empty() {
+            self.bluez_owner = None;
+            for (adress, _) in self.notifications.clone() {
+                self.lose_notifications(adress);
+            }
+            self.events.push_back(Event::BluezStopped);
+            return Ok(());
+        }
+        if self.bluez_owner.as_ref() == Some(&new_owner) {
+            return Ok(());
+        }
+        self.bluez_owner = Some(new_owner);
+        // one failure should not keep the rest from being registered
+        let mut failures = Vec::new();
+        failures.extend(self.register_agent().err());
+        failures.extend(self.reregister_applications());
+        failures.extend(self.reregister_advertisements());
+        failures.extend(self.reregister_battery_provider());
+        failures.extend(self.reregister_profiles());
+        self.events.push_back(Event::BluezRestarted(failures));
+        Ok(())
+    }
+
+    fn handle_properties_changed(&mut self, signal: MarshalledMessage) -> Result<(), Error> {
+        let path = signal.dynheader.object.clone().unwrap_or_default();
+        let mut signal = signal.unmarshall_all()?;
+        if signal.params.len() != 3 {
+            return Err(Error::UnexpectedDbusReply);
+        }
+        let _invalidated = signal.params.pop();
+        let changed = signal.params.pop().unwrap();
+        let interface = signal.params.pop().unwrap();
+
+        let interface = unwrap_base(interface)
+            .and_then(unwrap_string)
+            .ok_or(Error::UnexpectedDbusReply)?;
+        let mut changed = Properties::from_param(changed)?;
+
+        let adress = match self.our_device(&path) {
+            Some(path) => path.device_adress().unwrap(),
+            None => return Ok(()),
+        };
+        if interface == "org.bluez.Battery1" {
+            if let Some(percentage) = changed.take_u8("Percentage") {
+                self.events
+                    .push_back(Event::BatteryChanged(adress, percentage));
+            }
+            return Ok(());
+        }
+        if interface != "org.bluez.Device1" {
+            return Ok(());
+        }
+        match changed.take_bool("Connected") {
+            Some(true) => self.events.push_back(Event::Connected(adress)),
+            Some(false) => {
+                self.lose_notifications(adress);
+                self.events.push_back(Event::Disconnected(adress))
+            }
+            None => (),
+        }
+        if let Some(true) = changed.take_bool("ServicesResolved") {
+            self.events.push_back(Event::ServicesResolved(adress));
+            self.queue_reacquire(adress);
+        }
+        if let Some(rssi) = changed.take_i16("RSSI") {
+            self.events.push_back(Event::Rssi(adress, rssi));
+        }
+        Ok(())
+    }
+
+    /// path of the object the signal is about if it is a device on our adapter
+    fn our_device(&self, path: &str) -> Option<BluezPath> {
+        let path = path.parse::<BluezPath>().ok()?;
+        if path.kind() != PathKind::Device || path.adapter_path() != self.adapter_path() {
+            return None;
+        }
+        Some(path)
+    }
+
+    fn handle_interfaces_added(&mut self, signal: MarshalledMessage) -> Result<(), Error> {
+        let mut signal = signal.unmarshall_all()?;
+        if signal.params.len() != 2 {
+            return Err(Error::UnexpectedDbusReply);
+        }
+        let interfaces = signal.params.pop().unwrap();
+        let path = signal.params.pop().unwrap();
+        let path = match unwrap_base(path) {
+            Some(rustbus::params::Base::ObjectPath(path)) => path,
+            _ => return Err(Error::UnexpectedDbusReply),
+        };
+        if self.our_device(&path).is_none() {
+            return Ok(());
+        }
+        let mut interfaces = interfaces_from_param(interfaces)?;
+        if let Some(props) = interfaces.remove("org.bluez.Device1") {
+            let info = DeviceInfo::from_properties(props)?;
+            self.events.push_back(Event::DeviceAdded(info));
+        }
+        Ok(())
+    }
+
+    fn handle_interfaces_removed(&mut self, signal: MarshalledMessage) -> Result<(), Error> {
+        let (path, interfaces) = signal
+            .body
+            .parser()
+            .get2::<rustbus::wire::marshal::traits::ObjectPath, Vec<String>>()
+            .map_err(|_| Error::UnexpectedDbusReply)?;
+        let path = match self.our_device(path.as_ref()) {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if interfaces.iter().any(|i| i == "org.bluez.Device1") {
+            let adress = path.device_adress().unwrap();
+            self.events.push_back(Event::DeviceRemoved(adress));
+        }
+        Ok(())
+    }
+
+    /// aquire notify again for every characteristic notify was called on. Use this
+    /// after bluetoothd restarted (see `Event::BluezRestarted`) and the devices
+    /// are connected again. The old notifications will no longer recieve data.
+    /// Returns the adress and uuid with the new notification or the error.
+    pub fn reacquire_notifications(&mut self) -> Vec<(Address, Uuid, Result<Notification, Error>)> {
+        let notifications = self.notifications.clone();
+        notifications
+            .into_iter()
+            .map(|(adress, uuid)| {
+                let fd = self.notify(adress, uuid);
+                (adress, uuid, fd)
+            })
+            .collect()
+    }
+
+    /// stop re-establishing notify for this characteristic in `reacquire_notifications`
+    pub fn forget_notification(&mut self, adress: Address, uuid: Uuid) {
+        self.notifications
+            .retain(|(a, u)| a != &adress || u != &uuid);
+        self.reacquired
+            .retain(|n| n.adress() != adress || n.uuid() != uuid);
+    }
+
+    /// the stream announced by `Event::NotificationReacquired`
+    #[allow(dead_code)]
+    pub fn take_notification(
+        &mut self,
+        adress: impl IntoAddress,
+        uuid: impl IntoUuid,
+    ) -> Option<Notification> {
+        let adress = adress.into_address().ok()?;
+        let uuid = uuid.into_uuid().ok()?;
+        let index = self
+            .reacquired
+            .iter()
+            .position(|n| n.adress() == adress && n.uuid() == uuid)?;
+        Some(self.reacquired.remove(index))
+    }
+
+    /// the notify streams of this device ended, remember to acquire them
+    /// again once it is back
+    fn lose_notifications(&mut self, adress: Address) {
+        if !self.auto_reacquire || self.lost_notifications.contains(&adress) {
+            return;
+        }
+        if self.notifications.iter().any(|(a, _)| a == &adress) {
+            self.lost_notifications.push(adress);
+        }
+    }
+
+    /// remember to acquire notify again for a device that lost its streams.
+    /// Only after a disconnect, a device resolving its services for the
+    /// first time still has the streams we just acquired. Not done here as
+    /// signals are handled where we can not block, see `reacquire_one`.
+    fn queue_reacquire(&mut self, adress: Address) {
+        let index = match self.lost_notifications.iter().position(|a| a == &adress) {
+            Some(index) => index,
+            None => return,
+        };
+        self.lost_notifications.remove(index);
+        let lost = self.notifications.iter().filter(|(a, _)| a == &adress);
+        self.pending_reacquires.extend(lost);
+    }
+
+    /// acquire notify again for the oldest queued characteristic, taking at
+    /// most the timeout
+    fn reacquire_one(&mut self, timeout: Timeout) {
+        let (adress, uuid) = self.pending_reacquires.remove(0);
+        let options = match timeout {
+            Timeout::Duration(timeout) => CallOptions::new().with_timeout(timeout),
+            _ => CallOptions::new(),
+        };
+        match self.notify_with(adress, uuid, &options) {
+            Ok(notification) => {
+                // an older stream nobody took has ended by now
+                self.reacquired
+                    .retain(|n| n.adress() != adress || n.uuid() != uuid);
+                self.reacquired.push(notification);
+                self.events
+                    .push_back(Event::NotificationReacquired(adress, uuid));
+            }
+            Err(e) => self
+                .events
+                .push_back(Event::NotificationReacquireFailed(adress, uuid, e)),
+        }
+    }
+}
+
+fn timeout_left(start: Instant, timeout: Timeout) -> Result<Timeout, Error> {
+    match timeout {
+        Timeout::Duration(d) => match d.checked_sub(start.elapsed()) {
+            Some(left) if !left.is_zero() => Ok(Timeout::Duration(left)),
+            _ => Err(rustbus::client_conn::Error::TimedOut.into()),
+        },
+        other => Ok(other),
+    }
+}
@@ -0,0 +1,831 @@
+//! Expose local gatt services, the peripheral role. Describe the services
+//! using `Application`, `Service`, `Characteristic` and `Descriptor` then
+//! register them with `Ble::register_application`. Calls from remote devices
+//! are answered while `Ble::wait_event` or `Ble::try_event` runs.
+//!
+//! ```no_run
+//! use bluebus::gatt_server::{Application, Characteristic, Flag, Service};
+//! use bluebus::{BleBuilder, Uuid};
+//! use rustbus::client_conn::Timeout;
+//!
+//! let mut ble = BleBuilder::default().build().unwrap();
+//! let battery_level = Characteristic::new(Uuid::from_u16(0x2a19), &[Flag::Read, Flag::Notify])
+//!     .on_read(|_request| Ok(vec![42]));
+//! let app = Application::new()
+//!     .with_service(Service::new(Uuid::from_u16(0x180f)).with_characteristic(battery_level));
+//! let app = ble.register_application(app).unwrap();
+//!
+//! loop {
+//!     let event = ble.wait_event(Timeout::Infinite).unwrap();
+//!     println!("{:?}", event);
+//! }
+//! ```
+
+use rustbus::message_builder::MarshalledMessage;
+use rustbus::params::{self, Param};
+use rustbus::MessageBuilder;
+
+use crate::dbus_helpers::*;
+use crate::error::{Context, Error};
+use crate::path::BluezPath;
+use crate::uuid::Uuid;
+use crate::{Address, Ble};
+
+/// all gatt applications are exported below this path
+pub(crate) const ROOT: &str = "/bluebus/app";
+
+const SERVICE_IFACE: &str = "org.bluez.GattService1";
+const CHAR_IFACE: &str = "org.bluez.GattCharacteristic1";
+const DESC_IFACE: &str = "org.bluez.GattDescriptor1";
+
+/// Flags of a characteristic or descriptor, these determine what remote
+/// devices may do with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Flag {
+    Broadcast,
+    Read,
+    WriteWithoutResponse,
+    Write,
+    Notify,
+    Indicate,
+    AuthenticatedSignedWrites,
+    ExtendedProperties,
+    ReliableWrite,
+    WritableAuxiliaries,
+    EncryptRead,
+    EncryptWrite,
+    EncryptAuthenticatedRead,
+    EncryptAuthenticatedWrite,
+    SecureRead,
+    SecureWrite,
+    Authorize,
+}
+
+impl Flag {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Flag::Broadcast => "broadcast",
+            Flag::Read => "read",
+            Flag::WriteWithoutResponse => "write-without-response",
+            Flag::Write => "write",
+            Flag::Notify => "notify",
+            Flag::Indicate => "indicate",
+            Flag::AuthenticatedSignedWrites => "authenticated-signed-writes",
+            Flag::ExtendedProperties => "extended-properties",
+            Flag::ReliableWrite => "reliable-write",
+            Flag::WritableAuxiliaries => "writable-auxiliaries",
+            Flag::EncryptRead => "encrypt-read",
+            Flag::EncryptWrite => "encrypt-write",
+            Flag::EncryptAuthenticatedRead => "encrypt-authenticated-read",
+            Flag::EncryptAuthenticatedWrite => "encrypt-authenticated-write",
+            Flag::SecureRead => "secure-read",
+            Flag::SecureWrite => "secure-write",
+            Flag::Authorize => "authorize",
+        }
+    }
+
+    /// the flag for a string from the Flags property, None if unknown
+    pub(crate) fn from_bluez(flag: &str) -> Option<Flag> {
+        const ALL: [Flag; 17] = [
+            Flag::Broadcast,
+            Flag::Read,
+            Flag::WriteWithoutResponse,
+            Flag::Write,
+            Flag::Notify,
+            Flag::Indicate,
+            Flag::AuthenticatedSignedWrites,
+            Flag::ExtendedProperties,
+            Flag::ReliableWrite,
+            Flag::WritableAuxiliaries,
+            Flag::EncryptRead,
+            Flag::EncryptWrite,
+            Flag::EncryptAuthenticatedRead,
+            Flag::EncryptAuthenticatedWrite,
+            Flag::SecureRead,
+            Flag::SecureWrite,
+            Flag::Authorize,
+        ];
+        ALL.iter().copied().find(|f| f.as_str() == flag)
+    }
+}
+
+/// Errors a read or write callback can return to the remote device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GattError {
+    Failed,
+    InProgress,
+    NotPermitted,
+    InvalidValueLength,
+    InvalidOffset,
+    NotAuthorized,
+    NotSupported,
+}
+
+impl GattError {
+    fn name(&self) -> &'static str {
+        match self {
+            GattError::Failed => "org.bluez.Error.Failed",
+            GattError::InProgress => "org.bluez.Error.InProgress",
+            GattError::NotPermitted => "org.bluez.Error.NotPermitted",
+            GattError::InvalidValueLength => "org.bluez.Error.InvalidValueLength",
+            GattError::InvalidOffset => "org.bluez.Error.InvalidOffset",
+            GattError::NotAuthorized => "org.bluez.Error.NotAuthorized",
+            GattError::NotSupported => "org.bluez.Error.NotSupported",
+        }
+    }
+}
+
+/// Information bluez passes along with a read or write
+#[derive(Debug, Clone, Default)]
+pub struct RequestInfo {
+    /// the remote device doing the request
+    pub device: Option<Address>,
+    /// offset into the value, reads are sliced at this offset for you and
+    /// writes without a callback are placed at it
+    pub offset: u16,
+    pub mtu: Option<u16>,
+}
+
+impl RequestInfo {
+    pub(crate) fn from_options(options: Option<Param>) -> Self {
+        let mut options = match options.and_then(unwrap_container).and_then(unwrap_dict) {
+            Some(options) => options,
+            None => return RequestInfo::default(),
+        };
+        RequestInfo {
+            device: take_variant_objectpath(&mut options, "device")
+                .and_then(|p| p.parse::<BluezPath>().ok())
+                .and_then(|p| p.device_adress()),
+            offset: take_variant_u16(&mut options, "offset").unwrap_or(0),
+            mtu: take_variant_u16(&mut options, "mtu"),
+        }
+    }
+}
+
+pub type ReadCallback = Box<dyn FnMut(&RequestInfo) -> Result<Vec<u8>, GattError> + Send>;
+pub type WriteCallback = Box<dyn FnMut(&[u8], &RequestInfo) -> Result<(), GattError> + Send>;
+pub type NotifyCallback = Box<dyn FnMut(bool) + Send>;
+
+/// A descriptor, reads are answered with the value unless a read callback is set
+pub struct Descriptor {
+    uuid: Uuid,
+    flags: Vec<Flag>,
+    value: Vec<u8>,
+    on_read: Option<ReadCallback>,
+    on_write: Option<WriteCallback>,
+}
+
+impl Descriptor {
+    pub fn new(uuid: Uuid, flags: &[Flag]) -> Self {
+        Descriptor {
+            uuid,
+            flags: flags.to_vec(),
+            value: Vec::new(),
+            on_read: None,
+            on_write: None,
+        }
+    }
+
+    pub fn with_value(mut self, value: impl Into<Vec<u8>>) -> Self {
+        self.value = value.into();
+        self
+    }
+
+    pub fn on_read(
+        mut self,
+        callback: impl FnMut(&RequestInfo) -> Result<Vec<u8>, GattError> + Send + 'static,
+    ) -> Self {
+        self.on_read = Some(Box::new(callback));
+        self
+    }
+
+    /// without a write callback writes update the value
+    pub fn on_write(
+        mut self,
+        callback: impl FnMut(&[u8], &RequestInfo) -> Result<(), GattError> + Send + 'static,
+    ) -> Self {
+        self.on_write = Some(Box::new(callback));
+        self
+    }
+}
+
+/// A characteristic, reads are answered with the value unless a read callback
+/// is set. Use `Ble::notify_value` to change the value and notify subscribers.
+pub struct Characteristic {
+    uuid: Uuid,
+    flags: Vec<Flag>,
+    value: Vec<u8>,
+    notifying: bool,
+    on_read: Option<ReadCallback>,
+    on_write: Option<WriteCallback>,
+    on_notify: Option<NotifyCallback>,
+    descriptors: Vec<Descriptor>,
+}
+
+impl Characteristic {
+    pub fn new(uuid: Uuid, flags: &[Flag]) -> Self {
+        Characteristic {
+            uuid,
+            flags: flags.to_vec(),
+            value: Vec::new(),
+            notifying: false,
+            on_read: None,
+            on_write: None,
+            on_notify: None,
+            descriptors: Vec::new(),
+        }
+    }
+
+    pub fn with_value(mut self, value: impl Into<Vec<u8>>) -> Self {
+        self.value = value.into();
+        self
+    }
+
+    pub fn on_read(
+        mut self,
+        callback: impl FnMut(&RequestInfo) -> Result<Vec<u8>, GattError> + Send + 'static,
+    ) -> Self {
+        self.on_read = Some(Box::new(callback));
+        self
+    }
+
+    /// without a write callback writes update the value
+    pub fn on_write(
+        mut self,
+        callback: impl FnMut(&[u8], &RequestInfo) -> Result<(), GattError> + Send + 'static,
+    ) -> Self {
+        self.on_write = Some(Box::new(callback));
+        self
+    }
+
+    /// called with true when a remote device subscribes and false when
+    /// the last one unsubscribes
+    pub fn on_notify(mut self, callback: impl FnMut(bool) + Send + 'static) -> Self {
+        self.on_notify = Some(Box::new(callback));
+        self
+    }
+
+    pub fn with_descriptor(mut self, descriptor: Descriptor) -> Self {
+        self.descriptors.push(descriptor);
+        self
+    }
+}
+
+pub struct Service {
+    uuid: Uuid,
+    primary: bool,
+    characteristics: Vec<Characteristic>,
+}
+
+impl Service {
+    /// a primary service
+    pub fn new(uuid: Uuid) -> Self {
+        Service {
+            uuid,
+            primary: true,
+            characteristics: Vec::new(),
+        }
+    }
+
+    pub fn primary(mut self, primary: bool) -> Self {
+        self.primary = primary;
+        self
+    }
+
+    pub fn with_characteristic(mut self, characteristic: Characteristic) -> Self {
+        self.characteristics.push(characteristic);
+        self
+    }
+}
+
+#[derive(Default)]
+pub struct Application {
+    services: Vec<Service>,
+}
+
+impl Application {
+    pub fn new() -> Self {
+        Application::default()
+    }
+
+    pub fn with_service(mut self, service: Service) -> Self {
+        self.services.push(service);
+        self
+    }
+}
+
+/// Identifies a registered application
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ApplicationId(u32);
+
+impl ApplicationId {
+    /// the object path the application is exported at
+    pub fn path(&self) -> String {
+        format!("{}{}", ROOT, self.0)
+    }
+}
+
+pub(crate) struct RegisteredApp {
+    id: ApplicationId,
+    app: Application,
+}
+
+/// which object of an application a path points to
+enum Object {
+    Root,
+    Service(usize),
+    Characteristic(usize, usize),
+    Descriptor(usize, usize, usize),
+}
+
+fn parse_object(path: &str) -> Option<(ApplicationId, Object)> {
+    let rest = path.strip_prefix(ROOT)?;
+    let mut parts = rest.split('/');
+    let id = ApplicationId(parts.next()?.parse().ok()?);
+    let mut index = |prefix: &str| -> Option<Option<usize>> {
+        match parts.next() {
+            None => Some(None),
+            Some(part) => Some(Some(part.strip_prefix(prefix)?.parse().ok()?)),
+        }
+    };
+    let object = match (index("service")?, index("char")?, index("desc")?) {
+        (None, None, None) => Object::Root,
+        (Some(s), None, None) => Object::Service(s),
+        (Some(s), Some(c), None) => Object::Characteristic(s, c),
+        (Some(s), Some(c), Some(d)) => Object::Descriptor(s, c, d),
+        _ => return None,
+    };
+    Some((id, object))
+}
+
+fn unknown_object(call: &rustbus::message_builder::DynamicHeader) -> MarshalledMessage {
+    error_response(
+        call,
+        "org.freedesktop.DBus.Error.UnknownObject",
+        "no such object",
+    )
+}
+
+fn service_path(id: ApplicationId, s: usize) -> String {
+    format!("{}/service{}", id.path(), s)
+}
+
+fn char_path(id: ApplicationId, s: usize, c: usize) -> String {
+    format!("{}/char{}", service_path(id, s), c)
+}
+
+fn desc_path(id: ApplicationId, s: usize, c: usize, d: usize) -> String {
+    format!("{}/desc{}", char_path(id, s, c), d)
+}
+
+pub(crate) fn flags_param(flags: &[Flag]) -> Param<'static, 'static> {
+    string_array_param(flags.iter().map(|f| f.as_str().to_owned()).collect())
+}
+
+impl RegisteredApp {
+    fn service_properties(&self, s: usize) -> Option<Param<'static, 'static>> {
+        let service = self.app.services.get(s)?;
+        Some(variant_dict_param(vec![
+            ("UUID", string_param(service.uuid.to_string())),
+            (
+                "Primary",
+                Param::Base(params::Base::Boolean(service.primary)),
+            ),
+        ]))
+    }
+
+    fn char_properties(&self, s: usize, c: usize) -> Option<Param<'static, 'static>> {
+        let characteristic = self.app.services.get(s)?.characteristics.get(c)?;
+        let mut properties = vec![
+            ("UUID", string_param(characteristic.uuid.to_string())),
+            ("Service", objectpath_param(service_path(self.id, s))),
+            ("Flags", flags_param(&characteristic.flags)),
+            ("Value", byte_array_param(&characteristic.value)),
+        ];
+        if characteristic.flags.contains(&Flag::Notify)
+            || characteristic.flags.contains(&Flag::Indicate)
+        {
+            let notifying = Param::Base(params::Base::Boolean(characteristic.notifying));
+            properties.push(("Notifying", notifying));
+        }
+        Some(variant_dict_param(properties))
+    }
+
+    fn desc_properties(&self, s: usize, c: usize, d: usize) -> Option<Param<'static, 'static>> {
+        let characteristic = self.app.services.get(s)?.characteristics.get(c)?;
+        let descriptor = characteristic.descriptors.get(d)?;
+        Some(variant_dict_param(vec![
+            ("UUID", string_param(descriptor.uuid.to_string())),
+            ("Characteristic", objectpath_param(char_path(self.id, s, c))),
+            ("Flags", flags_param(&descriptor.flags)),
+        ]))
+    }
+
+    /// interface name and properties of an object
+    fn interface(&self, object: &Object) -> Option<(&'static str, Param<'static, 'static>)> {
+        match *object {
+            Object::Root => None,
+            Object::Service(s) => Some((SERVICE_IFACE, self.service_properties(s)?)),
+            Object::Characteristic(s, c) => Some((CHAR_IFACE, self.char_properties(s, c)?)),
+            Object::Descriptor(s, c, d) => Some((DESC_IFACE, self.desc_properties(s, c, d)?)),
+        }
+    }
+
+    fn managed_objects(&self) -> Param<'static, 'static> {
+        let mut objects = Vec::new();
+        for (s, service) in self.app.services.iter().enumerate() {
+            let props = self.service_properties(s).unwrap();
+            objects.push((service_path(self.id, s), vec![(SERVICE_IFACE, props)]));
+            for (c, characteristic) in service.characteristics.iter().enumerate() {
+                let props = self.char_properties(s, c).unwrap();
+                objects.push((char_path(self.id, s, c), vec![(CHAR_IFACE, props)]));
+                for d in 0..characteristic.descriptors.len() {
+                    let props = self.desc_properties(s, c, d).unwrap();
+                    objects.push((desc_path(self.id, s, c, d), vec![(DESC_IFACE, props)]));
+                }
+            }
+        }
+        let objects = objects
+            .into_iter()
+            .map(|(path, interfaces)| (path, interfaces_param(interfaces)))
+            .collect();
+        managed_objects_param(objects)
+    }
+
+    fn handle_call(
+        &mut self,
+        object: Object,
+        call: MarshalledMessage,
+    ) -> Result<MarshalledMessage, Error> {
+        let member = call.dynheader.member.clone().unwrap_or_default();
+        let header = call.dynheader.clone();
+        let mut reply = header.make_response();
+        let mut params = call.unmarshall_all()?.params.into_iter();
+
+        match (member.as_str(), &object) {
+            ("GetManagedObjects", Object::Root) => {
+                reply.body.push_old_param(&self.managed_objects())?;
+            }
+            ("GetAll", Object::Root) => {
+                reply.body.push_old_param(&variant_dict_param(Vec::new()))?;
+            }
+            ("GetAll", _) => {
+                let properties = match self.interface(&object) {
+                    Some((_, properties)) => properties,
+                    None => return Ok(unknown_object(&header)),
+                };
+                reply.body.push_old_param(&properties)?;
+            }
+            ("ReadValue", Object::Characteristic(s, c)) => {
+                let info = RequestInfo::from_options(params.next());
+                let characteristic = match self.characteristic_mut(*s, *c) {
+                    Some(characteristic) => characteristic,
+                    None => return Ok(unknown_object(&header)),
+                };
+                let value = match &mut characteristic.on_read {
+                    Some(on_read) => on_read(&info),
+                    None => Ok(characteristic.value.clone()),
+                };
+                return value_reply(&header, value, info.offset);
+            }
+            ("ReadValue", Object::Descriptor(s, c, d)) => {
+                let info = RequestInfo::from_options(params.next());
+                let descriptor = match self.descriptor_mut(*s, *c, *d) {
+                    Some(descriptor) => descriptor,
+                    None => return Ok(unknown_object(&header)),
+                };
+                let value = match &mut descriptor.on_read {
+                    Some(on_read) => on_read(&info),
+                    None => Ok(descriptor.value.clone()),
+                };
+                return value_reply(&header, value, info.offset);
+            }
+            ("WriteValue", Object::Characteristic(s, c)) => {
+                let value = bytes_from_param(params.next())?;
+                let info = RequestInfo::from_options(params.next());
+                let characteristic = match self.characteristic_mut(*s, *c) {
+                    Some(characteristic) => characteristic,
+                    None => return Ok(unknown_object(&header)),
+                };
+                let result = match &mut characteristic.on_write {
+                    Some(on_write) => on_write(&value, &info),
+                    None => write_at(&mut characteristic.value, &value, info.offset),
+                };
+                if let Err(e) = result {
+                    return Ok(error_response(&header, e.name(), "write rejected"));
+                }
+            }
+            ("WriteValue", Object::Descriptor(s, c, d)) => {
+                let value = bytes_from_param(params.next())?;
+                let info = RequestInfo::from_options(params.next());
+                let descriptor = match self.descriptor_mut(*s, *c, *d) {
+                    Some(descriptor) => descriptor,
+                    None => return Ok(unknown_object(&header)),
+                };
+                let result = match &mut descriptor.on_write {
+                    Some(on_write) => on_write(&value, &info),
+                    None => write_at(&mut descriptor.value, &value, info.offset),
+                };
+                if let Err(e) = result {
+                    return Ok(error_response(&header, e.name(), "write rejected"));
+                }
+            }
+            ("StartNotify", Object::Characteristic(s, c))
+            | ("StopNotify", Object::Characteristic(s, c)) => {
+                let characteristic = match self.characteristic_mut(*s, *c) {
+                    Some(characteristic) => characteristic,
+                    None => return Ok(unknown_object(&header)),
+                };
+                let notifying = member == "StartNotify";
+                if characteristic.notifying != notifying {
+                    characteristic.notifying = notifying;
+                    if let Some(on_notify) = &mut characteristic.on_notify {
+                        on_notify(notifying);
+                    }
+                }
+            }
+            _ => {
+                return Ok(error_response(
+                    &header,
+                    "org.freedesktop.DBus.Error.UnknownMethod",
+                    "unknown method",
+                ))
+            }
+        }
+        Ok(reply)
+    }
+
+    /// the indices come from the path of a call, they may not exist
+    fn characteristic_mut(&mut self, s: usize, c: usize) -> Option<&mut Characteristic> {
+        self.app.services.get_mut(s)?.characteristics.get_mut(c)
+    }
+
+    fn descriptor_mut(&mut self, s: usize, c: usize, d: usize) -> Option<&mut Descriptor> {
+        self.characteristic_mut(s, c)?.descriptors.get_mut(d)
+    }
+
+    fn find_char(&mut self, uuid: Uuid) -> Option<(usize, usize)> {
+        for (s, service) in self.app.services.iter().enumerate() {
+            for (c, characteristic) in service.characteristics.iter().enumerate() {
+                if characteristic.uuid == uuid {
+                    return Some((s, c));
+                }
+            }
+        }
+        None
+    }
+}
+
+fn value_reply(
+    call: &rustbus::message_builder::DynamicHeader,
+    value: Result<Vec<u8>, GattError>,
+    offset: u16,
+) -> Result<MarshalledMessage, Error> {
+    let value = match value {
+        Ok(value) => value,
+        Err(e) => return Ok(error_response(call, e.name(), "read rejected")),
+    };
+    let offset = offset as usize;
+    if offset > value.len() {
+        let e = GattError::InvalidOffset;
+        return Ok(error_response(call, e.name(), "offset past end of value"));
+    }
+    let mut reply = call.make_response();
+    reply.body.push_param(&value[offset..])?;
+    Ok(reply)
+}
+
+/// overwrite the stored value from offset on, growing it when the write
+/// runs past its end
+fn write_at(stored: &mut Vec<u8>, value: &[u8], offset: u16) -> Result<(), GattError> {
+    let offset = offset as usize;
+    if offset > stored.len() {
+        return Err(GattError::InvalidOffset);
+    }
+    let end = stored.len().min(offset + value.len());
+    stored.splice(offset..end, value.iter().copied());
+    Ok(())
+}
+
+pub(crate) fn bytes_from_param(param: Option<Param>) -> Result<Vec<u8>, Error> {
+    let param = param.ok_or(Error::UnexpectedDbusReply)?;
+    let container = unwrap_container(param).ok_or(Error::UnexpectedDbusReply)?;
+    let array = unwrap_array(container).ok_or(Error::UnexpectedDbusReply)?;
+    array
+        .values
+        .into_iter()
+        .map(|param| param.into_byte())
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| Error::UnexpectedDbusReply)
+}
+
+impl Ble {
+    /// export the application and register it with bluez
+    pub fn register_application(&mut self, app: Application) -> Result<ApplicationId, Error> {
+        let id = ApplicationId(self.next_object_id);
+        self.next_object_id += 1;
+        self.gatt_apps.push(RegisteredApp { id, app });
+
+        if let Err(e) = self.send_register_application(id) {
+            self.gatt_apps.retain(|registerd| registerd.id != id);
+            return Err(e);
+        }
+        Ok(id)
+    }
+
+    fn send_register_application(&mut self, id: ApplicationId) -> Result<(), Error> {
+        let mut register = MessageBuilder::new()
+            .call("RegisterApplication".into())
+            .at("org.bluez".into())
+            .on(self.adapter_path().into())
+            .with_interface("org.bluez.GattManager1".into())
+            .build();
+        register.body.push_old_param(&objectpath_param(id.path()))?;
+        register
+            .body
+            .push_old_param(&variant_dict_param(Vec::new()))?;
+
+        let response_serial = self.connection.send_message(&mut register, self.timeout)?;
+        let msg = self.wait_response_serving_calls(response_serial, self.timeout)?;
+
+        match msg.typ {
+            rustbus::MessageType::Reply => Ok(()),
+            rustbus::MessageType::Error => Err(Error::from((msg, Context::RegisterApplication))),
+            _ => Err(Error::UnexpectedDbusReply),
+        }
+    }
+
+    /// register all applications again, used after bluez restarted. Returns
+    /// the errors of the ones that failed.
+    pub(crate) fn reregister_applications(&mut self) -> Vec<Error> {
+        let ids: Vec<_> = self.gatt_apps.iter().map(|r| r.id).collect();
+        ids.into_iter()
+            .filter_map(|id| self.send_register_application(id).err())
+            .collect()
+    }
+
+    /// unregister the application from bluez and stop exporting it
+    pub fn unregister_application(&mut self, id: ApplicationId) -> Result<(), Error> {
+        let mut unregister = MessageBuilder::new()
+            .call("UnregisterApplication".into())
+            .at("org.bluez".into())
+            .on(self.adapter_path().into())
+            .with_interface("org.bluez.GattManager1".into())
+            .build();
+        unregister
+            .body
+            .push_old_param(&objectpath_param(id.path()))?;
+
+        let response_serial = self
+            .connection
+            .send_message(&mut unregister, self.timeout)?;
+        let msg = self.wait_response_serving_calls(response_serial, self.timeout)?;
+        self.gatt_apps.retain(|registerd| registerd.id != id);
+
+        match msg.typ {
+            rustbus::MessageType::Reply => Ok(()),
+            rustbus::MessageType::Error => Err(Error::from((msg, Context::UnregisterApplication))),
+            _ => Err(Error::UnexpectedDbusReply),
+        }
+    }
+
+    /// change the value of a characteristic in a registered application,
+    /// subscribed devices are notified of the new value
+    pub fn notify_value(
+        &mut self,
+        id: ApplicationId,
+        uuid: Uuid,
+        value: impl Into<Vec<u8>>,
+    ) -> Result<(), Error> {
+        let registered = self
+            .gatt_apps
+            .iter_mut()
+            .find(|registerd| registerd.id == id)
+            .ok_or(Error::CharacteristicNotFound(Context::NotifyValue(uuid)))?;
+        let (s, c) = registered
+            .find_char(uuid)
+            .ok_or(Error::CharacteristicNotFound(Context::NotifyValue(uuid)))?;
+
+        let characteristic = &mut registered.app.services[s].characteristics[c];
+        characteristic.value = value.into();
+        if !characteristic.notifying {
+            return Ok(());
+        }
+
+        let changed = variant_dict_param(vec![("Value", byte_array_param(&characteristic.value))]);
+        let mut signal = properties_changed(&char_path(id, s, c), CHAR_IFACE, changed)?;
+        self.connection.send_message(&mut signal, self.timeout)?;
+        Ok(())
+    }
+
+    pub(crate) fn handle_gatt_call(
+        &mut self,
+        call: MarshalledMessage,
+    ) -> Result<MarshalledMessage, Error> {
+        let path = call.dynheader.object.clone().unwrap_or_default();
+        let registered = parse_object(&path).and_then(|(id, object)| {
+            let registered = self.gatt_apps.iter_mut().find(|r| r.id == id)?;
+            Some((registered, object))
+        });
+        match registered {
+            Some((registered, object)) => registered.handle_call(object, call),
+            None => Ok(unknown_object(&call.dynheader)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registered() -> RegisteredApp {
+        let characteristic = Characteristic::new(Uuid::from_u16(0x2a19), &[Flag::Read])
+            .with_value(vec![42])
+            .with_descriptor(Descriptor::new(Uuid::from_u16(0x2901), &[Flag::Read]));
+        let app = Application::new()
+            .with_service(Service::new(Uuid::from_u16(0x180f)).with_characteristic(characteristic));
+        RegisteredApp {
+            id: ApplicationId(0),
+            app,
+        }
+    }
+
+    fn call(path: &str, interface: &str, member: &str) -> MarshalledMessage {
+        let mut call = MessageBuilder::new()
+            .call(member.into())
+            .at("org.bluez".into())
+            .on(path.into())
+            .with_interface(interface.into())
+            .build();
+        call.dynheader.serial = Some(1);
+        if member == "ReadValue" {
+            call.body
+                .push_old_param(&variant_dict_param(Vec::new()))
+                .unwrap();
+        }
+        call
+    }
+
+    #[test]
+    fn missing_objects_are_unknown() {
+        let mut registered = registered();
+        for (object, interface, member) in &[
+            ("/service9/char9", CHAR_IFACE, "ReadValue"),
+            ("/service0/char9", CHAR_IFACE, "StartNotify"),
+            ("/service0/char0/desc9", DESC_IFACE, "ReadValue"),
+            ("/service9", "org.freedesktop.DBus.Properties", "GetAll"),
+        ] {
+            let path = format!("/bluebus/app0{}", object);
+            let (_, object) = parse_object(&path).unwrap();
+            let reply = registered
+                .handle_call(object, call(&path, interface, member))
+                .unwrap();
+            assert_eq!(
+                reply.dynheader.error_name.as_deref(),
+                Some("org.freedesktop.DBus.Error.UnknownObject"),
+                "{}",
+                path
+            );
+        }
+
+        let path = "/bluebus/app0/service0/char0";
+        let (_, object) = parse_object(path).unwrap();
+        let reply = registered
+            .handle_call(object, call(path, CHAR_IFACE, "ReadValue"))
+            .unwrap();
+        assert_eq!(reply.dynheader.error_name, None);
+        assert_eq!(reply.body.parser().get::<Vec<u8>>().unwrap(), vec![42]);
+    }
+
+    fn write(registered: &mut RegisteredApp, bytes: &[u8], offset: u16) -> MarshalledMessage {
+        let path = "/bluebus/app0/service0/char0";
+        let (_, object) = parse_object(path).unwrap();
+        let mut call = call(path, CHAR_IFACE, "WriteValue");
+        call.body.push_old_param(&byte_array_param(bytes)).unwrap();
+        let offset = Param::Base(params::Base::Uint16(offset));
+        call.body
+            .push_old_param(&variant_dict_param(vec![("offset", offset)]))
+            .unwrap();
+        registered.handle_call(object, call).unwrap()
+    }
+
+    #[test]
+    fn writes_are_placed_at_their_offset() {
+        let mut registered = registered();
+        let reply = write(&mut registered, &[1, 2, 3], 0);
+        assert_eq!(reply.dynheader.error_name, None);
+        write(&mut registered, &[9], 1);
+        write(&mut registered, &[4, 5], 3);
+        let value = &registered.app.services[0].characteristics[0].value;
+        assert_eq!(value, &vec![1, 9, 3, 4, 5]);
+
+        let reply = write(&mut registered, &[6], 6);
+        assert_eq!(
+            reply.dynheader.error_name.as_deref(),
+            Some("org.bluez.Error.InvalidOffset")
+        );
+        let value = &registered.app.services[0].characteristics[0].value;
+        assert_eq!(value, &vec![1, 9, 3, 4, 5]);
+    }
+}
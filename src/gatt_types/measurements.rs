@@ -0,0 +1,317 @@
+use super::{DateTime, Reader, StandardCharacteristic};
+use crate::error::Error;
+use crate::uuid::Uuid;
+
+/// Heart rate, usually recieved as notification
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeartRateMeasurement {
+    /// beats per minute
+    pub bpm: u16,
+    /// None if the sensor can not detect skin contact
+    pub sensor_contact: Option<bool>,
+    /// kilo joule since the last reset
+    pub energy_expended: Option<u16>,
+    /// time between beats in 1/1024 second, oldest first
+    pub rr_intervals: Vec<u16>,
+}
+
+impl StandardCharacteristic for HeartRateMeasurement {
+    const UUID: Uuid = Uuid::from_u16(0x2a37);
+
+    fn decode(data: &[u8]) -> Result<Self, Error> {
+        let mut reader = Reader::new::<Self>(data);
+        let flags = reader.u8()?;
+        let bpm = if flags & 0x01 == 0 {
+            reader.u8()? as u16
+        } else {
+            reader.u16()?
+        };
+        let sensor_contact = match flags & 0x06 {
+            0x06 => Some(true),
+            0x04 => Some(false),
+            _ => None,
+        };
+        let energy_expended = if flags & 0x08 != 0 {
+            Some(reader.u16()?)
+        } else {
+            None
+        };
+        let mut rr_intervals = Vec::new();
+        if flags & 0x10 != 0 {
+            while !reader.is_empty() {
+                rr_intervals.push(reader.u16()?);
+            }
+        }
+        Ok(HeartRateMeasurement {
+            bpm,
+            sensor_contact,
+            energy_expended,
+            rr_intervals,
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut flags = 0;
+        let mut data = vec![0];
+        if self.bpm > u8::MAX as u16 {
+            flags |= 0x01;
+            data.extend_from_slice(&self.bpm.to_le_bytes());
+        } else {
+            data.push(self.bpm as u8);
+        }
+        match self.sensor_contact {
+            Some(true) => flags |= 0x06,
+            Some(false) => flags |= 0x04,
+            None => (),
+        }
+        if let Some(energy) = self.energy_expended {
+            flags |= 0x08;
+            data.extend_from_slice(&energy.to_le_bytes());
+        }
+        if !self.rr_intervals.is_empty() {
+            flags |= 0x10;
+            for interval in &self.rr_intervals {
+                data.extend_from_slice(&interval.to_le_bytes());
+            }
+        }
+        data[0] = flags;
+        data
+    }
+}
+
+/// The 32 bit IEEE-11073 FLOAT: `mantissa * 10^exponent`. Kept as is so
+/// encoding gives back the exact bytes, use `as_f64` to calculate with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Float32 {
+    /// 24 bit signed
+    pub mantissa: i32,
+    pub exponent: i8,
+}
+
+impl Float32 {
+    pub const NAN: Float32 = Float32::special(0x7f_ffff);
+    /// not at this resolution
+    pub const NRES: Float32 = Float32::special(-0x80_0000);
+    pub const INFINITY: Float32 = Float32::special(0x7f_fffe);
+    pub const NEG_INFINITY: Float32 = Float32::special(-0x7f_fffe);
+
+    const fn special(mantissa: i32) -> Self {
+        Float32 {
+            mantissa,
+            exponent: 0,
+        }
+    }
+
+    /// the special values NaN and NRes become NaN
+    pub fn as_f64(&self) -> f64 {
+        match (self.exponent, self.mantissa) {
+            (0, 0x7f_fffe) => f64::INFINITY,
+            (0, -0x7f_fffe) => f64::NEG_INFINITY,
+            (0, 0x7f_ffff) | (0, -0x80_0000) | (0, -0x7f_ffff) => f64::NAN,
+            (exponent, mantissa) => mantissa as f64 * 10f64.powi(exponent as i32),
+        }
+    }
+
+    fn from_bits(bits: u32) -> Self {
+        // shift up then back down to sign extend the 24 bit mantissa
+        let mantissa = ((bits << 8) as i32) >> 8;
+        Float32 {
+            mantissa,
+            exponent: (bits >> 24) as i8,
+        }
+    }
+
+    fn to_bits(self) -> u32 {
+        (self.mantissa as u32 & 0x00ff_ffff) | ((self.exponent as u8 as u32) << 24)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemperatureUnit {
+    Celsius,
+    Fahrenheit,
+}
+
+/// Health thermometer measurement, usually recieved as indication
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemperatureMeasurement {
+    pub value: Float32,
+    pub unit: TemperatureUnit,
+    pub timestamp: Option<DateTime>,
+    /// where on the body it was measured, see the Temperature Type characteristic
+    pub temperature_type: Option<u8>,
+}
+
+impl StandardCharacteristic for TemperatureMeasurement {
+    const UUID: Uuid = Uuid::from_u16(0x2a1c);
+
+    fn decode(data: &[u8]) -> Result<Self, Error> {
+        let mut reader = Reader::new::<Self>(data);
+        let flags = reader.u8()?;
+        let value = Float32::from_bits(reader.u32()?);
+        let unit = if flags & 0x01 == 0 {
+            TemperatureUnit::Celsius
+        } else {
+            TemperatureUnit::Fahrenheit
+        };
+        let timestamp = if flags & 0x02 != 0 {
+            Some(DateTime::read(&mut reader)?)
+        } else {
+            None
+        };
+        let temperature_type = if flags & 0x04 != 0 {
+            Some(reader.u8()?)
+        } else {
+            None
+        };
+        Ok(TemperatureMeasurement {
+            value,
+            unit,
+            timestamp,
+            temperature_type,
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut flags = 0;
+        if self.unit == TemperatureUnit::Fahrenheit {
+            flags |= 0x01;
+        }
+        let mut data = vec![0];
+        data.extend_from_slice(&self.value.to_bits().to_le_bytes());
+        if let Some(timestamp) = &self.timestamp {
+            flags |= 0x02;
+            timestamp.write(&mut data);
+        }
+        if let Some(temperature_type) = self.temperature_type {
+            flags |= 0x04;
+            data.push(temperature_type);
+        }
+        data[0] = flags;
+        data
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WheelRevolutions {
+    pub cumulative: u32,
+    /// time of the last revolution in 1/1024 second, rolls over
+    pub last_event_time: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CrankRevolutions {
+    pub cumulative: u16,
+    /// time of the last revolution in 1/1024 second, rolls over
+    pub last_event_time: u16,
+}
+
+/// Cycling speed and cadence, speed and cadence follow from the difference
+/// between two measurements
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CscMeasurement {
+    pub wheel: Option<WheelRevolutions>,
+    pub crank: Option<CrankRevolutions>,
+}
+
+impl StandardCharacteristic for CscMeasurement {
+    const UUID: Uuid = Uuid::from_u16(0x2a5b);
+
+    fn decode(data: &[u8]) -> Result<Self, Error> {
+        let mut reader = Reader::new::<Self>(data);
+        let flags = reader.u8()?;
+        let wheel = if flags & 0x01 != 0 {
+            Some(WheelRevolutions {
+                cumulative: reader.u32()?,
+                last_event_time: reader.u16()?,
+            })
+        } else {
+            None
+        };
+        let crank = if flags & 0x02 != 0 {
+            Some(CrankRevolutions {
+                cumulative: reader.u16()?,
+                last_event_time: reader.u16()?,
+            })
+        } else {
+            None
+        };
+        Ok(CscMeasurement { wheel, crank })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut data = vec![0];
+        if let Some(wheel) = &self.wheel {
+            data[0] |= 0x01;
+            data.extend_from_slice(&wheel.cumulative.to_le_bytes());
+            data.extend_from_slice(&wheel.last_event_time.to_le_bytes());
+        }
+        if let Some(crank) = &self.crank {
+            data[0] |= 0x02;
+            data.extend_from_slice(&crank.cumulative.to_le_bytes());
+            data.extend_from_slice(&crank.last_event_time.to_le_bytes());
+        }
+        data
+    }
+}
+
+/// Running speed and cadence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RscMeasurement {
+    /// in 1/256 meter per second
+    pub speed: u16,
+    /// steps per minute
+    pub cadence: u8,
+    /// in centimeter
+    pub stride_length: Option<u16>,
+    /// in 1/10 meter
+    pub total_distance: Option<u32>,
+    /// false while walking
+    pub running: bool,
+}
+
+impl StandardCharacteristic for RscMeasurement {
+    const UUID: Uuid = Uuid::from_u16(0x2a53);
+
+    fn decode(data: &[u8]) -> Result<Self, Error> {
+        let mut reader = Reader::new::<Self>(data);
+        let flags = reader.u8()?;
+        let speed = reader.u16()?;
+        let cadence = reader.u8()?;
+        let stride_length = if flags & 0x01 != 0 {
+            Some(reader.u16()?)
+        } else {
+            None
+        };
+        let total_distance = if flags & 0x02 != 0 {
+            Some(reader.u32()?)
+        } else {
+            None
+        };
+        Ok(RscMeasurement {
+            speed,
+            cadence,
+            stride_length,
+            total_distance,
+            running: flags & 0x04 != 0,
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut data = vec![0];
+        data.extend_from_slice(&self.speed.to_le_bytes());
+        data.push(self.cadence);
+        if let Some(stride_length) = self.stride_length {
+            data[0] |= 0x01;
+            data.extend_from_slice(&stride_length.to_le_bytes());
+        }
+        if let Some(total_distance) = self.total_distance {
+            data[0] |= 0x02;
+            data.extend_from_slice(&total_distance.to_le_bytes());
+        }
+        if self.running {
+            data[0] |= 0x04;
+        }
+        data
+    }
+}
@@ -0,0 +1,110 @@
+//! Typed values for standard characteristics, enable with the `gatt-types`
+//! feature. Every type knows its assigned uuid so it can be read directly:
+//!
+//! ```no_run
+//! use bluebus::gatt_types::{BatteryLevel, HeartRateMeasurement, StandardCharacteristic};
+//! use bluebus::BleBuilder;
+//!
+//! let mut ble = BleBuilder::default().build().unwrap();
+//! let level: BatteryLevel = ble.read_typed("0A:0A:0A:0A:0A:0A").unwrap();
+//! println!("battery at {}%", level.0);
+//!
+//! // notifications arrive as raw bytes, decode them the same way
+//! let packet = [0x00, 72];
+//! let measurement = HeartRateMeasurement::decode(&packet).unwrap();
+//! assert_eq!(measurement.bpm, 72);
+//! ```
+
+mod measurements;
+mod values;
+
+pub use measurements::{
+    CrankRevolutions, CscMeasurement, Float32, HeartRateMeasurement, RscMeasurement,
+    TemperatureMeasurement, TemperatureUnit, WheelRevolutions,
+};
+pub use values::{
+    Appearance, BatteryLevel, CurrentTime, DateTime, FirmwareRevision, HardwareRevision,
+    ManufacturerName, ModelNumber, PnpId, SerialNumber, SoftwareRevision, VendorIdSource,
+};
+
+use std::convert::TryInto;
+
+use crate::address::IntoAddress;
+use crate::error::{Context, Error};
+use crate::uuid::Uuid;
+use crate::Ble;
+
+/// A characteristic with a value format defined by the bluetooth SIG
+pub trait StandardCharacteristic: Sized {
+    /// the assigned uuid of the characteristic
+    const UUID: Uuid;
+
+    fn decode(data: &[u8]) -> Result<Self, Error>;
+    fn encode(&self) -> Vec<u8>;
+}
+
+impl Ble {
+    /// read the characteristic with the assigned uuid of `T` and decode it
+    #[allow(dead_code)]
+    pub fn read_typed<T: StandardCharacteristic>(
+        &mut self,
+        adress: impl IntoAddress,
+    ) -> Result<T, Error> {
+        let data = self.read(adress, T::UUID)?;
+        T::decode(&data)
+    }
+
+    /// encode the value and write it to the characteristic with its assigned uuid
+    #[allow(dead_code)]
+    pub fn write_typed<T: StandardCharacteristic>(
+        &mut self,
+        adress: impl IntoAddress,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.write(adress, T::UUID, value.encode())
+    }
+}
+
+/// reads little endian fields from the front of a value
+struct Reader<'a> {
+    data: &'a [u8],
+    uuid: Uuid,
+}
+
+impl<'a> Reader<'a> {
+    fn new<T: StandardCharacteristic>(data: &'a [u8]) -> Self {
+        Reader {
+            data,
+            uuid: T::UUID,
+        }
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        if self.data.len() < N {
+            return Err(Error::InvalidLength(Context::Decode(self.uuid)));
+        }
+        let (bytes, rest) = self.data.split_at(N);
+        self.data = rest;
+        Ok(bytes.try_into().expect("split at N"))
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_le_bytes(self.bytes()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.bytes()?))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn invalid(&self) -> Error {
+        Error::InvalidValue(Context::Decode(self.uuid))
+    }
+}
@@ -0,0 +1,207 @@
+use super::{Reader, StandardCharacteristic};
+use crate::error::{Context, Error};
+use crate::uuid::Uuid;
+
+/// charge left in percent, 0 to 100
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatteryLevel(pub u8);
+
+impl StandardCharacteristic for BatteryLevel {
+    const UUID: Uuid = Uuid::from_u16(0x2a19);
+
+    fn decode(data: &[u8]) -> Result<Self, Error> {
+        let mut reader = Reader::new::<Self>(data);
+        match reader.u8()? {
+            level @ 0..=100 => Ok(BatteryLevel(level)),
+            _ => Err(reader.invalid()),
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        vec![self.0]
+    }
+}
+
+/// the device information service strings, these are utf8 without a
+/// terminating zero though some devices send one anyway
+macro_rules! device_information_string {
+    ($(#[$doc:meta])* $name:ident, $uuid:expr) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        pub struct $name(pub String);
+
+        impl StandardCharacteristic for $name {
+            const UUID: Uuid = Uuid::from_u16($uuid);
+
+            fn decode(data: &[u8]) -> Result<Self, Error> {
+                let end = data.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+                match std::str::from_utf8(&data[..end]) {
+                    Ok(s) => Ok($name(s.to_owned())),
+                    Err(_) => Err(Error::InvalidValue(Context::Decode(Self::UUID))),
+                }
+            }
+
+            fn encode(&self) -> Vec<u8> {
+                self.0.as_bytes().to_vec()
+            }
+        }
+    };
+}
+
+device_information_string!(ManufacturerName, 0x2a29);
+device_information_string!(ModelNumber, 0x2a24);
+device_information_string!(SerialNumber, 0x2a25);
+device_information_string!(FirmwareRevision, 0x2a26);
+device_information_string!(HardwareRevision, 0x2a27);
+device_information_string!(SoftwareRevision, 0x2a28);
+
+/// what the device looks like, the upper 10 bits are the category the
+/// lower 6 the subcategory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Appearance(pub u16);
+
+impl Appearance {
+    pub fn category(&self) -> u16 {
+        self.0 >> 6
+    }
+
+    pub fn subcategory(&self) -> u8 {
+        (self.0 & 0x3f) as u8
+    }
+}
+
+impl StandardCharacteristic for Appearance {
+    const UUID: Uuid = Uuid::from_u16(0x2a01);
+
+    fn decode(data: &[u8]) -> Result<Self, Error> {
+        Ok(Appearance(Reader::new::<Self>(data).u16()?))
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        self.0.to_le_bytes().to_vec()
+    }
+}
+
+/// who assigned the vendor id of a `PnpId`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VendorIdSource {
+    BluetoothSig,
+    Usb,
+}
+
+/// vendor, product and version, used to pick drivers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PnpId {
+    pub vendor_id_source: VendorIdSource,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub product_version: u16,
+}
+
+impl StandardCharacteristic for PnpId {
+    const UUID: Uuid = Uuid::from_u16(0x2a50);
+
+    fn decode(data: &[u8]) -> Result<Self, Error> {
+        let mut reader = Reader::new::<Self>(data);
+        let vendor_id_source = match reader.u8()? {
+            1 => VendorIdSource::BluetoothSig,
+            2 => VendorIdSource::Usb,
+            _ => return Err(reader.invalid()),
+        };
+        Ok(PnpId {
+            vendor_id_source,
+            vendor_id: reader.u16()?,
+            product_id: reader.u16()?,
+            product_version: reader.u16()?,
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let source = match self.vendor_id_source {
+            VendorIdSource::BluetoothSig => 1,
+            VendorIdSource::Usb => 2,
+        };
+        let mut data = vec![source];
+        data.extend_from_slice(&self.vendor_id.to_le_bytes());
+        data.extend_from_slice(&self.product_id.to_le_bytes());
+        data.extend_from_slice(&self.product_version.to_le_bytes());
+        data
+    }
+}
+
+/// a date and time without timezone, fields that are not known are 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1 to 12
+    pub month: u8,
+    /// 1 to 31
+    pub day: u8,
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+}
+
+impl DateTime {
+    pub(super) fn read(reader: &mut Reader) -> Result<Self, Error> {
+        let date_time = DateTime {
+            year: reader.u16()?,
+            month: reader.u8()?,
+            day: reader.u8()?,
+            hours: reader.u8()?,
+            minutes: reader.u8()?,
+            seconds: reader.u8()?,
+        };
+        if date_time.month > 12 || date_time.day > 31 || date_time.hours > 23 {
+            return Err(reader.invalid());
+        }
+        if date_time.minutes > 59 || date_time.seconds > 59 {
+            return Err(reader.invalid());
+        }
+        Ok(date_time)
+    }
+
+    pub(super) fn write(&self, data: &mut Vec<u8>) {
+        data.extend_from_slice(&self.year.to_le_bytes());
+        data.extend_from_slice(&[self.month, self.day, self.hours, self.minutes, self.seconds]);
+    }
+}
+
+/// the time on the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CurrentTime {
+    pub date_time: DateTime,
+    /// 1 is monday, 7 sunday, 0 if not known
+    pub day_of_week: u8,
+    /// in 1/256 of a second
+    pub fractions256: u8,
+    /// bit field: manual update, external reference update, timezone and
+    /// daylight saving time change
+    pub adjust_reason: u8,
+}
+
+impl StandardCharacteristic for CurrentTime {
+    const UUID: Uuid = Uuid::from_u16(0x2a2b);
+
+    fn decode(data: &[u8]) -> Result<Self, Error> {
+        let mut reader = Reader::new::<Self>(data);
+        let date_time = DateTime::read(&mut reader)?;
+        let day_of_week = reader.u8()?;
+        if day_of_week > 7 {
+            return Err(reader.invalid());
+        }
+        Ok(CurrentTime {
+            date_time,
+            day_of_week,
+            fractions256: reader.u8()?,
+            adjust_reason: reader.u8()?,
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(10);
+        self.date_time.write(&mut data);
+        data.extend_from_slice(&[self.day_of_week, self.fractions256, self.adjust_reason]);
+        data
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+
+use rustbus::params::{self, Param};
+
+use crate::address::{Address, AddressType};
+use crate::dbus_helpers::*;
+use crate::error::Error;
+use crate::gatt_server::Flag;
+use crate::path::BluezPath;
+use crate::uuid::Uuid;
+
+/// An adapter (controller) as bluez reports it
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AdapterInfo {
+    pub numb: u8,
+    pub adress: Address,
+    pub name: String,
+    pub alias: String,
+    pub powered: bool,
+    pub discovering: bool,
+}
+
+/// A remote device bluez knows about, because it was discoverd, paired or
+/// connected before
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceInfo {
+    pub adress: Address,
+    /// the name the device advertises, if it sent one
+    pub name: Option<String>,
+    /// the name bluez shows, falls back to the adress if there is no name
+    pub alias: String,
+    /// signal strength during discovery in dBm, None when not seen recently
+    pub rssi: Option<i16>,
+    pub tx_power: Option<i16>,
+    pub appearance: Option<u16>,
+    pub paired: bool,
+    pub trusted: bool,
+    pub connected: bool,
+    pub services_resolved: bool,
+    /// uuids of the services the device advertises or has
+    pub uuids: Vec<Uuid>,
+    /// the ATT MTU of the connection, None while not connected or if bluez
+    /// is older than 5.62. The connection interval, latency, supervision
+    /// timeout and PHY the link negotiated are not reported by bluez. The
+    /// parameters it connects with are in the bond, see
+    /// `AdapterStorage::bond`, the PHY of an l2cap channel is
+    /// `L2capChannel::phy`.
+    pub mtu: Option<u16>,
+}
+
+/// A service of a remote device with its characteristics
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GattService {
+    pub path: BluezPath,
+    pub uuid: Uuid,
+    pub primary: bool,
+    pub characteristics: Vec<GattCharacteristic>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GattCharacteristic {
+    pub path: BluezPath,
+    pub uuid: Uuid,
+    pub flags: Vec<Flag>,
+    /// the ATT MTU of the connection the characteristic is read through
+    pub mtu: Option<u16>,
+    pub descriptors: Vec<GattDescriptor>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GattDescriptor {
+    pub path: BluezPath,
+    pub uuid: Uuid,
+}
+
+/// The properties of one interface of a bluez object, values are taken out
+/// of their variants by the typed getters
+pub(crate) struct Properties(params::DictMap<'static, 'static>);
+
+/// interface name to its properties
+pub(crate) type Interfaces = HashMap<String, Properties>;
+
+impl Properties {
+    /// parse a a{sv}
+    pub(crate) fn from_param(param: Param<'static, 'static>) -> Result<Self, Error> {
+        let dict = unwrap_container(param)
+            .and_then(unwrap_dict)
+            .ok_or(Error::UnexpectedDbusReply)?;
+        Ok(Properties(dict))
+    }
+
+    fn take(&mut self, key: &str) -> Option<Param<'static, 'static>> {
+        let param = self.0.remove(&params::Base::String(key.to_owned()))?;
+        let variant = unwrap_container(param).and_then(unwrap_variant)?;
+        Some(variant.value)
+    }
+
+    fn take_base(&mut self, key: &str) -> Option<params::Base<'static>> {
+        unwrap_base(self.take(key)?)
+    }
+
+    pub(crate) fn take_string(&mut self, key: &str) -> Option<String> {
+        match self.take_base(key)? {
+            params::Base::String(s) | params::Base::ObjectPath(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn take_bool(&mut self, key: &str) -> Option<bool> {
+        self.take_base(key).and_then(unwrap_bool)
+    }
+
+    pub(crate) fn take_u8(&mut self, key: &str) -> Option<u8> {
+        match self.take_base(key)? {
+            params::Base::Byte(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn take_i16(&mut self, key: &str) -> Option<i16> {
+        match self.take_base(key)? {
+            params::Base::Int16(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn take_u16(&mut self, key: &str) -> Option<u16> {
+        self.take_base(key).and_then(unwrap_u16)
+    }
+
+    pub(crate) fn take_strings(&mut self, key: &str) -> Option<Vec<String>> {
+        let array = unwrap_container(self.take(key)?).and_then(unwrap_array)?;
+        array
+            .values
+            .into_iter()
+            .map(|param| unwrap_base(param).and_then(unwrap_string))
+            .collect()
+    }
+
+    pub(crate) fn take_bytes(&mut self, key: &str) -> Option<Vec<u8>> {
+        crate::gatt_server::bytes_from_param(Some(self.take(key)?)).ok()
+    }
+
+    pub(crate) fn take_uuid(&mut self, key: &str) -> Result<Uuid, Error> {
+        let uuid = self.take_string(key).ok_or(Error::UnexpectedDbusReply)?;
+        uuid.parse()
+    }
+}
+
+/// parse a a{sa{sv}}
+pub(crate) fn interfaces_from_param(param: Param<'static, 'static>) -> Result<Interfaces, Error> {
+    let dict = unwrap_container(param)
+        .and_then(unwrap_dict)
+        .ok_or(Error::UnexpectedDbusReply)?;
+    dict.into_iter()
+        .map(|(name, properties)| {
+            let name = unwrap_string(name).ok_or(Error::UnexpectedDbusReply)?;
+            Ok((name, Properties::from_param(properties)?))
+        })
+        .collect()
+}
+
+impl AdapterInfo {
+    pub(crate) fn from_properties(numb: u8, mut props: Properties) -> Result<Self, Error> {
+        let adress = props
+            .take_string("Address")
+            .ok_or(Error::UnexpectedDbusReply)?;
+        Ok(AdapterInfo {
+            numb,
+            adress: adress.parse()?,
+            name: props.take_string("Name").unwrap_or_default(),
+            alias: props.take_string("Alias").unwrap_or_default(),
+            powered: props.take_bool("Powered").unwrap_or(false),
+            discovering: props.take_bool("Discovering").unwrap_or(false),
+        })
+    }
+}
+
+impl DeviceInfo {
+    pub(crate) fn from_properties(mut props: Properties) -> Result<Self, Error> {
+        let adress: Address = props
+            .take_string("Address")
+            .ok_or(Error::UnexpectedDbusReply)?
+            .parse()?;
+        let kind = match props.take_string("AddressType") {
+            Some(kind) => kind.parse()?,
+            None => AddressType::Public,
+        };
+        let uuids = props
+            .take_strings("UUIDs")
+            .unwrap_or_default()
+            .iter()
+            .map(|uuid| uuid.parse())
+            .collect::<Result<_, _>>()?;
+        Ok(DeviceInfo {
+            adress: adress.with_type(kind),
+            name: props.take_string("Name"),
+            alias: props
+                .take_string("Alias")
+                .unwrap_or_else(|| adress.to_string()),
+            rssi: props.take_i16("RSSI"),
+            tx_power: props.take_i16("TxPower"),
+            appearance: props.take_u16("Appearance"),
+            paired: props.take_bool("Paired").unwrap_or(false),
+            trusted: props.take_bool("Trusted").unwrap_or(false),
+            connected: props.take_bool("Connected").unwrap_or(false),
+            services_resolved: props.take_bool("ServicesResolved").unwrap_or(false),
+            uuids,
+            mtu: None,
+        })
+    }
+}
+
+impl GattCharacteristic {
+    pub(crate) fn from_properties(path: BluezPath, mut props: Properties) -> Result<Self, Error> {
+        // flags bluez added after this was written are left out
+        let flags = props
+            .take_strings("Flags")
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|flag| Flag::from_bluez(flag))
+            .collect();
+        Ok(GattCharacteristic {
+            path,
+            uuid: props.take_uuid("UUID")?,
+            flags,
+            mtu: props.take_u16("MTU"),
+            descriptors: Vec::new(),
+        })
+    }
+}
@@ -0,0 +1,456 @@
+//! L2CAP connection oriented channels (CoC) over LE. Much faster than
+//! notifications for bulk transfer. The channel is a `SOCK_SEQPACKET`
+//! socket: every write is sent as one packet (SDU) and every read returns
+//! one packet, so use a buffer of at least `recv_mtu` bytes.
+//!
+//! ```no_run
+//! use std::io::{Read, Write};
+//! use bluebus::l2cap::{L2capChannel, L2capOptions, SecurityLevel};
+//!
+//! let options = L2capOptions::new()
+//!     .with_mtu(2048)
+//!     .with_security(SecurityLevel::Medium);
+//! let mut channel = L2capChannel::connect_with("0A:0A:0A:0A:0A:0A", 0x0080, &options).unwrap();
+//! channel.write_all(b"start").unwrap();
+//! let mut packet = vec![0u8; channel.recv_mtu().unwrap() as usize];
+//! let len = channel.read(&mut packet).unwrap();
+//! ```
+
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::raw::c_int;
+use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
+
+use nix::libc;
+
+use crate::address::{Address, AddressType, IntoAddress};
+use crate::error::Error;
+
+const BTPROTO_L2CAP: c_int = 0;
+const SOL_BLUETOOTH: c_int = 274;
+const BT_SECURITY: c_int = 4;
+const BT_SNDMTU: c_int = 12;
+const BT_RCVMTU: c_int = 13;
+const BT_PHY: c_int = 14;
+
+const BT_PHY_LE_2M_TX: u32 = 0x0800;
+const BT_PHY_LE_2M_RX: u32 = 0x1000;
+const BT_PHY_LE_CODED_TX: u32 = 0x2000;
+const BT_PHY_LE_CODED_RX: u32 = 0x4000;
+
+const BDADDR_LE_PUBLIC: u8 = 0x01;
+const BDADDR_LE_RANDOM: u8 = 0x02;
+
+/// struct sockaddr_l2 from the kernel headers
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SockaddrL2 {
+    l2_family: libc::sa_family_t,
+    l2_psm: u16,
+    /// the address octets in reverse (little endian) order
+    l2_bdaddr: [u8; 6],
+    l2_cid: u16,
+    l2_bdaddr_type: u8,
+}
+
+impl SockaddrL2 {
+    fn new(adress: Address, psm: u16) -> Self {
+        let mut l2_bdaddr = adress.octets();
+        l2_bdaddr.reverse();
+        let l2_bdaddr_type = match adress.kind() {
+            AddressType::Public => BDADDR_LE_PUBLIC,
+            AddressType::Random => BDADDR_LE_RANDOM,
+        };
+        SockaddrL2 {
+            l2_family: libc::AF_BLUETOOTH as libc::sa_family_t,
+            l2_psm: psm.to_le(),
+            l2_bdaddr,
+            l2_cid: 0,
+            l2_bdaddr_type,
+        }
+    }
+
+    fn empty() -> Self {
+        Self::new(Address::new([0; 6]), 0)
+    }
+
+    fn adress(&self) -> Address {
+        let mut octets = self.l2_bdaddr;
+        octets.reverse();
+        let kind = match self.l2_bdaddr_type {
+            BDADDR_LE_RANDOM => AddressType::Random,
+            _ => AddressType::Public,
+        };
+        Address::new(octets).with_type(kind)
+    }
+}
+
+/// struct bt_security from the kernel headers
+#[repr(C)]
+struct BtSecurity {
+    level: u8,
+    key_size: u8,
+}
+
+/// How the link must be secured before the channel opens
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecurityLevel {
+    /// no encryption
+    Low = 1,
+    /// encrypted, the key may be unauthenticated (just works pairing)
+    Medium = 2,
+    /// encrypted with an authenticated key
+    High = 3,
+    /// LE secure connections with an authenticated key
+    Fips = 4,
+}
+
+/// The physical layer an LE link uses, in one direction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phy {
+    /// 1 Mbit/s, what every link starts with
+    Le1M,
+    /// 2 Mbit/s
+    Le2M,
+    /// long range, 125 or 500 kbit/s
+    LeCoded,
+}
+
+impl Phy {
+    /// pick one direction out of a BT_PHY mask
+    fn from_mask(mask: u32, two_m: u32, coded: u32) -> Self {
+        if mask & two_m != 0 {
+            Phy::Le2M
+        } else if mask & coded != 0 {
+            Phy::LeCoded
+        } else {
+            Phy::Le1M
+        }
+    }
+}
+
+/// Options for opening or accepting channels, the kernel defaults are
+/// used for anything not set.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct L2capOptions {
+    mtu: Option<u16>,
+    security: Option<SecurityLevel>,
+    adapter: Option<Address>,
+}
+
+impl L2capOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the largest packet we can recieve
+    pub fn with_mtu(mut self, mtu: u16) -> Self {
+        self.mtu = Some(mtu);
+        self
+    }
+
+    pub fn with_security(mut self, level: SecurityLevel) -> Self {
+        self.security = Some(level);
+        self
+    }
+
+    /// use the adapter with this address instead of any adapter
+    pub fn with_adapter(mut self, adress: Address) -> Self {
+        self.adapter = Some(adress);
+        self
+    }
+}
+
+/// an open L2CAP socket, closed when dropped
+#[derive(Debug)]
+struct Socket(RawFd);
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+fn check(ret: c_int) -> Result<c_int, Error> {
+    if ret < 0 {
+        Err(Error::Socket(io::Error::last_os_error()))
+    } else {
+        Ok(ret)
+    }
+}
+
+impl Socket {
+    fn new(options: &L2capOptions) -> Result<Self, Error> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_BLUETOOTH,
+                libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC,
+                BTPROTO_L2CAP,
+            )
+        };
+        let socket = Socket(check(fd)?);
+
+        if let Some(level) = options.security {
+            let security = BtSecurity {
+                level: level as u8,
+                key_size: 0,
+            };
+            socket.set_option(BT_SECURITY, &security)?;
+        }
+        if let Some(mtu) = options.mtu {
+            socket.set_option(BT_RCVMTU, &mtu)?;
+        }
+        Ok(socket)
+    }
+
+    fn set_option<T>(&self, name: c_int, value: &T) -> Result<(), Error> {
+        let ret = unsafe {
+            libc::setsockopt(
+                self.0,
+                SOL_BLUETOOTH,
+                name,
+                value as *const T as *const libc::c_void,
+                mem::size_of::<T>() as libc::socklen_t,
+            )
+        };
+        check(ret).map(|_| ())
+    }
+
+    fn option<T: Default>(&self, name: c_int) -> Result<T, Error> {
+        let mut value = T::default();
+        let mut len = mem::size_of::<T>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                self.0,
+                SOL_BLUETOOTH,
+                name,
+                &mut value as *mut T as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        check(ret).map(|_| value)
+    }
+
+    fn bind(&self, adress: Address, psm: u16) -> Result<(), Error> {
+        let addr = SockaddrL2::new(adress, psm);
+        let ret = unsafe {
+            libc::bind(
+                self.0,
+                &addr as *const SockaddrL2 as *const libc::sockaddr,
+                mem::size_of::<SockaddrL2>() as libc::socklen_t,
+            )
+        };
+        check(ret).map(|_| ())
+    }
+
+    fn local_addr(&self) -> Result<SockaddrL2, Error> {
+        let mut addr = SockaddrL2::empty();
+        let mut len = mem::size_of::<SockaddrL2>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockname(
+                self.0,
+                &mut addr as *mut SockaddrL2 as *mut libc::sockaddr,
+                &mut len,
+            )
+        };
+        check(ret).map(|_| addr)
+    }
+}
+
+/// An open channel to a device
+#[derive(Debug)]
+pub struct L2capChannel {
+    socket: Socket,
+    peer: Address,
+}
+
+impl L2capChannel {
+    /// open a channel to the PSM on the device with default options
+    #[allow(dead_code)]
+    pub fn connect(adress: impl IntoAddress, psm: u16) -> Result<Self, Error> {
+        Self::connect_with(adress, psm, &L2capOptions::new())
+    }
+
+    /// open a channel to the PSM on the device, the device must be known to
+    /// bluez and its address type set. A parsed address is taken to be
+    /// public, use the `adress` of `Ble::device_info` for one with the type
+    /// bluez knows the device by. Blocks until the channel is open.
+    #[allow(dead_code)]
+    pub fn connect_with(
+        adress: impl IntoAddress,
+        psm: u16,
+        options: &L2capOptions,
+    ) -> Result<Self, Error> {
+        let peer = adress.into_address()?;
+        let socket = Socket::new(options)?;
+        if let Some(adapter) = options.adapter {
+            socket.bind(adapter, 0)?;
+        }
+
+        let addr = SockaddrL2::new(peer, psm);
+        let ret = unsafe {
+            libc::connect(
+                socket.0,
+                &addr as *const SockaddrL2 as *const libc::sockaddr,
+                mem::size_of::<SockaddrL2>() as libc::socklen_t,
+            )
+        };
+        check(ret)?;
+        Ok(L2capChannel { socket, peer })
+    }
+
+    /// the device on the other end
+    pub fn peer(&self) -> Address {
+        self.peer
+    }
+
+    /// the largest packet the device accepts
+    pub fn send_mtu(&self) -> Result<u16, Error> {
+        self.socket.option(BT_SNDMTU)
+    }
+
+    /// the largest packet we accept
+    pub fn recv_mtu(&self) -> Result<u16, Error> {
+        self.socket.option(BT_RCVMTU)
+    }
+
+    /// the PHY the link to the device sends and receives with, these
+    /// change when either side asks for another PHY. Needs linux 5.10.
+    pub fn phy(&self) -> Result<(Phy, Phy), Error> {
+        let mask: u32 = self.socket.option(BT_PHY)?;
+        Ok((
+            Phy::from_mask(mask, BT_PHY_LE_2M_TX, BT_PHY_LE_CODED_TX),
+            Phy::from_mask(mask, BT_PHY_LE_2M_RX, BT_PHY_LE_CODED_RX),
+        ))
+    }
+
+    /// close the channel, the device sees it disconnect
+    pub fn shutdown(&self) -> Result<(), Error> {
+        check(unsafe { libc::shutdown(self.socket.0, libc::SHUT_RDWR) }).map(|_| ())
+    }
+}
+
+impl Read for L2capChannel {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let ret = unsafe {
+            libc::recv(
+                self.socket.0,
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                0,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ret as usize)
+    }
+}
+
+impl Write for L2capChannel {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let ret = unsafe {
+            libc::send(
+                self.socket.0,
+                buf.as_ptr() as *const libc::c_void,
+                buf.len(),
+                libc::MSG_NOSIGNAL,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ret as usize)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsRawFd for L2capChannel {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.0
+    }
+}
+
+impl IntoRawFd for L2capChannel {
+    fn into_raw_fd(self) -> RawFd {
+        let fd = self.socket.0;
+        mem::forget(self.socket);
+        fd
+    }
+}
+
+/// Accepts channels devices open to a PSM
+#[derive(Debug)]
+pub struct L2capListener {
+    socket: Socket,
+}
+
+impl L2capListener {
+    /// listen on the PSM, pass 0 to get a free dynamic PSM (see `psm`)
+    #[allow(dead_code)]
+    pub fn bind(psm: u16, options: &L2capOptions) -> Result<Self, Error> {
+        let socket = Socket::new(options)?;
+        let adapter = options.adapter.unwrap_or_else(|| Address::new([0; 6]));
+        socket.bind(adapter, psm)?;
+        check(unsafe { libc::listen(socket.0, 5) })?;
+        Ok(L2capListener { socket })
+    }
+
+    /// the PSM devices should connect to
+    pub fn psm(&self) -> Result<u16, Error> {
+        Ok(u16::from_le(self.socket.local_addr()?.l2_psm))
+    }
+
+    /// block until a device opens a channel
+    pub fn accept(&self) -> Result<L2capChannel, Error> {
+        let mut addr = SockaddrL2::empty();
+        let mut len = mem::size_of::<SockaddrL2>() as libc::socklen_t;
+        let fd = unsafe {
+            libc::accept4(
+                self.socket.0,
+                &mut addr as *mut SockaddrL2 as *mut libc::sockaddr,
+                &mut len,
+                libc::SOCK_CLOEXEC,
+            )
+        };
+        let socket = Socket(check(fd)?);
+        Ok(L2capChannel {
+            socket,
+            peer: addr.adress(),
+        })
+    }
+}
+
+impl AsRawFd for L2capListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sockaddr_matches_the_kernel_layout() {
+        assert_eq!(mem::size_of::<SockaddrL2>(), 14);
+
+        let adress =
+            Address::new([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]).with_type(AddressType::Random);
+        let addr = SockaddrL2::new(adress, 0x1001);
+        let offset = |field: *const u8| field as usize - &addr as *const _ as usize;
+        assert_eq!(offset(&addr.l2_psm as *const _ as *const u8), 2);
+        assert_eq!(offset(addr.l2_bdaddr.as_ptr()), 4);
+        assert_eq!(offset(&addr.l2_cid as *const _ as *const u8), 10);
+        assert_eq!(offset(&addr.l2_bdaddr_type), 12);
+
+        assert_eq!(addr.l2_psm.to_le_bytes(), [0x01, 0x10]);
+        assert_eq!(addr.l2_bdaddr, [0x06, 0x05, 0x04, 0x03, 0x02, 0x01]);
+        assert_eq!(addr.l2_bdaddr_type, BDADDR_LE_RANDOM);
+        assert_eq!(addr.adress(), adress);
+        assert_eq!(addr.adress().kind(), AddressType::Random);
+    }
+}
@@ -1,23 +1,75 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
 use std::time::Duration;
 
 pub use rustbus::client_conn::Timeout;
-use rustbus::{get_system_bus_path, standard_messages, Conn, RpcConn};
+use rustbus::standard_messages;
 
+mod address;
+mod bus;
+pub use address::{Address, AddressType, IntoAddress};
 mod dbus_helpers;
 use dbus_helpers::*;
 
+mod discovery;
+pub use discovery::{DiscoveryFilter, Transport};
 mod error;
 pub use error::{Context, Error};
+mod events;
+pub use events::Event;
+mod info;
+pub use info::{AdapterInfo, DeviceInfo, GattCharacteristic, GattDescriptor, GattService};
+mod notification;
+pub use notification::{
+    HubEvent, LeCounter, Notification, NotificationHub, NotificationStats, SequenceExtractor,
+};
+mod path;
+pub use path::{BluezPath, PathKind};
+#[cfg(feature = "serde")]
+mod serde_impls;
+mod uuid;
+pub use crate::uuid::{IntoUuid, Uuid};
+mod supervisor;
+pub use supervisor::{Backoff, ConnectionState, ConnectionSupervisor};
+pub mod advertising;
+pub mod battery;
+pub mod connection;
+use connection::{BusConnection, Connection};
+pub mod gatt_server;
+#[cfg(feature = "gatt-types")]
+pub mod gatt_types;
+pub mod l2cap;
 pub mod operations;
+pub use operations::{CallOptions, CancelToken};
+pub mod profile;
+pub mod recording;
+use recording::{Recorder, Replay};
+pub mod storage;
+#[cfg(feature = "test-support")]
+pub mod test_support;
 pub mod util;
-// pub re-export third party dependency rustbus 
+// pub re-export third party dependency rustbus
 // to allow users to access its error types that
 // are exposed by our Error anyway
 pub use rustbus;
 
+/// where build gets its connection from
+enum Bus {
+    System,
+    Session,
+    Address(String),
+    Replay(PathBuf),
+    Connection(Box<dyn BusConnection>),
+}
+
 pub struct BleBuilder {
     adapter_numb: u8,
     timeout: Timeout,
+    bus: Bus,
+    storage_root: PathBuf,
+    record_to: Option<PathBuf>,
+    auto_reacquire: bool,
+    events: bool,
 }
 
 impl Default for BleBuilder {
@@ -25,72 +77,235 @@ impl Default for BleBuilder {
         BleBuilder {
             adapter_numb: 0,
             timeout: Timeout::Duration(Duration::from_secs(5)),
+            bus: Bus::System,
+            storage_root: PathBuf::from(storage::DEFAULT_ROOT),
+            record_to: None,
+            auto_reacquire: false,
+            events: false,
         }
     }
 }
 
 impl BleBuilder {
-
     pub fn with_timeout(mut self, timeout: Timeout) -> Self {
         self.timeout = timeout;
         self
     }
 
+    /// use adapter `hci{numb}` instead of hci0
+    pub fn with_adapter(mut self, numb: u8) -> Self {
+        self.adapter_numb = numb;
+        self
+    }
+
+    /// connect to the bus at this address instead of the system bus,
+    /// for example `unix:path=/tmp/bus`. Useful to test against a fake
+    /// bluez, see `test_support`.
+    pub fn with_bus_address(mut self, address: impl Into<String>) -> Self {
+        self.bus = Bus::Address(address.into());
+        self
+    }
+
+    /// connect to the session bus (from `DBUS_SESSION_BUS_ADDRESS`)
+    /// instead of the system bus
+    pub fn with_session_bus(mut self) -> Self {
+        self.bus = Bus::Session;
+        self
+    }
+
+    /// use this connection instead of connecting to a bus. It must be ready
+    /// for use: the Hello call has been made, as `RpcConn::session_conn`
+    /// and `RpcConn::system_conn` do.
+    pub fn with_connection(mut self, connection: impl BusConnection + 'static) -> Self {
+        self.bus = Bus::Connection(Box::new(connection));
+        self
+    }
+
+    /// look for the bluez storage (attribute cache, bonds) below this
+    /// directory instead of `/var/lib/bluetooth`
+    pub fn with_storage_root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.storage_root = root.into();
+        self
+    }
+
+    /// write all dbus traffic to this file, see `recording`
+    pub fn record_to(mut self, path: impl Into<PathBuf>) -> Self {
+        self.record_to = Some(path.into());
+        self
+    }
+
+    /// do not connect to the bus, serve the replies from a recording
+    /// instead, see `recording`
+    pub fn replay_from(mut self, path: impl Into<PathBuf>) -> Self {
+        self.bus = Bus::Replay(path.into());
+        self
+    }
+
+    /// listen for device changes from the start instead of from the first
+    /// `wait_event` or `try_event`, so the events before it are not missed.
+    /// A Ble that never asks for events does not keep these signals.
+    pub fn with_events(mut self) -> Self {
+        self.events = true;
+        self
+    }
+
+    /// acquire notify again for a device that disconnected once its
+    /// services are resolved again, see `Event::NotificationReacquired`.
+    /// Implies `with_events`.
+    pub fn with_auto_reacquire(mut self) -> Self {
+        self.auto_reacquire = true;
+        self.events = true;
+        self
+    }
+
+    /// connect to the bus
     pub fn build(self) -> Result<Ble, Error> {
-        let session_path = get_system_bus_path()?;
-        let con = Conn::connect_to_bus(session_path, true)?;
-        let mut connection = RpcConn::new(con);
-        // send the obligatory hello message
-        let response_serial =
-            connection.send_message(&mut standard_messages::hello(), Timeout::Infinite)?;
-        let mut reply = connection
-            .wait_response(response_serial, self.timeout)?
-            .unmarshall_all()?;
-        let param = reply.params.pop().unwrap();
-        let container = unwrap_base(param).unwrap();
-        let conn_name = unwrap_string(container).unwrap();
+        let (transport, needs_hello): (Box<dyn BusConnection>, _) = match self.bus {
+            Bus::System => {
+                let address = std::env::var("DBUS_SYSTEM_BUS_ADDRESS");
+                let address = address.as_deref().unwrap_or(bus::SYSTEM_BUS);
+                (connect_to_bus(address)?, true)
+            }
+            Bus::Session => {
+                let address = std::env::var("DBUS_SESSION_BUS_ADDRESS").map_err(|_| {
+                    Error::CouldNotConnectToBus("DBUS_SESSION_BUS_ADDRESS is not set".into())
+                })?;
+                (connect_to_bus(&address)?, true)
+            }
+            Bus::Address(address) => (connect_to_bus(&address)?, true),
+            Bus::Replay(path) => {
+                let replay = Replay::open(&path)?;
+                let needs_hello = replay.starts_with_hello();
+                (Box::new(replay), needs_hello)
+            }
+            Bus::Connection(connection) => (connection, false),
+        };
+        let recorder = match &self.record_to {
+            Some(path) => Some(Recorder::create(path)?),
+            None => None,
+        };
+        let mut connection = Connection::new(transport, recorder);
+        if needs_hello {
+            // send the obligatory hello message
+            let response_serial =
+                connection.send_message(&mut standard_messages::hello(), Timeout::Infinite)?;
+            let mut reply = connection
+                .wait_response(response_serial, self.timeout)?
+                .unmarshall_all()?;
+            let param = reply.params.pop().unwrap();
+            let container = unwrap_base(param).unwrap();
+            let _conn_name = unwrap_string(container).unwrap();
+        }
 
-        let mut message = get_name_owner("org.bluez".to_owned())?;
+        // the other signals are only asked for once events are, see `listen`
+        let mut message = standard_messages::add_match(bluez_owner_changed_rule());
         let response_serial = connection.send_message(&mut message, self.timeout)?;
-        let msg = connection
-            .wait_response(response_serial, self.timeout)?
-            .unmarshall_all()?;
-
-        /*let mut message = standard_messages::request_name(
-            "org.bluebus".to_owned(),
-            standard_messages::DBUS_NAME_FLAG_REPLACE_EXISTING,
-        );
+        connection.wait_response(response_serial, self.timeout)?;
+
+        let mut message = get_name_owner("org.bluez".to_owned())?;
         let response_serial = connection.send_message(&mut message, self.timeout)?;
-        let msg = connection
-            .wait_response(response_serial, self.timeout)?
-            .unmarshall_all()
-            .unwrap();
-        dbg!(msg);*/
-
-        let mut message = register_agent("/bluebus/agent", "KeyboardDisplay").unwrap();
-        let response_serial = connection.send_message(&mut message, self.timeout).unwrap();
-        let msg = connection
-            .wait_response(response_serial, self.timeout)
-            .unwrap()
-            .unmarshall_all()
-            .unwrap();
+        let msg = connection.wait_response(response_serial, self.timeout)?;
+        let bluez_owner = match msg.typ {
+            rustbus::MessageType::Reply => msg.body.parser().get::<String>().ok(),
+            _ => None, // bluez is not running (yet)
+        };
 
         let BleBuilder {
             adapter_numb,
             timeout,
+            storage_root,
+            auto_reacquire,
+            events,
+            ..
         } = self;
 
-        Ok(Ble {
+        let mut ble = Ble {
             connection,
             adapter_numb,
             timeout,
-        })
+            bluez_owner,
+            notifications: Vec::new(),
+            auto_reacquire,
+            lost_notifications: Vec::new(),
+            pending_reacquires: Vec::new(),
+            reacquired: Vec::new(),
+            events: VecDeque::new(),
+            listening: false,
+            gatt_apps: Vec::new(),
+            advertisements: Vec::new(),
+            battery_provider: Default::default(),
+            profiles: Vec::new(),
+            profile_connections: Vec::new(),
+            next_object_id: 0,
+            storage_root,
+        };
+        // if bluez is not running the agent is registered once it starts,
+        // see: Event::BluezRestarted
+        if ble.bluez_owner.is_some() {
+            ble.register_agent()?;
+        }
+        if events {
+            ble.listen()?;
+        }
+        Ok(ble)
     }
 }
 
+fn connect_to_bus(address: &str) -> Result<Box<dyn BusConnection>, Error> {
+    Ok(Box::new(bus::BusSocket::connect(address)?))
+}
+
 pub struct Ble {
     //adapter
-    connection: RpcConn,
+    connection: Connection,
     adapter_numb: u8,
     timeout: Timeout,
+    /// unique bus name of the bluetoothd instance we are talking to
+    bluez_owner: Option<String>,
+    /// (adress, uuid) of every characteristic we aquired notify for
+    notifications: Vec<(Address, Uuid)>,
+    /// acquire notify again when a device is back, see
+    /// `BleBuilder::with_auto_reacquire`
+    auto_reacquire: bool,
+    /// devices that disconnected while we had notify acquired on them
+    lost_notifications: Vec<Address>,
+    /// characteristics of devices that are back, acquired again by
+    /// `wait_event`
+    pending_reacquires: Vec<(Address, Uuid)>,
+    /// notifications acquired again that the user did not take yet
+    reacquired: Vec<Notification>,
+    /// events parsed from signals but not yet handed to the user
+    events: VecDeque<Event>,
+    /// whether we get the signals about device changes, only once the user
+    /// wants events as nothing drains them otherwise
+    listening: bool,
+    gatt_apps: Vec<gatt_server::RegisteredApp>,
+    advertisements: Vec<advertising::RegisteredAdvertisement>,
+    battery_provider: battery::BatteryProvider,
+    profiles: Vec<profile::RegisteredProfile>,
+    /// connections bluez handed us that the user did not take yet
+    profile_connections: Vec<profile::ProfileConnection>,
+    /// used to give every object we export a unique path
+    next_object_id: u32,
+    /// where bluez keeps its state, see `storage`
+    storage_root: PathBuf,
+}
+
+/// where our agent is exported, bluez calls it while we pair
+const AGENT_PATH: &str = "/bluebus/agent";
+
+impl Ble {
+    fn register_agent(&mut self) -> Result<(), Error> {
+        let mut message = register_agent(AGENT_PATH, "KeyboardDisplay")?;
+        let response_serial = self.connection.send_message(&mut message, self.timeout)?;
+        let msg = self
+            .connection
+            .wait_response(response_serial, self.timeout)?;
+
+        match msg.typ {
+            rustbus::MessageType::Reply => Ok(()),
+            rustbus::MessageType::Error => Err(Error::from((msg, Context::RegisterAgent))),
+            _ => Err(Error::UnexpectedDbusReply),
+        }
+    }
 }
@@ -0,0 +1,191 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Instant;
+
+use nix::errno::Errno;
+use nix::sys::epoll::{
+    epoll_create1, epoll_ctl, epoll_wait, EpollCreateFlags, EpollEvent, EpollFlags, EpollOp,
+};
+use nix::unistd::close;
+use rustbus::client_conn::Timeout;
+
+use super::{millis_left, nix_error, Notification, MAX_MTU};
+use crate::address::Address;
+use crate::error::Error;
+use crate::uuid::Uuid;
+
+/// What `NotificationHub::wait` found
+#[derive(Debug)]
+pub enum HubEvent {
+    /// a notification from this characteristic of this device
+    Value {
+        adress: Address,
+        uuid: Uuid,
+        value: Vec<u8>,
+    },
+    /// the stream ended as the device disconnected, it is no longer in the
+    /// hub. Call `Ble::notify` again once the device is back, or take the
+    /// stream `BleBuilder::with_auto_reacquire` acquired.
+    Closed(Notification),
+}
+
+/// Waits on many notification streams at once, for example one per
+/// device on a gateway. Every value is tagged with where it came from.
+///
+/// ```no_run
+/// use bluebus::{BleBuilder, HubEvent, NotificationHub, Timeout};
+///
+/// let mut ble = BleBuilder::default().build().unwrap();
+/// let mut hub = NotificationHub::new().unwrap();
+/// for device in ["0A:0A:0A:0A:0A:0A", "0B:0B:0B:0B:0B:0B"] {
+///     ble.connect(device).unwrap();
+///     hub.add(ble.notify(device, 0x2a37).unwrap()).unwrap();
+/// }
+/// while !hub.is_empty() {
+///     match hub.wait(Timeout::Infinite).unwrap() {
+///         Some(HubEvent::Value { adress, value, .. }) => println!("{}: {:?}", adress, value),
+///         Some(HubEvent::Closed(gone)) => println!("{} disconnected", gone.adress()),
+///         None => (),
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct NotificationHub {
+    epoll: RawFd,
+    /// by the token registerd with epoll
+    streams: HashMap<u64, Notification>,
+    next_token: u64,
+    /// streams epoll reported that were not read yet
+    ready: VecDeque<u64>,
+    buffer: Vec<u8>,
+}
+
+impl NotificationHub {
+    pub fn new() -> Result<Self, Error> {
+        let epoll = epoll_create1(EpollCreateFlags::EPOLL_CLOEXEC).map_err(nix_error)?;
+        Ok(NotificationHub {
+            epoll,
+            streams: HashMap::new(),
+            next_token: 0,
+            ready: VecDeque::new(),
+            buffer: vec![0; MAX_MTU],
+        })
+    }
+
+    /// wait on this stream too
+    pub fn add(&mut self, notification: Notification) -> Result<(), Error> {
+        let token = self.next_token;
+        let mut event = EpollEvent::new(EpollFlags::EPOLLIN, token);
+        epoll_ctl(
+            self.epoll,
+            EpollOp::EpollCtlAdd,
+            notification.as_raw_fd(),
+            &mut event,
+        )
+        .map_err(nix_error)?;
+        self.next_token += 1;
+        self.streams.insert(token, notification);
+        Ok(())
+    }
+
+    /// stop waiting on the stream of this characteristic and hand it back
+    pub fn remove(&mut self, adress: Address, uuid: Uuid) -> Option<Notification> {
+        let token = self
+            .streams
+            .iter()
+            .find(|(_, n)| n.adress() == adress && n.uuid() == uuid)
+            .map(|(token, _)| *token)?;
+        self.take(token)
+    }
+
+    fn take(&mut self, token: u64) -> Option<Notification> {
+        let notification = self.streams.remove(&token)?;
+        // only fails if the fd is already gone from the set
+        let _ = epoll_ctl(
+            self.epoll,
+            EpollOp::EpollCtlDel,
+            notification.as_raw_fd(),
+            None,
+        );
+        Some(notification)
+    }
+
+    pub fn len(&self) -> usize {
+        self.streams.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.streams.is_empty()
+    }
+
+    /// the streams in the hub
+    pub fn notifications(&self) -> impl Iterator<Item = &Notification> {
+        self.streams.values()
+    }
+
+    /// block until a stream has a value or hangs up, None if the timeout
+    /// passed first. Streams that are ready take turns.
+    pub fn wait(&mut self, timeout: Timeout) -> Result<Option<HubEvent>, Error> {
+        let start = Instant::now();
+        loop {
+            while let Some(token) = self.ready.pop_front() {
+                if let Some(event) = self.read(token)? {
+                    return Ok(Some(event));
+                }
+            }
+
+            let timeout_ms = millis_left(start, timeout);
+            let mut events = vec![EpollEvent::empty(); self.streams.len().max(1)];
+            let n = match epoll_wait(self.epoll, &mut events, timeout_ms) {
+                Ok(n) => n,
+                Err(nix::Error::Sys(Errno::EINTR)) => continue,
+                Err(e) => return Err(nix_error(e)),
+            };
+            if n == 0 && timeout_ms >= 0 {
+                return Ok(None);
+            }
+            self.ready.extend(events[..n].iter().map(EpollEvent::data));
+        }
+    }
+
+    /// read one packet from a stream epoll reported, None if there was
+    /// nothing after all
+    fn read(&mut self, token: u64) -> Result<Option<HubEvent>, Error> {
+        let notification = match self.streams.get_mut(&token) {
+            Some(notification) => notification,
+            // removed since epoll reported it
+            None => return Ok(None),
+        };
+        match notification.read(&mut self.buffer) {
+            // the device hung up
+            Ok(0) => (),
+            Ok(n) => {
+                return Ok(Some(HubEvent::Value {
+                    adress: notification.adress(),
+                    uuid: notification.uuid(),
+                    value: self.buffer[..n].to_vec(),
+                }))
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                self.ready.push_front(token);
+                return Ok(None);
+            }
+            Err(e) => return Err(e.into()),
+        }
+        Ok(self.take(token).map(HubEvent::Closed))
+    }
+}
+
+impl AsRawFd for NotificationHub {
+    /// readable when a stream is, to wait on the hub from another poll loop
+    fn as_raw_fd(&self) -> RawFd {
+        self.epoll
+    }
+}
+
+impl Drop for NotificationHub {
+    fn drop(&mut self) {
+        let _ = close(self.epoll);
+    }
+}
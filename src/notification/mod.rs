@@ -0,0 +1,188 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::time::Instant;
+
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+use rustbus::client_conn::Timeout;
+
+mod hub;
+mod stats;
+pub use hub::{HubEvent, NotificationHub};
+use stats::Tracker;
+pub use stats::{LeCounter, NotificationStats, SequenceExtractor};
+
+use crate::address::Address;
+use crate::error::{Context, Error};
+use crate::uuid::Uuid;
+
+/// the largest ATT MTU there is, no notification is longer
+const MAX_MTU: usize = 517;
+
+fn nix_error(err: nix::Error) -> Error {
+    match err.as_errno() {
+        Some(errno) => Error::Io(io::Error::from(errno)),
+        None => Error::Io(io::Error::new(io::ErrorKind::Other, err)),
+    }
+}
+
+/// what is left of the timeout in milliseconds as poll and epoll take it,
+/// -1 to wait forever
+pub(crate) fn millis_left(start: Instant, timeout: Timeout) -> isize {
+    match timeout {
+        Timeout::Infinite => -1,
+        Timeout::Nonblock => 0,
+        Timeout::Duration(timeout) => {
+            let left = timeout.saturating_sub(start.elapsed());
+            // round up so we do not wake just before the deadline
+            (left.as_micros() as isize + 999) / 1000
+        }
+    }
+}
+
+/// The values a characteristic notifies, returned by `Ble::notify`. Every
+/// read returns one notification, a read of 0 bytes means the device
+/// disconnected (see `is_closed`). `recv` reports that as
+/// `Error::Disconnected` instead. Closed when dropped.
+#[derive(Debug)]
+pub struct Notification {
+    file: File,
+    adress: Address,
+    uuid: Uuid,
+    mtu: u16,
+    /// only when stats were asked for
+    tracker: Option<Tracker>,
+    /// the device hung up
+    closed: bool,
+}
+
+impl Notification {
+    pub(crate) fn new(fd: RawFd, adress: Address, uuid: Uuid, mtu: u16) -> Self {
+        Notification {
+            file: unsafe { File::from_raw_fd(fd) },
+            adress,
+            uuid,
+            mtu,
+            tracker: None,
+            closed: false,
+        }
+    }
+
+    /// count the packets and bytes read and time their arrival, see
+    /// `stats`
+    pub fn with_stats(mut self) -> Self {
+        if self.tracker.is_none() {
+            self.tracker = Some(Tracker::new(None));
+        }
+        self
+    }
+
+    /// track stats and also count lost packets, using the sequence number
+    /// the device puts in every packet. For example
+    /// `with_sequence(LeCounter::new(0, 4))` for a u32 counter at the start.
+    pub fn with_sequence(mut self, extractor: impl SequenceExtractor + 'static) -> Self {
+        self.tracker = Some(Tracker::new(Some(Box::new(extractor))));
+        self
+    }
+
+    /// the stats since tracking started or was reset, None unless
+    /// `with_stats` or `with_sequence` was used
+    pub fn stats(&self) -> Option<&NotificationStats> {
+        self.tracker.as_ref().map(Tracker::stats)
+    }
+
+    /// start counting from zero, for example to report every interval
+    pub fn reset_stats(&mut self) {
+        if let Some(tracker) = &mut self.tracker {
+            tracker.reset();
+        }
+    }
+
+    pub fn adress(&self) -> Address {
+        self.adress
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        self.uuid
+    }
+
+    /// the ATT MTU of the connection when notify was acquired, a
+    /// notification carries at most `mtu - 3` bytes. This is all bluez
+    /// tells of the link, see `DeviceInfo::mtu`.
+    pub fn mtu(&self) -> u16 {
+        self.mtu
+    }
+
+    /// the device disconnected and nothing more will arrive. Acquire notify
+    /// again once it is back, or let `BleBuilder::with_auto_reacquire` do so.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// block until the next notification, None if the timeout passed
+    /// first. Fails with `Error::Disconnected` once the device disconnected.
+    pub fn recv(&mut self, timeout: Timeout) -> Result<Option<Vec<u8>>, Error> {
+        let start = Instant::now();
+        let mut buffer = vec![0; MAX_MTU];
+        loop {
+            if self.closed {
+                return Err(Error::Disconnected(Context::Notification(self.uuid)));
+            }
+            let timeout_ms = millis_left(start, timeout);
+            let mut fds = [PollFd::new(self.as_raw_fd(), PollFlags::POLLIN)];
+            match poll(&mut fds, timeout_ms as i32) {
+                Ok(0) if timeout_ms >= 0 => return Ok(None),
+                Ok(_) => (),
+                Err(nix::Error::Sys(Errno::EINTR)) => continue,
+                Err(e) => return Err(nix_error(e)),
+            }
+            match self.read(&mut buffer) {
+                // closed is set now
+                Ok(0) => (),
+                Ok(n) => {
+                    buffer.truncate(n);
+                    return Ok(Some(buffer));
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl Read for Notification {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = match self.file.read(buf) {
+            Ok(0) if !buf.is_empty() => {
+                self.closed = true;
+                0
+            }
+            Ok(n) => n,
+            // bluez drops the socket when the link goes
+            Err(e) if e.kind() == io::ErrorKind::ConnectionReset => {
+                self.closed = true;
+                0
+            }
+            Err(e) => return Err(e),
+        };
+        if let Some(tracker) = &mut self.tracker {
+            if n > 0 {
+                tracker.record(&buf[..n], Instant::now());
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl AsRawFd for Notification {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for Notification {
+    fn into_raw_fd(self) -> RawFd {
+        self.file.into_raw_fd()
+    }
+}
@@ -0,0 +1,229 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Finds the sequence number a device puts in its notifications, used to
+/// count the packets that never arrived. Closures returning the number
+/// work for counters that wrap at `u32::MAX`.
+pub trait SequenceExtractor: Send {
+    /// the sequence number of this packet, None if it has none
+    fn sequence(&mut self, packet: &[u8]) -> Option<u64>;
+
+    /// the counter wraps to 0 after `modulus - 1`
+    fn modulus(&self) -> u64 {
+        1 << 32
+    }
+}
+
+impl<F: FnMut(&[u8]) -> Option<u64> + Send> SequenceExtractor for F {
+    fn sequence(&mut self, packet: &[u8]) -> Option<u64> {
+        self(packet)
+    }
+}
+
+/// A little endian counter of 1 to 8 bytes at a fixed offset in every
+/// packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LeCounter {
+    offset: usize,
+    size: usize,
+}
+
+impl LeCounter {
+    pub fn new(offset: usize, size: usize) -> Self {
+        assert!((1..=8).contains(&size), "a counter is 1 to 8 bytes");
+        LeCounter { offset, size }
+    }
+}
+
+impl SequenceExtractor for LeCounter {
+    fn sequence(&mut self, packet: &[u8]) -> Option<u64> {
+        let bytes = packet.get(self.offset..self.offset + self.size)?;
+        let mut value = [0u8; 8];
+        value[..self.size].copy_from_slice(bytes);
+        Some(u64::from_le_bytes(value))
+    }
+
+    fn modulus(&self) -> u64 {
+        // 0 stands for 2^64, wrapping_sub then wraps the same way
+        1u64.checked_shl(8 * self.size as u32).unwrap_or(0)
+    }
+}
+
+/// Throughput and loss of a notification stream since tracking started,
+/// see `Notification::with_stats`
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NotificationStats {
+    pub packets: u64,
+    pub bytes: u64,
+    /// from the first to the last packet
+    pub duration: Duration,
+    /// the longest time between two packets
+    pub max_interval: Duration,
+    /// how much the time between packets varies, smoothed like RFC 3550
+    /// does for RTP
+    pub jitter: Duration,
+    /// packets that never arrived going by their sequence numbers
+    pub lost: u64,
+    /// the number of places one or more packets went missing
+    pub gaps: u64,
+    /// packets with a sequence number that was already passed, duplicates
+    /// or packets that arrived late
+    pub out_of_order: u64,
+}
+
+impl NotificationStats {
+    /// average packets per second
+    pub fn packet_rate(&self) -> f64 {
+        per_second(self.packets, self.duration)
+    }
+
+    /// average bytes per second
+    pub fn byte_rate(&self) -> f64 {
+        per_second(self.bytes, self.duration)
+    }
+
+    /// the part of the packets sent that was lost, 0 without a sequence
+    /// extractor
+    pub fn loss_ratio(&self) -> f64 {
+        let sent = self.packets + self.lost;
+        if sent == 0 {
+            return 0.0;
+        }
+        self.lost as f64 / sent as f64
+    }
+}
+
+fn per_second(count: u64, duration: Duration) -> f64 {
+    if duration.is_zero() {
+        return 0.0;
+    }
+    count as f64 / duration.as_secs_f64()
+}
+
+/// packets at most this far behind arrived late, further behind the device
+/// started counting again
+const REORDER_WINDOW: u64 = 32;
+
+/// Keeps the stats up to date as packets are read
+pub(super) struct Tracker {
+    stats: NotificationStats,
+    sequence: Option<Box<dyn SequenceExtractor>>,
+    first: Option<Instant>,
+    last: Option<Instant>,
+    last_interval: Option<Duration>,
+    expected: Option<u64>,
+}
+
+impl fmt::Debug for Tracker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tracker")
+            .field("stats", &self.stats)
+            .field("sequence", &self.sequence.is_some())
+            .finish()
+    }
+}
+
+impl Tracker {
+    pub(super) fn new(sequence: Option<Box<dyn SequenceExtractor>>) -> Self {
+        Tracker {
+            stats: NotificationStats::default(),
+            sequence,
+            first: None,
+            last: None,
+            last_interval: None,
+            expected: None,
+        }
+    }
+
+    pub(super) fn stats(&self) -> &NotificationStats {
+        &self.stats
+    }
+
+    pub(super) fn reset(&mut self) {
+        let sequence = self.sequence.take();
+        *self = Tracker::new(sequence);
+    }
+
+    pub(super) fn record(&mut self, packet: &[u8], now: Instant) {
+        let stats = &mut self.stats;
+        stats.packets += 1;
+        stats.bytes += packet.len() as u64;
+
+        let first = *self.first.get_or_insert(now);
+        stats.duration = now.duration_since(first);
+        if let Some(last) = self.last {
+            let interval = now.duration_since(last);
+            stats.max_interval = stats.max_interval.max(interval);
+            if let Some(last_interval) = self.last_interval {
+                let change = interval.max(last_interval) - interval.min(last_interval);
+                // J += (|D| - J) / 16
+                let jitter = stats.jitter.as_secs_f64();
+                let jitter = jitter + (change.as_secs_f64() - jitter) / 16.0;
+                stats.jitter = Duration::from_secs_f64(jitter);
+            }
+            self.last_interval = Some(interval);
+        }
+        self.last = Some(now);
+
+        let extractor = match &mut self.sequence {
+            Some(extractor) => extractor,
+            None => return,
+        };
+        let sequence = match extractor.sequence(packet) {
+            Some(sequence) => sequence,
+            None => return,
+        };
+        let modulus = extractor.modulus();
+        let wrap = |n: u64| if modulus == 0 { n } else { n % modulus };
+        if let Some(expected) = self.expected {
+            let skipped = wrap(sequence.wrapping_sub(expected));
+            // further ahead than half the counter is taken as behind
+            let half = match modulus {
+                0 => 1 << 63,
+                modulus => modulus / 2,
+            };
+            if skipped >= half {
+                let behind = wrap(expected.wrapping_sub(sequence));
+                if behind <= REORDER_WINDOW {
+                    stats.out_of_order += 1;
+                    return;
+                }
+            } else if skipped > 0 {
+                stats.lost += skipped;
+                stats.gaps += 1;
+            }
+        }
+        self.expected = Some(wrap(sequence.wrapping_add(1)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record_all(tracker: &mut Tracker, sequences: &[u16]) {
+        let now = Instant::now();
+        for sequence in sequences {
+            tracker.record(&sequence.to_le_bytes(), now);
+        }
+    }
+
+    #[test]
+    fn restarted_counter_is_followed() {
+        let mut tracker = Tracker::new(Some(Box::new(LeCounter::new(0, 2))));
+        // the device rebooted after 1001 and lost 2 once it was back
+        record_all(&mut tracker, &[1000, 1001, 0, 1, 3, 4, 3]);
+        let stats = tracker.stats();
+        assert_eq!((stats.lost, stats.gaps, stats.out_of_order), (1, 1, 1));
+    }
+
+    #[test]
+    fn short_counter_wraps_under_the_default_modulus() {
+        let extractor = |packet: &[u8]| Some(u16::from_le_bytes([packet[0], packet[1]]) as u64);
+        let mut tracker = Tracker::new(Some(Box::new(extractor)));
+        record_all(&mut tracker, &[65534, 65535, 0, 2]);
+        let stats = tracker.stats();
+        assert_eq!((stats.lost, stats.gaps, stats.out_of_order), (1, 1, 0));
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::{BTreeMap, HashMap};
+
+use rustbus::MessageBuilder;
+
+use crate::address::IntoAddress;
+use crate::discovery::DiscoveryFilter;
+use crate::error::{Context, Error};
+use crate::info::{AdapterInfo, DeviceInfo, GattCharacteristic, GattDescriptor, GattService};
+use crate::path::{BluezPath, PathKind};
+use crate::Ble;
+
+impl Ble {
+    /// all adapters on this system, sorted by number
+    #[allow(dead_code)]
+    pub fn adapters(&mut self) -> Result<Vec<AdapterInfo>, Error> {
+        let mut adapters = Vec::new();
+        for (path, mut interfaces) in self.managed_objects()? {
+            if path.kind() != PathKind::Adapter {
+                continue;
+            }
+            if let Some(props) = interfaces.remove("org.bluez.Adapter1") {
+                adapters.push(AdapterInfo::from_properties(path.adapter_numb(), props)?);
+            }
+        }
+        adapters.sort_by_key(|a| a.numb);
+        Ok(adapters)
+    }
+
+    /// all devices the adapter knows about
+    #[allow(dead_code)]
+    pub fn devices(&mut self) -> Result<Vec<DeviceInfo>, Error> {
+        self.device_infos(None)
+    }
+
+    #[allow(dead_code)]
+    pub fn device_info(&mut self, adress: impl IntoAddress) -> Result<DeviceInfo, Error> {
+        let device_path = self.device_path(adress.into_address()?);
+        self.device_infos(Some(device_path))?
+            .pop()
+            .ok_or(Error::DoesNotExist(Context::DeviceInfo))
+    }
+
+    /// the devices of our adapter, or only the one at this path, with the
+    /// mtu of the link filled in
+    fn device_infos(&mut self, only: Option<BluezPath>) -> Result<Vec<DeviceInfo>, Error> {
+        let adapter = self.adapter_path();
+        let mut devices = Vec::new();
+        // the mtu is only reported on the characteristics of a device
+        let mut mtus = HashMap::new();
+        for (path, mut interfaces) in self.managed_objects()? {
+            if path.adapter_path() != adapter {
+                continue;
+            }
+            let device_path = match path.device_path() {
+                Some(device_path) => device_path,
+                None => continue,
+            };
+            if only.map_or(false, |only| only != device_path) {
+                continue;
+            }
+            match path.kind() {
+                PathKind::Device => {
+                    if let Some(props) = interfaces.remove("org.bluez.Device1") {
+                        devices.push((path, DeviceInfo::from_properties(props)?));
+                    }
+                }
+                PathKind::Characteristic => {
+                    let mtu = interfaces
+                        .get_mut("org.bluez.GattCharacteristic1")
+                        .and_then(|props| props.take_u16("MTU"));
+                    if let Some(mtu) = mtu {
+                        mtus.insert(device_path, mtu);
+                    }
+                }
+                _ => (),
+            }
+        }
+        Ok(devices
+            .into_iter()
+            .map(|(path, mut info)| {
+                info.mtu = mtus.get(&path).copied();
+                info
+            })
+            .collect())
+    }
+
+    /// the services of a device with their characteristics and descriptors,
+    /// all sorted by handle. Empty until the services are resolved.
+    #[allow(dead_code)]
+    pub fn gatt_tree(&mut self, adress: impl IntoAddress) -> Result<Vec<GattService>, Error> {
+        let device_path = self.device_path(adress.into_address()?);
+        let objects = self.managed_objects()?;
+        if !objects.iter().any(|(path, _)| path == &device_path) {
+            return Err(Error::DoesNotExist(Context::GattTree));
+        }
+
+        // services keyed by handle so they come out sorted
+        let mut services = BTreeMap::new();
+        let mut characteristics = HashMap::new();
+        let mut descriptors = Vec::new();
+        for (path, mut interfaces) in objects {
+            if path.device_path() != Some(device_path) {
+                continue;
+            }
+            match path.kind() {
+                PathKind::Service => {
+                    let mut props = interfaces
+                        .remove("org.bluez.GattService1")
+                        .ok_or(Error::UnexpectedDbusReply)?;
+                    let service = GattService {
+                        path,
+                        uuid: props.take_uuid("UUID")?,
+                        primary: props.take_bool("Primary").unwrap_or(true),
+                        characteristics: Vec::new(),
+                    };
+                    services.insert(path.service_handle(), service);
+                }
+                PathKind::Characteristic => {
+                    let props = interfaces
+                        .remove("org.bluez.GattCharacteristic1")
+                        .ok_or(Error::UnexpectedDbusReply)?;
+                    let characteristic = GattCharacteristic::from_properties(path, props)?;
+                    characteristics.insert(path, characteristic);
+                }
+                PathKind::Descriptor => {
+                    let mut props = interfaces
+                        .remove("org.bluez.GattDescriptor1")
+                        .ok_or(Error::UnexpectedDbusReply)?;
+                    let uuid = props.take_uuid("UUID")?;
+                    descriptors.push(GattDescriptor { path, uuid });
+                }
+                _ => (),
+            }
+        }
+
+        descriptors.sort_by_key(|d| d.path.descriptor_handle());
+        for descriptor in descriptors {
+            let parent = descriptor.path.characteristic_path();
+            if let Some(characteristic) = characteristics.get_mut(&parent) {
+                characteristic.descriptors.push(descriptor);
+            }
+        }
+        let mut characteristics: Vec<_> = characteristics.into_values().collect();
+        characteristics.sort_by_key(|c| c.path.characteristic_handle());
+        for characteristic in characteristics {
+            let handle = characteristic.path.service_handle();
+            if let Some(service) = services.get_mut(&handle) {
+                service.characteristics.push(characteristic);
+            }
+        }
+        Ok(services.into_values().collect())
+    }
+
+    /// limit what is found during discovery, see `DiscoveryFilter`
+    #[allow(dead_code)]
+    pub fn set_discovery_filter(&mut self, filter: &DiscoveryFilter) -> Result<(), Error> {
+        let mut set_filter = MessageBuilder::new()
+            .call("SetDiscoveryFilter".into())
+            .at("org.bluez".into())
+            .on(self.adapter_path().into())
+            .with_interface("org.bluez.Adapter1".into())
+            .build();
+        set_filter.body.push_old_param(&filter.as_param())?;
+
+        let response_serial = self
+            .connection
+            .send_message(&mut set_filter, self.timeout)?;
+        let msg = self
+            .connection
+            .wait_response(response_serial, self.timeout)?;
+
+        match msg.typ {
+            rustbus::MessageType::Reply => Ok(()),
+            rustbus::MessageType::Error => Err(Error::from((msg, Context::SetDiscoveryFilter))),
+            _ => Err(Error::UnexpectedDbusReply),
+        }
+    }
+}
@@ -1,85 +1,169 @@
 use std::collections::HashMap;
-use std::os::unix::io::RawFd;
 
-pub use rustbus::client_conn::Timeout;
+use rustbus::message_builder::MarshalledMessage;
 use rustbus::params::message;
 use rustbus::{params, MessageBuilder};
 
+use crate::address::{Address, IntoAddress};
 use crate::dbus_helpers::*;
 use crate::error::{Context, Error};
+use crate::notification::Notification;
+use crate::operations::options::Wait;
+use crate::operations::CallOptions;
+use crate::path::{BluezPath, PathKind};
+use crate::uuid::{IntoUuid, Uuid};
 use crate::Ble;
 
 impl Ble {
     #[allow(dead_code)]
     pub fn read(
         &mut self,
-        adress: impl Into<String>,
-        uuid: impl AsRef<str>,
+        adress: impl IntoAddress,
+        uuid: impl IntoUuid,
     ) -> Result<Vec<u8>, Error> {
+        self.read_with(adress, uuid, &CallOptions::default())
+    }
+
+    /// read with a timeout and/or cancel token of its own
+    #[allow(dead_code)]
+    pub fn read_with(
+        &mut self,
+        adress: impl IntoAddress,
+        uuid: impl IntoUuid,
+        options: &CallOptions,
+    ) -> Result<Vec<u8>, Error> {
+        let adress = adress.into_address()?;
+        let uuid = uuid.into_uuid()?;
+        // the lookup counts against the wait as well
+        let wait = self.start_wait(options, Context::ReadValue(uuid));
         let char_path = self
-            .path_for_char(adress, &uuid)?
-            .ok_or_else(|| Error::CharacteristicNotFound(Context::ReadValue(
-                uuid.as_ref().to_owned(),
-            )))?;
+            .path_for_char(adress, uuid, &wait)?
+            .ok_or(Error::CharacteristicNotFound(Context::ReadValue(uuid)))?;
 
-        let mut read = MessageBuilder::new()
-            .call("ReadValue".into())
-            .at("org.bluez".into())
-            .on(char_path)
-            .with_interface("org.bluez.GattCharacteristic1".into()) //is always GattCharacteristic1
-            .build();
+        let mut read = read_value_call(char_path)?;
+        let response_serial = self.connection.send_message(&mut read, self.timeout)?;
+        let reply = self.wait_reply(response_serial, &wait)?;
+        value_from_reply(reply, uuid)
+    }
 
-        let param = empty_options_param();
-        read.body.push_old_param(&param)?;
+    /// read several characteristics of a device at once. Their paths are
+    /// looked up in one go and all reads are sent before waiting for the
+    /// replies. Returns the value or error for every uuid, in the same order.
+    #[allow(dead_code)]
+    pub fn read_many<U: IntoUuid + Copy>(
+        &mut self,
+        adress: impl IntoAddress,
+        uuids: &[U],
+    ) -> Result<Vec<Result<Vec<u8>, Error>>, Error> {
+        self.read_many_with(adress, uuids, &CallOptions::default())
+    }
 
-        let response_serial = self.connection.send_message(&mut read, self.timeout)?;
-        let reply = self
-            .connection
-            .wait_response(response_serial, self.timeout)?
-            .unmarshall_all()?;
+    /// read_many with a timeout and/or cancel token of its own, reads that
+    /// did not finish in time each fail with `Error::TimedOut`
+    #[allow(dead_code)]
+    pub fn read_many_with<U: IntoUuid + Copy>(
+        &mut self,
+        adress: impl IntoAddress,
+        uuids: &[U],
+        options: &CallOptions,
+    ) -> Result<Vec<Result<Vec<u8>, Error>>, Error> {
+        let adress = adress.into_address()?;
+        let uuids = uuids
+            .iter()
+            .map(|uuid| uuid.into_uuid())
+            .collect::<Result<Vec<Uuid>, Error>>()?;
+        if uuids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let wait = self.start_wait(options, Context::ReadValue(uuids[0]));
+        let paths = self.paths_for_chars(adress, &uuids, &wait)?;
 
-        match &reply.typ {
-            rustbus::MessageType::Error => {
-                return Err(Error::from((
-                    reply,
-                    Context::ReadValue(uuid.as_ref().to_owned()),
-                )))
+        // the serial of every read in flight, results as they come in
+        let mut serials = Vec::with_capacity(uuids.len());
+        let mut results = Vec::with_capacity(uuids.len());
+        for uuid in &uuids {
+            match paths.get(uuid) {
+                Some(path) => {
+                    let mut read = read_value_call(*path)?;
+                    let serial = self.connection.send_message(&mut read, self.timeout)?;
+                    serials.push(Some(serial));
+                    results.push(None);
+                }
+                None => {
+                    let not_found = Error::CharacteristicNotFound(Context::ReadValue(*uuid));
+                    serials.push(None);
+                    results.push(Some(Err(not_found)));
+                }
             }
-            rustbus::MessageType::Reply => (),
-            _ => return Err(Error::UnexpectedDbusReply),
         }
 
-        let mut params = reply.params;
-        let param = params.pop().ok_or(Error::UnexpectedDbusReply)?;
-        let container = unwrap_container(param).ok_or(Error::UnexpectedDbusReply)?;
-        let array = unwrap_array(container).ok_or(Error::UnexpectedDbusReply)?;
+        // reads still missing at the deadline each time out on their own
+        let collected = self.wait_until(&wait, |ble| {
+            for ((serial, uuid), result) in serials.iter().zip(&uuids).zip(&mut results) {
+                let serial = match (serial, &result) {
+                    (Some(serial), None) => *serial,
+                    _ => continue,
+                };
+                if let Some(reply) = ble.connection.try_get_response(serial) {
+                    *result = Some(value_from_reply(reply, *uuid));
+                }
+            }
+            Ok(if results.iter().all(Option::is_some) {
+                Some(())
+            } else {
+                None
+            })
+        });
+        // replies to the reads we gave up on are dropped as they arrive
+        if collected.is_err() {
+            for (serial, result) in serials.iter().zip(&results) {
+                if let (Some(serial), None) = (serial, result) {
+                    self.connection.abandon(*serial);
+                }
+            }
+        }
+        match collected {
+            Ok(()) | Err(Error::TimedOut(_)) => (),
+            Err(e) => return Err(e),
+        }
 
-        let data: Vec<u8> = array
-            .values
+        Ok(uuids
             .into_iter()
-            .map(|param| param.into_byte())
-            .collect::<Result<Vec<u8>, _>>()
-            .map_err(|_| Error::UnexpectedDbusReply)?;
-        Ok(data)
+            .zip(results)
+            .map(|(uuid, result)| result.unwrap_or(Err(Error::TimedOut(Context::ReadValue(uuid)))))
+            .collect())
     }
 
     #[allow(dead_code)]
     pub fn write(
         &mut self,
-        adress: impl Into<String>,
-        uuid: impl AsRef<str>,
+        adress: impl IntoAddress,
+        uuid: impl IntoUuid,
+        data: impl AsRef<[u8]>,
+    ) -> Result<(), Error> {
+        self.write_with(adress, uuid, data, &CallOptions::default())
+    }
+
+    /// write with a timeout and/or cancel token of its own
+    #[allow(dead_code)]
+    pub fn write_with(
+        &mut self,
+        adress: impl IntoAddress,
+        uuid: impl IntoUuid,
         data: impl AsRef<[u8]>,
+        call_options: &CallOptions,
     ) -> Result<(), Error> {
+        let adress = adress.into_address()?;
+        let uuid = uuid.into_uuid()?;
+        let wait = self.start_wait(call_options, Context::WriteValue(uuid));
         let char_path = self
-            .path_for_char(adress, &uuid)?
-            .ok_or_else(|| Error::CharacteristicNotFound(Context::WriteValue(
-                uuid.as_ref().to_owned(),
-            )))?;
+            .path_for_char(adress, uuid, &wait)?
+            .ok_or(Error::CharacteristicNotFound(Context::WriteValue(uuid)))?;
 
         let mut write = MessageBuilder::new()
             .call("WriteValue".into())
             .at("org.bluez".into())
-            .on(char_path)
+            .on(char_path.into())
             .with_interface("org.bluez.GattCharacteristic1".into()) //is always GattCharacteristic1
             .build();
 
@@ -89,15 +173,14 @@ impl Ble {
 
         let response_serial = self.connection.send_message(&mut write, self.timeout)?;
         let reply = self
-            .connection
-            .wait_response(response_serial, self.timeout)?
+            .wait_reply(response_serial, &wait)?
             .unmarshall_all()?;
 
         match &reply.typ {
             rustbus::MessageType::Error => {
                 return Err(Error::from((
                     reply,
-                    Context::WriteValue(uuid.as_ref().to_owned()),
+                    Context::WriteValue(uuid),
                 )));
             }
             rustbus::MessageType::Reply => (),
@@ -109,19 +192,31 @@ impl Ble {
     #[allow(dead_code)]
     pub fn notify(
         &mut self,
-        adress: impl Into<String>,
-        uuid: impl AsRef<str>,
-    ) -> Result<RawFd, Error> {
+        adress: impl IntoAddress,
+        uuid: impl IntoUuid,
+    ) -> Result<Notification, Error> {
+        self.notify_with(adress, uuid, &CallOptions::default())
+    }
+
+    /// acquire notify with a timeout and/or cancel token of its own
+    #[allow(dead_code)]
+    pub fn notify_with(
+        &mut self,
+        adress: impl IntoAddress,
+        uuid: impl IntoUuid,
+        options: &CallOptions,
+    ) -> Result<Notification, Error> {
+        let adress = adress.into_address()?;
+        let uuid = uuid.into_uuid()?;
+        let wait = self.start_wait(options, Context::AquireNotify(uuid));
         let char_path = self
-            .path_for_char(adress, &uuid)?
-            .ok_or_else(|| Error::CharacteristicNotFound(Context::AquireNotify(
-                uuid.as_ref().to_owned(),
-            )))?;
+            .path_for_char(adress, uuid, &wait)?
+            .ok_or(Error::CharacteristicNotFound(Context::AquireNotify(uuid)))?;
 
         let mut aquire_notify = MessageBuilder::new()
             .call("AcquireNotify".into())
             .at("org.bluez".into())
-            .on(char_path)
+            .on(char_path.into())
             .with_interface("org.bluez.GattCharacteristic1".into()) //is always GattCharacteristic1
             .build();
 
@@ -132,15 +227,14 @@ impl Ble {
             .connection
             .send_message(&mut aquire_notify, self.timeout)?;
         let reply = self
-            .connection
-            .wait_response(response_serial, self.timeout)?
+            .wait_reply(response_serial, &wait)?
             .unmarshall_all()?;
 
         match &reply.typ {
             rustbus::MessageType::Error => {
                 return Err(Error::from((
                     reply,
-                    Context::AquireNotify(uuid.as_ref().to_owned()),
+                    Context::AquireNotify(uuid),
                 )))
             }
             rustbus::MessageType::Reply => (),
@@ -157,69 +251,102 @@ impl Ble {
         let mtu = unwrap_u16(mtu).ok_or(Error::UnexpectedDbusReply)?;
 
         let fd = raw_fds.pop().ok_or(Error::NoFdReturned)?;
-        //let file = unsafe { File::from_raw_fd(raw_fd) };
-        Ok(fd)
+        let subscription = (adress, uuid);
+        if !self.notifications.contains(&subscription) {
+            self.notifications.push(subscription);
+        }
+        Ok(Notification::new(fd, adress, uuid, mtu))
+    }
+
+    /// the ATT MTU of the connection to the device, as bluez reports it on
+    /// the characteristic. Needs bluez 5.62 or newer.
+    #[allow(dead_code)]
+    pub fn mtu(&mut self, adress: impl IntoAddress, uuid: impl IntoUuid) -> Result<u16, Error> {
+        let adress = adress.into_address()?;
+        let uuid = uuid.into_uuid()?;
+        let wait = self.start_wait(&CallOptions::default(), Context::Mtu(uuid));
+        let char_path = self
+            .path_for_char(adress, uuid, &wait)?
+            .ok_or(Error::CharacteristicNotFound(Context::Mtu(uuid)))?;
+        let interface = "org.bluez.GattCharacteristic1";
+        let value = self.get_property_within(char_path, interface, "MTU", &wait)?;
+        unwrap_base(value)
+            .and_then(unwrap_u16)
+            .ok_or(Error::UnexpectedDbusReply)
     }
 
     fn path_for_char(
         &mut self,
-        adress: impl Into<String>,
-        char_uuid: impl AsRef<str>,
-    ) -> Result<Option<String>, Error> {
-        let mut get_paths = MessageBuilder::new()
-            .call("GetManagedObjects".into())
-            .at("org.bluez".into())
-            .on("/".into())
-            .with_interface("org.freedesktop.DBus.ObjectManager".into())
-            .build();
+        adress: Address,
+        char_uuid: Uuid,
+        wait: &Wait,
+    ) -> Result<Option<BluezPath>, Error> {
+        Ok(self
+            .paths_for_chars(adress, &[char_uuid], wait)?
+            .remove(&char_uuid))
+    }
 
-        let response_serial = self.connection.send_message(&mut get_paths, self.timeout)?;
-        let mut reply = self
-            .connection
-            .wait_response(response_serial, self.timeout)?
-            .unmarshall_all()?;
+    /// the paths of the characteristics of the device with these uuids,
+    /// from a single object scan. Missing ones are not found.
+    fn paths_for_chars(
+        &mut self,
+        adress: Address,
+        char_uuids: &[Uuid],
+        wait: &Wait,
+    ) -> Result<HashMap<Uuid, BluezPath>, Error> {
+        let device_path = self.device_path(adress);
+        let mut paths = HashMap::new();
+        for (path, mut interfaces) in self.managed_objects_within(wait)? {
+            if path.device_path() != Some(device_path) || path.kind() != PathKind::Characteristic
+            {
+                continue;
+            }
+            let gatt_char = interfaces
+                .get_mut("org.bluez.GattCharacteristic1")
+                .ok_or(Error::UnexpectedDbusReply)?;
+            let uuid = gatt_char.take_uuid("UUID")?;
+            if char_uuids.contains(&uuid) {
+                paths.entry(uuid).or_insert(path);
+            }
+        }
+        Ok(paths)
+    }
+}
 
-        let param = reply.params.pop().unwrap();
-        let container = unwrap_container(param).unwrap();
-        let dict = unwrap_dict(container).unwrap();
+fn read_value_call(char_path: BluezPath) -> Result<MarshalledMessage, Error> {
+    let mut read = MessageBuilder::new()
+        .call("ReadValue".into())
+        .at("org.bluez".into())
+        .on(char_path.into())
+        .with_interface("org.bluez.GattCharacteristic1".into()) //is always GattCharacteristic1
+        .build();
 
-        let device_path = format!(
-            "/org/bluez/hci{}/dev_{}",
-            self.adapter_numb,
-            adress.into().replace(":", "_")
-        );
+    let param = empty_options_param();
+    read.body.push_old_param(&param)?;
+    Ok(read)
+}
 
-        for (path, base) in dict
-            .into_iter()
-            .filter_map(unwrap_objectpath)
-            .filter(|(p, _)| p.contains(&device_path))
-            .filter(|(p, _)| p.contains("char"))
-            .filter(|(p, _)| !p.contains("desc"))
-        {
-            let container = unwrap_container(base).unwrap();
-            let mut dict = unwrap_dict(container).unwrap();
-            let gatt_char = dict
-                .remove(&params::Base::String(
-                    "org.bluez.GattCharacteristic1".into(),
-                ))
-                .expect("char object path should always have GattCharacteristic1");
-            let gatt_char = unwrap_container(gatt_char).unwrap();
-            let mut gatt_char = unwrap_dict(gatt_char).unwrap();
-            let uuid = gatt_char
-                .remove(&params::Base::String("UUID".into()))
-                .expect("char object should always have a UUID");
-            let uuid = unwrap_container(uuid).unwrap();
-            let uuid = unwrap_variant(uuid).unwrap();
-            let uuid = uuid.value;
-            let uuid = unwrap_base(uuid).unwrap();
-            let uuid = unwrap_string(uuid).unwrap();
-
-            if uuid == char_uuid.as_ref() {
-                return Ok(Some(path));
-            }
-        }
-        Ok(None)
+/// the value ReadValue replied with
+fn value_from_reply(reply: MarshalledMessage, uuid: Uuid) -> Result<Vec<u8>, Error> {
+    let reply = reply.unmarshall_all()?;
+    match &reply.typ {
+        rustbus::MessageType::Error => return Err(Error::from((reply, Context::ReadValue(uuid)))),
+        rustbus::MessageType::Reply => (),
+        _ => return Err(Error::UnexpectedDbusReply),
     }
+
+    let mut params = reply.params;
+    let param = params.pop().ok_or(Error::UnexpectedDbusReply)?;
+    let container = unwrap_container(param).ok_or(Error::UnexpectedDbusReply)?;
+    let array = unwrap_array(container).ok_or(Error::UnexpectedDbusReply)?;
+
+    let data: Vec<u8> = array
+        .values
+        .into_iter()
+        .map(|param| param.into_byte())
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| Error::UnexpectedDbusReply)?;
+    Ok(data)
 }
 
 fn empty_options_param<'a, 'e>() -> rustbus::params::Param<'a, 'e> {
@@ -1,35 +1,53 @@
 //use std::fs::File;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
-pub use rustbus::client_conn::Timeout;
 use rustbus::message_builder::MarshalledMessage;
 use rustbus::wire::marshal::traits::ObjectPath;
-use rustbus::MessageBuilder;
+use rustbus::{params, MessageBuilder};
 
+use crate::address::IntoAddress;
 use crate::dbus_helpers::*;
 use crate::error::{Context, Error};
-use crate::Ble;
+use crate::operations::CallOptions;
+use crate::{Ble, AGENT_PATH};
+
+/// the largest passkey, pin codes from get_key are held to the same six digits
+const MAX_KEY: u32 = 999_999;
 
 impl Ble {
     #[allow(dead_code)]
-    pub fn connect(&mut self, adress: impl Into<String>) -> Result<(), Error> {
-        let adress = adress.into().replace(":", "_");
+    pub fn connect(&mut self, adress: impl IntoAddress) -> Result<(), Error> {
+        self.connect_with(adress, &CallOptions::default())
+    }
+
+    /// connect with a timeout and/or cancel token of its own. Bluez is told to
+    /// disconnect if we give up before the device is connected.
+    #[allow(dead_code)]
+    pub fn connect_with(
+        &mut self,
+        adress: impl IntoAddress,
+        options: &CallOptions,
+    ) -> Result<(), Error> {
+        let adress = adress.into_address()?;
+        let wait = self.start_wait(options, Context::Connect);
 
         let mut connect = MessageBuilder::new()
             .call("Connect".into())
             .at("org.bluez".into())
-            .on(format!(
-                "/org/bluez/hci{}/dev_{}",
-                self.adapter_numb, adress
-            ))
+            .on(self.device_path(adress).into())
             .with_interface("org.bluez.Device1".into()) //is always Device1
             .build();
 
         let response_serial = self.connection.send_message(&mut connect, self.timeout)?;
-        let msg = self
-            .connection
-            .wait_response(response_serial, self.timeout)
-            .map_err(|_| Error::CouldNotConnectToDevice)?;
+        let msg = match self.wait_reply(response_serial, &wait) {
+            Ok(msg) => msg,
+            Err(e @ Error::TimedOut(_)) | Err(e @ Error::Cancelled(_)) => {
+                // bluez keeps trying otherwise
+                let _ = self.disconnect(adress);
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        };
 
         match msg.typ {
             rustbus::MessageType::Reply => Ok(()),
@@ -40,95 +58,174 @@ impl Ble {
                     be awnserd with Error or Reply however we got: {:?}",
                     &msg
                 );
-                panic!(dbg_str);
+                panic!("{}", dbg_str);
             }
         }
     }
 
-    fn awnser_passkey(&mut self, messg: MarshalledMessage, get_key: impl Fn() -> u32) {
-        let passkey: u32 = get_key();
-        let mut response = messg.unmarshall_all().unwrap().make_response();
-        response.body.push_param(passkey).unwrap();
-        self.connection
-            .send_message(&mut response, self.timeout)
-            .unwrap();
+    /// answer a request bluez makes to our agent while we pair. Passkeys
+    /// and pin codes come from get_key, confirm gets the passkey to compare
+    /// or None when the device asks to pair without one. Services are not
+    /// authorized. A key of more than six digits is rejected and returned
+    /// as an error.
+    fn answer_agent(
+        &mut self,
+        call: MarshalledMessage,
+        get_key: &impl Fn() -> u32,
+        confirm: &impl Fn(Option<u32>) -> bool,
+    ) -> Result<(), Error> {
+        let header = call.dynheader.clone();
+        let rejected = error_response(&header, "org.bluez.Error.Rejected", "rejected");
+        let mut invalid_key = false;
+        let mut response = match header.member.as_deref() {
+            Some("RequestPasskey") => match get_key() {
+                key if key > MAX_KEY => {
+                    invalid_key = true;
+                    rejected
+                }
+                key => {
+                    let mut response = header.make_response();
+                    response.body.push_param(key)?;
+                    response
+                }
+            },
+            Some("RequestPinCode") => match get_key() {
+                key if key > MAX_KEY => {
+                    invalid_key = true;
+                    rejected
+                }
+                key => {
+                    let mut response = header.make_response();
+                    response.body.push_param(key.to_string().as_str())?;
+                    response
+                }
+            },
+            Some("RequestConfirmation") => {
+                let passkey = call
+                    .unmarshall_all()?
+                    .params
+                    .into_iter()
+                    .nth(1)
+                    .and_then(unwrap_base);
+                match passkey {
+                    Some(params::Base::Uint32(passkey)) if confirm(Some(passkey)) => {
+                        header.make_response()
+                    }
+                    _ => rejected,
+                }
+            }
+            Some("RequestAuthorization") if confirm(None) => header.make_response(),
+            Some("RequestAuthorization") | Some("AuthorizeService") => rejected,
+            Some("DisplayPasskey") | Some("DisplayPinCode") | Some("Cancel") | Some("Release") => {
+                header.make_response()
+            }
+            _ => error_response(&header, "org.bluez.Error.Rejected", "unknown request"),
+        };
+        self.connection.send_message(&mut response, self.timeout)?;
+        if invalid_key {
+            return Err(Error::InvalidValue(Context::Pair));
+        }
+        Ok(())
     }
 
-    /// This will time out if the device is already paired
+    /// Pair with the device, answering every request bluez makes to our
+    /// agent until pairing completes. Returns right away if the device is
+    /// already paired.
     #[allow(dead_code)]
     pub fn pair(
         &mut self,
-        adress: impl Into<String>,
+        adress: impl IntoAddress,
         get_key: impl Fn() -> u32,
         timeout: Duration,
     ) -> Result<(), Error> {
-        let adress = adress.into().replace(":", "_");
+        self.pair_with(adress, get_key, &CallOptions::new().with_timeout(timeout))
+    }
+
+    /// pair with a timeout and/or cancel token of its own. Pairing is
+    /// cancelled if we give up before it completes. Pairing without a
+    /// passkey is authorized as pairing was asked for, devices that ask to
+    /// compare passkeys are rejected, use `pair_confirming` for those.
+    #[allow(dead_code)]
+    pub fn pair_with(
+        &mut self,
+        adress: impl IntoAddress,
+        get_key: impl Fn() -> u32,
+        options: &CallOptions,
+    ) -> Result<(), Error> {
+        let confirm = |passkey: Option<u32>| passkey.is_none();
+        self.pair_confirming(adress, get_key, confirm, options)
+    }
+
+    /// pair with a device that may show a passkey for the user to compare
+    /// with ours. confirm gets the passkey and returns if they match, it
+    /// gets None when the device asks to pair without a passkey. Passkeys
+    /// and pin codes from get_key have at most six digits.
+    #[allow(dead_code)]
+    pub fn pair_confirming(
+        &mut self,
+        adress: impl IntoAddress,
+        get_key: impl Fn() -> u32,
+        confirm: impl Fn(Option<u32>) -> bool,
+        options: &CallOptions,
+    ) -> Result<(), Error> {
+        let adress = adress.into_address()?;
+        let wait = self.start_wait(options, Context::Pair);
 
         let mut connect = MessageBuilder::new()
             .call("Pair".into())
             .at("org.bluez".into())
-            .on(format!(
-                "/org/bluez/hci{}/dev_{}",
-                self.adapter_numb, adress
-            ))
+            .on(self.device_path(adress).into())
             .with_interface("org.bluez.Device1".into()) //is always Device1
             .build();
 
-        let response_serial = self
-            .connection
-            .send_message(&mut connect, self.timeout)
-            .unwrap();
-
-        let now = Instant::now();
-        loop {
-            let messg = self
-                .connection
-                .wait_call(Timeout::Duration(timeout))
-                .map_err(|e| match e {
-                    rustbus::client_conn::Error::TimedOut => {
-                        dbg!("test timeout");
-                        Error::PairingTimeOut
-                    }
-                    _ => e.into(),
-                })?;
-            if messg.dynheader.member == Some("RequestPasskey".into()) {
-                self.awnser_passkey(messg, get_key);
-                break;
+        let response_serial = self.connection.send_message(&mut connect, self.timeout)?;
+
+        // bluez may call the agent any number of times (or not at all)
+        // before it replies
+        let result = self.wait_until(&wait, |ble| {
+            while let Some(call) = ble.connection.try_get_call() {
+                if call.dynheader.object.as_deref() == Some(AGENT_PATH) {
+                    ble.answer_agent(call, &get_key, &confirm)?;
+                } else {
+                    ble.handle_call(call)?;
+                }
             }
-            if now.elapsed() > timeout {
-                return Err(Error::PairingTimeOut);
+            Ok(ble.connection.try_get_response(response_serial))
+        });
+        let msg = match result {
+            Ok(msg) => msg,
+            Err(e) => {
+                // bluez is left pairing otherwise
+                self.connection.abandon(response_serial);
+                let _ = self.cancel_pairing(adress);
+                return Err(e);
             }
-        }
-
-        let msg = self
-            .connection
-            .wait_response(response_serial, Timeout::Duration(timeout))
-            .unwrap();
+        };
 
         match msg.typ {
             rustbus::MessageType::Reply => Ok(()),
-            rustbus::MessageType::Error => Err(Error::from((msg, Context::Pair))),
+            rustbus::MessageType::Error => match Error::from((msg, Context::Pair)) {
+                Error::AlreadyExists(_) => Ok(()),
+                e => Err(e),
+            },
             _ => {
                 let dbg_str = format!(
                     "Unexpected Dbus message, Pair should only 
                     be awnserd with Error or Reply however we got: {:?}",
                     &msg
                 );
-                panic!(dbg_str);
+                panic!("{}", dbg_str);
             }
         }
     }
 
     #[allow(dead_code)]
-    pub fn is_paired(&mut self, adress: impl Into<String>) -> Result<bool, Error> {
-        let adress = adress.into().replace(":", "_");
+    pub fn is_paired(&mut self, adress: impl IntoAddress) -> Result<bool, Error> {
+        let adress = adress.into_address()?;
         let mut is_paired = MessageBuilder::new()
             .call("Get".into())
             .at("org.bluez".into())
-            .on(format!(
-                "/org/bluez/hci{}/dev_{}",
-                self.adapter_numb, &adress
-            ))
+            .on(self.device_path(adress).into())
             .with_interface("org.freedesktop.DBus.Properties".into())
             .build();
         is_paired.body.push_param("org.bluez.Device1")?;
@@ -151,15 +248,12 @@ impl Ble {
     }
 
     #[allow(dead_code)]
-    pub fn is_connected(&mut self, adress: impl Into<String>) -> Result<bool, Error> {
-        let adress = adress.into().replace(":", "_");
+    pub fn is_connected(&mut self, adress: impl IntoAddress) -> Result<bool, Error> {
+        let adress = adress.into_address()?;
         let mut is_connected = MessageBuilder::new()
             .call("Get".into())
             .at("org.bluez".into())
-            .on(format!(
-                "/org/bluez/hci{}/dev_{}",
-                self.adapter_numb, &adress
-            ))
+            .on(self.device_path(adress).into())
             .with_interface("org.freedesktop.DBus.Properties".into())
             .build();
         is_connected.body.push_param("org.bluez.Device1")?;
@@ -184,16 +278,13 @@ impl Ble {
     }
 
     #[allow(dead_code)]
-    pub fn disconnect(&mut self, adress: impl Into<String>) -> Result<(), Error> {
-        let adress = adress.into().replace(":", "_");
+    pub fn disconnect(&mut self, adress: impl IntoAddress) -> Result<(), Error> {
+        let adress = adress.into_address()?;
 
         let mut connect = MessageBuilder::new()
             .call("Disconnect".into())
             .at("org.bluez".into())
-            .on(format!(
-                "/org/bluez/hci{}/dev_{}",
-                self.adapter_numb, adress
-            ))
+            .on(self.device_path(adress).into())
             .with_interface("org.bluez.Device1".into()) //is always Device1
             .build();
 
@@ -211,19 +302,43 @@ impl Ble {
                     with Error or Reply however we got: {:?}",
                     &msg
                 );
-                panic!(dbg_str);
+                panic!("{}", dbg_str);
             }
         }
     }
 
+    /// stop a pairing that is in progress, its Pair call fails
+    #[allow(dead_code)]
+    pub fn cancel_pairing(&mut self, adress: impl IntoAddress) -> Result<(), Error> {
+        let adress = adress.into_address()?;
+
+        let mut cancel = MessageBuilder::new()
+            .call("CancelPairing".into())
+            .at("org.bluez".into())
+            .on(self.device_path(adress).into())
+            .with_interface("org.bluez.Device1".into()) //is always Device1
+            .build();
+
+        let response_serial = self.connection.send_message(&mut cancel, self.timeout)?;
+        let msg = self
+            .connection
+            .wait_response(response_serial, self.timeout)?;
+
+        match msg.typ {
+            rustbus::MessageType::Reply => Ok(()),
+            rustbus::MessageType::Error => Err(Error::from((msg, Context::CancelPairing))),
+            _ => Err(Error::UnexpectedDbusReply),
+        }
+    }
+
     #[allow(dead_code)]
-    pub fn remove(&mut self, adress: impl Into<String>) -> Result<(), Error> {
-        let object_path = format!("/org/bluez/hci0/dev_{}", adress.into().replace(":", "_"));
+    pub fn remove(&mut self, adress: impl IntoAddress) -> Result<(), Error> {
+        let object_path = self.device_path(adress.into_address()?).to_string();
         let object_path = ObjectPath::new(&object_path).unwrap();
         let mut remove = MessageBuilder::new()
             .call("RemoveDevice".into())
             .at("org.bluez".into())
-            .on(format!("/org/bluez/hci{}", self.adapter_numb))
+            .on(self.adapter_path().into())
             .with_interface("org.bluez.Adapter1".into()) //is always Device1
             .build();
         remove.body.push_param(object_path)?;
@@ -242,7 +357,7 @@ impl Ble {
                     with Error or Reply however we got: {:?}",
                     &msg
                 );
-                panic!(dbg_str);
+                panic!("{}", dbg_str);
             }
         }
     }
@@ -252,7 +367,7 @@ impl Ble {
         let mut remove = MessageBuilder::new()
             .call("StartDiscovery".into())
             .at("org.bluez".into())
-            .on(format!("/org/bluez/hci{}", self.adapter_numb))
+            .on(self.adapter_path().into())
             .with_interface("org.bluez.Adapter1".into()) //is always Device1
             .build();
 
@@ -270,7 +385,7 @@ impl Ble {
                     with Error or Reply however we got: {:?}",
                     &msg
                 );
-                panic!(dbg_str);
+                panic!("{}", dbg_str);
             }
         }
     }
@@ -280,7 +395,7 @@ impl Ble {
         let mut remove = MessageBuilder::new()
             .call("StopDiscovery".into())
             .at("org.bluez".into())
-            .on(format!("/org/bluez/hci{}", self.adapter_numb))
+            .on(self.adapter_path().into())
             .with_interface("org.bluez.Adapter1".into()) //is always Device1
             .build();
 
@@ -298,7 +413,7 @@ impl Ble {
                     with Error or Reply however we got: {:?}",
                     &msg
                 );
-                panic!(dbg_str);
+                panic!("{}", dbg_str);
             }
         }
     }
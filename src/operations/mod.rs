@@ -1,18 +1,145 @@
+mod adapter;
 mod characteristic;
 mod device;
+mod options;
+use options::Wait;
+pub use options::{CallOptions, CancelToken};
 
-use crate::Ble;
+use crate::dbus_helpers::*;
+use crate::error::{Context, Error};
+use crate::info::{interfaces_from_param, Interfaces};
+use crate::{Address, Ble, BluezPath};
 use rustbus::client_conn::Timeout;
+use rustbus::message_builder::MarshalledMessage;
+use rustbus::params::Param;
+use rustbus::MessageBuilder;
 
 impl Ble {
+    pub(crate) fn adapter_path(&self) -> BluezPath {
+        BluezPath::adapter(self.adapter_numb)
+    }
+
+    pub(crate) fn device_path(&self, adress: Address) -> BluezPath {
+        self.adapter_path().device(adress)
+    }
+
+    /// get a property of a bluez object, returns the value inside the variant
+    pub(crate) fn get_property(
+        &mut self,
+        path: BluezPath,
+        interface: &str,
+        property: &str,
+        context: Context,
+    ) -> Result<Param<'static, 'static>, Error> {
+        let response_serial = self.send_get_property(path, interface, property)?;
+        let reply = self
+            .connection
+            .wait_response(response_serial, self.timeout)?;
+        property_from_reply(reply, context)
+    }
+
+    /// get_property bounded by the wait of the operation that needs it
+    pub(crate) fn get_property_within(
+        &mut self,
+        path: BluezPath,
+        interface: &str,
+        property: &str,
+        wait: &Wait,
+    ) -> Result<Param<'static, 'static>, Error> {
+        let response_serial = self.send_get_property(path, interface, property)?;
+        let reply = self.wait_reply(response_serial, wait)?;
+        property_from_reply(reply, wait.context())
+    }
+
+    fn send_get_property(
+        &mut self,
+        path: BluezPath,
+        interface: &str,
+        property: &str,
+    ) -> Result<u32, Error> {
+        let mut get = MessageBuilder::new()
+            .call("Get".into())
+            .at("org.bluez".into())
+            .on(path.into())
+            .with_interface("org.freedesktop.DBus.Properties".into())
+            .build();
+        get.body.push_param2(interface, property)?;
+        self.connection.send_message(&mut get, self.timeout)
+    }
+
+    /// every bluez object below an adapter with its interfaces, the
+    /// result of ObjectManager.GetManagedObjects
+    pub(crate) fn managed_objects(&mut self) -> Result<Vec<(BluezPath, Interfaces)>, Error> {
+        let response_serial = self.send_get_managed_objects()?;
+        let reply = self
+            .connection
+            .wait_response(response_serial, self.timeout)?;
+        objects_from_reply(reply)
+    }
+
+    /// managed_objects bounded by the wait of the operation that needs them
+    pub(crate) fn managed_objects_within(
+        &mut self,
+        wait: &Wait,
+    ) -> Result<Vec<(BluezPath, Interfaces)>, Error> {
+        let response_serial = self.send_get_managed_objects()?;
+        let reply = self.wait_reply(response_serial, wait)?;
+        objects_from_reply(reply)
+    }
+
+    fn send_get_managed_objects(&mut self) -> Result<u32, Error> {
+        let mut get_paths = MessageBuilder::new()
+            .call("GetManagedObjects".into())
+            .at("org.bluez".into())
+            .on("/".into())
+            .with_interface("org.freedesktop.DBus.ObjectManager".into())
+            .build();
+        self.connection.send_message(&mut get_paths, self.timeout)
+    }
+
+    /// wait for signals forever, with the `tracing` feature every signal is
+    /// logged. Only useful while debugging.
     pub fn listen_dbus(&mut self) {
         loop {
-            let messg = self
-                .connection
-                .wait_signal(Timeout::Infinite) //wait_call(Timeout::Infinite)
-                .unwrap();
+            self.connection.wait_signal(Timeout::Infinite).unwrap();
+        }
+    }
+}
+
+fn property_from_reply(
+    reply: MarshalledMessage,
+    context: Context,
+) -> Result<Param<'static, 'static>, Error> {
+    let reply = reply.unmarshall_all()?;
+    match reply.typ {
+        rustbus::MessageType::Reply => (),
+        rustbus::MessageType::Error => return Err(Error::from((reply, context))),
+        _ => return Err(Error::UnexpectedDbusReply),
+    }
+    let param = reply.params.into_iter().next();
+    let container = param
+        .and_then(unwrap_container)
+        .ok_or(Error::UnexpectedDbusReply)?;
+    let variant = unwrap_variant(container).ok_or(Error::UnexpectedDbusReply)?;
+    Ok(variant.value)
+}
+
+fn objects_from_reply(reply: MarshalledMessage) -> Result<Vec<(BluezPath, Interfaces)>, Error> {
+    let mut reply = reply.unmarshall_all()?;
+    if !matches!(reply.typ, rustbus::MessageType::Reply) {
+        return Err(Error::UnexpectedDbusReply);
+    }
 
-            dbg!(messg.unmarshall_all().unwrap());
+    let param = reply.params.pop().ok_or(Error::UnexpectedDbusReply)?;
+    let dict = unwrap_container(param)
+        .and_then(unwrap_dict)
+        .ok_or(Error::UnexpectedDbusReply)?;
+    let mut objects = Vec::new();
+    for (path, interfaces) in dict.into_iter().filter_map(unwrap_objectpath) {
+        // skips /org/bluez which is not below an adapter
+        if let Ok(path) = path.parse::<BluezPath>() {
+            objects.push((path, interfaces_from_param(interfaces)?));
         }
     }
+    Ok(objects)
 }
@@ -0,0 +1,145 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rustbus::client_conn::Timeout;
+use rustbus::message_builder::MarshalledMessage;
+
+use crate::error::{Context, Error};
+use crate::Ble;
+
+/// how often a wait checks if it was cancelled
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Aborts blocking operations it is passed to, from any thread. Once
+/// cancelled it stays cancelled, use a new token for the next operation.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Options for a single operation such as `Ble::connect_with`, anything not
+/// set uses the defaults from the `BleBuilder`.
+#[derive(Debug, Clone, Default)]
+pub struct CallOptions {
+    timeout: Option<Duration>,
+    cancel: Option<CancelToken>,
+}
+
+impl CallOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// give up with `Error::TimedOut` after this long
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// give up with `Error::Cancelled` once the token is cancelled
+    pub fn with_cancel(mut self, token: &CancelToken) -> Self {
+        self.cancel = Some(token.clone());
+        self
+    }
+}
+
+/// A wait for bluez that ends at a deadline or when cancelled
+pub(crate) struct Wait {
+    deadline: Option<Instant>,
+    cancel: Option<CancelToken>,
+    context: Context,
+}
+
+impl Wait {
+    pub(crate) fn context(&self) -> Context {
+        self.context.clone()
+    }
+
+    fn check(&self) -> Result<Option<Duration>, Error> {
+        if self
+            .cancel
+            .as_ref()
+            .map_or(false, CancelToken::is_cancelled)
+        {
+            return Err(Error::Cancelled(self.context.clone()));
+        }
+        let left = match self.deadline {
+            Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                Some(left) if !left.is_zero() => Some(left),
+                _ => return Err(Error::TimedOut(self.context.clone())),
+            },
+            None => None,
+        };
+        Ok(match (left, &self.cancel) {
+            (Some(left), Some(_)) => Some(left.min(POLL_INTERVAL)),
+            (None, Some(_)) => Some(POLL_INTERVAL),
+            (left, None) => left,
+        })
+    }
+}
+
+impl Ble {
+    pub(crate) fn start_wait(&self, options: &CallOptions, context: Context) -> Wait {
+        let deadline = match (options.timeout, self.timeout) {
+            (Some(timeout), _) | (None, Timeout::Duration(timeout)) => Some(timeout),
+            (None, Timeout::Nonblock) => Some(Duration::from_secs(0)),
+            (None, Timeout::Infinite) => None,
+        }
+        .map(|timeout| Instant::now() + timeout);
+        Wait {
+            deadline,
+            cancel: options.cancel.clone(),
+            context,
+        }
+    }
+
+    /// call poll until it returns something, reading messages in between.
+    /// Ends with `Error::TimedOut` or `Error::Cancelled` as the wait says.
+    pub(crate) fn wait_until<T>(
+        &mut self,
+        wait: &Wait,
+        mut poll: impl FnMut(&mut Ble) -> Result<Option<T>, Error>,
+    ) -> Result<T, Error> {
+        loop {
+            if let Some(done) = poll(self)? {
+                return Ok(done);
+            }
+            let slice = wait.check()?;
+            match self
+                .connection
+                .refill_once(slice.map_or(Timeout::Infinite, Timeout::Duration))
+            {
+                // nothing arrived in this slice, check the wait again
+                Err(Error::DbusConnectionError(rustbus::client_conn::Error::TimedOut)) => (),
+                result => result?,
+            }
+        }
+    }
+
+    /// wait for the reply to the call with this serial, if we give up on it
+    /// the reply is dropped when it arrives
+    pub(crate) fn wait_reply(
+        &mut self,
+        serial: u32,
+        wait: &Wait,
+    ) -> Result<MarshalledMessage, Error> {
+        let result = self.wait_until(wait, |ble| Ok(ble.connection.try_get_response(serial)));
+        if result.is_err() {
+            self.connection.abandon(serial);
+        }
+        result
+    }
+}
@@ -0,0 +1,198 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::address::Address;
+use crate::error::Error;
+
+/// What kind of bluez object a BluezPath points to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PathKind {
+    Adapter,
+    Device,
+    Service,
+    Characteristic,
+    Descriptor,
+}
+
+/// A bluez object path such as
+/// `/org/bluez/hci0/dev_0A_0A_0A_0A_0A_0A/service000a/char000b/desc000d`.
+/// Build one starting from an adapter:
+/// `BluezPath::adapter(0).device(adress).service(0x0a).characteristic(0x0b)`
+/// or parse one from a string. Every part is compared structurally so a
+/// device path never matches the path of another device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BluezPath {
+    adapter: u8,
+    device: Option<Address>,
+    service: Option<u16>,
+    characteristic: Option<u16>,
+    descriptor: Option<u16>,
+}
+
+impl BluezPath {
+    pub fn adapter(adapter_numb: u8) -> Self {
+        BluezPath {
+            adapter: adapter_numb,
+            device: None,
+            service: None,
+            characteristic: None,
+            descriptor: None,
+        }
+    }
+
+    pub fn device(mut self, adress: Address) -> Self {
+        self.device = Some(adress);
+        self
+    }
+
+    /// only valid on a device path, panics otherwise
+    pub fn service(mut self, handle: u16) -> Self {
+        assert!(self.device.is_some(), "a service belongs to a device");
+        self.service = Some(handle);
+        self
+    }
+
+    /// only valid on a service path, panics otherwise
+    pub fn characteristic(mut self, handle: u16) -> Self {
+        assert!(
+            self.service.is_some(),
+            "a characteristic belongs to a service"
+        );
+        self.characteristic = Some(handle);
+        self
+    }
+
+    /// only valid on a characteristic path, panics otherwise
+    pub fn descriptor(mut self, handle: u16) -> Self {
+        assert!(
+            self.characteristic.is_some(),
+            "a descriptor belongs to a characteristic"
+        );
+        self.descriptor = Some(handle);
+        self
+    }
+
+    pub fn kind(&self) -> PathKind {
+        if self.descriptor.is_some() {
+            PathKind::Descriptor
+        } else if self.characteristic.is_some() {
+            PathKind::Characteristic
+        } else if self.service.is_some() {
+            PathKind::Service
+        } else if self.device.is_some() {
+            PathKind::Device
+        } else {
+            PathKind::Adapter
+        }
+    }
+
+    pub fn adapter_numb(&self) -> u8 {
+        self.adapter
+    }
+
+    pub fn device_adress(&self) -> Option<Address> {
+        self.device
+    }
+
+    pub fn service_handle(&self) -> Option<u16> {
+        self.service
+    }
+
+    pub fn characteristic_handle(&self) -> Option<u16> {
+        self.characteristic
+    }
+
+    pub fn descriptor_handle(&self) -> Option<u16> {
+        self.descriptor
+    }
+
+    /// the path of the adapter this object belongs to
+    pub fn adapter_path(&self) -> BluezPath {
+        BluezPath::adapter(self.adapter)
+    }
+
+    /// the path of the device this object belongs to, if any
+    pub fn device_path(&self) -> Option<BluezPath> {
+        Some(self.adapter_path().device(self.device?))
+    }
+
+    /// the path of the characteristic this descriptor belongs to, on any
+    /// other path the parts below the characteristic are dropped
+    pub fn characteristic_path(&self) -> BluezPath {
+        BluezPath {
+            descriptor: None,
+            ..*self
+        }
+    }
+}
+
+fn parse_handle(part: &str, prefix: &str) -> Option<u16> {
+    let digits = part.strip_prefix(prefix)?;
+    // from_str_radix would also take a sign
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    u16::from_str_radix(digits, 16).ok()
+}
+
+impl FromStr for BluezPath {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidPath(s.to_owned());
+        let rest = s.strip_prefix("/org/bluez/").ok_or_else(invalid)?;
+        let mut parts = rest.split('/');
+
+        let adapter = parts
+            .next()
+            .and_then(|p| p.strip_prefix("hci"))
+            .filter(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|n| n.parse().ok())
+            .ok_or_else(invalid)?;
+        let mut path = BluezPath::adapter(adapter);
+
+        if let Some(part) = parts.next() {
+            let adress = part.strip_prefix("dev_").ok_or_else(invalid)?;
+            path.device = Some(adress.parse().map_err(|_| invalid())?);
+        }
+        if let Some(part) = parts.next() {
+            path.service = Some(parse_handle(part, "service").ok_or_else(invalid)?);
+        }
+        if let Some(part) = parts.next() {
+            path.characteristic = Some(parse_handle(part, "char").ok_or_else(invalid)?);
+        }
+        if let Some(part) = parts.next() {
+            path.descriptor = Some(parse_handle(part, "desc").ok_or_else(invalid)?);
+        }
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(path)
+    }
+}
+
+impl fmt::Display for BluezPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "/org/bluez/hci{}", self.adapter)?;
+        if let Some(adress) = self.device {
+            write!(f, "/dev_{}", adress.dbus_fragment())?;
+        }
+        if let Some(handle) = self.service {
+            write!(f, "/service{:04x}", handle)?;
+        }
+        if let Some(handle) = self.characteristic {
+            write!(f, "/char{:04x}", handle)?;
+        }
+        if let Some(handle) = self.descriptor {
+            write!(f, "/desc{:04x}", handle)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<BluezPath> for String {
+    fn from(path: BluezPath) -> String {
+        path.to_string()
+    }
+}
@@ -0,0 +1,517 @@
+//! Classic bluetooth profiles such as the serial port profile (SPP) using
+//! ProfileManager1. Bluez sets up the RFCOMM (or L2CAP) link and hands us
+//! the connected socket. Register a `Profile`, incoming connections arrive
+//! as `Event::ProfileConnected` after which the stream is taken with
+//! `Ble::take_profile_connection`. Outgoing links are made with
+//! `Ble::connect_profile`.
+//!
+//! ```no_run
+//! use std::io::Write;
+//! use bluebus::profile::Profile;
+//! use bluebus::BleBuilder;
+//!
+//! let mut ble = BleBuilder::default().build().unwrap();
+//! let id = ble.register_profile(Profile::serial_port()).unwrap();
+//! let mut connection = ble.connect_profile("0A:0A:0A:0A:0A:0A", id).unwrap();
+//! connection.stream.write_all(b"AT\r\n").unwrap();
+//! ```
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+
+use nix::sys::socket::{shutdown, Shutdown};
+use rustbus::message_builder::MarshalledMessage;
+use rustbus::params::{self, Param};
+use rustbus::MessageBuilder;
+
+use crate::address::{Address, IntoAddress};
+use crate::dbus_helpers::*;
+use crate::error::{Context, Error};
+use crate::info::Properties;
+use crate::path::{BluezPath, PathKind};
+use crate::uuid::Uuid;
+use crate::{Ble, Event};
+
+/// all profiles are exported below this path
+pub(crate) const ROOT: &str = "/bluebus/profile";
+
+const MANAGER_IFACE: &str = "org.bluez.ProfileManager1";
+
+/// the uuid of the serial port profile
+pub const SERIAL_PORT: Uuid = Uuid::from_u16(0x1101);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// only make outgoing connections
+    Client,
+    /// only accept incoming connections
+    Server,
+}
+
+/// A profile to register with `Ble::register_profile`, options that are not
+/// set are left to bluez which knows good defaults for well known profiles.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    uuid: Uuid,
+    name: Option<String>,
+    role: Option<Role>,
+    channel: Option<u16>,
+    psm: Option<u16>,
+    require_authentication: Option<bool>,
+    require_authorization: Option<bool>,
+    auto_connect: Option<bool>,
+    service_record: Option<String>,
+    version: Option<u16>,
+    features: Option<u16>,
+}
+
+impl Profile {
+    pub fn new(uuid: Uuid) -> Self {
+        Profile {
+            uuid,
+            name: None,
+            role: None,
+            channel: None,
+            psm: None,
+            require_authentication: None,
+            require_authorization: None,
+            auto_connect: None,
+            service_record: None,
+            version: None,
+            features: None,
+        }
+    }
+
+    /// the serial port profile, bluez picks the channel and SDP record
+    pub fn serial_port() -> Self {
+        Self::new(SERIAL_PORT).with_name("Serial Port")
+    }
+
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn with_role(mut self, role: Role) -> Self {
+        self.role = Some(role);
+        self
+    }
+
+    /// the RFCOMM channel to listen on
+    pub fn with_channel(mut self, channel: u16) -> Self {
+        self.channel = Some(channel);
+        self
+    }
+
+    /// use L2CAP on this PSM instead of RFCOMM
+    pub fn with_psm(mut self, psm: u16) -> Self {
+        self.psm = Some(psm);
+        self
+    }
+
+    /// require the link to be paired
+    pub fn require_authentication(mut self, required: bool) -> Self {
+        self.require_authentication = Some(required);
+        self
+    }
+
+    /// ask the agent before accepting a connection
+    pub fn require_authorization(mut self, required: bool) -> Self {
+        self.require_authorization = Some(required);
+        self
+    }
+
+    /// connect automatically when the device connects
+    pub fn auto_connect(mut self, auto_connect: bool) -> Self {
+        self.auto_connect = Some(auto_connect);
+        self
+    }
+
+    /// a complete SDP record as XML, replaces the one bluez would generate
+    pub fn with_service_record(mut self, record: impl Into<String>) -> Self {
+        self.service_record = Some(record.into());
+        self
+    }
+
+    /// profile version advertised in the SDP record
+    pub fn with_version(mut self, version: u16) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    /// profile features advertised in the SDP record
+    pub fn with_features(mut self, features: u16) -> Self {
+        self.features = Some(features);
+        self
+    }
+
+    fn options(&self) -> Param<'static, 'static> {
+        let base = Param::Base;
+        let mut options = Vec::new();
+        if let Some(name) = &self.name {
+            options.push(("Name", string_param(name.as_str())));
+        }
+        if let Some(role) = self.role {
+            let role = match role {
+                Role::Client => "client",
+                Role::Server => "server",
+            };
+            options.push(("Role", string_param(role)));
+        }
+        if let Some(channel) = self.channel {
+            options.push(("Channel", base(params::Base::Uint16(channel))));
+        }
+        if let Some(psm) = self.psm {
+            options.push(("PSM", base(params::Base::Uint16(psm))));
+        }
+        if let Some(required) = self.require_authentication {
+            options.push((
+                "RequireAuthentication",
+                base(params::Base::Boolean(required)),
+            ));
+        }
+        if let Some(required) = self.require_authorization {
+            options.push((
+                "RequireAuthorization",
+                base(params::Base::Boolean(required)),
+            ));
+        }
+        if let Some(auto_connect) = self.auto_connect {
+            options.push(("AutoConnect", base(params::Base::Boolean(auto_connect))));
+        }
+        if let Some(record) = &self.service_record {
+            options.push(("ServiceRecord", string_param(record.as_str())));
+        }
+        if let Some(version) = self.version {
+            options.push(("Version", base(params::Base::Uint16(version))));
+        }
+        if let Some(features) = self.features {
+            options.push(("Features", base(params::Base::Uint16(features))));
+        }
+        variant_dict_param(options)
+    }
+}
+
+/// Identifies a registered profile
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ProfileId(u32);
+
+impl ProfileId {
+    fn path(&self) -> String {
+        format!("{}{}", ROOT, self.0)
+    }
+}
+
+pub(crate) struct RegisteredProfile {
+    id: ProfileId,
+    profile: Profile,
+}
+
+/// The connected socket bluez handed over, closed when dropped
+#[derive(Debug)]
+pub struct ProfileStream(File);
+
+impl ProfileStream {
+    /// stop reading and writing, the remote sees the link close
+    pub fn shutdown(&self) -> io::Result<()> {
+        shutdown(self.0.as_raw_fd(), Shutdown::Both).map_err(|e| match e.as_errno() {
+            Some(errno) => io::Error::from(errno),
+            None => io::Error::new(io::ErrorKind::Other, e),
+        })
+    }
+}
+
+impl Read for ProfileStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for ProfileStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsRawFd for ProfileStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for ProfileStream {
+    fn into_raw_fd(self) -> RawFd {
+        self.0.into_raw_fd()
+    }
+}
+
+/// A link to a remote device made for a registered profile
+#[derive(Debug)]
+pub struct ProfileConnection {
+    pub id: ProfileId,
+    pub adress: Address,
+    pub stream: ProfileStream,
+    /// profile version of the remote, if it has one in its SDP record
+    pub version: Option<u16>,
+    /// profile features of the remote, if it has them in its SDP record
+    pub features: Option<u16>,
+}
+
+impl Ble {
+    /// export the profile and register it with bluez
+    #[allow(dead_code)]
+    pub fn register_profile(&mut self, profile: Profile) -> Result<ProfileId, Error> {
+        let id = ProfileId(self.next_object_id);
+        self.next_object_id += 1;
+        self.profiles.push(RegisteredProfile { id, profile });
+
+        if let Err(e) = self.send_register_profile(id) {
+            self.profiles.retain(|registerd| registerd.id != id);
+            return Err(e);
+        }
+        Ok(id)
+    }
+
+    fn send_register_profile(&mut self, id: ProfileId) -> Result<(), Error> {
+        let profile = &self
+            .profiles
+            .iter()
+            .find(|registerd| registerd.id == id)
+            .expect("only called for registerd profiles")
+            .profile;
+        let mut register = MessageBuilder::new()
+            .call("RegisterProfile".into())
+            .at("org.bluez".into())
+            .on("/org/bluez".into())
+            .with_interface(MANAGER_IFACE.into())
+            .build();
+        register.body.push_old_params(&[
+            objectpath_param(id.path()),
+            string_param(profile.uuid.to_string()),
+            profile.options(),
+        ])?;
+
+        let response_serial = self.connection.send_message(&mut register, self.timeout)?;
+        let msg = self.wait_response_serving_calls(response_serial, self.timeout)?;
+
+        match msg.typ {
+            rustbus::MessageType::Reply => Ok(()),
+            rustbus::MessageType::Error => Err(Error::from((msg, Context::RegisterProfile))),
+            _ => Err(Error::UnexpectedDbusReply),
+        }
+    }
+
+    /// register all profiles again, used after bluez restarted. Returns
+    /// the errors of the ones that failed.
+    pub(crate) fn reregister_profiles(&mut self) -> Vec<Error> {
+        let ids: Vec<_> = self.profiles.iter().map(|r| r.id).collect();
+        ids.into_iter()
+            .filter_map(|id| self.send_register_profile(id).err())
+            .collect()
+    }
+
+    /// unregister the profile from bluez and stop exporting it, existing
+    /// connections are not closed
+    #[allow(dead_code)]
+    pub fn unregister_profile(&mut self, id: ProfileId) -> Result<(), Error> {
+        let mut unregister = MessageBuilder::new()
+            .call("UnregisterProfile".into())
+            .at("org.bluez".into())
+            .on("/org/bluez".into())
+            .with_interface(MANAGER_IFACE.into())
+            .build();
+        unregister
+            .body
+            .push_old_param(&objectpath_param(id.path()))?;
+
+        let response_serial = self
+            .connection
+            .send_message(&mut unregister, self.timeout)?;
+        let msg = self.wait_response_serving_calls(response_serial, self.timeout)?;
+        self.profiles.retain(|registerd| registerd.id != id);
+
+        match msg.typ {
+            rustbus::MessageType::Reply => Ok(()),
+            rustbus::MessageType::Error => Err(Error::from((msg, Context::UnregisterProfile))),
+            _ => Err(Error::UnexpectedDbusReply),
+        }
+    }
+
+    /// the connection announced by `Event::ProfileConnected`
+    #[allow(dead_code)]
+    pub fn take_profile_connection(
+        &mut self,
+        id: ProfileId,
+        adress: impl IntoAddress,
+    ) -> Option<ProfileConnection> {
+        let adress = adress.into_address().ok()?;
+        let index = self
+            .profile_connections
+            .iter()
+            .position(|c| c.id == id && c.adress == adress)?;
+        Some(self.profile_connections.remove(index))
+    }
+
+    /// connect to the profile on the device, it must be registerd (with
+    /// role client or without a role) so bluez can hand us the connection
+    #[allow(dead_code)]
+    pub fn connect_profile(
+        &mut self,
+        adress: impl IntoAddress,
+        id: ProfileId,
+    ) -> Result<ProfileConnection, Error> {
+        let adress = adress.into_address()?;
+        let uuid = self
+            .profiles
+            .iter()
+            .find(|registerd| registerd.id == id)
+            .map(|registerd| registerd.profile.uuid)
+            .ok_or(Error::DoesNotExist(Context::ConnectProfile))?;
+        self.device_profile_call("ConnectProfile", adress, uuid, Context::ConnectProfile)?;
+
+        // bluez hands over the connection before it replies
+        self.events
+            .retain(|event| event != &Event::ProfileConnected(id, adress));
+        self.take_profile_connection(id, adress)
+            .ok_or(Error::NoFdReturned)
+    }
+
+    /// disconnect the profile on the device
+    #[allow(dead_code)]
+    pub fn disconnect_profile(
+        &mut self,
+        adress: impl IntoAddress,
+        uuid: Uuid,
+    ) -> Result<(), Error> {
+        let adress = adress.into_address()?;
+        self.device_profile_call(
+            "DisconnectProfile",
+            adress,
+            uuid,
+            Context::DisconnectProfile,
+        )
+    }
+
+    fn device_profile_call(
+        &mut self,
+        member: &str,
+        adress: Address,
+        uuid: Uuid,
+        context: Context,
+    ) -> Result<(), Error> {
+        let mut call = MessageBuilder::new()
+            .call(member.into())
+            .at("org.bluez".into())
+            .on(self.device_path(adress).into())
+            .with_interface("org.bluez.Device1".into())
+            .build();
+        call.body.push_param(uuid.to_string())?;
+
+        let response_serial = self.connection.send_message(&mut call, self.timeout)?;
+        let msg = self.wait_response_serving_calls(response_serial, self.timeout)?;
+
+        match msg.typ {
+            rustbus::MessageType::Reply => Ok(()),
+            rustbus::MessageType::Error => Err(Error::from((msg, context))),
+            _ => Err(Error::UnexpectedDbusReply),
+        }
+    }
+
+    pub(crate) fn handle_profile_call(
+        &mut self,
+        mut call: MarshalledMessage,
+    ) -> Result<MarshalledMessage, Error> {
+        let path = call.dynheader.object.clone().unwrap_or_default();
+        let member = call.dynheader.member.clone().unwrap_or_default();
+        let id = path
+            .strip_prefix(ROOT)
+            .and_then(|id| id.parse().ok())
+            .map(ProfileId)
+            .filter(|id| self.profiles.iter().any(|r| &r.id == id));
+        let id = match id {
+            Some(id) => id,
+            None => {
+                return Ok(error_response(
+                    &call.dynheader,
+                    "org.freedesktop.DBus.Error.UnknownObject",
+                    "no such object",
+                ))
+            }
+        };
+
+        // take the fds first so they are closed if the call is malformed
+        let fds: Vec<_> = call
+            .raw_fds
+            .drain(..)
+            .map(|fd| unsafe { File::from_raw_fd(fd) })
+            .collect();
+        let reply = call.dynheader.make_response();
+        let unknown_method = error_response(
+            &call.dynheader,
+            "org.freedesktop.DBus.Error.UnknownMethod",
+            "unknown method",
+        );
+        let invalid_arguments = error_response(
+            &call.dynheader,
+            "org.bluez.Error.InvalidArguments",
+            "missing or invalid file descriptor",
+        );
+        let mut params = call.unmarshall_all()?.params.into_iter();
+        let adress = params
+            .next()
+            .and_then(unwrap_base)
+            .and_then(|base| match base {
+                params::Base::ObjectPath(path) => path.parse::<BluezPath>().ok(),
+                _ => None,
+            })
+            .filter(|path| path.kind() == PathKind::Device)
+            .and_then(|path| path.device_adress());
+
+        match (member.as_str(), adress) {
+            ("NewConnection", Some(adress)) => {
+                // a bad call from bluez should not stop us handling the rest
+                let fd_index = match params.next().and_then(unwrap_base) {
+                    Some(params::Base::UnixFd(index)) => index as usize,
+                    _ => return Ok(invalid_arguments),
+                };
+                let stream = match fds.into_iter().nth(fd_index) {
+                    Some(file) => ProfileStream(file),
+                    None => return Ok(invalid_arguments),
+                };
+                let (version, features) = match params.next() {
+                    Some(properties) => {
+                        let mut properties = Properties::from_param(properties)?;
+                        (
+                            properties.take_u16("Version"),
+                            properties.take_u16("Features"),
+                        )
+                    }
+                    None => (None, None),
+                };
+                self.profile_connections.push(ProfileConnection {
+                    id,
+                    adress,
+                    stream,
+                    version,
+                    features,
+                });
+                self.events.push_back(Event::ProfileConnected(id, adress));
+            }
+            ("RequestDisconnection", Some(adress)) => {
+                self.events
+                    .push_back(Event::ProfileDisconnectRequested(id, adress));
+            }
+            ("Release", _) => {
+                self.profiles.retain(|registerd| registerd.id != id);
+                self.events.push_back(Event::ProfileReleased(id));
+            }
+            _ => return Ok(unknown_method),
+        }
+        Ok(reply)
+    }
+}
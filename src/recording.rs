@@ -0,0 +1,329 @@
+//! Record the dbus traffic of a session and replay it later without bluez
+//! or the device. Record with `BleBuilder::record_to`, then replay with
+//! `BleBuilder::replay_from` and make the same calls in the same order.
+//! Replayed replies and signals are handed out only after the call that
+//! preceded them in the recording is made. Calls that differ from the
+//! recording fail with `Error::ReplayMismatch`.
+//!
+//! The contents of file descriptors (notify streams, profile connections)
+//! are not recorded, during replay they are replaced by sockets nothing is
+//! ever written to.
+//!
+//! ```no_run
+//! use bluebus::BleBuilder;
+//!
+//! // against the real device
+//! let mut ble = BleBuilder::default().record_to("session.bin").build().unwrap();
+//! let value = ble.read("0A:0B:0C:0D:0E:0F", 0x2a19).unwrap();
+//! drop(ble);
+//!
+//! // anywhere, for example in a test
+//! let mut ble = BleBuilder::default().replay_from("session.bin").build().unwrap();
+//! assert_eq!(ble.read("0A:0B:0C:0D:0E:0F", 0x2a19).unwrap(), value);
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::Write;
+use std::mem;
+use std::os::unix::io::IntoRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+use rustbus::client_conn::Timeout;
+use rustbus::message_builder::{MarshalledMessage, MarshalledMessageBody};
+use rustbus::wire::marshal::marshal;
+use rustbus::wire::unmarshal::{
+    unmarshal_dynamic_header, unmarshal_header, unmarshal_next_message,
+};
+use rustbus::wire::HeaderField;
+use rustbus::{ByteOrder, MessageType};
+
+use crate::connection::BusConnection;
+use crate::error::Error;
+
+const MAGIC: &[u8] = b"bluebus recording 1\n";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Direction {
+    Sent,
+    Received,
+}
+
+impl Direction {
+    fn tag(self) -> u8 {
+        match self {
+            Direction::Sent => b'>',
+            Direction::Received => b'<',
+        }
+    }
+}
+
+/// the message as it is on the wire, including the header fields rustbus
+/// does not marshal
+fn to_bytes(msg: &MarshalledMessage, serial: u32) -> Result<Vec<u8>, Error> {
+    let mut fields = Vec::new();
+    if let Some(sender) = &msg.dynheader.sender {
+        fields.push(HeaderField::Sender(sender.clone()));
+    }
+    if let Some(name) = &msg.dynheader.error_name {
+        fields.push(HeaderField::ErrorName(name.clone()));
+    }
+    let mut msg_with_serial = MarshalledMessage {
+        body: MarshalledMessageBody::from_parts(
+            msg.get_buf().to_vec(),
+            msg.get_sig().to_owned(),
+            ByteOrder::LittleEndian,
+        ),
+        dynheader: msg.dynheader.clone(),
+        raw_fds: Vec::new(),
+        typ: msg.typ,
+        flags: msg.flags,
+    };
+    msg_with_serial.dynheader.serial = Some(serial);
+    let mut buf = Vec::new();
+    marshal(&msg_with_serial, ByteOrder::LittleEndian, &fields, &mut buf)?;
+    Ok(buf)
+}
+
+fn from_bytes(buf: &[u8]) -> Result<MarshalledMessage, Error> {
+    let (header_len, header) = unmarshal_header(buf, 0)?;
+    let (dynheader_len, dynheader) = unmarshal_dynamic_header(&header, buf, header_len)?;
+    let (_, msg) = unmarshal_next_message(&header, dynheader, buf, header_len + dynheader_len)?;
+    Ok(msg)
+}
+
+/// Writes every message to a file as it passes
+pub(crate) struct Recorder {
+    file: File,
+    /// a message could not be recorded, reported on the next send
+    error: Option<Error>,
+    /// recording stops after an error, the rest could not be replayed
+    failed: bool,
+}
+
+impl Recorder {
+    pub(crate) fn create(path: &Path) -> Result<Self, Error> {
+        let mut file = File::create(path)?;
+        file.write_all(MAGIC)?;
+        Ok(Recorder {
+            file,
+            error: None,
+            failed: false,
+        })
+    }
+
+    pub(crate) fn record(&mut self, direction: Direction, msg: &MarshalledMessage, serial: u32) {
+        if self.failed {
+            return;
+        }
+        let bytes = match to_bytes(msg, serial) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                self.fail(err);
+                return;
+            }
+        };
+        let mut entry = Vec::with_capacity(bytes.len() + 5);
+        entry.push(direction.tag());
+        entry.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        entry.extend_from_slice(&bytes);
+        // one write per entry so a crash leaves at most one partial entry
+        if let Err(err) = self.file.write_all(&entry) {
+            self.fail(Error::Io(err));
+        }
+    }
+
+    fn fail(&mut self, err: Error) {
+        self.failed = true;
+        self.error = Some(err);
+    }
+
+    /// the error if recording failed since the last check
+    pub(crate) fn check(&mut self) -> Result<(), Error> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
+
+struct Entry {
+    direction: Direction,
+    msg: MarshalledMessage,
+}
+
+fn read_entries(path: &Path) -> Result<VecDeque<Entry>, Error> {
+    let invalid = || Error::InvalidRecording(path.display().to_string());
+    let data = fs::read(path)?;
+    let mut rest = data.strip_prefix(MAGIC).ok_or_else(invalid)?;
+    let mut entries = VecDeque::new();
+    while !rest.is_empty() {
+        if rest.len() < 5 {
+            return Err(invalid());
+        }
+        let direction = match rest[0] {
+            b'>' => Direction::Sent,
+            b'<' => Direction::Received,
+            _ => return Err(invalid()),
+        };
+        let mut len = [0u8; 4];
+        len.copy_from_slice(&rest[1..5]);
+        let len = u32::from_le_bytes(len) as usize;
+        let bytes = rest.get(5..5 + len).ok_or_else(invalid)?;
+        let msg = from_bytes(bytes).map_err(|_| invalid())?;
+        entries.push_back(Entry { direction, msg });
+        rest = &rest[5 + len..];
+    }
+    Ok(entries)
+}
+
+/// type, member and path, to explain a mismatch
+fn describe(msg: &MarshalledMessage) -> String {
+    let header = &msg.dynheader;
+    format!(
+        "{:?} {}.{} on {}",
+        msg.typ,
+        header.interface.as_deref().unwrap_or_default(),
+        header.member.as_deref().unwrap_or_default(),
+        header.object.as_deref().unwrap_or_default(),
+    )
+}
+
+/// Serves a recorded session instead of the bus
+pub(crate) struct Replay {
+    entries: VecDeque<Entry>,
+    next_serial: u32,
+    /// serial of a call in the recording to the serial it has now
+    serials: HashMap<u32, u32>,
+    calls: VecDeque<MarshalledMessage>,
+    signals: VecDeque<MarshalledMessage>,
+    responses: HashMap<u32, MarshalledMessage>,
+    /// the other ends of the sockets handed out in place of recorded fds
+    fd_peers: Vec<UnixStream>,
+}
+
+impl Replay {
+    pub(crate) fn open(path: &Path) -> Result<Self, Error> {
+        let mut replay = Replay {
+            entries: read_entries(path)?,
+            next_serial: 1,
+            serials: HashMap::new(),
+            calls: VecDeque::new(),
+            signals: VecDeque::new(),
+            responses: HashMap::new(),
+            fd_peers: Vec::new(),
+        };
+        replay.release_received()?;
+        Ok(replay)
+    }
+
+    /// whether the recording was made on a connection build made itself,
+    /// those start with the Hello call
+    pub(crate) fn starts_with_hello(&self) -> bool {
+        match self.entries.front() {
+            Some(entry) => {
+                entry.direction == Direction::Sent
+                    && entry.msg.dynheader.member.as_deref() == Some("Hello")
+            }
+            None => false,
+        }
+    }
+
+    /// queue the received messages up to the next one we sent
+    fn release_received(&mut self) -> Result<(), Error> {
+        while let Some(entry) = self.entries.front() {
+            if entry.direction == Direction::Sent {
+                break;
+            }
+            let mut msg = self.entries.pop_front().unwrap().msg;
+            for _ in 0..msg.dynheader.num_fds.unwrap_or(0) {
+                let (ours, theirs) = UnixStream::pair()?;
+                msg.raw_fds.push(ours.into_raw_fd());
+                self.fd_peers.push(theirs);
+            }
+            match msg.typ {
+                MessageType::Call => self.calls.push_back(msg),
+                MessageType::Signal => self.signals.push_back(msg),
+                MessageType::Reply | MessageType::Error => {
+                    let recorded = msg.dynheader.response_serial.unwrap_or_default();
+                    let serial = self.serials.get(&recorded).copied().unwrap_or(recorded);
+                    msg.dynheader.response_serial = Some(serial);
+                    self.responses.insert(serial, msg);
+                }
+                MessageType::Invalid => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn same_message(a: &MarshalledMessage, b: &MarshalledMessage) -> bool {
+    let (ha, hb) = (&a.dynheader, &b.dynheader);
+    mem::discriminant(&a.typ) == mem::discriminant(&b.typ)
+        && ha.destination == hb.destination
+        && ha.object == hb.object
+        && ha.interface == hb.interface
+        && ha.member == hb.member
+        && ha.error_name == hb.error_name
+        && ha.response_serial == hb.response_serial
+        && a.get_sig() == b.get_sig()
+        && a.get_buf() == b.get_buf()
+}
+
+impl BusConnection for Replay {
+    fn send_message(&mut self, msg: &mut MarshalledMessage, _: Timeout) -> Result<u32, Error> {
+        let recorded = match self.entries.pop_front() {
+            Some(entry) => entry.msg,
+            None => {
+                return Err(Error::ReplayMismatch(format!(
+                    "recording ended, sent: {}",
+                    describe(msg)
+                )))
+            }
+        };
+        if !same_message(msg, &recorded) {
+            return Err(Error::ReplayMismatch(format!(
+                "sent: {}, recorded: {}",
+                describe(msg),
+                describe(&recorded)
+            )));
+        }
+
+        let serial = match msg.dynheader.serial {
+            Some(serial) => serial,
+            None => {
+                self.next_serial += 1;
+                self.next_serial - 1
+            }
+        };
+        if let Some(recorded) = recorded.dynheader.serial {
+            self.serials.insert(recorded, serial);
+        }
+        self.release_received()?;
+        Ok(serial)
+    }
+
+    fn try_get_response(&mut self, serial: u32) -> Option<MarshalledMessage> {
+        self.responses.remove(&serial)
+    }
+
+    fn try_get_call(&mut self) -> Option<MarshalledMessage> {
+        self.calls.pop_front()
+    }
+
+    fn try_get_signal(&mut self) -> Option<MarshalledMessage> {
+        self.signals.pop_front()
+    }
+
+    /// nothing arrives until the next call is made, waiting would not help
+    fn refill_once(&mut self, _: Timeout) -> Result<(), Error> {
+        Err(Error::ReplayMismatch(
+            "waiting for a message that is not in the recording".to_owned(),
+        ))
+    }
+
+    fn refill_all(&mut self) -> Result<Vec<MarshalledMessage>, Error> {
+        Ok(Vec::new())
+    }
+}
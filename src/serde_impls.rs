@@ -0,0 +1,53 @@
+//! Addresses, uuids and paths are serialized in the same form bluez uses
+//! for them: as strings. The type of an address is not part of its string
+//! form and is therefore lost, just like it is ignored when comparing.
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+use serde::de::{self, Deserializer, Visitor};
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+
+use crate::address::Address;
+use crate::path::BluezPath;
+use crate::uuid::Uuid;
+
+struct FromStrVisitor<T>(PhantomData<T>, &'static str);
+
+impl<'de, T> Visitor<'de> for FromStrVisitor<T>
+where
+    T: FromStr,
+    T::Err: fmt::Debug,
+{
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.1)
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<T, E> {
+        s.parse().map_err(|e| E::custom(format!("{:?}", e)))
+    }
+}
+
+macro_rules! serde_as_string {
+    ($type:ty, $expecting:expr) => {
+        impl Serialize for $type {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $type {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                deserializer.deserialize_str(FromStrVisitor(PhantomData, $expecting))
+            }
+        }
+    };
+}
+
+serde_as_string!(Address, "a bluetooth address such as 0A:0B:0C:0D:0E:0F");
+serde_as_string!(Uuid, "a 16, 32 or 128 bit uuid");
+serde_as_string!(BluezPath, "a bluez object path");
@@ -0,0 +1,284 @@
+use std::fmt;
+use std::str::FromStr;
+
+use super::KeyFile;
+use crate::address::{Address, AddressType};
+use crate::error::{Context, Error};
+
+fn invalid() -> Error {
+    Error::InvalidValue(Context::BondInfo)
+}
+
+fn parse<T: FromStr>(file: &KeyFile, group: &str, key: &str) -> Result<Option<T>, Error> {
+    match file.get(group, key) {
+        Some(value) => value.parse().map(Some).map_err(|_| invalid()),
+        None => Ok(None),
+    }
+}
+
+fn parse_or_default<T: FromStr + Default>(
+    file: &KeyFile,
+    group: &str,
+    key: &str,
+) -> Result<T, Error> {
+    Ok(parse(file, group, key)?.unwrap_or_default())
+}
+
+/// A 128 bit key, stored by bluez as 32 hex digits
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Key(pub [u8; 16]);
+
+impl FromStr for Key {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 32 || !s.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let mut key = [0u8; 16];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).map_err(|_| invalid())?;
+        }
+        Ok(Key(key))
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// keys are secret, keep them out of logs
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+/// LE encryption key, the `[LongTermKey]` and `[PeripheralLongTermKey]` groups
+#[derive(Debug, Clone, PartialEq)]
+pub struct LongTermKey {
+    pub key: Key,
+    /// 0 unauthenticated, 1 authenticated, 2 authenticated secure connections
+    pub authenticated: u8,
+    pub enc_size: u8,
+    pub ediv: u16,
+    pub rand: u64,
+}
+
+impl LongTermKey {
+    fn read(file: &KeyFile, group: &str) -> Result<Option<Self>, Error> {
+        let key = match parse(file, group, "Key")? {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+        Ok(Some(LongTermKey {
+            key,
+            authenticated: parse_or_default(file, group, "Authenticated")?,
+            enc_size: parse_or_default(file, group, "EncSize")?,
+            ediv: parse_or_default(file, group, "EDiv")?,
+            rand: parse_or_default(file, group, "Rand")?,
+        }))
+    }
+
+    fn write(&self, file: &mut KeyFile, group: &str) {
+        file.set(group, "Key", self.key);
+        file.set(group, "Authenticated", self.authenticated);
+        file.set(group, "EncSize", self.enc_size);
+        file.set(group, "EDiv", self.ediv);
+        file.set(group, "Rand", self.rand);
+    }
+}
+
+/// Key to sign data without encryption (CSRK)
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignatureKey {
+    pub key: Key,
+    pub counter: u32,
+    pub authenticated: bool,
+}
+
+impl SignatureKey {
+    fn read(file: &KeyFile, group: &str) -> Result<Option<Self>, Error> {
+        let key = match parse(file, group, "Key")? {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+        Ok(Some(SignatureKey {
+            key,
+            counter: parse_or_default(file, group, "Counter")?,
+            authenticated: parse_or_default(file, group, "Authenticated")?,
+        }))
+    }
+
+    fn write(&self, file: &mut KeyFile, group: &str) {
+        file.set(group, "Key", self.key);
+        file.set(group, "Counter", self.counter);
+        file.set(group, "Authenticated", self.authenticated);
+    }
+}
+
+/// BR/EDR (classic) bonding key
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkKey {
+    pub key: Key,
+    /// the link key type from the core specification
+    pub kind: u8,
+    pub pin_length: u8,
+}
+
+/// The connection parameters bluez uses when connecting, the intervals in
+/// 1.25 ms, the timeout in 10 ms
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConnectionParameters {
+    pub min_interval: u16,
+    pub max_interval: u16,
+    pub latency: u16,
+    pub timeout: u16,
+}
+
+/// The `info` file bluez keeps for every paired or trusted device. Entries
+/// this does not know about are kept so writing it elsewhere loses nothing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BondInfo {
+    pub device: Address,
+    pub name: Option<String>,
+    pub trusted: bool,
+    pub blocked: bool,
+    pub long_term_key: Option<LongTermKey>,
+    /// the key used when we are the peripheral
+    pub peripheral_long_term_key: Option<LongTermKey>,
+    /// IRK, resolves the random addresses of the device
+    pub identity_resolving_key: Option<Key>,
+    pub local_signature_key: Option<SignatureKey>,
+    pub remote_signature_key: Option<SignatureKey>,
+    pub link_key: Option<LinkKey>,
+    pub connection_parameters: Option<ConnectionParameters>,
+    file: KeyFile,
+}
+
+const GENERAL: &str = "General";
+const LTK: &str = "LongTermKey";
+const PERIPHERAL_LTK: &str = "PeripheralLongTermKey";
+/// what bluez called the peripheral key before 5.62
+const SLAVE_LTK: &str = "SlaveLongTermKey";
+const IRK: &str = "IdentityResolvingKey";
+const LOCAL_CSRK: &str = "LocalSignatureKey";
+const REMOTE_CSRK: &str = "RemoteSignatureKey";
+const LINK_KEY: &str = "LinkKey";
+const CONNECTION_PARAMETERS: &str = "ConnectionParameters";
+
+impl BondInfo {
+    pub(super) fn from_keyfile(device: Address, file: KeyFile) -> Result<Self, Error> {
+        // bluez writes "static" for static random addresses
+        let device = match file.get(GENERAL, "AddressType") {
+            Some("public") | None => device.with_type(AddressType::Public),
+            Some(_) => device.with_type(AddressType::Random),
+        };
+        let peripheral_long_term_key = match LongTermKey::read(&file, PERIPHERAL_LTK)? {
+            Some(key) => Some(key),
+            None => LongTermKey::read(&file, SLAVE_LTK)?,
+        };
+        let link_key = match parse(&file, LINK_KEY, "Key")? {
+            Some(key) => Some(LinkKey {
+                key,
+                kind: parse_or_default(&file, LINK_KEY, "Type")?,
+                pin_length: parse_or_default(&file, LINK_KEY, "PINLength")?,
+            }),
+            None => None,
+        };
+        let connection_parameters = match file.group(CONNECTION_PARAMETERS) {
+            Some(_) => Some(ConnectionParameters {
+                min_interval: parse_or_default(&file, CONNECTION_PARAMETERS, "MinInterval")?,
+                max_interval: parse_or_default(&file, CONNECTION_PARAMETERS, "MaxInterval")?,
+                latency: parse_or_default(&file, CONNECTION_PARAMETERS, "Latency")?,
+                timeout: parse_or_default(&file, CONNECTION_PARAMETERS, "Timeout")?,
+            }),
+            None => None,
+        };
+
+        Ok(BondInfo {
+            device,
+            name: file.get(GENERAL, "Name").map(str::to_owned),
+            trusted: parse_or_default(&file, GENERAL, "Trusted")?,
+            blocked: parse_or_default(&file, GENERAL, "Blocked")?,
+            long_term_key: LongTermKey::read(&file, LTK)?,
+            peripheral_long_term_key,
+            identity_resolving_key: parse(&file, IRK, "Key")?,
+            local_signature_key: SignatureKey::read(&file, LOCAL_CSRK)?,
+            remote_signature_key: SignatureKey::read(&file, REMOTE_CSRK)?,
+            link_key,
+            connection_parameters,
+            file,
+        })
+    }
+
+    /// the file with the fields of this struct applied
+    pub(super) fn to_keyfile(&self) -> KeyFile {
+        let mut file = self.file.clone();
+        if let Some(name) = &self.name {
+            file.set(GENERAL, "Name", name);
+        }
+        let address_type = match self.device.kind() {
+            AddressType::Public => "public".to_owned(),
+            // keep the kind of random address bluez wrote
+            AddressType::Random => match file.get(GENERAL, "AddressType") {
+                Some(kind) if kind != "public" => kind.to_owned(),
+                _ => "static".to_owned(),
+            },
+        };
+        file.set(GENERAL, "AddressType", address_type);
+        file.set(GENERAL, "Trusted", self.trusted);
+        file.set(GENERAL, "Blocked", self.blocked);
+
+        // groups are updated in place so the file keeps its order, the
+        // peripheral key keeps the name it was read with
+        let peripheral_ltk = match (file.group(PERIPHERAL_LTK), file.group(SLAVE_LTK)) {
+            (None, Some(_)) => SLAVE_LTK,
+            _ => PERIPHERAL_LTK,
+        };
+        match &self.long_term_key {
+            Some(key) => key.write(&mut file, LTK),
+            None => file.remove_group(LTK),
+        }
+        match &self.peripheral_long_term_key {
+            Some(key) => key.write(&mut file, peripheral_ltk),
+            None => file.remove_group(peripheral_ltk),
+        }
+        match &self.identity_resolving_key {
+            Some(key) => file.set(IRK, "Key", key),
+            None => file.remove_group(IRK),
+        }
+        match &self.local_signature_key {
+            Some(key) => key.write(&mut file, LOCAL_CSRK),
+            None => file.remove_group(LOCAL_CSRK),
+        }
+        match &self.remote_signature_key {
+            Some(key) => key.write(&mut file, REMOTE_CSRK),
+            None => file.remove_group(REMOTE_CSRK),
+        }
+        match &self.link_key {
+            Some(key) => {
+                file.set(LINK_KEY, "Key", key.key);
+                file.set(LINK_KEY, "Type", key.kind);
+                file.set(LINK_KEY, "PINLength", key.pin_length);
+            }
+            None => file.remove_group(LINK_KEY),
+        }
+        match &self.connection_parameters {
+            Some(params) => {
+                file.set(CONNECTION_PARAMETERS, "MinInterval", params.min_interval);
+                file.set(CONNECTION_PARAMETERS, "MaxInterval", params.max_interval);
+                file.set(CONNECTION_PARAMETERS, "Latency", params.latency);
+                file.set(CONNECTION_PARAMETERS, "Timeout", params.timeout);
+            }
+            None => file.remove_group(CONNECTION_PARAMETERS),
+        }
+        file
+    }
+}
@@ -0,0 +1,128 @@
+use super::KeyFile;
+use crate::address::Address;
+use crate::uuid::Uuid;
+
+const PRIMARY_SERVICE: &str = "2800";
+const SECONDARY_SERVICE: &str = "2801";
+const INCLUDE: &str = "2802";
+const CHARACTERISTIC: &str = "2803";
+
+/// One line of the `[Attributes]` group of a cache file
+#[derive(Debug, Clone, PartialEq)]
+pub enum CachedAttribute {
+    Service {
+        handle: u16,
+        end_handle: u16,
+        uuid: Uuid,
+        primary: bool,
+    },
+    Characteristic {
+        handle: u16,
+        value_handle: u16,
+        /// the properties bit field as the device sent it
+        properties: u8,
+        uuid: Uuid,
+    },
+    Descriptor {
+        handle: u16,
+        uuid: Uuid,
+    },
+    /// includes and anything we do not understand, value as stored
+    Other {
+        handle: u16,
+        value: String,
+    },
+}
+
+impl CachedAttribute {
+    pub fn handle(&self) -> u16 {
+        match self {
+            CachedAttribute::Service { handle, .. }
+            | CachedAttribute::Characteristic { handle, .. }
+            | CachedAttribute::Descriptor { handle, .. }
+            | CachedAttribute::Other { handle, .. } => *handle,
+        }
+    }
+
+    fn parse(handle: u16, value: &str) -> Self {
+        Self::parse_known(handle, value).unwrap_or_else(|| CachedAttribute::Other {
+            handle,
+            value: value.to_owned(),
+        })
+    }
+
+    fn parse_known(handle: u16, value: &str) -> Option<Self> {
+        let fields: Vec<_> = value.split(':').collect();
+        match fields.as_slice() {
+            [kind @ PRIMARY_SERVICE, end, uuid] | [kind @ SECONDARY_SERVICE, end, uuid] => {
+                Some(CachedAttribute::Service {
+                    handle,
+                    end_handle: parse_hex(end)? as u16,
+                    uuid: uuid.parse().ok()?,
+                    primary: *kind == PRIMARY_SERVICE,
+                })
+            }
+            [INCLUDE, ..] => None,
+            [CHARACTERISTIC, value_handle, properties, uuid] => {
+                Some(CachedAttribute::Characteristic {
+                    handle,
+                    value_handle: parse_hex(value_handle)? as u16,
+                    properties: parse_hex(properties)? as u8,
+                    uuid: uuid.parse().ok()?,
+                })
+            }
+            // descriptors with a cached value store it before the uuid
+            [uuid] | [_, uuid] => Some(CachedAttribute::Descriptor {
+                handle,
+                uuid: uuid.parse().ok()?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+fn parse_hex(s: &str) -> Option<u32> {
+    u32::from_str_radix(s.trim_start_matches("0x"), 16).ok()
+}
+
+/// The attribute cache bluez keeps for a device so it does not need to
+/// discover its services on every connect.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttributeCache {
+    pub device: Address,
+    /// name of the device when the cache was written
+    pub name: Option<String>,
+    /// sorted by handle
+    pub attributes: Vec<CachedAttribute>,
+}
+
+impl AttributeCache {
+    pub(super) fn from_keyfile(device: Address, file: &KeyFile) -> Self {
+        let mut attributes: Vec<_> = file
+            .group("Attributes")
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|(handle, value)| {
+                let handle = parse_hex(handle)? as u16;
+                Some(CachedAttribute::parse(handle, value))
+            })
+            .collect();
+        attributes.sort_by_key(CachedAttribute::handle);
+        AttributeCache {
+            device,
+            name: file.get("General", "Name").map(str::to_owned),
+            attributes,
+        }
+    }
+
+    /// uuids of the cached services
+    pub fn services(&self) -> Vec<Uuid> {
+        self.attributes
+            .iter()
+            .filter_map(|attribute| match attribute {
+                CachedAttribute::Service { uuid, .. } => Some(*uuid),
+                _ => None,
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,78 @@
+/// The ini like key files bluez stores its state in: `[Group]` headers
+/// followed by `Key=Value` lines. Order is kept so a file written back
+/// looks like the one read.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct KeyFile {
+    groups: Vec<(String, Vec<(String, String)>)>,
+}
+
+impl KeyFile {
+    pub(crate) fn parse(text: &str) -> Self {
+        let mut file = KeyFile::default();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                file.groups.push((name.to_owned(), Vec::new()));
+                continue;
+            }
+            // keys outside any group are not valid, skip them like glib does
+            if let (Some((key, value)), Some((_, entries))) =
+                (line.split_once('='), file.groups.last_mut())
+            {
+                entries.push((key.trim().to_owned(), value.trim().to_owned()));
+            }
+        }
+        file
+    }
+
+    pub(crate) fn group(&self, name: &str) -> Option<&[(String, String)]> {
+        self.groups
+            .iter()
+            .find(|(group, _)| group == name)
+            .map(|(_, entries)| entries.as_slice())
+    }
+
+    pub(crate) fn get(&self, group: &str, key: &str) -> Option<&str> {
+        self.group(group)?
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// set the key, the group is added if it does not exist yet
+    pub(crate) fn set(&mut self, group: &str, key: &str, value: impl ToString) {
+        let entries = match self.groups.iter().position(|(g, _)| g == group) {
+            Some(i) => &mut self.groups[i].1,
+            None => {
+                self.groups.push((group.to_owned(), Vec::new()));
+                &mut self.groups.last_mut().expect("just pushed").1
+            }
+        };
+        let value = value.to_string();
+        match entries.iter_mut().find(|(k, _)| k == key) {
+            Some(entry) => entry.1 = value,
+            None => entries.push((key.to_owned(), value)),
+        }
+    }
+
+    pub(crate) fn remove_group(&mut self, name: &str) {
+        self.groups.retain(|(group, _)| group != name);
+    }
+}
+
+impl std::fmt::Display for KeyFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (i, (group, entries)) in self.groups.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "[{}]", group)?;
+            for (key, value) in entries {
+                writeln!(f, "{}={}", key, value)?;
+            }
+        }
+        Ok(())
+    }
+}
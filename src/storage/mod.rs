@@ -0,0 +1,208 @@
+//! The files bluez keeps per adapter below `/var/lib/bluetooth`. Reading
+//! and changing them needs the privileges bluetoothd runs with, usually
+//! root. Bluez only reads them when it starts or a device is (re)added so
+//! remove the device before touching its files, `Ble::reset_device` does
+//! both.
+//!
+//! ```no_run
+//! use bluebus::BleBuilder;
+//!
+//! let mut ble = BleBuilder::default().build().unwrap();
+//! let storage = ble.adapter_storage().unwrap();
+//! for device in storage.cached_devices().unwrap() {
+//!     let cache = storage.attribute_cache(device).unwrap();
+//!     println!("{}: {} attributes", device, cache.attributes.len());
+//! }
+//! ```
+//!
+//! Bonds can be moved to another machine by copying their info files, write
+//! them to the storage of the new adapter before bluez starts there:
+//!
+//! ```no_run
+//! use bluebus::storage::{AdapterStorage, DEFAULT_ROOT};
+//!
+//! let old = AdapterStorage::new("/mnt/old-gateway/var/lib/bluetooth", "00:1A:7D:DA:71:13").unwrap();
+//! let new = AdapterStorage::new(DEFAULT_ROOT, "00:1A:7D:DA:71:14").unwrap();
+//! for bond in old.bonds().unwrap() {
+//!     new.write_bond(&bond).unwrap();
+//! }
+//! ```
+
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+mod bonding;
+mod cache;
+mod keyfile;
+
+pub use bonding::{BondInfo, ConnectionParameters, Key, LinkKey, LongTermKey, SignatureKey};
+pub use cache::{AttributeCache, CachedAttribute};
+use keyfile::KeyFile;
+
+use crate::address::{Address, IntoAddress};
+use crate::error::{Context, Error};
+
+/// where bluetoothd keeps its state by default
+pub const DEFAULT_ROOT: &str = "/var/lib/bluetooth";
+
+/// turn an io error on a path into the error we report, missing
+/// privileges are common enough to get their own error
+fn io_error(err: io::Error, path: &Path) -> Error {
+    match err.kind() {
+        io::ErrorKind::PermissionDenied => Error::PermissionDenied(path.to_owned()),
+        _ => Error::Io(err),
+    }
+}
+
+/// The storage directory of one adapter: `<root>/<adapter address>`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdapterStorage {
+    dir: PathBuf,
+}
+
+impl AdapterStorage {
+    /// the storage of the adapter with this address below root, use
+    /// `DEFAULT_ROOT` for the storage of the running bluez
+    pub fn new(root: impl AsRef<Path>, adapter: impl IntoAddress) -> Result<Self, Error> {
+        let adapter = adapter.into_address()?;
+        Ok(AdapterStorage {
+            dir: root.as_ref().join(adapter.to_string()),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn cache_path(&self, device: Address) -> PathBuf {
+        // an Address only formats to hex digits and colons, so this
+        // can not point outside the cache dir
+        self.dir.join("cache").join(device.to_string())
+    }
+
+    /// devices bluez has an attribute cache for
+    pub fn cached_devices(&self) -> Result<Vec<Address>, Error> {
+        let dir = self.dir.join("cache");
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(io_error(e, &dir)),
+        };
+        let mut devices = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(|e| io_error(e, &dir))?;
+            let name = entry.file_name();
+            if let Some(adress) = name.to_str().and_then(|n| n.parse().ok()) {
+                devices.push(adress);
+            }
+        }
+        devices.sort_by_key(Address::octets);
+        Ok(devices)
+    }
+
+    /// the services, characteristics and descriptors bluez cached for the device
+    pub fn attribute_cache(&self, device: impl IntoAddress) -> Result<AttributeCache, Error> {
+        let device = device.into_address()?;
+        let path = self.cache_path(device);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(Error::DoesNotExist(Context::AttributeCache))
+            }
+            Err(e) => return Err(io_error(e, &path)),
+        };
+        Ok(AttributeCache::from_keyfile(device, &KeyFile::parse(&text)))
+    }
+
+    /// delete the attribute cache of the device, returns false if there was
+    /// none. Only regular files are removed.
+    pub fn remove_attribute_cache(&self, device: impl IntoAddress) -> Result<bool, Error> {
+        let path = self.cache_path(device.into_address()?);
+        match fs::symlink_metadata(&path) {
+            Ok(meta) if meta.is_file() => (),
+            Ok(_) => return Err(Error::InvalidPath(path.display().to_string())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(io_error(e, &path)),
+        }
+        match fs::remove_file(&path) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                Err(Error::PermissionDenied(path))
+            }
+            Err(e) => Err(Error::CouldNotRemoveCache(e)),
+        }
+    }
+
+    fn info_path(&self, device: Address) -> PathBuf {
+        self.dir.join(device.to_string()).join("info")
+    }
+
+    /// devices with an info file: the ones that are paired or trusted
+    pub fn bonded_devices(&self) -> Result<Vec<Address>, Error> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(io_error(e, &self.dir)),
+        };
+        let mut devices = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(|e| io_error(e, &self.dir))?;
+            let name = entry.file_name();
+            let adress = match name.to_str().and_then(|n| n.parse().ok()) {
+                Some(adress) => adress,
+                None => continue, // settings, cache etc
+            };
+            if self.info_path(adress).is_file() {
+                devices.push(adress);
+            }
+        }
+        devices.sort_by_key(Address::octets);
+        Ok(devices)
+    }
+
+    /// the keys and settings bluez stored for the device
+    pub fn bond(&self, device: impl IntoAddress) -> Result<BondInfo, Error> {
+        let device = device.into_address()?;
+        let path = self.info_path(device);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(Error::DoesNotExist(Context::BondInfo))
+            }
+            Err(e) => return Err(io_error(e, &path)),
+        };
+        BondInfo::from_keyfile(device, KeyFile::parse(&text))
+    }
+
+    pub fn bonds(&self) -> Result<Vec<BondInfo>, Error> {
+        self.bonded_devices()?
+            .into_iter()
+            .map(|device| self.bond(device))
+            .collect()
+    }
+
+    /// write the info file of the device, replacing any that exists. It is
+    /// only readable by the owner as it contains the keys.
+    pub fn write_bond(&self, bond: &BondInfo) -> Result<(), Error> {
+        let path = self.info_path(bond.device);
+        let dir = path.parent().expect("info is in the device dir");
+        fs::create_dir_all(dir).map_err(|e| io_error(e, dir))?;
+
+        // write next to it then rename so bluez never sees half a file
+        let tmp = dir.join("info.tmp");
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp)
+            .map_err(|e| io_error(e, &tmp))?;
+        file.write_all(bond.to_keyfile().to_string().as_bytes())
+            .map_err(|e| io_error(e, &tmp))?;
+        file.sync_all().map_err(|e| io_error(e, &tmp))?;
+        fs::rename(&tmp, &path).map_err(|e| io_error(e, &path))
+    }
+}
@@ -0,0 +1,238 @@
+use std::time::{Duration, Instant};
+
+use rustbus::client_conn::Timeout;
+
+use crate::error::{Context, Error};
+use crate::{Address, Ble, Event};
+
+/// How long to wait between connection attempts. The wait starts at
+/// `initial` and is multiplied by `factor` after every failed attempt
+/// until it reaches `max`.
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub factor: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+            factor: 2,
+        }
+    }
+}
+
+impl Backoff {
+    fn delay(&self, failed_attempts: u32) -> Duration {
+        let mut delay = self.initial;
+        for _ in 1..failed_attempts {
+            delay = delay.checked_mul(self.factor).unwrap_or(self.max);
+            if delay >= self.max {
+                return self.max;
+            }
+        }
+        delay.min(self.max)
+    }
+}
+
+/// State changes reported by the ConnectionSupervisor
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionState {
+    /// trying to connect, this is the n-th attempt since the last connection
+    Connecting(u32),
+    Connected,
+    /// the device is connected and its characteristics can be used
+    ServicesResolved,
+    Disconnected,
+    /// the maximum number of attempts was reached, no new attempts will be made
+    GaveUp,
+}
+
+/// Keeps a device connected. It reconnects with a backoff whenever the device
+/// disconnects. Either call `run` or drive it from your own event loop by passing
+/// every event from `Ble::wait_event` to `handle_event` and calling `poll`
+/// regularly (see `time_until_next_attempt`).
+pub struct ConnectionSupervisor {
+    adress: Address,
+    backoff: Backoff,
+    max_attempts: Option<u32>,
+    discover_when_unknown: bool,
+    state: ConnectionState,
+    attempts: u32,
+    next_attempt: Option<Instant>,
+    discovering: bool,
+}
+
+impl ConnectionSupervisor {
+    pub fn new(adress: Address) -> Self {
+        ConnectionSupervisor {
+            adress,
+            backoff: Backoff::default(),
+            max_attempts: None,
+            discover_when_unknown: false,
+            state: ConnectionState::Disconnected,
+            attempts: 0,
+            next_attempt: Some(Instant::now()),
+            discovering: false,
+        }
+    }
+
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// give up after this many failed attempts in a row, by default
+    /// the supervisor never gives up
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+
+    /// start discovery if bluez does not know the device, it is stopped
+    /// again once the device is connected. When the connection is reported
+    /// by an event the next `poll` stops it.
+    pub fn discover_when_unknown(mut self, discover: bool) -> Self {
+        self.discover_when_unknown = discover;
+        self
+    }
+
+    pub fn state(&self) -> &ConnectionState {
+        &self.state
+    }
+
+    /// None if no attempt is planned, for example because we are connected
+    pub fn time_until_next_attempt(&self) -> Option<Duration> {
+        self.next_attempt
+            .map(|at| at.saturating_duration_since(Instant::now()))
+    }
+
+    fn is_our_device(&self, adress: &Address) -> bool {
+        &self.adress == adress
+    }
+
+    fn set_state(&mut self, state: ConnectionState) -> Option<ConnectionState> {
+        if self.state == state {
+            return None;
+        }
+        self.state = state.clone();
+        Some(state)
+    }
+
+    fn schedule_reconnect(&mut self) -> Option<ConnectionState> {
+        self.attempts = 0;
+        self.next_attempt = Some(Instant::now());
+        self.set_state(ConnectionState::Disconnected)
+    }
+
+    /// update the state using an event recieved from `Ble::wait_event`
+    pub fn handle_event(&mut self, event: &Event) -> Option<ConnectionState> {
+        if self.state == ConnectionState::GaveUp {
+            return None;
+        }
+        match event {
+            Event::Connected(adress) if self.is_our_device(adress) => {
+                self.next_attempt = None;
+                self.set_state(ConnectionState::Connected)
+            }
+            Event::ServicesResolved(adress) if self.is_our_device(adress) => {
+                self.next_attempt = None;
+                self.set_state(ConnectionState::ServicesResolved)
+            }
+            Event::Disconnected(adress) if self.is_our_device(adress) => self.schedule_reconnect(),
+            Event::BluezStopped => self.schedule_reconnect(),
+            _ => None,
+        }
+    }
+
+    /// make a connection attempt if one is due, returns the state changes
+    /// that happend
+    pub fn poll(&mut self, ble: &mut Ble) -> Result<Vec<ConnectionState>, Error> {
+        let mut changes = Vec::new();
+        let connected = matches!(
+            self.state,
+            ConnectionState::Connected | ConnectionState::ServicesResolved
+        );
+        if connected && self.discovering {
+            // the device connected without an attempt of ours, see handle_event
+            self.discovering = false;
+            ble.stop_discovery()?;
+        }
+        match self.next_attempt {
+            Some(at) if at <= Instant::now() => (),
+            _ => return Ok(changes),
+        }
+
+        self.attempts += 1;
+        changes.extend(self.set_state(ConnectionState::Connecting(self.attempts)));
+
+        match ble.connect(self.adress) {
+            Ok(()) => {
+                self.next_attempt = None;
+                changes.extend(self.set_state(ConnectionState::Connected));
+                if self.discovering {
+                    self.discovering = false;
+                    ble.stop_discovery()?;
+                }
+                return Ok(changes);
+            }
+            Err(Error::DoesNotExist(Context::Connect)) if self.discover_when_unknown => {
+                if !self.discovering {
+                    match ble.start_discovery() {
+                        Ok(()) | Err(Error::InProgress(_)) => self.discovering = true,
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            Err(_) => (),
+        }
+
+        if let Some(max) = self.max_attempts {
+            if self.attempts >= max {
+                self.next_attempt = None;
+                if self.discovering {
+                    self.discovering = false;
+                    ble.stop_discovery()?;
+                }
+                changes.extend(self.set_state(ConnectionState::GaveUp));
+                return Ok(changes);
+            }
+        }
+        self.next_attempt = Some(Instant::now() + self.backoff.delay(self.attempts));
+        Ok(changes)
+    }
+
+    /// keep the device connected until the supervisor gives up, calls on_change
+    /// for every state change. Events not meant for this supervisor are dropped.
+    pub fn run(
+        &mut self,
+        ble: &mut Ble,
+        mut on_change: impl FnMut(&ConnectionState),
+    ) -> Result<(), Error> {
+        loop {
+            for change in self.poll(ble)? {
+                on_change(&change);
+            }
+            if self.state == ConnectionState::GaveUp {
+                return Ok(());
+            }
+
+            let timeout = match self.time_until_next_attempt() {
+                Some(left) => Timeout::Duration(left),
+                None => Timeout::Infinite,
+            };
+            match ble.wait_event(timeout) {
+                Ok(event) => {
+                    if let Some(change) = self.handle_event(&event) {
+                        on_change(&change);
+                    }
+                }
+                Err(Error::DbusConnectionError(rustbus::client_conn::Error::TimedOut)) => (),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::process::{self, Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::error::Error;
+
+static DAEMON_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+const CONFIG: &str = r#"<!DOCTYPE busconfig PUBLIC "-//freedesktop//DTD D-Bus Bus Configuration 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/busconfig.dtd">
+<busconfig>
+  <type>session</type>
+  <listen>unix:path=SOCKET</listen>
+  <auth>EXTERNAL</auth>
+  <policy context="default">
+    <allow send_destination="*" eavesdrop="true"/>
+    <allow eavesdrop="true"/>
+    <allow own="*"/>
+  </policy>
+</busconfig>
+"#;
+
+/// A private dbus-daemon listening on a socket in a temporary directory,
+/// it is killed when dropped. Uses the binary in `BLUEBUS_DBUS_DAEMON` or
+/// `dbus-daemon` from the PATH.
+pub struct DbusDaemon {
+    child: Child,
+    dir: PathBuf,
+    address: String,
+}
+
+fn start_error(e: impl std::fmt::Display) -> Error {
+    Error::CouldNotConnectToBus(format!("could not start dbus-daemon: {}", e))
+}
+
+impl DbusDaemon {
+    pub fn start() -> Result<Self, Error> {
+        let numb = DAEMON_COUNT.fetch_add(1, Ordering::Relaxed);
+        let dir = env::temp_dir().join(format!("bluebus-{}-{}", process::id(), numb));
+        fs::create_dir_all(&dir).map_err(start_error)?;
+
+        let socket = dir.join("bus");
+        let config = CONFIG.replace("SOCKET", &socket.to_string_lossy());
+        let config_path = dir.join("bus.conf");
+        fs::write(&config_path, config).map_err(start_error)?;
+
+        let binary = env::var("BLUEBUS_DBUS_DAEMON").unwrap_or_else(|_| "dbus-daemon".to_owned());
+        let child = Command::new(binary)
+            .arg(format!("--config-file={}", config_path.display()))
+            .arg("--nofork")
+            .arg("--print-address")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(e) => {
+                let _ = fs::remove_dir_all(&dir);
+                return Err(start_error(e));
+            }
+        };
+
+        // the daemon prints its address once it is ready to accept connections
+        let mut address = String::new();
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let mut daemon = DbusDaemon {
+            child,
+            dir,
+            address: String::new(),
+        };
+        match BufReader::new(stdout).read_line(&mut address) {
+            Ok(n) if n > 0 => (),
+            Ok(_) => return Err(start_error("exited before printing its address")),
+            Err(e) => return Err(start_error(e)),
+        }
+        daemon.address = address.trim().to_owned();
+        Ok(daemon)
+    }
+
+    /// pass this to `BleBuilder::with_bus_address`
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+}
+
+impl Drop for DbusDaemon {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
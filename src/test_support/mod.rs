@@ -0,0 +1,594 @@
+//! A fake bluez for testing without bluetooth hardware, enabled with the
+//! `test-support` feature. Describe the adapters and devices bluez should
+//! know about then start it on a private `DbusDaemon`. Connect a `Ble` to the
+//! same bus and use it as you would with the real bluez. The fake answers
+//! connect, pair, read, write and notify from the configuration and can be
+//! told to fail calls with the errors bluez would reply with.
+//!
+//! ```no_run
+//! use bluebus::gatt_server::Flag;
+//! use bluebus::test_support::{DbusDaemon, FakeBluez, FakeCharacteristic, FakeDevice, FakeService};
+//! use bluebus::{Address, BleBuilder, Uuid};
+//!
+//! let adress = Address::new([0x0a; 6]);
+//! let battery_level = FakeCharacteristic::new(Uuid::from_u16(0x2a19), &[Flag::Read, Flag::Notify])
+//!     .with_value(vec![42]);
+//! let device = FakeDevice::new(adress)
+//!     .with_service(FakeService::new(Uuid::from_u16(0x180f)).with_characteristic(battery_level));
+//!
+//! let bus = DbusDaemon::start().unwrap();
+//! let bluez = FakeBluez::new().with_device(device).start(&bus).unwrap();
+//! let mut ble = BleBuilder::default().with_bus_address(bus.address()).build().unwrap();
+//!
+//! ble.connect(adress).unwrap();
+//! assert_eq!(ble.read(adress, 0x2a19u16).unwrap(), vec![42]);
+//! bluez.notify(adress, Uuid::from_u16(0x2a19), &[41]);
+//! ```
+
+use std::collections::HashMap;
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use rustbus::standard_messages;
+
+use crate::address::Address;
+use crate::error::Error;
+use crate::gatt_server::Flag;
+use crate::uuid::Uuid;
+
+use crate::bus::BusSocket;
+mod daemon;
+pub use daemon::DbusDaemon;
+mod server;
+
+/// An error reply, the constructors give the replies bluez uses
+#[derive(Debug, Clone, PartialEq)]
+pub struct FakeError {
+    name: String,
+    message: String,
+}
+
+impl FakeError {
+    pub fn new(name: impl Into<String>, message: impl Into<String>) -> Self {
+        FakeError {
+            name: name.into(),
+            message: message.into(),
+        }
+    }
+
+    pub fn failed(message: impl Into<String>) -> Self {
+        Self::new("org.bluez.Error.Failed", message)
+    }
+
+    pub fn not_permitted() -> Self {
+        Self::new("org.bluez.Error.NotPermitted", "Not permitted")
+    }
+
+    pub fn in_progress() -> Self {
+        Self::new("org.bluez.Error.InProgress", "In Progress")
+    }
+
+    pub fn does_not_exist() -> Self {
+        Self::new("org.bluez.Error.DoesNotExist", "Does Not Exist")
+    }
+
+    pub fn not_supported() -> Self {
+        Self::new("org.bluez.Error.NotSupported", "Operation is not supported")
+    }
+
+    pub fn authentication_failed() -> Self {
+        Self::new(
+            "org.bluez.Error.AuthenticationFailed",
+            "Authentication Failed",
+        )
+    }
+
+    pub fn authentication_canceled() -> Self {
+        Self::new(
+            "org.bluez.Error.AuthenticationCanceled",
+            "Authentication Canceled",
+        )
+    }
+}
+
+/// How a device behaves when it is paired
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pairing {
+    /// pair without asking the agent anything
+    JustWorks,
+    /// ask the agent for a passkey, pairing fails if it is not this one
+    Passkey(u32),
+    /// ask the agent to confirm this passkey
+    Confirmation(u32),
+    /// ask the agent to authorize the pairing
+    Authorization,
+    /// pairing fails with this error
+    Fail(FakeError),
+    /// pairing never completes, like a device that went out of range. Only
+    /// CancelPairing ends it.
+    NoResponse,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FakeDescriptor {
+    uuid: Uuid,
+    value: Vec<u8>,
+}
+
+impl FakeDescriptor {
+    pub fn new(uuid: Uuid) -> Self {
+        FakeDescriptor {
+            uuid,
+            value: Vec::new(),
+        }
+    }
+
+    pub fn with_value(mut self, value: impl Into<Vec<u8>>) -> Self {
+        self.value = value.into();
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FakeCharacteristic {
+    uuid: Uuid,
+    flags: Vec<Flag>,
+    value: Vec<u8>,
+    mtu: u16,
+    descriptors: Vec<FakeDescriptor>,
+    errors: HashMap<String, FakeError>,
+    delays: HashMap<String, Duration>,
+}
+
+impl FakeCharacteristic {
+    pub fn new(uuid: Uuid, flags: &[Flag]) -> Self {
+        FakeCharacteristic {
+            uuid,
+            flags: flags.to_vec(),
+            value: Vec::new(),
+            mtu: 23,
+            descriptors: Vec::new(),
+            errors: HashMap::new(),
+            delays: HashMap::new(),
+        }
+    }
+
+    pub fn with_value(mut self, value: impl Into<Vec<u8>>) -> Self {
+        self.value = value.into();
+        self
+    }
+
+    /// the MTU property and the mtu returned by AcquireNotify, 23 by default
+    pub fn with_mtu(mut self, mtu: u16) -> Self {
+        self.mtu = mtu;
+        self
+    }
+
+    pub fn with_descriptor(mut self, descriptor: FakeDescriptor) -> Self {
+        self.descriptors.push(descriptor);
+        self
+    }
+
+    /// reply to calls of this method (for example `ReadValue`) with the error
+    pub fn fail_on(mut self, method: &str, error: FakeError) -> Self {
+        self.errors.insert(method.to_owned(), error);
+        self
+    }
+
+    /// reply to calls of this method this late, like a slow device
+    pub fn delay_on(mut self, method: &str, delay: Duration) -> Self {
+        self.delays.insert(method.to_owned(), delay);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FakeService {
+    uuid: Uuid,
+    primary: bool,
+    characteristics: Vec<FakeCharacteristic>,
+}
+
+impl FakeService {
+    pub fn new(uuid: Uuid) -> Self {
+        FakeService {
+            uuid,
+            primary: true,
+            characteristics: Vec::new(),
+        }
+    }
+
+    pub fn primary(mut self, primary: bool) -> Self {
+        self.primary = primary;
+        self
+    }
+
+    pub fn with_characteristic(mut self, characteristic: FakeCharacteristic) -> Self {
+        self.characteristics.push(characteristic);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FakeDevice {
+    adress: Address,
+    adapter: u8,
+    name: Option<String>,
+    rssi: Option<i16>,
+    battery: Option<u8>,
+    paired: bool,
+    connected: bool,
+    pairing: Pairing,
+    services: Vec<FakeService>,
+    errors: HashMap<String, FakeError>,
+}
+
+impl FakeDevice {
+    /// a device known to adapter 0 that is not paired or connected and
+    /// pairs without user interaction
+    pub fn new(adress: Address) -> Self {
+        FakeDevice {
+            adress,
+            adapter: 0,
+            name: None,
+            rssi: None,
+            battery: None,
+            paired: false,
+            connected: false,
+            pairing: Pairing::JustWorks,
+            services: Vec::new(),
+            errors: HashMap::new(),
+        }
+    }
+
+    pub fn on_adapter(mut self, adapter_numb: u8) -> Self {
+        self.adapter = adapter_numb;
+        self
+    }
+
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn with_rssi(mut self, rssi: i16) -> Self {
+        self.rssi = Some(rssi);
+        self
+    }
+
+    /// the device reports its battery level, bluez shows it as Battery1
+    pub fn with_battery(mut self, percentage: u8) -> Self {
+        self.battery = Some(percentage);
+        self
+    }
+
+    pub fn paired(mut self, paired: bool) -> Self {
+        self.paired = paired;
+        self
+    }
+
+    pub fn connected(mut self, connected: bool) -> Self {
+        self.connected = connected;
+        self
+    }
+
+    pub fn with_pairing(mut self, pairing: Pairing) -> Self {
+        self.pairing = pairing;
+        self
+    }
+
+    pub fn with_service(mut self, service: FakeService) -> Self {
+        self.services.push(service);
+        self
+    }
+
+    /// reply to calls of this method (for example `Connect`) with the error
+    pub fn fail_on(mut self, method: &str, error: FakeError) -> Self {
+        self.errors.insert(method.to_owned(), error);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FakeAdapter {
+    adress: Address,
+    name: String,
+    powered: bool,
+    errors: HashMap<String, FakeError>,
+}
+
+impl FakeAdapter {
+    pub fn new(adress: Address) -> Self {
+        FakeAdapter {
+            adress,
+            name: "bluebus-fake".to_owned(),
+            powered: true,
+            errors: HashMap::new(),
+        }
+    }
+
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    pub fn powered(mut self, powered: bool) -> Self {
+        self.powered = powered;
+        self
+    }
+
+    /// reply to calls of this method (for example `StartDiscovery`) with the error
+    pub fn fail_on(mut self, method: &str, error: FakeError) -> Self {
+        self.errors.insert(method.to_owned(), error);
+        self
+    }
+}
+
+/// A method call the fake recieved
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedCall {
+    pub path: String,
+    pub interface: String,
+    pub member: String,
+}
+
+/// An object of a registerd gatt application as bluez reads it back with
+/// GetManagedObjects, sorted by path
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppObject {
+    pub path: String,
+    pub interface: String,
+    pub uuid: Uuid,
+    /// the service of a characteristic or the characteristic of a descriptor
+    pub parent: Option<String>,
+    pub flags: Vec<String>,
+}
+
+/// A registerd advertisement as bluez reads it back
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdvertisementProperties {
+    pub typ: String,
+    pub local_name: Option<String>,
+    pub service_uuids: Vec<Uuid>,
+}
+
+/// Configuration of the fake, the n-th adapter added is `hci{n}`. If no
+/// adapters are added a single adapter `hci0` is used.
+#[derive(Debug, Clone, Default)]
+pub struct FakeBluez {
+    adapters: Vec<FakeAdapter>,
+    devices: Vec<FakeDevice>,
+}
+
+impl FakeBluez {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_adapter(mut self, adapter: FakeAdapter) -> Self {
+        self.adapters.push(adapter);
+        self
+    }
+
+    pub fn with_device(mut self, device: FakeDevice) -> Self {
+        self.devices.push(device);
+        self
+    }
+
+    /// claim `org.bluez` on the bus and start answering calls on a
+    /// background thread
+    pub fn start(mut self, bus: &DbusDaemon) -> Result<RunningFakeBluez, Error> {
+        if self.adapters.is_empty() {
+            let adress = Address::new([0x00, 0x00, 0x00, 0x00, 0x00, 0x01]);
+            self.adapters.push(FakeAdapter::new(adress));
+        }
+
+        let timeout = Duration::from_secs(5);
+        let mut connection = BusSocket::connect(bus.address())?;
+        connection.call(&mut standard_messages::hello(), timeout)?;
+
+        let mut request_name = standard_messages::request_name(
+            "org.bluez".to_owned(),
+            standard_messages::DBUS_NAME_FLAG_DO_NOT_QUEUE,
+        );
+        let reply = connection.call(&mut request_name, timeout)?;
+        match reply.body.parser().get::<u32>() {
+            Ok(standard_messages::DBUS_REQUEST_NAME_REPLY_PRIMARY_OWNER) => (),
+            _ => return Err(Error::UnexpectedDbusReply),
+        }
+
+        // a battery provider reports changes through signals
+        for rule in &[
+            "type='signal',interface='org.freedesktop.DBus.ObjectManager'",
+            "type='signal',interface='org.freedesktop.DBus.Properties'",
+        ] {
+            let mut add_match = standard_messages::add_match(rule.to_string());
+            connection.call(&mut add_match, timeout)?;
+        }
+
+        let state = Arc::new(Mutex::new(server::State::new(self)));
+        let thread = {
+            let state = state.clone();
+            thread::spawn(move || server::run(connection, state))
+        };
+        Ok(RunningFakeBluez {
+            state,
+            thread: Some(thread),
+        })
+    }
+}
+
+/// The running fake, bluez disappears from the bus when this is stopped
+/// or dropped. Use it to inspect what the code under test did and to make
+/// the devices act.
+pub struct RunningFakeBluez {
+    state: Arc<Mutex<server::State>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RunningFakeBluez {
+    fn state(&self) -> std::sync::MutexGuard<'_, server::State> {
+        self.state.lock().expect("fake bluez thread panicked")
+    }
+
+    /// current value of the first characteristic with this uuid, written
+    /// values show up here
+    pub fn value(&self, adress: Address, uuid: Uuid) -> Option<Vec<u8>> {
+        self.state().value(adress, uuid)
+    }
+
+    /// change the value of a characteristic without notifying
+    pub fn set_value(&self, adress: Address, uuid: Uuid, value: &[u8]) {
+        self.state().set_value(adress, uuid, value, false)
+    }
+
+    /// change the value of a characteristic and send it to every notify file
+    /// descriptor and, if notifying, as a PropertiesChanged signal
+    pub fn notify(&self, adress: Address, uuid: Uuid, value: &[u8]) {
+        self.state().set_value(adress, uuid, value, true)
+    }
+
+    pub fn is_paired(&self, adress: Address) -> bool {
+        self.state()
+            .device(adress)
+            .map(|d| d.paired)
+            .unwrap_or(false)
+    }
+
+    pub fn is_connected(&self, adress: Address) -> bool {
+        self.state()
+            .device(adress)
+            .map(|d| d.connected)
+            .unwrap_or(false)
+    }
+
+    /// the device drops the connection, this closes its notify file descriptors
+    pub fn disconnect(&self, adress: Address) {
+        self.state().set_connected(adress, false)
+    }
+
+    /// the device (re)connects on its own
+    pub fn connect(&self, adress: Address) {
+        self.state().set_connected(adress, true)
+    }
+
+    /// the device is heard with a new signal strength
+    pub fn set_rssi(&self, adress: Address, rssi: i16) {
+        self.state().set_rssi(adress, rssi)
+    }
+
+    /// the device reports a new battery level
+    pub fn set_battery(&self, adress: Address, percentage: u8) {
+        self.state().set_battery(adress, percentage)
+    }
+
+    /// the level a registerd battery provider gives for this device
+    pub fn provided_battery(&self, adress: Address) -> Option<u8> {
+        self.state().provided_battery(adress)
+    }
+
+    /// true while a profile with this uuid is registerd
+    pub fn is_profile_registerd(&self, uuid: Uuid) -> bool {
+        self.state().is_profile_registerd(uuid)
+    }
+
+    /// the device connects to the profile registerd for the uuid, returns
+    /// the device end of the connection
+    pub fn connect_profile(&self, adress: Address, uuid: Uuid) -> Option<UnixStream> {
+        self.state()
+            .new_profile_connection(adress, uuid)
+            .ok()
+            .flatten()
+    }
+
+    /// hand the profile a NewConnection that has no file descriptor, as a
+    /// broken bluez might. Returns the call to get the reply of.
+    pub fn connect_profile_without_fd(&self, adress: Address, uuid: Uuid) -> Option<usize> {
+        self.state().profile_call_without_fd(adress, uuid)
+    }
+
+    /// the device end of a connection made with `Ble::connect_profile`
+    pub fn take_profile_socket(&self, adress: Address, uuid: Uuid) -> Option<UnixStream> {
+        self.state().take_profile_socket(adress, uuid)
+    }
+
+    /// ask the profile to close its connection to the device
+    pub fn request_profile_disconnection(&self, adress: Address, uuid: Uuid) {
+        let _ = self.state().request_profile_disconnection(adress, uuid);
+    }
+
+    /// bluez releases the profile registerd for the uuid
+    pub fn release_profile(&self, uuid: Uuid) {
+        self.state().release_profile(uuid)
+    }
+
+    /// the objects of the gatt application registerd at this path, None
+    /// until bluez read them
+    pub fn app_objects(&self, path: &str) -> Option<Vec<AppObject>> {
+        self.state().app_objects(path)
+    }
+
+    /// every value the application sent for this characteristic path
+    pub fn app_notified(&self, path: &str) -> Vec<Vec<u8>> {
+        self.state().app_notified(path)
+    }
+
+    /// a remote device calls an object of a registerd gatt application, the
+    /// value is passed to WriteValue. Returns the call to get the reply of.
+    /// Paths outside every application go to whoever registerd the agent.
+    pub fn call_app(
+        &self,
+        path: &str,
+        interface: &str,
+        member: &str,
+        value: Option<&[u8]>,
+    ) -> Result<usize, Error> {
+        self.state().call_app(path, interface, member, value)
+    }
+
+    /// the value or error the application replied to the call with, None
+    /// until it replied
+    pub fn app_reply(&self, call: usize) -> Option<Result<Vec<u8>, FakeError>> {
+        self.state().app_reply(call)
+    }
+
+    /// the advertisement registerd at this path, None until bluez read it
+    pub fn advertisement(&self, path: &str) -> Option<AdvertisementProperties> {
+        self.state().advertisement(path)
+    }
+
+    /// bluez stops the advertisement registerd at this path on its own
+    pub fn release_advertisement(&self, path: &str) {
+        self.state().release_advertisement(path)
+    }
+
+    /// a new device shows up, for example during discovery
+    pub fn add_device(&self, device: FakeDevice) {
+        self.state().add_device(device)
+    }
+
+    /// every method call recieved so far, oldest first
+    pub fn calls(&self) -> Vec<RecordedCall> {
+        self.state().calls.clone()
+    }
+
+    /// release `org.bluez` and stop the background thread
+    pub fn stop(mut self) {
+        self.shutdown()
+    }
+
+    fn shutdown(&mut self) {
+        self.state().stop = true;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for RunningFakeBluez {
+    fn drop(&mut self) {
+        self.shutdown()
+    }
+}
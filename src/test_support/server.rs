@@ -0,0 +1,1618 @@
+use std::collections::{HashMap, HashSet};
+use std::os::unix::io::{IntoRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::socket::{self, AddressFamily, MsgFlags, SockFlag, SockType};
+use rustbus::client_conn::Timeout;
+use rustbus::message_builder::{DynamicHeader, MarshalledMessage, MessageType};
+use rustbus::params::{self, Param};
+use rustbus::MessageBuilder;
+
+use super::{
+    AdvertisementProperties, AppObject, FakeBluez, FakeDevice, FakeError, Pairing, RecordedCall,
+};
+use crate::address::Address;
+use crate::bus::BusSocket;
+use crate::dbus_helpers::*;
+use crate::error::Error;
+use crate::gatt_server::{bytes_from_param, flags_param, Flag, RequestInfo};
+use crate::info;
+use crate::path::{BluezPath, PathKind};
+use crate::uuid::Uuid;
+
+/// how often the server thread checks if it should stop
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+/// the number of advertisements the fake adapters support
+const ADVERTISING_INSTANCES: u8 = 5;
+
+const ADAPTER_IFACE: &str = "org.bluez.Adapter1";
+const DEVICE_IFACE: &str = "org.bluez.Device1";
+const SERVICE_IFACE: &str = "org.bluez.GattService1";
+const CHAR_IFACE: &str = "org.bluez.GattCharacteristic1";
+const DESC_IFACE: &str = "org.bluez.GattDescriptor1";
+const BATTERY_IFACE: &str = "org.bluez.Battery1";
+const PROVIDER_IFACE: &str = "org.bluez.BatteryProvider1";
+
+type Properties = Vec<(&'static str, Param<'static, 'static>)>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Gatt {
+    Service(usize),
+    Characteristic(usize, usize),
+    Descriptor(usize, usize, usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Object {
+    Root,
+    Manager,
+    Adapter(usize),
+    Device(usize),
+    Gatt(usize, Gatt),
+}
+
+/// our end of a socket handed out by AcquireNotify
+struct NotifySocket(RawFd);
+
+impl NotifySocket {
+    /// the acquirer closed its end
+    fn hung_up(&self) -> bool {
+        let mut fds = [PollFd::new(self.0, PollFlags::empty())];
+        let revents = match poll(&mut fds, 0) {
+            Ok(_) => fds[0].revents(),
+            Err(_) => None,
+        };
+        revents.map_or(false, |revents| revents.contains(PollFlags::POLLHUP))
+    }
+}
+
+impl Drop for NotifySocket {
+    fn drop(&mut self) {
+        let _ = nix::unistd::close(self.0);
+    }
+}
+
+struct Agent {
+    bus_name: String,
+    path: String,
+}
+
+/// a battery provider registerd through BatteryProviderManager1
+struct BatteryProvider {
+    bus_name: String,
+    path: String,
+    /// serial of our GetManagedObjects call to the provider once sent
+    objects_serial: Option<u32>,
+    /// object path of the battery to the device path and percentage
+    batteries: HashMap<String, (String, u8)>,
+}
+
+/// an application registerd through GattManager1
+struct GattApp {
+    bus_name: String,
+    path: String,
+    /// serial of our GetManagedObjects call to the application once sent
+    objects_serial: Option<u32>,
+    /// what it exported, once read back
+    objects: Option<Vec<AppObject>>,
+    /// values it sent as PropertiesChanged, oldest first
+    notified: Vec<(String, Vec<u8>)>,
+}
+
+/// an advertisement registerd through LEAdvertisingManager1
+struct Advertisement {
+    bus_name: String,
+    path: String,
+    /// serial of our GetAll call to the advertisement once sent
+    properties_serial: Option<u32>,
+    properties: Option<AdvertisementProperties>,
+}
+
+/// a call made to an application as a remote device would
+struct AppCall {
+    request: Option<MarshalledMessage>,
+    serial: Option<u32>,
+    reply: Option<Result<Vec<u8>, FakeError>>,
+}
+
+/// a profile registerd through ProfileManager1
+struct Profile {
+    bus_name: String,
+    path: String,
+    uuid: Uuid,
+}
+
+struct PendingPair {
+    call: DynamicHeader,
+    device: usize,
+    request_serial: Option<u32>,
+}
+
+pub(super) struct State {
+    adapters: Vec<super::FakeAdapter>,
+    discovering: Vec<bool>,
+    devices: Vec<FakeDevice>,
+    agent: Option<Agent>,
+    pending_pair: Option<PendingPair>,
+    notify_sockets: HashMap<BluezPath, Vec<NotifySocket>>,
+    notifying: HashSet<BluezPath>,
+    gatt_apps: Vec<GattApp>,
+    app_calls: Vec<AppCall>,
+    advertisements: Vec<Advertisement>,
+    battery_provider: Option<BatteryProvider>,
+    profiles: Vec<Profile>,
+    /// our end of the connections made with ConnectProfile
+    profile_sockets: Vec<(Address, Uuid, UnixStream)>,
+    outbox: Vec<MarshalledMessage>,
+    /// replies to send once their time comes, see `FakeCharacteristic::delay_on`
+    delayed: Vec<(Instant, MarshalledMessage)>,
+    /// replies to the calls we made
+    responses: HashMap<u32, MarshalledMessage>,
+    pub(super) calls: Vec<RecordedCall>,
+    pub(super) stop: bool,
+}
+
+pub(super) fn run(mut connection: BusSocket, state: Arc<Mutex<State>>) {
+    loop {
+        let msg = match connection.recv(Timeout::Duration(POLL_INTERVAL)) {
+            Ok(msg) => msg,
+            Err(_) => return,
+        };
+        let mut state = match state.lock() {
+            Ok(state) => state,
+            Err(_) => return,
+        };
+        if state.stop {
+            // remote ends see a hangup, dropping the connection releases org.bluez
+            state.notify_sockets.clear();
+            return;
+        }
+        if state.serve(&mut connection, msg).is_err() {
+            return;
+        }
+    }
+}
+
+fn send(connection: &mut BusSocket, mut msg: MarshalledMessage) -> Result<(), Error> {
+    connection.send(&mut msg)?;
+    // the reciever got its own copy
+    for fd in msg.raw_fds.drain(..) {
+        let _ = nix::unistd::close(fd);
+    }
+    Ok(())
+}
+
+fn reply_error(call: &DynamicHeader, error: &FakeError) -> Option<MarshalledMessage> {
+    Some(error_response(call, &error.name, &error.message))
+}
+
+fn path_arg(param: Option<Param>) -> Option<String> {
+    match param.and_then(unwrap_base)? {
+        params::Base::ObjectPath(path) => Some(path),
+        _ => None,
+    }
+}
+
+fn bool_param(value: bool) -> Param<'static, 'static> {
+    Param::Base(params::Base::Boolean(value))
+}
+
+fn flags_contain(flags: &[Flag], any_of: &[Flag]) -> bool {
+    flags.iter().any(|flag| any_of.contains(flag))
+}
+
+impl State {
+    pub(super) fn new(config: FakeBluez) -> Self {
+        State {
+            discovering: vec![false; config.adapters.len()],
+            adapters: config.adapters,
+            devices: config.devices,
+            agent: None,
+            pending_pair: None,
+            notify_sockets: HashMap::new(),
+            notifying: HashSet::new(),
+            gatt_apps: Vec::new(),
+            app_calls: Vec::new(),
+            advertisements: Vec::new(),
+            battery_provider: None,
+            profiles: Vec::new(),
+            profile_sockets: Vec::new(),
+            outbox: Vec::new(),
+            delayed: Vec::new(),
+            responses: HashMap::new(),
+            calls: Vec::new(),
+            stop: false,
+        }
+    }
+
+    fn serve(
+        &mut self,
+        connection: &mut BusSocket,
+        msg: Option<MarshalledMessage>,
+    ) -> Result<(), Error> {
+        match msg {
+            Some(call) if matches!(call.typ, MessageType::Call) => {
+                let delay = self.configured_delay(&call);
+                match (self.handle_call(call)?, delay) {
+                    (Some(reply), Some(delay)) => {
+                        self.delayed.push((Instant::now() + delay, reply))
+                    }
+                    (Some(reply), None) => send(connection, reply)?,
+                    (None, _) => (),
+                }
+            }
+            Some(signal) if matches!(signal.typ, MessageType::Signal) => {
+                if self.is_app_signal(&signal) {
+                    self.handle_app_signal(signal)?;
+                } else {
+                    self.handle_provider_signal(signal)?;
+                }
+            }
+            Some(response) => {
+                if let Some(serial) = response.dynheader.response_serial {
+                    self.responses.insert(serial, response);
+                }
+            }
+            None => (),
+        }
+        self.progress_pairing(connection)?;
+        self.progress_battery_provider(connection)?;
+        self.progress_gatt_apps(connection)?;
+        self.progress_advertisements(connection)?;
+        self.progress_app_calls(connection)?;
+        for msg in std::mem::take(&mut self.outbox) {
+            send(connection, msg)?;
+        }
+        let now = Instant::now();
+        let (due, later) = std::mem::take(&mut self.delayed)
+            .into_iter()
+            .partition(|(at, _)| *at <= now);
+        self.delayed = later;
+        for (_, reply) in due {
+            send(connection, reply)?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn device(&self, adress: Address) -> Option<&FakeDevice> {
+        self.devices.iter().find(|d| d.adress == adress)
+    }
+
+    fn device_path(device: &FakeDevice) -> BluezPath {
+        BluezPath::adapter(device.adapter).device(device.adress)
+    }
+
+    /// the path of every gatt object of a device, handles are given out in order
+    fn gatt_objects(device: &FakeDevice) -> Vec<(BluezPath, Gatt)> {
+        let mut objects = Vec::new();
+        let mut handle = 0x0001;
+        let device_path = Self::device_path(device);
+        for (s, service) in device.services.iter().enumerate() {
+            let service_path = device_path.service(handle);
+            handle += 1;
+            objects.push((service_path, Gatt::Service(s)));
+            for (c, characteristic) in service.characteristics.iter().enumerate() {
+                let char_path = service_path.characteristic(handle);
+                handle += 1;
+                objects.push((char_path, Gatt::Characteristic(s, c)));
+                for d in 0..characteristic.descriptors.len() {
+                    objects.push((char_path.descriptor(handle), Gatt::Descriptor(s, c, d)));
+                    handle += 1;
+                }
+            }
+        }
+        objects
+    }
+
+    fn find_object(&self, path: &str) -> Option<Object> {
+        match path {
+            "/" => return Some(Object::Root),
+            "/org/bluez" => return Some(Object::Manager),
+            _ => (),
+        }
+        let path: BluezPath = path.parse().ok()?;
+        let adapter = path.adapter_numb() as usize;
+        if adapter >= self.adapters.len() {
+            return None;
+        }
+        if path.kind() == PathKind::Adapter {
+            return Some(Object::Adapter(adapter));
+        }
+
+        let device_path = path.device_path()?;
+        let d = self
+            .devices
+            .iter()
+            .position(|device| Self::device_path(device) == device_path)?;
+        if path.kind() == PathKind::Device {
+            return Some(Object::Device(d));
+        }
+        Self::gatt_objects(&self.devices[d])
+            .into_iter()
+            .find(|(gatt_path, _)| gatt_path == &path)
+            .map(|(_, gatt)| Object::Gatt(d, gatt))
+    }
+
+    fn object_path(&self, object: Object) -> String {
+        match object {
+            Object::Root => "/".to_owned(),
+            Object::Manager => "/org/bluez".to_owned(),
+            Object::Adapter(a) => BluezPath::adapter(a as u8).to_string(),
+            Object::Device(d) => Self::device_path(&self.devices[d]).to_string(),
+            Object::Gatt(d, gatt) => Self::gatt_objects(&self.devices[d])
+                .into_iter()
+                .find(|(_, g)| g == &gatt)
+                .map(|(path, _)| path.to_string())
+                .unwrap_or_default(),
+        }
+    }
+
+    fn interfaces(&self, object: Object) -> Vec<(&'static str, Properties)> {
+        match object {
+            Object::Root => Vec::new(),
+            Object::Manager => vec![
+                ("org.bluez.AgentManager1", Vec::new()),
+                ("org.bluez.ProfileManager1", Vec::new()),
+            ],
+            Object::Adapter(a) => {
+                let adapter = &self.adapters[a];
+                let active = self.advertisements.len() as u8;
+                let byte = |n| Param::Base(params::Base::Byte(n));
+                vec![
+                    (
+                        ADAPTER_IFACE,
+                        vec![
+                            ("Address", string_param(adapter.adress.to_string())),
+                            ("AddressType", string_param("public")),
+                            ("Name", string_param(adapter.name.as_str())),
+                            ("Alias", string_param(adapter.name.as_str())),
+                            ("Powered", bool_param(adapter.powered)),
+                            ("Discovering", bool_param(self.discovering[a])),
+                        ],
+                    ),
+                    ("org.bluez.GattManager1", Vec::new()),
+                    ("org.bluez.BatteryProviderManager1", Vec::new()),
+                    (
+                        "org.bluez.LEAdvertisingManager1",
+                        vec![
+                            ("ActiveInstances", byte(active)),
+                            (
+                                "SupportedInstances",
+                                byte(ADVERTISING_INSTANCES.saturating_sub(active)),
+                            ),
+                        ],
+                    ),
+                ]
+            }
+            Object::Device(d) => {
+                let mut interfaces = vec![(DEVICE_IFACE, self.device_properties(d))];
+                if let Some(percentage) = self.devices[d].battery {
+                    let percentage = Param::Base(params::Base::Byte(percentage));
+                    interfaces.push((BATTERY_IFACE, vec![("Percentage", percentage)]));
+                }
+                interfaces
+            }
+            Object::Gatt(d, gatt) => vec![self.gatt_properties(d, gatt)],
+        }
+    }
+
+    fn device_properties(&self, d: usize) -> Properties {
+        let device = &self.devices[d];
+        let alias = match &device.name {
+            Some(name) => name.clone(),
+            None => device.adress.to_string().replace(":", "-"),
+        };
+        let uuids = device.services.iter().map(|s| s.uuid.to_string()).collect();
+        let mut properties = vec![
+            ("Address", string_param(device.adress.to_string())),
+            (
+                "AddressType",
+                string_param(device.adress.kind().to_string()),
+            ),
+            ("Alias", string_param(alias)),
+            ("Paired", bool_param(device.paired)),
+            ("Trusted", bool_param(false)),
+            ("Connected", bool_param(device.connected)),
+            ("ServicesResolved", bool_param(device.connected)),
+            (
+                "Adapter",
+                objectpath_param(BluezPath::adapter(device.adapter).to_string()),
+            ),
+            ("UUIDs", string_array_param(uuids)),
+        ];
+        if let Some(name) = &device.name {
+            properties.push(("Name", string_param(name.as_str())));
+        }
+        if let Some(rssi) = device.rssi {
+            properties.push(("RSSI", Param::Base(params::Base::Int16(rssi))));
+        }
+        properties
+    }
+
+    fn gatt_properties(&self, d: usize, gatt: Gatt) -> (&'static str, Properties) {
+        let device = &self.devices[d];
+        let objects = Self::gatt_objects(device);
+        let path_of = |target: Gatt| {
+            let (path, _) = objects.iter().find(|(_, g)| g == &target).unwrap();
+            objectpath_param(path.to_string())
+        };
+        match gatt {
+            Gatt::Service(s) => {
+                let service = &device.services[s];
+                let properties = vec![
+                    ("UUID", string_param(service.uuid.to_string())),
+                    ("Primary", bool_param(service.primary)),
+                    (
+                        "Device",
+                        objectpath_param(Self::device_path(device).to_string()),
+                    ),
+                ];
+                (SERVICE_IFACE, properties)
+            }
+            Gatt::Characteristic(s, c) => {
+                let characteristic = &device.services[s].characteristics[c];
+                let path = objects.iter().find(|(_, g)| g == &gatt).unwrap().0;
+                let notify_acquired = self.notify_sockets.contains_key(&path);
+                let properties = vec![
+                    ("UUID", string_param(characteristic.uuid.to_string())),
+                    ("Service", path_of(Gatt::Service(s))),
+                    ("Value", byte_array_param(&characteristic.value)),
+                    ("Flags", flags_param(&characteristic.flags)),
+                    ("Notifying", bool_param(self.notifying.contains(&path))),
+                    ("NotifyAcquired", bool_param(notify_acquired)),
+                    ("MTU", Param::Base(params::Base::Uint16(characteristic.mtu))),
+                ];
+                (CHAR_IFACE, properties)
+            }
+            Gatt::Descriptor(s, c, k) => {
+                let descriptor = &device.services[s].characteristics[c].descriptors[k];
+                let properties = vec![
+                    ("UUID", string_param(descriptor.uuid.to_string())),
+                    ("Characteristic", path_of(Gatt::Characteristic(s, c))),
+                    ("Value", byte_array_param(&descriptor.value)),
+                ];
+                (DESC_IFACE, properties)
+            }
+        }
+    }
+
+    fn interfaces_as_param(&self, object: Object) -> Param<'static, 'static> {
+        let interfaces = self
+            .interfaces(object)
+            .into_iter()
+            .map(|(name, properties)| (name, variant_dict_param(properties)))
+            .collect();
+        interfaces_param(interfaces)
+    }
+
+    fn managed_objects(&self) -> Param<'static, 'static> {
+        let mut objects = vec![Object::Manager];
+        objects.extend((0..self.adapters.len()).map(Object::Adapter));
+        for (d, device) in self.devices.iter().enumerate() {
+            objects.push(Object::Device(d));
+            let gatt = Self::gatt_objects(device).into_iter();
+            objects.extend(gatt.map(|(_, gatt)| Object::Gatt(d, gatt)));
+        }
+        let objects = objects
+            .into_iter()
+            .map(|object| (self.object_path(object), self.interfaces_as_param(object)))
+            .collect();
+        managed_objects_param(objects)
+    }
+
+    fn emit_properties_changed(&mut self, object: Object, interface: &str, changed: Properties) {
+        let path = self.object_path(object);
+        let signal = properties_changed(&path, interface, variant_dict_param(changed))
+            .expect("constant signature is valid");
+        self.outbox.push(signal);
+    }
+
+    fn configured_error(&self, object: Object, member: &str) -> Option<FakeError> {
+        let errors = match object {
+            Object::Adapter(a) => &self.adapters[a].errors,
+            Object::Device(d) => &self.devices[d].errors,
+            Object::Gatt(d, Gatt::Characteristic(s, c)) => {
+                &self.devices[d].services[s].characteristics[c].errors
+            }
+            _ => return None,
+        };
+        errors.get(member).cloned()
+    }
+
+    fn configured_delay(&self, call: &MarshalledMessage) -> Option<Duration> {
+        let path = call.dynheader.object.as_deref()?;
+        let member = call.dynheader.member.as_deref()?;
+        match self.find_object(path)? {
+            Object::Gatt(d, Gatt::Characteristic(s, c)) => {
+                let characteristic = &self.devices[d].services[s].characteristics[c];
+                characteristic.delays.get(member).copied()
+            }
+            _ => None,
+        }
+    }
+
+    fn handle_call(&mut self, call: MarshalledMessage) -> Result<Option<MarshalledMessage>, Error> {
+        let header = call.dynheader.clone();
+        let path = header.object.clone().unwrap_or_default();
+        let interface = header.interface.clone().unwrap_or_default();
+        let member = header.member.clone().unwrap_or_default();
+        self.calls.push(RecordedCall {
+            path: path.clone(),
+            interface: interface.clone(),
+            member: member.clone(),
+        });
+
+        let object = match self.find_object(&path) {
+            Some(object) => object,
+            None => {
+                let error =
+                    FakeError::new("org.freedesktop.DBus.Error.UnknownObject", "no such object");
+                return Ok(reply_error(&header, &error));
+            }
+        };
+        if let Some(error) = self.configured_error(object, &member) {
+            return Ok(reply_error(&header, &error));
+        }
+
+        let mut params = call.unmarshall_all()?.params.into_iter();
+        let mut reply = header.make_response();
+        match (interface.as_str(), member.as_str(), object) {
+            ("org.freedesktop.DBus.ObjectManager", "GetManagedObjects", Object::Root) => {
+                reply.body.push_old_param(&self.managed_objects())?;
+            }
+            ("org.freedesktop.DBus.Properties", "GetAll", _) => {
+                let wanted = params.next().and_then(unwrap_base).and_then(unwrap_string);
+                let properties = self
+                    .interfaces(object)
+                    .into_iter()
+                    .find(|(name, _)| Some(*name) == wanted.as_deref());
+                match properties {
+                    Some((_, properties)) => {
+                        reply.body.push_old_param(&variant_dict_param(properties))?
+                    }
+                    None => return Ok(reply_error(&header, &invalid_args("No such interface"))),
+                }
+            }
+            ("org.freedesktop.DBus.Properties", "Get", _) => {
+                let wanted = params.next().and_then(unwrap_base).and_then(unwrap_string);
+                let property = params.next().and_then(unwrap_base).and_then(unwrap_string);
+                let value = self
+                    .interfaces(object)
+                    .into_iter()
+                    .find(|(name, _)| Some(*name) == wanted.as_deref())
+                    .and_then(|(_, properties)| {
+                        properties
+                            .into_iter()
+                            .find(|(name, _)| Some(*name) == property.as_deref())
+                    });
+                match value {
+                    Some((_, value)) => {
+                        let variant = params::Container::make_variant(value);
+                        reply.body.push_old_param(&Param::Container(variant))?;
+                    }
+                    None => return Ok(reply_error(&header, &invalid_args("No such property"))),
+                }
+            }
+            ("org.bluez.AgentManager1", "RegisterAgent", Object::Manager) => {
+                let path = path_arg(params.next()).unwrap_or_default();
+                let bus_name = header.sender.clone().unwrap_or_default();
+                self.agent = Some(Agent { bus_name, path });
+            }
+            ("org.bluez.AgentManager1", "UnregisterAgent", Object::Manager) => {
+                self.agent = None;
+            }
+            ("org.bluez.AgentManager1", "RequestDefaultAgent", Object::Manager) => (),
+            (ADAPTER_IFACE, "StartDiscovery", Object::Adapter(a)) => {
+                if self.discovering[a] {
+                    return Ok(reply_error(&header, &FakeError::in_progress()));
+                }
+                self.set_discovering(a, true);
+            }
+            (ADAPTER_IFACE, "StopDiscovery", Object::Adapter(a)) => {
+                if !self.discovering[a] {
+                    return Ok(reply_error(
+                        &header,
+                        &FakeError::failed("No discovery started"),
+                    ));
+                }
+                self.set_discovering(a, false);
+            }
+            (ADAPTER_IFACE, "SetDiscoveryFilter", Object::Adapter(_)) => (),
+            (ADAPTER_IFACE, "RemoveDevice", Object::Adapter(a)) => {
+                let device = path_arg(params.next()).and_then(|p| self.find_object(&p));
+                match device {
+                    Some(Object::Device(d)) if self.devices[d].adapter as usize == a => {
+                        self.remove_device(d)
+                    }
+                    _ => return Ok(reply_error(&header, &FakeError::does_not_exist())),
+                }
+            }
+            (DEVICE_IFACE, "Connect", Object::Device(d)) => {
+                let adress = self.devices[d].adress;
+                self.set_connected(adress, true);
+            }
+            (DEVICE_IFACE, "Disconnect", Object::Device(d)) => {
+                let adress = self.devices[d].adress;
+                self.set_connected(adress, false);
+            }
+            (DEVICE_IFACE, "ConnectProfile", Object::Device(d)) => {
+                let uuid = params.next().and_then(unwrap_base).and_then(unwrap_string);
+                let uuid = match uuid.and_then(|uuid| uuid.parse().ok()) {
+                    Some(uuid) => uuid,
+                    None => return Ok(reply_error(&header, &invalid_args("Invalid UUID"))),
+                };
+                let adress = self.devices[d].adress;
+                let ours = match self.new_profile_connection(adress, uuid)? {
+                    Some(ours) => ours,
+                    None => return Ok(reply_error(&header, &FakeError::does_not_exist())),
+                };
+                self.profile_sockets.push((adress, uuid, ours));
+                // like bluez only reply once the connection is handed over
+                self.outbox.push(reply);
+                return Ok(None);
+            }
+            (DEVICE_IFACE, "DisconnectProfile", Object::Device(d)) => {
+                let uuid = params.next().and_then(unwrap_base).and_then(unwrap_string);
+                let uuid: Uuid = match uuid.and_then(|uuid| uuid.parse().ok()) {
+                    Some(uuid) => uuid,
+                    None => return Ok(reply_error(&header, &invalid_args("Invalid UUID"))),
+                };
+                let adress = self.devices[d].adress;
+                if !self.request_profile_disconnection(adress, uuid)? {
+                    return Ok(reply_error(&header, &FakeError::does_not_exist()));
+                }
+                self.profile_sockets
+                    .retain(|(a, u, _)| (*a, *u) != (adress, uuid));
+            }
+            (DEVICE_IFACE, "Pair", Object::Device(d)) => return Ok(self.start_pairing(header, d)),
+            (DEVICE_IFACE, "CancelPairing", Object::Device(d)) => match self.pending_pair.take() {
+                Some(pending) if pending.device == d => {
+                    let error = FakeError::authentication_canceled();
+                    self.outbox.extend(reply_error(&pending.call, &error));
+                }
+                other => {
+                    self.pending_pair = other;
+                    return Ok(reply_error(&header, &FakeError::does_not_exist()));
+                }
+            },
+            (CHAR_IFACE, _, Object::Gatt(d, Gatt::Characteristic(s, c))) => {
+                return self.handle_char_call(header, d, s, c, params.collect());
+            }
+            (DESC_IFACE, "ReadValue", Object::Gatt(d, Gatt::Descriptor(s, c, k))) => {
+                if !self.devices[d].connected {
+                    return Ok(reply_error(&header, &FakeError::failed("Not connected")));
+                }
+                let info = RequestInfo::from_options(params.next());
+                let value = &self.devices[d].services[s].characteristics[c].descriptors[k].value;
+                let offset = (info.offset as usize).min(value.len());
+                reply.body.push_param(&value[offset..])?;
+            }
+            (DESC_IFACE, "WriteValue", Object::Gatt(d, Gatt::Descriptor(s, c, k))) => {
+                if !self.devices[d].connected {
+                    return Ok(reply_error(&header, &FakeError::failed("Not connected")));
+                }
+                let value = bytes_from_param(params.next())?;
+                self.devices[d].services[s].characteristics[c].descriptors[k].value = value;
+            }
+            ("org.bluez.GattManager1", "RegisterApplication", Object::Adapter(_)) => {
+                let path = path_arg(params.next()).unwrap_or_default();
+                if self.gatt_apps.iter().any(|app| app.path == path) {
+                    return Ok(reply_error(&header, &already_exists()));
+                }
+                self.gatt_apps.push(GattApp {
+                    bus_name: header.sender.clone().unwrap_or_default(),
+                    path,
+                    objects_serial: None,
+                    objects: None,
+                    notified: Vec::new(),
+                });
+            }
+            ("org.bluez.GattManager1", "UnregisterApplication", Object::Adapter(_)) => {
+                let path = path_arg(params.next()).unwrap_or_default();
+                if !self.gatt_apps.iter().any(|app| app.path == path) {
+                    return Ok(reply_error(&header, &FakeError::does_not_exist()));
+                }
+                self.gatt_apps.retain(|app| app.path != path);
+            }
+            ("org.bluez.LEAdvertisingManager1", "RegisterAdvertisement", Object::Adapter(_)) => {
+                let path = path_arg(params.next()).unwrap_or_default();
+                if self.advertisements.iter().any(|a| a.path == path) {
+                    return Ok(reply_error(&header, &already_exists()));
+                }
+                if self.advertisements.len() >= ADVERTISING_INSTANCES as usize {
+                    let error = FakeError::new(
+                        "org.bluez.Error.NotPermitted",
+                        "Maximum advertisements reached",
+                    );
+                    return Ok(reply_error(&header, &error));
+                }
+                self.advertisements.push(Advertisement {
+                    bus_name: header.sender.clone().unwrap_or_default(),
+                    path,
+                    properties_serial: None,
+                    properties: None,
+                });
+            }
+            ("org.bluez.LEAdvertisingManager1", "UnregisterAdvertisement", Object::Adapter(_)) => {
+                let path = path_arg(params.next()).unwrap_or_default();
+                if !self.advertisements.iter().any(|a| a.path == path) {
+                    return Ok(reply_error(&header, &FakeError::does_not_exist()));
+                }
+                self.advertisements.retain(|a| a.path != path);
+            }
+            ("org.bluez.ProfileManager1", "RegisterProfile", Object::Manager) => {
+                let path = path_arg(params.next()).unwrap_or_default();
+                let uuid = params.next().and_then(unwrap_base).and_then(unwrap_string);
+                let uuid = match uuid.and_then(|uuid| uuid.parse().ok()) {
+                    Some(uuid) => uuid,
+                    None => return Ok(reply_error(&header, &invalid_args("Invalid UUID"))),
+                };
+                if self.profiles.iter().any(|profile| profile.uuid == uuid) {
+                    return Ok(reply_error(&header, &already_exists()));
+                }
+                self.profiles.push(Profile {
+                    bus_name: header.sender.clone().unwrap_or_default(),
+                    path,
+                    uuid,
+                });
+            }
+            ("org.bluez.ProfileManager1", "UnregisterProfile", Object::Manager) => {
+                let path = path_arg(params.next()).unwrap_or_default();
+                let sender = header.sender.clone().unwrap_or_default();
+                let registerd =
+                    |profile: &Profile| profile.path == path && profile.bus_name == sender;
+                if !self.profiles.iter().any(registerd) {
+                    return Ok(reply_error(&header, &FakeError::does_not_exist()));
+                }
+                self.profiles.retain(|profile| !registerd(profile));
+            }
+            (
+                "org.bluez.BatteryProviderManager1",
+                "RegisterBatteryProvider",
+                Object::Adapter(_),
+            ) => {
+                if self.battery_provider.is_some() {
+                    return Ok(reply_error(&header, &already_exists()));
+                }
+                self.battery_provider = Some(BatteryProvider {
+                    bus_name: header.sender.clone().unwrap_or_default(),
+                    path: path_arg(params.next()).unwrap_or_default(),
+                    objects_serial: None,
+                    batteries: HashMap::new(),
+                });
+            }
+            (
+                "org.bluez.BatteryProviderManager1",
+                "UnregisterBatteryProvider",
+                Object::Adapter(_),
+            ) => {
+                if self.battery_provider.take().is_none() {
+                    return Ok(reply_error(&header, &FakeError::does_not_exist()));
+                }
+            }
+            _ => {
+                let error =
+                    FakeError::new("org.freedesktop.DBus.Error.UnknownMethod", "unknown method");
+                return Ok(reply_error(&header, &error));
+            }
+        }
+        Ok(Some(reply))
+    }
+
+    /// ask a newly registerd battery provider for its batteries, like bluez does
+    fn progress_battery_provider(&mut self, connection: &mut BusSocket) -> Result<(), Error> {
+        let provider = match &mut self.battery_provider {
+            Some(provider) => provider,
+            None => return Ok(()),
+        };
+        let serial = match provider.objects_serial {
+            Some(serial) => serial,
+            None => {
+                let mut request = MessageBuilder::new()
+                    .call("GetManagedObjects".into())
+                    .at(provider.bus_name.clone())
+                    .on(provider.path.clone())
+                    .with_interface("org.freedesktop.DBus.ObjectManager".into())
+                    .build();
+                provider.objects_serial = Some(connection.send(&mut request)?);
+                return Ok(());
+            }
+        };
+        let response = match self.responses.remove(&serial) {
+            Some(response) => response,
+            None => return Ok(()),
+        };
+        if !matches!(response.typ, MessageType::Reply) {
+            return Ok(());
+        }
+        let mut response = response.unmarshall_all()?;
+        let objects = response
+            .params
+            .pop()
+            .and_then(unwrap_container)
+            .and_then(unwrap_dict)
+            .ok_or(Error::UnexpectedDbusReply)?;
+        for (path, interfaces) in objects.into_iter().filter_map(unwrap_objectpath) {
+            provider.add_battery(path, interfaces)?;
+        }
+        Ok(())
+    }
+
+    /// read the objects of newly registerd applications, like bluez does
+    fn progress_gatt_apps(&mut self, connection: &mut BusSocket) -> Result<(), Error> {
+        for app in &mut self.gatt_apps {
+            let serial = match app.objects_serial {
+                Some(serial) => serial,
+                None => {
+                    let mut request = MessageBuilder::new()
+                        .call("GetManagedObjects".into())
+                        .at(app.bus_name.clone())
+                        .on(app.path.clone())
+                        .with_interface("org.freedesktop.DBus.ObjectManager".into())
+                        .build();
+                    app.objects_serial = Some(connection.send(&mut request)?);
+                    continue;
+                }
+            };
+            let response = match self.responses.remove(&serial) {
+                Some(response) => response,
+                None => continue,
+            };
+            if !matches!(response.typ, MessageType::Reply) {
+                app.objects = Some(Vec::new());
+                continue;
+            }
+            let mut response = response.unmarshall_all()?;
+            let objects = response
+                .params
+                .pop()
+                .and_then(unwrap_container)
+                .and_then(unwrap_dict)
+                .ok_or(Error::UnexpectedDbusReply)?;
+            let mut read = Vec::new();
+            for (path, interfaces) in objects.into_iter().filter_map(unwrap_objectpath) {
+                for (interface, mut properties) in info::interfaces_from_param(interfaces)? {
+                    read.push(AppObject {
+                        path: path.clone(),
+                        uuid: properties.take_uuid("UUID")?,
+                        parent: properties
+                            .take_string("Service")
+                            .or_else(|| properties.take_string("Characteristic")),
+                        flags: properties.take_strings("Flags").unwrap_or_default(),
+                        interface,
+                    });
+                }
+            }
+            read.sort_by(|a, b| a.path.cmp(&b.path));
+            app.objects = Some(read);
+        }
+        Ok(())
+    }
+
+    /// read the properties of newly registerd advertisements, like bluez does
+    fn progress_advertisements(&mut self, connection: &mut BusSocket) -> Result<(), Error> {
+        for advertisement in &mut self.advertisements {
+            let serial = match advertisement.properties_serial {
+                Some(serial) => serial,
+                None => {
+                    let mut request = MessageBuilder::new()
+                        .call("GetAll".into())
+                        .at(advertisement.bus_name.clone())
+                        .on(advertisement.path.clone())
+                        .with_interface("org.freedesktop.DBus.Properties".into())
+                        .build();
+                    request.body.push_param("org.bluez.LEAdvertisement1")?;
+                    advertisement.properties_serial = Some(connection.send(&mut request)?);
+                    continue;
+                }
+            };
+            let response = match self.responses.remove(&serial) {
+                Some(response) => response,
+                None => continue,
+            };
+            if !matches!(response.typ, MessageType::Reply) {
+                continue;
+            }
+            let mut response = response.unmarshall_all()?;
+            let properties = response.params.pop().ok_or(Error::UnexpectedDbusReply)?;
+            let mut properties = info::Properties::from_param(properties)?;
+            let service_uuids = properties.take_strings("ServiceUUIDs").unwrap_or_default();
+            advertisement.properties = Some(AdvertisementProperties {
+                typ: properties.take_string("Type").unwrap_or_default(),
+                local_name: properties.take_string("LocalName"),
+                service_uuids: service_uuids
+                    .iter()
+                    .map(|uuid| uuid.parse())
+                    .collect::<Result<_, _>>()?,
+            });
+        }
+        Ok(())
+    }
+
+    /// the properties of the advertisement registerd at this path, None
+    /// until they are read
+    pub(super) fn advertisement(&self, path: &str) -> Option<AdvertisementProperties> {
+        let advertisement = self.advertisements.iter().find(|a| a.path == path)?;
+        advertisement.properties.clone()
+    }
+
+    /// bluez stops the advertisement on its own, for example because its
+    /// timeout passed
+    pub(super) fn release_advertisement(&mut self, path: &str) {
+        if let Some(index) = self.advertisements.iter().position(|a| a.path == path) {
+            let advertisement = self.advertisements.remove(index);
+            let release = MessageBuilder::new()
+                .call("Release".into())
+                .at(advertisement.bus_name)
+                .on(advertisement.path)
+                .with_interface("org.bluez.LEAdvertisement1".into())
+                .build();
+            self.outbox.push(release);
+        }
+    }
+
+    fn is_app_signal(&self, signal: &MarshalledMessage) -> bool {
+        let path = signal.dynheader.object.as_deref().unwrap_or_default();
+        self.gatt_apps.iter().any(|app| {
+            signal.dynheader.sender.as_ref() == Some(&app.bus_name)
+                && path.starts_with(&format!("{}/", app.path))
+        })
+    }
+
+    /// an application notifies a new value
+    fn handle_app_signal(&mut self, signal: MarshalledMessage) -> Result<(), Error> {
+        let path = signal.dynheader.object.clone().unwrap_or_default();
+        if signal.dynheader.member.as_deref() != Some("PropertiesChanged") {
+            return Ok(());
+        }
+        let mut params = signal.unmarshall_all()?.params.into_iter();
+        let interface = params.next().and_then(unwrap_base).and_then(unwrap_string);
+        let changed = params.next().ok_or(Error::UnexpectedDbusReply)?;
+        let mut changed = info::Properties::from_param(changed)?;
+        let value = match changed.take_bytes("Value") {
+            Some(value) if interface.as_deref() == Some(CHAR_IFACE) => value,
+            _ => return Ok(()),
+        };
+        let app = self
+            .gatt_apps
+            .iter_mut()
+            .find(|app| path.starts_with(&format!("{}/", app.path)));
+        if let Some(app) = app {
+            app.notified.push((path, value));
+        }
+        Ok(())
+    }
+
+    /// the objects of the application registerd at this path, None until
+    /// they are read back
+    pub(super) fn app_objects(&self, path: &str) -> Option<Vec<AppObject>> {
+        let app = self.gatt_apps.iter().find(|app| app.path == path)?;
+        app.objects.clone()
+    }
+
+    pub(super) fn app_notified(&self, path: &str) -> Vec<Vec<u8>> {
+        self.gatt_apps
+            .iter()
+            .flat_map(|app| &app.notified)
+            .filter(|(notified, _)| notified == path)
+            .map(|(_, value)| value.clone())
+            .collect()
+    }
+
+    /// call an object of a registerd application, returns the index of the
+    /// call to get the reply with
+    pub(super) fn call_app(
+        &mut self,
+        path: &str,
+        interface: &str,
+        member: &str,
+        value: Option<&[u8]>,
+    ) -> Result<usize, Error> {
+        let app = self
+            .gatt_apps
+            .iter()
+            .find(|app| path.starts_with(&format!("{}/", app.path)))
+            .map(|app| &app.bus_name);
+        let bus_name = app
+            .or_else(|| self.agent.as_ref().map(|agent| &agent.bus_name))
+            .ok_or(Error::UnexpectedDbusReply)?;
+        let mut request = MessageBuilder::new()
+            .call(member.into())
+            .at(bus_name.clone())
+            .on(path.into())
+            .with_interface(interface.into())
+            .build();
+        if let Some(value) = value {
+            request.body.push_old_param(&byte_array_param(value))?;
+        }
+        if member == "ReadValue" || member == "WriteValue" {
+            request
+                .body
+                .push_old_param(&variant_dict_param(Vec::new()))?;
+        }
+        self.app_calls.push(AppCall {
+            request: Some(request),
+            serial: None,
+            reply: None,
+        });
+        Ok(self.app_calls.len() - 1)
+    }
+
+    pub(super) fn app_reply(&self, call: usize) -> Option<Result<Vec<u8>, FakeError>> {
+        self.app_calls.get(call)?.reply.clone()
+    }
+
+    fn progress_app_calls(&mut self, connection: &mut BusSocket) -> Result<(), Error> {
+        let responses = &mut self.responses;
+        for call in &mut self.app_calls {
+            if let Some(mut request) = call.request.take() {
+                call.serial = Some(connection.send(&mut request)?);
+            }
+            let response = match call.serial.and_then(|serial| responses.remove(&serial)) {
+                Some(response) => response,
+                None => continue,
+            };
+            // the parser of rustbus 0.6 panics on an empty body
+            let empty = response.get_sig().is_empty();
+            call.reply = Some(match response.typ {
+                MessageType::Error => {
+                    let name = response.dynheader.error_name.clone().unwrap_or_default();
+                    let message = match empty {
+                        true => String::new(),
+                        false => response.body.parser().get().unwrap_or_default(),
+                    };
+                    Err(FakeError::new(name, message))
+                }
+                _ if empty => Ok(Vec::new()),
+                _ => Ok(response.body.parser().get().unwrap_or_default()),
+            });
+        }
+        Ok(())
+    }
+
+    /// the provider changed its batteries
+    fn handle_provider_signal(&mut self, signal: MarshalledMessage) -> Result<(), Error> {
+        let provider = match &mut self.battery_provider {
+            Some(provider) if signal.dynheader.sender.as_ref() == Some(&provider.bus_name) => {
+                provider
+            }
+            _ => return Ok(()),
+        };
+        let path = signal.dynheader.object.clone().unwrap_or_default();
+        let member = signal.dynheader.member.clone().unwrap_or_default();
+        let mut params = signal.unmarshall_all()?.params.into_iter();
+        match member.as_str() {
+            "InterfacesAdded" => {
+                let battery = path_arg(params.next()).unwrap_or_default();
+                let interfaces = params.next().ok_or(Error::UnexpectedDbusReply)?;
+                provider.add_battery(battery, interfaces)?;
+            }
+            "InterfacesRemoved" => {
+                let battery = path_arg(params.next()).unwrap_or_default();
+                provider.batteries.remove(&battery);
+            }
+            "PropertiesChanged" => {
+                let interface = params.next().and_then(unwrap_base).and_then(unwrap_string);
+                let changed = params.next().ok_or(Error::UnexpectedDbusReply)?;
+                let mut changed = info::Properties::from_param(changed)?;
+                if interface.as_deref() != Some(PROVIDER_IFACE) {
+                    return Ok(());
+                }
+                if let (Some(battery), Some(percentage)) = (
+                    provider.batteries.get_mut(&path),
+                    changed.take_u8("Percentage"),
+                ) {
+                    battery.1 = percentage;
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    pub(super) fn provided_battery(&self, adress: Address) -> Option<u8> {
+        let device = self.device(adress)?;
+        let device_path = Self::device_path(device).to_string();
+        let provider = self.battery_provider.as_ref()?;
+        provider
+            .batteries
+            .values()
+            .find(|(device, _)| device == &device_path)
+            .map(|(_, percentage)| *percentage)
+    }
+
+    pub(super) fn set_battery(&mut self, adress: Address, percentage: u8) {
+        let d = match self.devices.iter().position(|d| d.adress == adress) {
+            Some(d) => d,
+            None => return,
+        };
+        self.devices[d].battery = Some(percentage);
+        let properties = vec![("Percentage", Param::Base(params::Base::Byte(percentage)))];
+        self.emit_properties_changed(Object::Device(d), BATTERY_IFACE, properties);
+    }
+
+    fn handle_char_call(
+        &mut self,
+        header: DynamicHeader,
+        d: usize,
+        s: usize,
+        c: usize,
+        params: Vec<Param>,
+    ) -> Result<Option<MarshalledMessage>, Error> {
+        let member = header.member.clone().unwrap_or_default();
+        let path: BluezPath = self
+            .object_path(Object::Gatt(d, Gatt::Characteristic(s, c)))
+            .parse()?;
+        let connected = self.devices[d].connected;
+        let characteristic = &mut self.devices[d].services[s].characteristics[c];
+        let mut params = params.into_iter();
+        let mut reply = header.make_response();
+
+        if !connected {
+            return Ok(reply_error(&header, &FakeError::failed("Not connected")));
+        }
+        match member.as_str() {
+            "ReadValue" => {
+                if !flags_contain(&characteristic.flags, &[Flag::Read]) {
+                    let error =
+                        FakeError::new("org.bluez.Error.NotPermitted", "Read not permitted");
+                    return Ok(reply_error(&header, &error));
+                }
+                let info = RequestInfo::from_options(params.next());
+                let offset = (info.offset as usize).min(characteristic.value.len());
+                reply.body.push_param(&characteristic.value[offset..])?;
+            }
+            "WriteValue" => {
+                let writable = [Flag::Write, Flag::WriteWithoutResponse];
+                if !flags_contain(&characteristic.flags, &writable) {
+                    let error =
+                        FakeError::new("org.bluez.Error.NotPermitted", "Write not permitted");
+                    return Ok(reply_error(&header, &error));
+                }
+                let value = bytes_from_param(params.next())?;
+                let info = RequestInfo::from_options(params.next());
+                let offset = (info.offset as usize).min(characteristic.value.len());
+                characteristic.value.truncate(offset);
+                characteristic.value.extend(value);
+            }
+            "AcquireNotify" => {
+                if !flags_contain(&characteristic.flags, &[Flag::Notify, Flag::Indicate]) {
+                    return Ok(reply_error(&header, &FakeError::not_supported()));
+                }
+                if self.notifying.contains(&path) {
+                    return Ok(reply_error(&header, &FakeError::in_progress()));
+                }
+                // like bluez one acquirer at a time, until it closes its end
+                if let Some(sockets) = self.notify_sockets.get_mut(&path) {
+                    sockets.retain(|socket| !socket.hung_up());
+                    if !sockets.is_empty() {
+                        return Ok(reply_error(&header, &FakeError::not_permitted()));
+                    }
+                }
+                let (ours, theirs) = socket::socketpair(
+                    AddressFamily::Unix,
+                    SockType::SeqPacket,
+                    None,
+                    SockFlag::SOCK_CLOEXEC,
+                )
+                .map_err(|_| Error::NoFdReturned)?;
+                let mtu = characteristic.mtu;
+                self.notify_sockets
+                    .entry(path)
+                    .or_default()
+                    .push(NotifySocket(ours));
+                reply
+                    .body
+                    .push_old_param(&Param::Base(params::Base::UnixFd(0)))?;
+                reply.body.push_param(mtu)?;
+                reply.raw_fds.push(theirs);
+                reply.dynheader.num_fds = Some(1);
+            }
+            "StartNotify" | "StopNotify" => {
+                if !flags_contain(&characteristic.flags, &[Flag::Notify, Flag::Indicate]) {
+                    return Ok(reply_error(&header, &FakeError::not_supported()));
+                }
+                let notifying = member == "StartNotify";
+                let changed = if notifying {
+                    self.notifying.insert(path)
+                } else {
+                    self.notifying.remove(&path)
+                };
+                if changed {
+                    let object = Object::Gatt(d, Gatt::Characteristic(s, c));
+                    let properties = vec![("Notifying", bool_param(notifying))];
+                    self.emit_properties_changed(object, CHAR_IFACE, properties);
+                }
+            }
+            _ => {
+                let error =
+                    FakeError::new("org.freedesktop.DBus.Error.UnknownMethod", "unknown method");
+                return Ok(reply_error(&header, &error));
+            }
+        }
+        Ok(Some(reply))
+    }
+
+    fn set_discovering(&mut self, a: usize, discovering: bool) {
+        self.discovering[a] = discovering;
+        let properties = vec![("Discovering", bool_param(discovering))];
+        self.emit_properties_changed(Object::Adapter(a), ADAPTER_IFACE, properties);
+    }
+
+    fn start_pairing(&mut self, call: DynamicHeader, d: usize) -> Option<MarshalledMessage> {
+        let device = &self.devices[d];
+        if device.paired {
+            return reply_error(&call, &already_exists());
+        }
+        if self.pending_pair.is_some() {
+            return reply_error(&call, &FakeError::in_progress());
+        }
+        match &device.pairing {
+            Pairing::JustWorks => {
+                self.set_paired(d);
+                Some(call.make_response())
+            }
+            Pairing::Fail(error) => reply_error(&call, error),
+            Pairing::NoResponse => {
+                self.pending_pair = Some(PendingPair {
+                    call,
+                    device: d,
+                    request_serial: None,
+                });
+                None
+            }
+            _ if self.agent.is_none() => reply_error(&call, &FakeError::authentication_failed()),
+            _ => {
+                self.pending_pair = Some(PendingPair {
+                    call,
+                    device: d,
+                    request_serial: None,
+                });
+                None
+            }
+        }
+    }
+
+    fn set_paired(&mut self, d: usize) {
+        self.devices[d].paired = true;
+        let properties = vec![("Paired", bool_param(true))];
+        self.emit_properties_changed(Object::Device(d), DEVICE_IFACE, properties);
+    }
+
+    /// ask the agent for input then finish the pending pairing using its reply
+    fn progress_pairing(&mut self, connection: &mut BusSocket) -> Result<(), Error> {
+        let pending = match &mut self.pending_pair {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+        let device = &self.devices[pending.device];
+        if device.pairing == Pairing::NoResponse {
+            return Ok(());
+        }
+        let agent = match &self.agent {
+            Some(agent) => agent,
+            None => {
+                let pending = self.pending_pair.take().unwrap();
+                let error = FakeError::authentication_failed();
+                return send(
+                    connection,
+                    error_response(&pending.call, &error.name, &error.message),
+                );
+            }
+        };
+
+        let serial = match pending.request_serial {
+            Some(serial) => serial,
+            None => {
+                let mut request = agent_request(agent, device)?;
+                pending.request_serial = Some(connection.send(&mut request)?);
+                return Ok(());
+            }
+        };
+        let response = match self.responses.remove(&serial) {
+            Some(response) => response,
+            None => return Ok(()),
+        };
+
+        let pending = self.pending_pair.take().unwrap();
+        let outcome = match response.typ {
+            rustbus::MessageType::Error => match response.dynheader.error_name.as_deref() {
+                Some("org.bluez.Error.Canceled") => Err(FakeError::authentication_canceled()),
+                _ => Err(FakeError::new(
+                    "org.bluez.Error.AuthenticationRejected",
+                    "Authentication Rejected",
+                )),
+            },
+            _ => match &self.devices[pending.device].pairing {
+                Pairing::Passkey(expected) => match response.body.parser().get::<u32>() {
+                    Ok(passkey) if passkey == *expected => Ok(()),
+                    _ => Err(FakeError::authentication_failed()),
+                },
+                _ => Ok(()),
+            },
+        };
+        match outcome {
+            Ok(()) => {
+                self.set_paired(pending.device);
+                send(connection, pending.call.make_response())
+            }
+            Err(error) => send(
+                connection,
+                error_response(&pending.call, &error.name, &error.message),
+            ),
+        }
+    }
+
+    pub(super) fn set_connected(&mut self, adress: Address, connected: bool) {
+        let d = match self.devices.iter().position(|d| d.adress == adress) {
+            Some(d) => d,
+            None => return,
+        };
+        if self.devices[d].connected == connected {
+            return;
+        }
+        self.devices[d].connected = connected;
+        if !connected {
+            let device_path = Self::device_path(&self.devices[d]);
+            self.notify_sockets
+                .retain(|path, _| path.device_path() != Some(device_path));
+            self.notifying
+                .retain(|path| path.device_path() != Some(device_path));
+        }
+        let properties = vec![
+            ("Connected", bool_param(connected)),
+            ("ServicesResolved", bool_param(connected)),
+        ];
+        self.emit_properties_changed(Object::Device(d), DEVICE_IFACE, properties);
+    }
+
+    pub(super) fn set_rssi(&mut self, adress: Address, rssi: i16) {
+        let d = match self.devices.iter().position(|d| d.adress == adress) {
+            Some(d) => d,
+            None => return,
+        };
+        self.devices[d].rssi = Some(rssi);
+        let properties = vec![("RSSI", Param::Base(params::Base::Int16(rssi)))];
+        self.emit_properties_changed(Object::Device(d), DEVICE_IFACE, properties);
+    }
+
+    fn find_char(&self, adress: Address, uuid: Uuid) -> Option<(usize, usize, usize)> {
+        let d = self.devices.iter().position(|d| d.adress == adress)?;
+        for (s, service) in self.devices[d].services.iter().enumerate() {
+            if let Some(c) = service.characteristics.iter().position(|c| c.uuid == uuid) {
+                return Some((d, s, c));
+            }
+        }
+        None
+    }
+
+    pub(super) fn value(&self, adress: Address, uuid: Uuid) -> Option<Vec<u8>> {
+        let (d, s, c) = self.find_char(adress, uuid)?;
+        Some(self.devices[d].services[s].characteristics[c].value.clone())
+    }
+
+    pub(super) fn set_value(&mut self, adress: Address, uuid: Uuid, value: &[u8], notify: bool) {
+        let (d, s, c) = match self.find_char(adress, uuid) {
+            Some(found) => found,
+            None => return,
+        };
+        self.devices[d].services[s].characteristics[c].value = value.to_vec();
+        if !notify {
+            return;
+        }
+
+        let object = Object::Gatt(d, Gatt::Characteristic(s, c));
+        let path: BluezPath = self.object_path(object).parse().expect("we made the path");
+        if let Some(sockets) = self.notify_sockets.get_mut(&path) {
+            sockets.retain(|socket| {
+                let sent = socket::send(socket.0, value, MsgFlags::MSG_DONTWAIT);
+                sent.is_ok()
+            });
+        }
+        if self.notifying.contains(&path) {
+            let properties = vec![("Value", byte_array_param(value))];
+            self.emit_properties_changed(object, CHAR_IFACE, properties);
+        }
+    }
+
+    pub(super) fn add_device(&mut self, device: FakeDevice) {
+        self.devices.push(device);
+        let d = self.devices.len() - 1;
+        let mut objects = vec![Object::Device(d)];
+        let gatt = Self::gatt_objects(&self.devices[d]).into_iter();
+        objects.extend(gatt.map(|(_, gatt)| Object::Gatt(d, gatt)));
+
+        for object in objects {
+            let mut signal = MessageBuilder::new()
+                .signal(
+                    "org.freedesktop.DBus.ObjectManager".into(),
+                    "InterfacesAdded".into(),
+                    "/".into(),
+                )
+                .build();
+            let path = objectpath_param(self.object_path(object));
+            let interfaces = self.interfaces_as_param(object);
+            signal
+                .body
+                .push_old_params(&[path, interfaces])
+                .expect("constant signature is valid");
+            self.outbox.push(signal);
+        }
+    }
+
+    fn remove_device(&mut self, d: usize) {
+        let adress = self.devices[d].adress;
+        self.set_connected(adress, false);
+        let path = self.object_path(Object::Device(d));
+        let interfaces: Vec<String> = self
+            .interfaces(Object::Device(d))
+            .into_iter()
+            .map(|(name, _)| name.to_owned())
+            .collect();
+        self.devices.remove(d);
+        if matches!(&self.pending_pair, Some(pending) if pending.device == d) {
+            self.pending_pair = None;
+        }
+
+        let mut signal = MessageBuilder::new()
+            .signal(
+                "org.freedesktop.DBus.ObjectManager".into(),
+                "InterfacesRemoved".into(),
+                "/".into(),
+            )
+            .build();
+        signal
+            .body
+            .push_old_params(&[objectpath_param(path), string_array_param(interfaces)])
+            .expect("constant signature is valid");
+        self.outbox.push(signal);
+    }
+
+    /// hand a new connection to the profile registerd for the uuid, returns
+    /// our end or None if no profile is registerd for it
+    pub(super) fn new_profile_connection(
+        &mut self,
+        adress: Address,
+        uuid: Uuid,
+    ) -> Result<Option<UnixStream>, Error> {
+        let profile = match self.profiles.iter().find(|profile| profile.uuid == uuid) {
+            Some(profile) => profile,
+            None => return Ok(None),
+        };
+        let device = match self.device(adress) {
+            Some(device) => device,
+            None => return Ok(None),
+        };
+        let (ours, theirs) = UnixStream::pair().map_err(|_| Error::NoFdReturned)?;
+        let mut call = profile_call(profile, "NewConnection");
+        call.body.push_old_params(&[
+            objectpath_param(Self::device_path(device).to_string()),
+            Param::Base(params::Base::UnixFd(0)),
+            variant_dict_param(Vec::new()),
+        ])?;
+        call.raw_fds.push(theirs.into_raw_fd());
+        call.dynheader.num_fds = Some(1);
+        self.outbox.push(call);
+        Ok(Some(ours))
+    }
+
+    pub(super) fn profile_call_without_fd(&mut self, adress: Address, uuid: Uuid) -> Option<usize> {
+        let profile = self.profiles.iter().find(|profile| profile.uuid == uuid)?;
+        let device = self.device(adress)?;
+        let mut call = profile_call(profile, "NewConnection");
+        call.body
+            .push_old_param(&objectpath_param(Self::device_path(device).to_string()))
+            .ok()?;
+        self.app_calls.push(AppCall {
+            request: Some(call),
+            serial: None,
+            reply: None,
+        });
+        Some(self.app_calls.len() - 1)
+    }
+
+    /// ask the profile to close its connection to the device, false if no
+    /// profile is registerd for the uuid
+    pub(super) fn request_profile_disconnection(
+        &mut self,
+        adress: Address,
+        uuid: Uuid,
+    ) -> Result<bool, Error> {
+        let profile = self.profiles.iter().find(|profile| profile.uuid == uuid);
+        let (profile, device) = match (profile, self.device(adress)) {
+            (Some(profile), Some(device)) => (profile, device),
+            _ => return Ok(false),
+        };
+        let mut call = profile_call(profile, "RequestDisconnection");
+        call.body
+            .push_old_param(&objectpath_param(Self::device_path(device).to_string()))?;
+        self.outbox.push(call);
+        Ok(true)
+    }
+
+    /// bluez drops the profile, for example because it is shutting down
+    pub(super) fn release_profile(&mut self, uuid: Uuid) {
+        if let Some(index) = self
+            .profiles
+            .iter()
+            .position(|profile| profile.uuid == uuid)
+        {
+            let profile = self.profiles.remove(index);
+            self.outbox.push(profile_call(&profile, "Release"));
+        }
+    }
+
+    pub(super) fn is_profile_registerd(&self, uuid: Uuid) -> bool {
+        self.profiles.iter().any(|profile| profile.uuid == uuid)
+    }
+
+    pub(super) fn take_profile_socket(
+        &mut self,
+        adress: Address,
+        uuid: Uuid,
+    ) -> Option<UnixStream> {
+        let index = self
+            .profile_sockets
+            .iter()
+            .position(|(a, u, _)| (*a, *u) == (adress, uuid))?;
+        Some(self.profile_sockets.remove(index).2)
+    }
+}
+
+impl BatteryProvider {
+    fn add_battery(
+        &mut self,
+        path: String,
+        interfaces: Param<'static, 'static>,
+    ) -> Result<(), Error> {
+        let mut interfaces = info::interfaces_from_param(interfaces)?;
+        if let Some(mut properties) = interfaces.remove(PROVIDER_IFACE) {
+            let device = properties.take_string("Device").unwrap_or_default();
+            let percentage = properties.take_u8("Percentage").unwrap_or_default();
+            self.batteries.insert(path, (device, percentage));
+        }
+        Ok(())
+    }
+}
+
+fn profile_call(profile: &Profile, member: &str) -> MarshalledMessage {
+    MessageBuilder::new()
+        .call(member.into())
+        .at(profile.bus_name.clone())
+        .on(profile.path.clone())
+        .with_interface("org.bluez.Profile1".into())
+        .build()
+}
+
+fn already_exists() -> FakeError {
+    FakeError::new("org.bluez.Error.AlreadyExists", "Already Exists")
+}
+
+fn invalid_args(message: &str) -> FakeError {
+    FakeError::new("org.freedesktop.DBus.Error.InvalidArgs", message)
+}
+
+fn agent_request(agent: &Agent, device: &FakeDevice) -> Result<MarshalledMessage, Error> {
+    let member = match device.pairing {
+        Pairing::Passkey(_) => "RequestPasskey",
+        Pairing::Confirmation(_) => "RequestConfirmation",
+        _ => "RequestAuthorization",
+    };
+    let mut request = MessageBuilder::new()
+        .call(member.into())
+        .at(agent.bus_name.clone())
+        .on(agent.path.clone())
+        .with_interface("org.bluez.Agent1".into())
+        .build();
+    let device_path = State::device_path(device).to_string();
+    request
+        .body
+        .push_old_param(&objectpath_param(device_path))?;
+    if let Pairing::Confirmation(passkey) = device.pairing {
+        request.body.push_param(passkey)?;
+    }
+    Ok(request)
+}
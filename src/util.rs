@@ -1,54 +1,54 @@
-use crate::dbus_helpers::{unwrap_container, unwrap_variant};
-use crate::error::Error;
+use crate::address::{Address, IntoAddress};
+use crate::dbus_helpers::{unwrap_base, unwrap_string};
+use crate::error::{Context, Error};
+use crate::storage::AdapterStorage;
 use crate::Ble;
 
-use rustbus::MessageBuilder;
-use std::fs::remove_file;
-use std::io::ErrorKind;
-use std::path::PathBuf;
-
 impl Ble {
-    fn adapter_adress(&mut self) -> Result<String, Error> {
-        let mut get_addr = MessageBuilder::new()
-            .call("Get".into())
-            .on("/org/bluez/hci0".into())
-            .with_interface("org.freedesktop.DBus.Properties".into())
-            .at("org.bluez".into())
-            .build();
-
-        let adapter = "org.bluez.Adapter1".to_string();
-        get_addr.body.push_param2(adapter, "Address")?;
-
-        let response_serial = self.connection.send_message(&mut get_addr, self.timeout)?;
-        let mut reply = self
-            .connection
-            .wait_response(response_serial, self.timeout)?
-            .unmarshall_all()?;
-        let param = reply.params.pop().unwrap();
-        let p = unwrap_container(param).unwrap();
-        let p = unwrap_variant(p).unwrap();
-        let adress = p.value.into_string().unwrap();
+    fn adapter_adress(&mut self) -> Result<Address, Error> {
+        let value = self.get_property(
+            self.adapter_path(),
+            "org.bluez.Adapter1",
+            "Address",
+            Context::AdapterStorage,
+        )?;
+        unwrap_base(value)
+            .and_then(unwrap_string)
+            .ok_or(Error::UnexpectedDbusReply)?
+            .parse()
+    }
 
-        Ok(adress)
+    /// the storage directory of the adapter this Ble uses
+    #[allow(dead_code)]
+    pub fn adapter_storage(&mut self) -> Result<AdapterStorage, Error> {
+        let adapter = self.adapter_adress()?;
+        AdapterStorage::new(&self.storage_root, adapter)
     }
 
     /// util function that clears the device cache. When used after removing
     /// the device from bluez this well make sure all caracteristics are rediscovered
     /// if the device is added again (by connecting). This function will need to run
     /// with superuser privileges.
+    #[allow(dead_code)]
+    pub fn remove_attribute_cache(&mut self, device_mac: impl IntoAddress) -> Result<(), Error> {
+        let device_mac = device_mac.into_address()?;
+        self.adapter_storage()?.remove_attribute_cache(device_mac)?;
+        Ok(())
+    }
 
-    //TODO FIXME does not work?
-    pub fn remove_attribute_cache(&mut self, device_mac: &str) -> Result<(), Error> {
-        let mut path = PathBuf::from("/var/lib/bluetooth");
-        path.push(self.adapter_adress()?);
-        path.push("cache");
-        path.push(device_mac);
-
-        if let Err(e) = remove_file(path) {
-            if e.kind() != ErrorKind::NotFound {
-                return Err(e.into());
-            }
+    /// forget the device and its attribute cache, the next connect starts
+    /// from scratch. Succeeds if bluez did not know the device. Needs the
+    /// privileges to remove the cache, see `remove_attribute_cache`.
+    #[allow(dead_code)]
+    pub fn reset_device(&mut self, adress: impl IntoAddress) -> Result<(), Error> {
+        let adress = adress.into_address()?;
+        // check we can get at the storage before bluez forgets the device
+        let storage = self.adapter_storage()?;
+        match self.remove(adress) {
+            Ok(()) | Err(Error::DoesNotExist(_)) => (),
+            Err(e) => return Err(e),
         }
+        storage.remove_attribute_cache(adress)?;
         Ok(())
     }
 }
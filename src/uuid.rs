@@ -0,0 +1,157 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::Error;
+
+/// 00000000-0000-1000-8000-00805f9b34fb, 16 and 32 bit uuids are
+/// shorthands for this uuid with the first 32 bits replaced
+const BASE_UUID: u128 = 0x0000_0000_0000_1000_8000_0080_5f9b_34fb;
+
+/// A bluetooth uuid, 16 and 32 bit uuids are expanded using the bluetooth
+/// base uuid. Therefore "2a19", "00002a19" and
+/// "00002a19-0000-1000-8000-00805f9b34fb" are all the same Uuid. Parsing
+/// ignores case, formatting uses the lower case full form as bluez does.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Uuid(u128);
+
+impl Uuid {
+    pub const fn from_u128(uuid: u128) -> Self {
+        Uuid(uuid)
+    }
+
+    pub const fn from_u16(uuid: u16) -> Self {
+        Self::from_u32(uuid as u32)
+    }
+
+    pub const fn from_u32(uuid: u32) -> Self {
+        Uuid(BASE_UUID | (uuid as u128) << 96)
+    }
+
+    pub fn as_u128(&self) -> u128 {
+        self.0
+    }
+
+    /// the 32 bit short form if this uuid is based on the bluetooth base uuid
+    pub fn as_u32(&self) -> Option<u32> {
+        if self.0 & ((1 << 96) - 1) == BASE_UUID {
+            Some((self.0 >> 96) as u32)
+        } else {
+            None
+        }
+    }
+
+    /// the 16 bit short form if this uuid is based on the bluetooth base uuid
+    pub fn as_u16(&self) -> Option<u16> {
+        let short = self.as_u32()?;
+        if short <= u16::MAX as u32 {
+            Some(short as u16)
+        } else {
+            None
+        }
+    }
+}
+
+impl FromStr for Uuid {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidUuid(s.to_owned());
+        let hex = |digits: &str| {
+            if digits.chars().all(|c| c.is_ascii_hexdigit()) {
+                u128::from_str_radix(digits, 16).map_err(|_| invalid())
+            } else {
+                Err(invalid())
+            }
+        };
+
+        match s.len() {
+            4 => Ok(Uuid::from_u16(hex(s)? as u16)),
+            8 => Ok(Uuid::from_u32(hex(s)? as u32)),
+            32 => Ok(Uuid(hex(s)?)),
+            36 => {
+                let dashes = [8, 13, 18, 23];
+                let valid = s.bytes().enumerate().all(|(i, b)| {
+                    if dashes.contains(&i) {
+                        b == b'-'
+                    } else {
+                        b.is_ascii_hexdigit()
+                    }
+                });
+                if !valid {
+                    return Err(invalid());
+                }
+                Ok(Uuid(hex(&s.replace("-", ""))?))
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let v = self.0;
+        write!(
+            f,
+            "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+            v >> 96,
+            (v >> 80) & 0xffff,
+            (v >> 64) & 0xffff,
+            (v >> 48) & 0xffff,
+            v & 0xffff_ffff_ffff
+        )
+    }
+}
+
+impl fmt::Debug for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Uuid({})", self)
+    }
+}
+
+impl From<u16> for Uuid {
+    fn from(uuid: u16) -> Self {
+        Uuid::from_u16(uuid)
+    }
+}
+
+/// Anything that can be turned into a Uuid, implemented for Uuid, for 16 bit
+/// assigned numbers and for strings which are parsed.
+pub trait IntoUuid {
+    fn into_uuid(self) -> Result<Uuid, Error>;
+}
+
+impl IntoUuid for Uuid {
+    fn into_uuid(self) -> Result<Uuid, Error> {
+        Ok(self)
+    }
+}
+
+impl IntoUuid for &Uuid {
+    fn into_uuid(self) -> Result<Uuid, Error> {
+        Ok(*self)
+    }
+}
+
+impl IntoUuid for u16 {
+    fn into_uuid(self) -> Result<Uuid, Error> {
+        Ok(Uuid::from_u16(self))
+    }
+}
+
+impl IntoUuid for &str {
+    fn into_uuid(self) -> Result<Uuid, Error> {
+        self.parse()
+    }
+}
+
+impl IntoUuid for String {
+    fn into_uuid(self) -> Result<Uuid, Error> {
+        self.parse()
+    }
+}
+
+impl IntoUuid for &String {
+    fn into_uuid(self) -> Result<Uuid, Error> {
+        self.parse()
+    }
+}
@@ -0,0 +1,92 @@
+use std::process::{Command, Output};
+
+use bluebus::gatt_server::Flag;
+use bluebus::test_support::{
+    DbusDaemon, FakeBluez, FakeCharacteristic, FakeDevice, FakeService, RunningFakeBluez,
+};
+use bluebus::{Address, Uuid};
+
+const DEVICE: Address = Address::new([0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f]);
+const CHARACTERISTIC: Uuid = Uuid::from_u16(0x2a19);
+
+fn setup() -> (DbusDaemon, RunningFakeBluez) {
+    let bus = DbusDaemon::start().unwrap();
+    let characteristic = FakeCharacteristic::new(CHARACTERISTIC, &[Flag::Read, Flag::Write])
+        .with_value(b"hi".to_vec());
+    let device = FakeDevice::new(DEVICE)
+        .with_name("fake")
+        .connected(true)
+        .with_service(FakeService::new(Uuid::from_u16(0x180f)).with_characteristic(characteristic));
+    let bluez = FakeBluez::new().with_device(device).start(&bus).unwrap();
+    (bus, bluez)
+}
+
+fn bluebus(bus: &DbusDaemon, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_bluebus"))
+        .arg("--bus")
+        .arg(bus.address())
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+#[test]
+fn read_and_write_in_every_format() {
+    let (bus, bluez) = setup();
+    let device = DEVICE.to_string();
+    let read = |format| {
+        stdout(&bluebus(
+            &bus,
+            &["read", &device, "2a19", "--format", format],
+        ))
+    };
+    assert_eq!(read("hex"), "68 69\n");
+    assert_eq!(read("utf8"), "hi\n");
+    assert_eq!(read("base64"), "aGk=\n");
+
+    let write = |data, format| {
+        stdout(&bluebus(
+            &bus,
+            &["write", &device, "2a19", data, "--format", format],
+        ));
+    };
+    write("01:02:ff", "hex");
+    assert_eq!(bluez.value(DEVICE, CHARACTERISTIC), Some(vec![1, 2, 0xff]));
+    write("AQID", "base64");
+    assert_eq!(bluez.value(DEVICE, CHARACTERISTIC), Some(vec![1, 2, 3]));
+}
+
+#[test]
+fn info_and_gatt() {
+    let (bus, _bluez) = setup();
+    let device = DEVICE.to_string();
+    let info = stdout(&bluebus(&bus, &["info", &device]));
+    assert!(info.contains("name: fake"));
+    assert!(info.contains("connected: true"));
+
+    let gatt = stdout(&bluebus(&bus, &["gatt", &device]));
+    assert!(gatt.contains("service 0000180f-0000-1000-8000-00805f9b34fb (primary)"));
+    assert!(gatt.contains("characteristic 00002a19-0000-1000-8000-00805f9b34fb [Read, Write]"));
+}
+
+#[test]
+fn usage_errors_fail() {
+    let (bus, _bluez) = setup();
+    let output = bluebus(&bus, &["read", &DEVICE.to_string()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("missing characteristic uuid"));
+
+    let device = DEVICE.to_string();
+    let output = bluebus(&bus, &["write", &device, "2a19", "+f", "--format", "hex"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("data is not valid Hex"));
+}
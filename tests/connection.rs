@@ -0,0 +1,145 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::rc::Rc;
+use std::time::Duration;
+
+use bluebus::connection::BusConnection;
+use bluebus::rustbus::client_conn::{self, Timeout};
+use bluebus::rustbus::message_builder::MarshalledMessage;
+use bluebus::rustbus::{MessageType, RpcConn};
+use bluebus::test_support::{DbusDaemon, FakeBluez, FakeDevice};
+use bluebus::{Address, BleBuilder, CallOptions, Context, Error};
+
+const DEVICE: Address = Address::new([0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f]);
+
+/// Answers every call with an empty reply, as if bluez is not running
+#[derive(Default)]
+struct Double {
+    /// member and path of every call made
+    calls: Rc<RefCell<Vec<(String, String)>>>,
+    responses: Rc<RefCell<HashMap<u32, MarshalledMessage>>>,
+    /// replies to calls to this member arrive with the next message sent
+    hold: Option<&'static str>,
+    held: Vec<(u32, MarshalledMessage)>,
+    next_serial: u32,
+}
+
+impl BusConnection for Double {
+    fn send_message(&mut self, msg: &mut MarshalledMessage, _: Timeout) -> Result<u32, Error> {
+        self.next_serial += 1;
+        let serial = self.next_serial;
+        msg.dynheader.serial = Some(serial);
+        self.responses.borrow_mut().extend(self.held.drain(..));
+        if !matches!(msg.typ, MessageType::Call) {
+            return Ok(serial);
+        }
+
+        let header = &msg.dynheader;
+        let member = header.member.clone().unwrap_or_default();
+        let path = header.object.clone().unwrap_or_default();
+        let response = if member == "GetNameOwner" {
+            let mut error = header
+                .make_error_response("org.freedesktop.DBus.Error.NameHasNoOwner".to_owned(), None);
+            error.typ = MessageType::Error;
+            error
+        } else {
+            header.make_response()
+        };
+        if self.hold == Some(member.as_str()) {
+            self.held.push((serial, response));
+        } else {
+            self.responses.borrow_mut().insert(serial, response);
+        }
+        self.calls.borrow_mut().push((member, path));
+        Ok(serial)
+    }
+
+    fn try_get_response(&mut self, serial: u32) -> Option<MarshalledMessage> {
+        self.responses.borrow_mut().remove(&serial)
+    }
+
+    fn try_get_call(&mut self) -> Option<MarshalledMessage> {
+        None
+    }
+
+    fn try_get_signal(&mut self) -> Option<MarshalledMessage> {
+        None
+    }
+
+    fn refill_once(&mut self, _: Timeout) -> Result<(), Error> {
+        Err(client_conn::Error::TimedOut.into())
+    }
+
+    fn refill_all(&mut self) -> Result<Vec<MarshalledMessage>, Error> {
+        Ok(Vec::new())
+    }
+}
+
+#[test]
+fn test_double() {
+    let double = Double::default();
+    let calls = double.calls.clone();
+    let mut ble = BleBuilder::default()
+        .with_connection(double)
+        .build()
+        .unwrap();
+    ble.disconnect(DEVICE).unwrap();
+
+    let calls = calls.borrow();
+    // no Hello, the connection is assumed to be set up
+    assert_eq!(calls[0].0, "AddMatch");
+    assert_eq!(
+        calls.last().unwrap(),
+        &(
+            "Disconnect".to_owned(),
+            "/org/bluez/hci0/dev_0A_0B_0C_0D_0E_0F".to_owned()
+        )
+    );
+}
+
+#[test]
+fn late_replies_are_dropped() {
+    let double = Double {
+        hold: Some("Connect"),
+        ..Double::default()
+    };
+    let responses = double.responses.clone();
+    let mut ble = BleBuilder::default()
+        .with_connection(double)
+        .build()
+        .unwrap();
+
+    let options = CallOptions::new().with_timeout(Duration::from_millis(50));
+    let err = ble.connect_with(DEVICE, &options).unwrap_err();
+    assert_eq!(err, Error::TimedOut(Context::Connect));
+    // the reply to Connect arrived with the Disconnect that followed
+    assert_eq!(responses.borrow().len(), 1);
+    assert_eq!(ble.try_event(), Ok(None));
+    assert!(responses.borrow().is_empty());
+}
+
+#[test]
+fn session_bus_and_premade_connection() {
+    let bus = DbusDaemon::start().unwrap();
+    let bluez = FakeBluez::new()
+        .with_device(FakeDevice::new(DEVICE))
+        .start(&bus)
+        .unwrap();
+    // the only test in this binary that touches the environment, without
+    // the guid as RpcConn::session_conn does not understand it
+    let address = bus.address().split(',').next().unwrap();
+    env::set_var("DBUS_SESSION_BUS_ADDRESS", address);
+
+    let mut ble = BleBuilder::default().with_session_bus().build().unwrap();
+    ble.connect(DEVICE).unwrap();
+    assert!(bluez.is_connected(DEVICE));
+
+    let connection = RpcConn::session_conn(Timeout::Infinite).unwrap();
+    let mut ble = BleBuilder::default()
+        .with_connection(connection)
+        .build()
+        .unwrap();
+    ble.disconnect(DEVICE).unwrap();
+    assert!(!bluez.is_connected(DEVICE));
+}
@@ -0,0 +1,90 @@
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+use bluebus::test_support::{DbusDaemon, FakeBluez, FakeDevice};
+use bluebus::{Address, BleBuilder};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+const DEVICE: Address = Address::new([0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f]);
+
+/// the fields of every event as "name=value" strings
+#[derive(Clone, Default)]
+struct Collect(Arc<Mutex<Vec<Vec<String>>>>);
+
+struct Fields(Vec<String>);
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0.push(format!("{}={:?}", field.name(), value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.push(format!("{}={}", field.name(), value));
+    }
+}
+
+impl Subscriber for Collect {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.target() == "bluebus::dbus"
+    }
+    fn new_span(&self, _: &Attributes) -> Id {
+        Id::from_u64(1)
+    }
+    fn record(&self, _: &Id, _: &Record) {}
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+    fn event(&self, event: &Event) {
+        let mut fields = Fields(Vec::new());
+        event.record(&mut fields);
+        self.0.lock().unwrap().push(fields.0);
+    }
+    fn enter(&self, _: &Id) {}
+    fn exit(&self, _: &Id) {}
+}
+
+impl Collect {
+    fn find(&self, wanted: &[&str]) -> Option<Vec<String>> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|fields| wanted.iter().all(|w| fields.iter().any(|f| f == w)))
+            .cloned()
+    }
+}
+
+#[test]
+fn calls_and_replies_are_traced() {
+    let bus = DbusDaemon::start().unwrap();
+    let _bluez = FakeBluez::new()
+        .with_device(FakeDevice::new(DEVICE))
+        .start(&bus)
+        .unwrap();
+
+    let collect = Collect::default();
+    tracing::subscriber::with_default(collect.clone(), || {
+        let mut ble = BleBuilder::default()
+            .with_bus_address(bus.address())
+            .build()
+            .unwrap();
+        ble.connect(DEVICE).unwrap();
+        ble.connect("01:02:03:04:05:06").unwrap_err();
+    });
+
+    let path = "path=/org/bluez/hci0/dev_0A_0B_0C_0D_0E_0F";
+    let call = collect
+        .find(&["message=calling method", path, "member=Connect"])
+        .unwrap();
+    assert!(call.contains(&"interface=org.bluez.Device1".to_owned()));
+    let reply = collect
+        .find(&["message=method returned", path, "member=Connect"])
+        .unwrap();
+    assert!(reply.iter().any(|f| f.starts_with("latency_ms=")));
+    assert!(collect
+        .find(&[
+            "message=method returned error",
+            "path=/org/bluez/hci0/dev_01_02_03_04_05_06"
+        ])
+        .is_some());
+}
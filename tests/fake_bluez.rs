@@ -0,0 +1,1071 @@
+use std::io::{Read, Write};
+use std::thread;
+use std::time::Duration;
+
+use bluebus::advertising::Advertisement;
+use bluebus::gatt_server::{self, Application, Flag};
+use bluebus::gatt_types::{BatteryLevel, StandardCharacteristic};
+use bluebus::profile::{self, Profile};
+use bluebus::rustbus::client_conn::Timeout;
+use bluebus::test_support::{
+    AdvertisementProperties, AppObject, DbusDaemon, FakeAdapter, FakeBluez, FakeCharacteristic,
+    FakeDescriptor, FakeDevice, FakeError, FakeService, Pairing, RunningFakeBluez,
+};
+use bluebus::{
+    Address, Ble, BleBuilder, CallOptions, CancelToken, Context, DiscoveryFilter, Error, Event,
+    HubEvent, LeCounter, NotificationHub, NotificationStats, Uuid,
+};
+
+const DEVICE: Address = Address::new([0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f]);
+const ADAPTER: Address = Address::new([0x00, 0x00, 0x00, 0x00, 0x00, 0x01]);
+const SERVICE: Uuid = Uuid::from_u16(0x180f);
+const CHARACTERISTIC: Uuid = Uuid::from_u16(0x2a19);
+
+fn device() -> FakeDevice {
+    let characteristic =
+        FakeCharacteristic::new(CHARACTERISTIC, &[Flag::Read, Flag::Write, Flag::Notify])
+            .with_value(vec![42]);
+    FakeDevice::new(DEVICE)
+        .with_name("fake")
+        .with_service(FakeService::new(SERVICE).with_characteristic(characteristic))
+}
+
+fn setup(bluez: FakeBluez) -> (DbusDaemon, RunningFakeBluez, Ble) {
+    setup_with(bluez, BleBuilder::with_events)
+}
+
+fn setup_with(
+    bluez: FakeBluez,
+    configure: impl FnOnce(BleBuilder) -> BleBuilder,
+) -> (DbusDaemon, RunningFakeBluez, Ble) {
+    let bus = DbusDaemon::start().unwrap();
+    let bluez = bluez.start(&bus).unwrap();
+    let ble = configure(BleBuilder::default().with_bus_address(bus.address()))
+        .build()
+        .unwrap();
+    (bus, bluez, ble)
+}
+
+fn next_event(ble: &mut Ble) -> Event {
+    ble.wait_event(Timeout::Duration(Duration::from_secs(5)))
+        .unwrap()
+}
+
+#[test]
+fn connect_and_disconnect() {
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new().with_device(device()));
+
+    ble.connect(DEVICE).unwrap();
+    assert!(bluez.is_connected(DEVICE));
+    assert!(ble.is_connected(DEVICE).unwrap());
+    assert_eq!(next_event(&mut ble), Event::Connected(DEVICE));
+    assert_eq!(next_event(&mut ble), Event::ServicesResolved(DEVICE));
+
+    bluez.disconnect(DEVICE);
+    assert_eq!(next_event(&mut ble), Event::Disconnected(DEVICE));
+}
+
+#[test]
+fn events_start_with_the_first_wait() {
+    let config = FakeBluez::new().with_device(device());
+    let (_bus, bluez, mut ble) = setup_with(config, |builder| builder);
+
+    // nobody asked for events yet, these changes are not kept
+    ble.connect(DEVICE).unwrap();
+    bluez.set_rssi(DEVICE, -40);
+    // the fake sends signals after its next reply, the second reply comes
+    // after the signal was on the bus
+    for _ in 0..2 {
+        assert!(ble.is_connected(DEVICE).unwrap());
+    }
+    let nothing = ble.wait_event(Timeout::Duration(Duration::from_millis(100)));
+    assert!(nothing.is_err(), "{:?}", nothing);
+
+    bluez.disconnect(DEVICE);
+    assert_eq!(next_event(&mut ble), Event::Disconnected(DEVICE));
+}
+
+#[test]
+fn unknown_device_does_not_exist() {
+    let (_bus, _bluez, mut ble) = setup(FakeBluez::new());
+    let err = ble.connect(DEVICE).unwrap_err();
+    assert_eq!(err, Error::DoesNotExist(Context::Connect));
+}
+
+#[test]
+fn configured_error_is_returned() {
+    let device = device().fail_on(
+        "Connect",
+        FakeError::failed("Software caused connection abort"),
+    );
+    let (_bus, _bluez, mut ble) = setup(FakeBluez::new().with_device(device));
+    match ble.connect(DEVICE) {
+        Err(Error::BluezFailed(Context::Connect)) => (),
+        other => panic!("expected BluezFailed, got: {:?}", other),
+    }
+}
+
+#[test]
+fn read_and_write() {
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new().with_device(device()));
+    ble.connect(DEVICE).unwrap();
+
+    assert_eq!(ble.read(DEVICE, CHARACTERISTIC).unwrap(), vec![42]);
+    ble.write(DEVICE, CHARACTERISTIC, vec![1, 2, 3]).unwrap();
+    assert_eq!(bluez.value(DEVICE, CHARACTERISTIC), Some(vec![1, 2, 3]));
+}
+
+#[test]
+fn read_many_in_one_go() {
+    const NAME: Uuid = Uuid::from_u16(0x2a00);
+    const LOCKED: Uuid = Uuid::from_u16(0x2a01);
+    const MISSING: Uuid = Uuid::from_u16(0x2a02);
+    let service = FakeService::new(Uuid::from_u16(0x1800))
+        .with_characteristic(FakeCharacteristic::new(NAME, &[Flag::Read]).with_value(*b"fake"))
+        .with_characteristic(
+            FakeCharacteristic::new(LOCKED, &[Flag::Read])
+                .fail_on("ReadValue", FakeError::not_permitted()),
+        );
+    let device = device().with_service(service);
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new().with_device(device));
+    ble.connect(DEVICE).unwrap();
+
+    let calls_before = bluez.calls().len();
+    let results = ble
+        .read_many(DEVICE, &[CHARACTERISTIC, NAME, LOCKED, MISSING])
+        .unwrap();
+    assert_eq!(results.len(), 4);
+    assert_eq!(results[0].as_ref().unwrap(), &vec![42]);
+    assert_eq!(results[1].as_ref().unwrap(), b"fake");
+    assert!(matches!(
+        results[2],
+        Err(Error::NotPermitted(Context::ReadValue(uuid))) if uuid == LOCKED
+    ));
+    assert!(matches!(
+        results[3],
+        Err(Error::CharacteristicNotFound(Context::ReadValue(uuid))) if uuid == MISSING
+    ));
+
+    // one object scan, then the reads
+    let members: Vec<String> = bluez.calls()[calls_before..]
+        .iter()
+        .map(|call| call.member.clone())
+        .collect();
+    assert_eq!(
+        members,
+        ["GetManagedObjects", "ReadValue", "ReadValue", "ReadValue"]
+    );
+    assert!(ble.read_many::<Uuid>(DEVICE, &[]).unwrap().is_empty());
+
+    // an already cancelled token gives up while the paths are looked up,
+    // the late reply does not get in the way of the next read
+    let token = CancelToken::new();
+    token.cancel();
+    let options = CallOptions::new().with_cancel(&token);
+    let calls_before = bluez.calls().len();
+    let err = ble
+        .read_many_with(DEVICE, &[CHARACTERISTIC, NAME], &options)
+        .unwrap_err();
+    assert_eq!(err, Error::Cancelled(Context::ReadValue(CHARACTERISTIC)));
+    let err = ble.read_with(DEVICE, NAME, &options).unwrap_err();
+    assert_eq!(err, Error::Cancelled(Context::ReadValue(NAME)));
+    assert_eq!(ble.read(DEVICE, NAME).unwrap(), b"fake");
+    let members: Vec<String> = bluez.calls()[calls_before..]
+        .iter()
+        .map(|call| call.member.clone())
+        .collect();
+    assert_eq!(
+        members,
+        [
+            "GetManagedObjects",
+            "GetManagedObjects",
+            "GetManagedObjects",
+            "ReadValue"
+        ]
+    );
+}
+
+#[test]
+fn read_and_write_typed() {
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new().with_device(device()));
+    ble.connect(DEVICE).unwrap();
+
+    assert_eq!(
+        ble.read_typed::<BatteryLevel>(DEVICE).unwrap(),
+        BatteryLevel(42)
+    );
+    ble.write_typed(DEVICE, &BatteryLevel(7)).unwrap();
+    assert_eq!(bluez.value(DEVICE, BatteryLevel::UUID), Some(vec![7]));
+
+    bluez.set_value(DEVICE, CHARACTERISTIC, &[200]);
+    let err = ble.read_typed::<BatteryLevel>(DEVICE).unwrap_err();
+    assert_eq!(err, Error::InvalidValue(Context::Decode(CHARACTERISTIC)));
+}
+
+#[test]
+fn notify_delivers_values() {
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new().with_device(device()));
+    ble.connect(DEVICE).unwrap();
+
+    let mut notification = ble.notify(DEVICE, CHARACTERISTIC).unwrap();
+    assert_eq!(notification.adress(), DEVICE);
+    assert_eq!(notification.uuid(), CHARACTERISTIC);
+    bluez.notify(DEVICE, CHARACTERISTIC, &[7, 8]);
+
+    let mut buffer = [0u8; 20];
+    let n = notification.read(&mut buffer).unwrap();
+    assert_eq!(&buffer[..n], &[7, 8]);
+
+    bluez.disconnect(DEVICE);
+    assert_eq!(notification.read(&mut buffer).unwrap(), 0);
+}
+
+#[test]
+fn notify_again_after_giving_up() {
+    let characteristic = FakeCharacteristic::new(CHARACTERISTIC, &[Flag::Notify])
+        .delay_on("AcquireNotify", Duration::from_millis(300));
+    let device = FakeDevice::new(DEVICE)
+        .with_service(FakeService::new(SERVICE).with_characteristic(characteristic));
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new().with_device(device));
+    ble.connect(DEVICE).unwrap();
+
+    let options = CallOptions::new().with_timeout(Duration::from_millis(100));
+    let err = ble
+        .notify_with(DEVICE, CHARACTERISTIC, &options)
+        .unwrap_err();
+    assert_eq!(err, Error::TimedOut(Context::AquireNotify(CHARACTERISTIC)));
+    // the late reply arrives and is dropped along with its socket, bluez
+    // sees it closed and lets us acquire again
+    thread::sleep(Duration::from_millis(400));
+    ble.adapters().unwrap();
+    let mut notification = ble.notify(DEVICE, CHARACTERISTIC).unwrap();
+    bluez.notify(DEVICE, CHARACTERISTIC, &[7]);
+    let mut buffer = [0u8; 20];
+    let n = notification.read(&mut buffer).unwrap();
+    assert_eq!(&buffer[..n], &[7]);
+}
+
+#[test]
+fn notification_stats_count_lost_packets() {
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new().with_device(device()));
+    ble.connect(DEVICE).unwrap();
+
+    let mut notification = ble
+        .notify(DEVICE, CHARACTERISTIC)
+        .unwrap()
+        .with_sequence(LeCounter::new(0, 2));
+    // 2, 5, 6 and 7 are lost, 2 arrives late
+    for sequence in [0u16, 1, 3, 4, 8, 2] {
+        bluez.notify(DEVICE, CHARACTERISTIC, &sequence.to_le_bytes());
+    }
+    let mut buffer = [0u8; 20];
+    for _ in 0..6 {
+        assert_eq!(notification.read(&mut buffer).unwrap(), 2);
+    }
+
+    let stats = notification.stats().unwrap();
+    assert_eq!((stats.packets, stats.bytes), (6, 12));
+    assert_eq!((stats.lost, stats.gaps, stats.out_of_order), (4, 2, 1));
+    assert!((stats.loss_ratio() - 0.4).abs() < 1e-9);
+
+    notification.reset_stats();
+    assert_eq!(notification.stats(), Some(&NotificationStats::default()));
+    // a counter that wraps is not a gap
+    for sequence in [0xffffu16, 0] {
+        bluez.notify(DEVICE, CHARACTERISTIC, &sequence.to_le_bytes());
+    }
+    assert_eq!(notification.read(&mut buffer).unwrap(), 2);
+    assert_eq!(notification.read(&mut buffer).unwrap(), 2);
+    assert_eq!(notification.stats().unwrap().lost, 0);
+}
+
+#[test]
+fn notification_without_stats() {
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new().with_device(device()));
+    ble.connect(DEVICE).unwrap();
+
+    let mut notification = ble.notify(DEVICE, CHARACTERISTIC).unwrap();
+    bluez.notify(DEVICE, CHARACTERISTIC, &[1]);
+    assert_eq!(notification.read(&mut [0u8; 20]).unwrap(), 1);
+    assert_eq!(notification.stats(), None);
+
+    let mut notification = notification.with_stats();
+    bluez.notify(DEVICE, CHARACTERISTIC, &[1, 2, 3]);
+    assert_eq!(notification.read(&mut [0u8; 20]).unwrap(), 3);
+    let stats = notification.stats().unwrap();
+    assert_eq!((stats.packets, stats.bytes, stats.lost), (1, 3, 0));
+}
+
+#[test]
+fn hub_tags_values_and_reports_hangups() {
+    const OTHER: Address = Address::new([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+    let other = FakeDevice::new(OTHER).with_service(
+        FakeService::new(SERVICE)
+            .with_characteristic(FakeCharacteristic::new(CHARACTERISTIC, &[Flag::Notify])),
+    );
+    let config = FakeBluez::new().with_device(device()).with_device(other);
+    let (_bus, bluez, mut ble) = setup(config);
+    let mut hub = NotificationHub::new().unwrap();
+    for adress in [DEVICE, OTHER] {
+        ble.connect(adress).unwrap();
+        hub.add(ble.notify(adress, CHARACTERISTIC).unwrap())
+            .unwrap();
+    }
+    assert_eq!(hub.len(), 2);
+    let timeout = Timeout::Duration(Duration::from_secs(5));
+
+    bluez.notify(OTHER, CHARACTERISTIC, &[1]);
+    match hub.wait(timeout).unwrap() {
+        Some(HubEvent::Value {
+            adress,
+            uuid,
+            value,
+        }) => assert_eq!((adress, uuid, value), (OTHER, CHARACTERISTIC, vec![1])),
+        other => panic!("unexpected {:?}", other),
+    }
+    bluez.notify(DEVICE, CHARACTERISTIC, &[2]);
+    match hub.wait(timeout).unwrap() {
+        Some(HubEvent::Value { adress, value, .. }) => {
+            assert_eq!((adress, value), (DEVICE, vec![2]))
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    let nothing = hub.wait(Timeout::Duration(Duration::from_millis(10)));
+    assert!(matches!(nothing, Ok(None)));
+
+    bluez.disconnect(DEVICE);
+    match hub.wait(timeout).unwrap() {
+        Some(HubEvent::Closed(gone)) => assert_eq!(gone.adress(), DEVICE),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(hub.len(), 1);
+    let removed = hub.remove(OTHER, CHARACTERISTIC).unwrap();
+    assert_eq!(removed.adress(), OTHER);
+    assert!(hub.is_empty());
+}
+
+#[test]
+fn notification_reports_disconnect() {
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new().with_device(device()));
+    ble.connect(DEVICE).unwrap();
+    let mut notification = ble.notify(DEVICE, CHARACTERISTIC).unwrap();
+    let timeout = Timeout::Duration(Duration::from_secs(5));
+
+    bluez.notify(DEVICE, CHARACTERISTIC, &[1, 2]);
+    assert_eq!(notification.recv(timeout).unwrap(), Some(vec![1, 2]));
+    let nothing = notification.recv(Timeout::Duration(Duration::from_millis(10)));
+    assert_eq!(nothing.unwrap(), None);
+    assert!(!notification.is_closed());
+
+    bluez.disconnect(DEVICE);
+    let err = notification.recv(timeout).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Disconnected(Context::Notification(uuid)) if uuid == CHARACTERISTIC
+    ));
+    assert!(notification.is_closed());
+    let mut buffer = [0u8; 8];
+    assert_eq!(notification.read(&mut buffer).unwrap(), 0);
+}
+
+#[test]
+fn notification_reacquired_after_reconnect() {
+    let config = FakeBluez::new().with_device(device());
+    let (_bus, bluez, mut ble) = setup_with(config, BleBuilder::with_auto_reacquire);
+    ble.connect(DEVICE).unwrap();
+    let mut old = ble.notify(DEVICE, CHARACTERISTIC).unwrap();
+    let timeout = Timeout::Duration(Duration::from_secs(5));
+    assert_eq!(next_event(&mut ble), Event::Connected(DEVICE));
+    // the first time the services resolve notify is not acquired again
+    assert_eq!(next_event(&mut ble), Event::ServicesResolved(DEVICE));
+    assert!(ble.take_notification(DEVICE, CHARACTERISTIC).is_none());
+
+    bluez.disconnect(DEVICE);
+    assert_eq!(next_event(&mut ble), Event::Disconnected(DEVICE));
+    assert!(old.recv(timeout).is_err());
+
+    bluez.connect(DEVICE);
+    assert_eq!(next_event(&mut ble), Event::Connected(DEVICE));
+    assert_eq!(next_event(&mut ble), Event::ServicesResolved(DEVICE));
+    // acquired again by wait_event, try_event does not block for it
+    let acquired = || {
+        bluez
+            .calls()
+            .iter()
+            .filter(|call| call.member == "AcquireNotify")
+            .count()
+    };
+    assert_eq!(ble.try_event(), Ok(None));
+    assert_eq!(acquired(), 1);
+    assert_eq!(
+        next_event(&mut ble),
+        Event::NotificationReacquired(DEVICE, CHARACTERISTIC)
+    );
+    assert_eq!(acquired(), 2);
+    let mut new = ble.take_notification(DEVICE, CHARACTERISTIC).unwrap();
+    bluez.notify(DEVICE, CHARACTERISTIC, &[7]);
+    assert_eq!(new.recv(timeout).unwrap(), Some(vec![7]));
+    assert!(ble.take_notification(DEVICE, CHARACTERISTIC).is_none());
+}
+
+#[test]
+fn pair_with_passkey() {
+    let device = device().with_pairing(Pairing::Passkey(123456));
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new().with_device(device));
+
+    ble.pair(DEVICE, || 123456, Duration::from_secs(5)).unwrap();
+    assert!(bluez.is_paired(DEVICE));
+    assert!(ble.is_paired(DEVICE).unwrap());
+}
+
+#[test]
+fn pair_with_wrong_passkey_fails() {
+    let device = device().with_pairing(Pairing::Passkey(123456));
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new().with_device(device));
+
+    let err = ble
+        .pair(DEVICE, || 654321, Duration::from_secs(5))
+        .unwrap_err();
+    assert_eq!(err, Error::AuthenticationFailed(Context::Pair));
+    assert!(!bluez.is_paired(DEVICE));
+}
+
+#[test]
+fn pair_answers_any_agent_request() {
+    for pairing in [Pairing::JustWorks, Pairing::Authorization] {
+        let device = device().with_pairing(pairing.clone());
+        let (_bus, bluez, mut ble) = setup(FakeBluez::new().with_device(device));
+
+        ble.pair(DEVICE, || 0, Duration::from_secs(5)).unwrap();
+        assert!(bluez.is_paired(DEVICE), "{:?}", pairing);
+    }
+}
+
+#[test]
+fn pair_confirming_compares_the_passkey() {
+    let device = device().with_pairing(Pairing::Confirmation(123456));
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new().with_device(device));
+
+    let options = CallOptions::new().with_timeout(Duration::from_secs(5));
+    ble.pair_confirming(DEVICE, || 0, |passkey| passkey == Some(123456), &options)
+        .unwrap();
+    assert!(bluez.is_paired(DEVICE));
+}
+
+#[test]
+fn pair_confirming_asks_to_authorize() {
+    let device = device().with_pairing(Pairing::Authorization);
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new().with_device(device));
+
+    let options = CallOptions::new().with_timeout(Duration::from_secs(5));
+    let err = ble
+        .pair_confirming(DEVICE, || 0, |_| false, &options)
+        .unwrap_err();
+    assert_eq!(err, Error::AuthenticationFailed(Context::Pair));
+    assert!(!bluez.is_paired(DEVICE));
+
+    ble.pair_confirming(DEVICE, || 0, |passkey| passkey.is_none(), &options)
+        .unwrap();
+    assert!(bluez.is_paired(DEVICE));
+}
+
+#[test]
+fn pair_rejects_unconfirmed_passkeys() {
+    let device = device().with_pairing(Pairing::Confirmation(123456));
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new().with_device(device));
+
+    let options = CallOptions::new().with_timeout(Duration::from_secs(5));
+    let err = ble.pair_with(DEVICE, || 0, &options).unwrap_err();
+    assert_eq!(err, Error::AuthenticationFailed(Context::Pair));
+    assert!(!bluez.is_paired(DEVICE));
+    // rejecting did not cost us the connection
+    assert!(!ble.is_paired(DEVICE).unwrap());
+}
+
+#[test]
+fn pair_rejects_passkeys_of_more_than_six_digits() {
+    let device = device().with_pairing(Pairing::Passkey(123456));
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new().with_device(device));
+
+    let options = CallOptions::new().with_timeout(Duration::from_secs(5));
+    let err = ble.pair_with(DEVICE, || 1_234_567, &options).unwrap_err();
+    assert_eq!(err, Error::InvalidValue(Context::Pair));
+    assert!(bluez
+        .calls()
+        .iter()
+        .any(|call| call.member == "CancelPairing"));
+    assert!(!bluez.is_paired(DEVICE));
+    assert!(!ble.is_paired(DEVICE).unwrap());
+}
+
+#[test]
+fn pair_when_already_paired() {
+    let device = device().paired(true).with_pairing(Pairing::Passkey(123456));
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new().with_device(device));
+
+    ble.pair(
+        DEVICE,
+        || panic!("asked for a passkey"),
+        Duration::from_secs(5),
+    )
+    .unwrap();
+    assert!(bluez.is_paired(DEVICE));
+}
+
+#[test]
+fn pair_timeout_cancels_pairing() {
+    let device = device().with_pairing(Pairing::NoResponse);
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new().with_device(device));
+
+    let options = CallOptions::new().with_timeout(Duration::from_millis(200));
+    let err = ble.pair_with(DEVICE, || 123456, &options).unwrap_err();
+    assert_eq!(err, Error::TimedOut(Context::Pair));
+    assert!(bluez
+        .calls()
+        .iter()
+        .any(|call| call.member == "CancelPairing"));
+    assert!(!bluez.is_paired(DEVICE));
+}
+
+#[test]
+fn pair_can_be_cancelled() {
+    let device = device().with_pairing(Pairing::NoResponse);
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new().with_device(device));
+
+    let token = CancelToken::new();
+    let canceller = token.clone();
+    let thread = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        canceller.cancel();
+    });
+    let options = CallOptions::new().with_cancel(&token);
+    let err = ble.pair_with(DEVICE, || 123456, &options).unwrap_err();
+    thread.join().unwrap();
+    assert_eq!(err, Error::Cancelled(Context::Pair));
+    assert!(bluez
+        .calls()
+        .iter()
+        .any(|call| call.member == "CancelPairing"));
+
+    // an already cancelled token stops the next call before it waits
+    ble.connect(DEVICE).unwrap();
+    let err = ble.read_with(DEVICE, CHARACTERISTIC, &options).unwrap_err();
+    assert_eq!(err, Error::Cancelled(Context::ReadValue(CHARACTERISTIC)));
+}
+
+#[test]
+fn bluez_restart_is_reported() {
+    let config = FakeBluez::new().with_device(device());
+    let (bus, bluez, mut ble) = setup(config.clone());
+
+    bluez.stop();
+    assert_eq!(next_event(&mut ble), Event::BluezStopped);
+    let bluez = config.start(&bus).unwrap();
+    assert_eq!(next_event(&mut ble), Event::BluezRestarted(Vec::new()));
+
+    // the agent was registered again
+    let calls = bluez.calls();
+    assert!(calls.iter().any(|call| call.member == "RegisterAgent"));
+}
+
+#[test]
+fn bluez_restart_registers_what_it_can() {
+    let (bus, bluez, mut ble) = setup(FakeBluez::new());
+    ble.register_advertisement(Advertisement::peripheral())
+        .unwrap();
+    ble.register_profile(Profile::serial_port()).unwrap();
+
+    bluez.stop();
+    assert_eq!(next_event(&mut ble), Event::BluezStopped);
+    let adapter = FakeAdapter::new(ADAPTER).fail_on(
+        "RegisterAdvertisement",
+        FakeError::failed("Failed to register advertisement"),
+    );
+    let bluez = FakeBluez::new().with_adapter(adapter).start(&bus).unwrap();
+    match next_event(&mut ble) {
+        Event::BluezRestarted(failures) => {
+            assert!(matches!(
+                failures.as_slice(),
+                [Error::BluezFailed(Context::RegisterAdvertisement)]
+            ))
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    // the profile after the failed advertisement was registered anyway
+    assert!(bluez.is_profile_registerd(profile::SERIAL_PORT));
+}
+
+#[test]
+fn advertisement_register_and_unregister() {
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new());
+    let supported = ble.supported_advertisement_instances().unwrap();
+    let advertisement = Advertisement::peripheral()
+        .with_service_uuid(SERVICE)
+        .with_local_name("fake");
+    let id = ble.register_advertisement(advertisement).unwrap();
+    assert_eq!(ble.active_advertisement_instances().unwrap(), 1);
+    assert_eq!(
+        ble.supported_advertisement_instances().unwrap(),
+        supported - 1
+    );
+
+    let mut properties = None;
+    for _ in 0..100 {
+        let _ = ble.try_event().unwrap();
+        properties = bluez.advertisement(&id.path());
+        if properties.is_some() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    let expected = AdvertisementProperties {
+        typ: "peripheral".into(),
+        local_name: Some("fake".into()),
+        service_uuids: vec![SERVICE],
+    };
+    assert_eq!(properties, Some(expected));
+
+    ble.unregister_advertisement(id).unwrap();
+    assert_eq!(ble.active_advertisement_instances().unwrap(), 0);
+    assert_eq!(bluez.advertisement(&id.path()), None);
+    let err = ble.unregister_advertisement(id).unwrap_err();
+    assert_eq!(err, Error::DoesNotExist(Context::UnregisterAdvertisement));
+}
+
+#[test]
+fn advertisement_released_by_bluez() {
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new());
+    let id = ble
+        .register_advertisement(Advertisement::broadcast().with_timeout(1))
+        .unwrap();
+    bluez.release_advertisement(&id.path());
+    assert_eq!(next_event(&mut ble), Event::AdvertisementReleased(id));
+    assert_eq!(ble.active_advertisement_instances().unwrap(), 0);
+}
+
+#[test]
+fn advertisement_rejects_unknown_calls() {
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new());
+    let id = ble
+        .register_advertisement(Advertisement::peripheral())
+        .unwrap();
+    let advertisement = "org.bluez.LEAdvertisement1";
+    let call = bluez
+        .call_app(&id.path(), advertisement, "Frobnicate", None)
+        .unwrap();
+    let expected = FakeError::new("org.freedesktop.DBus.Error.UnknownMethod", "unknown method");
+    assert_eq!(app_reply(&mut ble, &bluez, call), Err(expected));
+    let call = bluez
+        .call_app("/bluebus/advertisement9", advertisement, "Release", None)
+        .unwrap();
+    let expected = FakeError::new("org.freedesktop.DBus.Error.UnknownObject", "no such object");
+    assert_eq!(app_reply(&mut ble, &bluez, call), Err(expected));
+
+    // the advertisement is still there and so is our connection
+    assert_eq!(ble.active_advertisement_instances().unwrap(), 1);
+    ble.unregister_advertisement(id).unwrap();
+}
+
+#[test]
+fn advertisement_registered_again_after_restart() {
+    let (bus, bluez, mut ble) = setup(FakeBluez::new());
+    let id = ble
+        .register_advertisement(Advertisement::peripheral())
+        .unwrap();
+
+    bluez.stop();
+    assert_eq!(next_event(&mut ble), Event::BluezStopped);
+    let bluez = FakeBluez::new().start(&bus).unwrap();
+    assert_eq!(next_event(&mut ble), Event::BluezRestarted(Vec::new()));
+    assert_eq!(ble.active_advertisement_instances().unwrap(), 1);
+    ble.unregister_advertisement(id).unwrap();
+    assert_eq!(bluez.advertisement(&id.path()), None);
+}
+
+#[test]
+fn adapters_and_device_info() {
+    let (_bus, _bluez, mut ble) = setup(FakeBluez::new().with_device(device()));
+
+    let adapters = ble.adapters().unwrap();
+    assert_eq!(adapters.len(), 1);
+    assert_eq!(adapters[0].numb, 0);
+    assert!(adapters[0].powered);
+
+    let info = ble.device_info(DEVICE).unwrap();
+    assert_eq!(info.adress, DEVICE);
+    assert_eq!(info.name.as_deref(), Some("fake"));
+    assert_eq!(info.uuids, vec![SERVICE]);
+    assert!(!info.connected);
+    assert_eq!(ble.devices().unwrap(), vec![info]);
+
+    let other = Address::new([1, 2, 3, 4, 5, 6]);
+    let err = ble.device_info(other).unwrap_err();
+    assert_eq!(err, Error::DoesNotExist(Context::DeviceInfo));
+}
+
+#[test]
+fn gatt_tree_lists_services() {
+    let descriptor = FakeDescriptor::new(Uuid::from_u16(0x2902));
+    let characteristic = FakeCharacteristic::new(CHARACTERISTIC, &[Flag::Read, Flag::Notify])
+        .with_descriptor(descriptor);
+    let second = FakeCharacteristic::new(Uuid::from_u16(0x2a1a), &[Flag::Write]);
+    let service = FakeService::new(SERVICE)
+        .with_characteristic(characteristic)
+        .with_characteristic(second);
+    let device = FakeDevice::new(DEVICE).with_service(service);
+    let (_bus, _bluez, mut ble) = setup(FakeBluez::new().with_device(device));
+    ble.connect(DEVICE).unwrap();
+
+    let tree = ble.gatt_tree(DEVICE).unwrap();
+    assert_eq!(tree.len(), 1);
+    assert_eq!(tree[0].uuid, SERVICE);
+    assert!(tree[0].primary);
+    let characteristics = &tree[0].characteristics;
+    assert_eq!(characteristics.len(), 2);
+    assert_eq!(characteristics[0].uuid, CHARACTERISTIC);
+    assert_eq!(characteristics[0].flags, vec![Flag::Read, Flag::Notify]);
+    assert_eq!(characteristics[0].descriptors.len(), 1);
+    assert_eq!(
+        characteristics[0].descriptors[0].uuid,
+        Uuid::from_u16(0x2902)
+    );
+    assert_eq!(characteristics[1].flags, vec![Flag::Write]);
+}
+
+/// serve calls until the application replied to the call of the fake
+fn app_reply(ble: &mut Ble, bluez: &RunningFakeBluez, call: usize) -> Result<Vec<u8>, FakeError> {
+    for _ in 0..100 {
+        let _ = ble.try_event().unwrap();
+        if let Some(reply) = bluez.app_reply(call) {
+            return reply;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("the application never replied");
+}
+
+fn application() -> Application {
+    let descriptor = gatt_server::Descriptor::new(Uuid::from_u16(0x2901), &[Flag::Read])
+        .with_value(b"level".to_vec());
+    let characteristic =
+        gatt_server::Characteristic::new(CHARACTERISTIC, &[Flag::Read, Flag::Notify])
+            .with_value(vec![42])
+            .with_descriptor(descriptor);
+    Application::new()
+        .with_service(gatt_server::Service::new(SERVICE).with_characteristic(characteristic))
+}
+
+#[test]
+fn gatt_application_is_read_back() {
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new());
+    let id = ble.register_application(application()).unwrap();
+    let app = id.path();
+    let mut objects = None;
+    for _ in 0..100 {
+        let _ = ble.try_event().unwrap();
+        objects = bluez.app_objects(&app);
+        if objects.is_some() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let service = format!("{}/service0", app);
+    let characteristic = format!("{}/char0", service);
+    let expected = vec![
+        AppObject {
+            path: service.clone(),
+            interface: "org.bluez.GattService1".into(),
+            uuid: SERVICE,
+            parent: None,
+            flags: Vec::new(),
+        },
+        AppObject {
+            path: characteristic.clone(),
+            interface: "org.bluez.GattCharacteristic1".into(),
+            uuid: CHARACTERISTIC,
+            parent: Some(service),
+            flags: vec!["read".into(), "notify".into()],
+        },
+        AppObject {
+            path: format!("{}/desc0", characteristic),
+            interface: "org.bluez.GattDescriptor1".into(),
+            uuid: Uuid::from_u16(0x2901),
+            parent: Some(characteristic.clone()),
+            flags: vec!["read".into()],
+        },
+    ];
+    assert_eq!(objects, Some(expected));
+
+    let char_iface = "org.bluez.GattCharacteristic1";
+    let call = bluez
+        .call_app(&characteristic, char_iface, "ReadValue", None)
+        .unwrap();
+    assert_eq!(app_reply(&mut ble, &bluez, call), Ok(vec![42]));
+    let descriptor = format!("{}/desc0", characteristic);
+    let desc_iface = "org.bluez.GattDescriptor1";
+    let call = bluez
+        .call_app(&descriptor, desc_iface, "ReadValue", None)
+        .unwrap();
+    assert_eq!(app_reply(&mut ble, &bluez, call), Ok(b"level".to_vec()));
+
+    ble.unregister_application(id).unwrap();
+    assert_eq!(bluez.app_objects(&app), None);
+}
+
+#[test]
+fn gatt_application_notifies_values() {
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new());
+    let id = ble.register_application(application()).unwrap();
+    let characteristic = format!("{}/service0/char0", id.path());
+
+    // nobody subscribed yet, only the value changes
+    ble.notify_value(id, CHARACTERISTIC, vec![41]).unwrap();
+    let char_iface = "org.bluez.GattCharacteristic1";
+    let call = bluez
+        .call_app(&characteristic, char_iface, "StartNotify", None)
+        .unwrap();
+    assert_eq!(app_reply(&mut ble, &bluez, call), Ok(Vec::new()));
+    ble.notify_value(id, CHARACTERISTIC, vec![40]).unwrap();
+    for _ in 0..100 {
+        let _ = ble.try_event().unwrap();
+        if !bluez.app_notified(&characteristic).is_empty() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(bluez.app_notified(&characteristic), vec![vec![40]]);
+
+    let err = ble
+        .notify_value(id, Uuid::from_u16(0x2a1a), vec![1])
+        .unwrap_err();
+    assert_eq!(
+        err,
+        Error::CharacteristicNotFound(Context::NotifyValue(Uuid::from_u16(0x2a1a)))
+    );
+}
+
+#[test]
+fn gatt_call_to_missing_object_fails() {
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new());
+    let id = ble.register_application(application()).unwrap();
+    let path = format!("{}/service9/char9", id.path());
+    let char_iface = "org.bluez.GattCharacteristic1";
+    let call = bluez
+        .call_app(&path, char_iface, "ReadValue", None)
+        .unwrap();
+    let expected = FakeError::new("org.freedesktop.DBus.Error.UnknownObject", "no such object");
+    assert_eq!(app_reply(&mut ble, &bluez, call), Err(expected));
+
+    // the error reply did not cost us the connection
+    let path = format!("{}/service0/char0", id.path());
+    let call = bluez
+        .call_app(&path, char_iface, "ReadValue", None)
+        .unwrap();
+    assert_eq!(app_reply(&mut ble, &bluez, call), Ok(vec![42]));
+    assert_eq!(ble.adapters().unwrap().len(), 1);
+}
+
+#[test]
+fn call_to_unknown_path_fails() {
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new());
+    let properties = "org.freedesktop.DBus.Properties";
+    let call = bluez
+        .call_app("/bluebus/nothing", properties, "GetAll", None)
+        .unwrap();
+    let expected = FakeError::new("org.freedesktop.DBus.Error.UnknownObject", "no such object");
+    assert_eq!(app_reply(&mut ble, &bluez, call), Err(expected));
+    assert_eq!(ble.adapters().unwrap().len(), 1);
+}
+
+#[test]
+fn link_mtu_is_reported() {
+    let characteristic = FakeCharacteristic::new(CHARACTERISTIC, &[Flag::Notify]).with_mtu(247);
+    let device = FakeDevice::new(DEVICE)
+        .with_service(FakeService::new(SERVICE).with_characteristic(characteristic));
+    let (_bus, _bluez, mut ble) = setup(FakeBluez::new().with_device(device));
+    ble.connect(DEVICE).unwrap();
+
+    assert_eq!(ble.mtu(DEVICE, CHARACTERISTIC).unwrap(), 247);
+    assert_eq!(ble.device_info(DEVICE).unwrap().mtu, Some(247));
+    assert_eq!(
+        ble.gatt_tree(DEVICE).unwrap()[0].characteristics[0].mtu,
+        Some(247)
+    );
+    let notification = ble.notify(DEVICE, CHARACTERISTIC).unwrap();
+    assert_eq!(notification.mtu(), 247);
+}
+
+#[test]
+fn discovery_reports_devices_and_rssi() {
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new());
+    let filter = DiscoveryFilter::new()
+        .with_uuid(SERVICE)
+        .duplicate_data(true);
+    ble.set_discovery_filter(&filter).unwrap();
+    ble.start_discovery().unwrap();
+
+    bluez.add_device(device().with_rssi(-70));
+    match next_event(&mut ble) {
+        Event::DeviceAdded(info) => {
+            assert_eq!(info.adress, DEVICE);
+            assert_eq!(info.rssi, Some(-70));
+        }
+        other => panic!("expected DeviceAdded, got: {:?}", other),
+    }
+    bluez.set_rssi(DEVICE, -55);
+    assert_eq!(next_event(&mut ble), Event::Rssi(DEVICE, -55));
+
+    ble.remove(DEVICE).unwrap();
+    assert_eq!(next_event(&mut ble), Event::DeviceRemoved(DEVICE));
+    ble.stop_discovery().unwrap();
+}
+
+#[test]
+fn battery_percentage_and_changes() {
+    let device = device().with_battery(80);
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new().with_device(device));
+    assert_eq!(ble.battery_percentage(DEVICE).unwrap(), 80);
+    bluez.set_battery(DEVICE, 79);
+    assert_eq!(next_event(&mut ble), Event::BatteryChanged(DEVICE, 79));
+    assert_eq!(ble.battery_percentage(DEVICE).unwrap(), 79);
+}
+
+#[test]
+fn battery_missing_does_not_exist() {
+    let (_bus, _bluez, mut ble) = setup(FakeBluez::new().with_device(device()));
+    let err = ble.battery_percentage(DEVICE).unwrap_err();
+    assert_eq!(err, Error::DoesNotExist(Context::BatteryPercentage));
+}
+
+#[test]
+fn battery_provider_publishes_levels() {
+    let other = Address::new([1, 2, 3, 4, 5, 6]);
+    let config = FakeBluez::new()
+        .with_device(device())
+        .with_device(FakeDevice::new(other));
+    let (_bus, bluez, mut ble) = setup(config);
+    let wait_for = |ble: &mut Ble, adress, expected| {
+        for _ in 0..100 {
+            let _ = ble.try_event().unwrap();
+            if bluez.provided_battery(adress) == expected {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("provided battery never became {:?}", expected);
+    };
+
+    // set before registering, bluez asks for it once registerd
+    ble.provide_battery(DEVICE, 50, Some("proprietary"))
+        .unwrap();
+    ble.register_battery_provider().unwrap();
+    wait_for(&mut ble, DEVICE, Some(50));
+
+    ble.provide_battery(DEVICE, 45, None).unwrap();
+    wait_for(&mut ble, DEVICE, Some(45));
+    ble.provide_battery(other, 10, None).unwrap();
+    wait_for(&mut ble, other, Some(10));
+    ble.remove_provided_battery(DEVICE).unwrap();
+    wait_for(&mut ble, DEVICE, None);
+
+    let err = ble.provide_battery(DEVICE, 101, None).unwrap_err();
+    assert_eq!(err, Error::InvalidValue(Context::ProvideBattery));
+    ble.unregister_battery_provider().unwrap();
+}
+
+#[test]
+fn battery_provider_rejects_unknown_calls() {
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new().with_device(device()));
+    ble.provide_battery(DEVICE, 50, None).unwrap();
+    let properties = "org.freedesktop.DBus.Properties";
+    let missing = "/bluebus/battery/dev_00_00_00_00_00_09";
+    let call = bluez.call_app(missing, properties, "GetAll", None).unwrap();
+    let expected = FakeError::new("org.freedesktop.DBus.Error.UnknownObject", "no such object");
+    assert_eq!(app_reply(&mut ble, &bluez, call), Err(expected));
+    let call = bluez
+        .call_app("/bluebus/battery", properties, "GetAll", None)
+        .unwrap();
+    let expected = FakeError::new("org.freedesktop.DBus.Error.UnknownMethod", "unknown method");
+    assert_eq!(app_reply(&mut ble, &bluez, call), Err(expected));
+
+    // the connection survived the error replies
+    ble.provide_battery(DEVICE, 45, None).unwrap();
+    assert_eq!(ble.adapters().unwrap().len(), 1);
+}
+
+#[test]
+fn profile_connection_without_fd_is_rejected() {
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new().with_device(device()));
+    let id = ble.register_profile(Profile::serial_port()).unwrap();
+
+    let call = bluez
+        .connect_profile_without_fd(DEVICE, profile::SERIAL_PORT)
+        .unwrap();
+    let expected = FakeError::new(
+        "org.bluez.Error.InvalidArguments",
+        "missing or invalid file descriptor",
+    );
+    assert_eq!(app_reply(&mut ble, &bluez, call), Err(expected));
+
+    // the profile still takes connections
+    let _device_end = bluez.connect_profile(DEVICE, profile::SERIAL_PORT).unwrap();
+    assert_eq!(next_event(&mut ble), Event::ProfileConnected(id, DEVICE));
+}
+
+#[test]
+fn profile_connect_and_incoming() {
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new().with_device(device()));
+
+    let id = ble.register_profile(Profile::serial_port()).unwrap();
+    assert!(bluez.is_profile_registerd(profile::SERIAL_PORT));
+
+    // outgoing, the stream is handed over before ConnectProfile returns
+    let mut connection = ble.connect_profile(DEVICE, id).unwrap();
+    assert_eq!(connection.adress, DEVICE);
+    let mut remote = bluez
+        .take_profile_socket(DEVICE, profile::SERIAL_PORT)
+        .unwrap();
+    connection.stream.write_all(b"AT\r\n").unwrap();
+    let mut buf = [0u8; 4];
+    remote.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"AT\r\n");
+    remote.write_all(b"OK").unwrap();
+    let mut buf = [0u8; 2];
+    connection.stream.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"OK");
+
+    // incoming, announced as an event
+    let mut remote = bluez.connect_profile(DEVICE, profile::SERIAL_PORT).unwrap();
+    assert_eq!(next_event(&mut ble), Event::ProfileConnected(id, DEVICE));
+    let mut connection = ble.take_profile_connection(id, DEVICE).unwrap();
+    remote.write_all(b"hi").unwrap();
+    connection.stream.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"hi");
+    assert!(ble.take_profile_connection(id, DEVICE).is_none());
+
+    bluez.request_profile_disconnection(DEVICE, profile::SERIAL_PORT);
+    assert_eq!(
+        next_event(&mut ble),
+        Event::ProfileDisconnectRequested(id, DEVICE)
+    );
+    connection.stream.shutdown().unwrap();
+    assert_eq!(remote.read(&mut buf).unwrap(), 0);
+
+    ble.unregister_profile(id).unwrap();
+    assert!(!bluez.is_profile_registerd(profile::SERIAL_PORT));
+    let err = ble.connect_profile(DEVICE, id).unwrap_err();
+    assert_eq!(err, Error::DoesNotExist(Context::ConnectProfile));
+}
+
+#[test]
+fn profile_released_by_bluez() {
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new().with_device(device()));
+
+    let id = ble.register_profile(Profile::serial_port()).unwrap();
+    assert!(ble.register_profile(Profile::serial_port()).is_err());
+
+    bluez.release_profile(profile::SERIAL_PORT);
+    assert_eq!(next_event(&mut ble), Event::ProfileReleased(id));
+    assert!(bluez.connect_profile(DEVICE, profile::SERIAL_PORT).is_none());
+}
@@ -0,0 +1,131 @@
+use bluebus::gatt_types::*;
+use bluebus::{Context, Error};
+
+fn roundtrip<T>(data: &[u8]) -> T
+where
+    T: StandardCharacteristic + std::fmt::Debug,
+{
+    let value = T::decode(data).unwrap();
+    assert_eq!(value.encode(), data, "{:?}", value);
+    value
+}
+
+#[test]
+fn battery_level() {
+    assert_eq!(roundtrip::<BatteryLevel>(&[87]), BatteryLevel(87));
+    let err = BatteryLevel::decode(&[101]).unwrap_err();
+    assert_eq!(
+        err,
+        Error::InvalidValue(Context::Decode(BatteryLevel::UUID))
+    );
+    let err = BatteryLevel::decode(&[]).unwrap_err();
+    assert_eq!(
+        err,
+        Error::InvalidLength(Context::Decode(BatteryLevel::UUID))
+    );
+}
+
+#[test]
+fn device_information_strings() {
+    let name = roundtrip::<ManufacturerName>(b"Nordic");
+    assert_eq!(name.0, "Nordic");
+    // some devices send a terminating zero
+    let model = ModelNumber::decode(b"nRF52\0").unwrap();
+    assert_eq!(model.0, "nRF52");
+    assert!(SerialNumber::decode(&[0xff, 0xfe]).is_err());
+}
+
+#[test]
+fn heart_rate_measurement() {
+    let simple = roundtrip::<HeartRateMeasurement>(&[0x00, 72]);
+    assert_eq!(simple.bpm, 72);
+    assert_eq!(simple.sensor_contact, None);
+
+    // u16 bpm, contact detected, energy expended and two rr intervals
+    let data = [0x1f, 0x2c, 0x01, 0x10, 0x00, 0x00, 0x04, 0x00, 0x02];
+    let full = roundtrip::<HeartRateMeasurement>(&data);
+    assert_eq!(full.bpm, 300);
+    assert_eq!(full.sensor_contact, Some(true));
+    assert_eq!(full.energy_expended, Some(16));
+    assert_eq!(full.rr_intervals, vec![1024, 512]);
+
+    assert!(HeartRateMeasurement::decode(&[0x01, 0x2c]).is_err());
+}
+
+#[test]
+fn temperature_measurement() {
+    // 36.5 celsius as 365 * 10^-1, no timestamp or type
+    let data = [0x00, 0x6d, 0x01, 0x00, 0xff];
+    let measurement = roundtrip::<TemperatureMeasurement>(&data);
+    assert_eq!(measurement.unit, TemperatureUnit::Celsius);
+    assert_eq!(measurement.value.mantissa, 365);
+    assert_eq!(measurement.value.exponent, -1);
+    assert!((measurement.value.as_f64() - 36.5).abs() < 1e-9);
+
+    // negative mantissa, fahrenheit, timestamp and type
+    let data = [
+        0x07, 0xfe, 0xff, 0xff, 0x00, 0xe8, 0x07, 0x03, 0x0f, 0x0c, 0x1e, 0x00, 0x02,
+    ];
+    let measurement = roundtrip::<TemperatureMeasurement>(&data);
+    assert_eq!(measurement.unit, TemperatureUnit::Fahrenheit);
+    assert_eq!(measurement.value.as_f64(), -2.0);
+    let timestamp = measurement.timestamp.unwrap();
+    assert_eq!(
+        (timestamp.year, timestamp.month, timestamp.day),
+        (2024, 3, 15)
+    );
+    assert_eq!(measurement.temperature_type, Some(2));
+
+    let nan = [0x00, 0xff, 0xff, 0x7f, 0x00];
+    let measurement = roundtrip::<TemperatureMeasurement>(&nan);
+    assert_eq!(measurement.value, Float32::NAN);
+    assert!(measurement.value.as_f64().is_nan());
+}
+
+#[test]
+fn current_time() {
+    let data = [0xe8, 0x07, 0x0c, 0x1f, 0x17, 0x3b, 0x3b, 0x02, 0x80, 0x01];
+    let time = roundtrip::<CurrentTime>(&data);
+    assert_eq!(time.date_time.year, 2024);
+    assert_eq!(time.date_time.seconds, 59);
+    assert_eq!(time.day_of_week, 2);
+    assert_eq!(time.fractions256, 128);
+
+    let invalid_month = [0xe8, 0x07, 0x0d, 0x1f, 0x17, 0x3b, 0x3b, 0x02, 0x80, 0x01];
+    assert!(CurrentTime::decode(&invalid_month).is_err());
+}
+
+#[test]
+fn appearance_and_pnp_id() {
+    // generic heart rate sensor, heart rate belt
+    let appearance = roundtrip::<Appearance>(&[0x41, 0x03]);
+    assert_eq!(appearance.category(), 13);
+    assert_eq!(appearance.subcategory(), 1);
+
+    let pnp = roundtrip::<PnpId>(&[0x01, 0x59, 0x00, 0x01, 0x00, 0x03, 0x02]);
+    assert_eq!(pnp.vendor_id_source, VendorIdSource::BluetoothSig);
+    assert_eq!(pnp.vendor_id, 0x0059);
+    assert_eq!(pnp.product_version, 0x0203);
+    assert!(PnpId::decode(&[0x03, 0, 0, 0, 0, 0, 0]).is_err());
+}
+
+#[test]
+fn cycling_and_running() {
+    let data = [
+        0x03, 0x10, 0x00, 0x00, 0x00, 0x00, 0x04, 0x05, 0x00, 0x00, 0x08,
+    ];
+    let csc = roundtrip::<CscMeasurement>(&data);
+    let wheel = csc.wheel.unwrap();
+    assert_eq!((wheel.cumulative, wheel.last_event_time), (16, 1024));
+    let crank = csc.crank.unwrap();
+    assert_eq!((crank.cumulative, crank.last_event_time), (5, 2048));
+    assert_eq!(roundtrip::<CscMeasurement>(&[0x00]).wheel, None);
+
+    let data = [0x07, 0x00, 0x03, 0xaa, 0x78, 0x00, 0x10, 0x27, 0x00, 0x00];
+    let rsc = roundtrip::<RscMeasurement>(&data);
+    assert_eq!(rsc.speed, 3 * 256);
+    assert_eq!(rsc.cadence, 170);
+    assert_eq!(rsc.stride_length, Some(120));
+    assert_eq!(rsc.total_distance, Some(10_000));
+    assert!(rsc.running);
+}
@@ -0,0 +1,147 @@
+use bluebus::{Address, BluezPath, Error, PathKind, Uuid};
+
+#[test]
+fn address_round_trips() {
+    let address: Address = "0a:0B:0c:0D:0e:0F".parse().unwrap();
+    assert_eq!(address, Address::new([0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f]));
+    assert_eq!(address.to_string(), "0A:0B:0C:0D:0E:0F");
+    // as found in object paths
+    assert_eq!("0A_0B_0C_0D_0E_0F".parse::<Address>().unwrap(), address);
+}
+
+#[test]
+fn malformed_addresses_are_rejected() {
+    for malformed in &[
+        "",
+        "0A:0B:0C:0D:0E",
+        "0A:0B:0C:0D:0E:0F:10",
+        "0A:0B:0C:0D:0E:F",
+        "0A:0B:0C:0D:0E:0FF",
+        "+1:02:03:04:05:06",
+        "-1:02:03:04:05:06",
+        "0G:0B:0C:0D:0E:0F",
+        "0A-0B-0C-0D-0E-0F",
+    ] {
+        match malformed.parse::<Address>() {
+            Err(Error::InvalidAddress(_)) => (),
+            other => panic!("{:?} parsed as {:?}", malformed, other),
+        }
+    }
+}
+
+#[test]
+fn short_uuids_expand_to_the_base_uuid() {
+    let full: Uuid = "00002a19-0000-1000-8000-00805f9b34fb".parse().unwrap();
+    assert_eq!("2a19".parse::<Uuid>().unwrap(), full);
+    assert_eq!("2A19".parse::<Uuid>().unwrap(), full);
+    assert_eq!("00002a19".parse::<Uuid>().unwrap(), full);
+    assert_eq!(
+        "00002a1900001000800000805f9b34fb".parse::<Uuid>().unwrap(),
+        full
+    );
+    assert_eq!(Uuid::from_u16(0x2a19), full);
+    assert_eq!(full.as_u16(), Some(0x2a19));
+    assert_eq!(full.to_string(), "00002a19-0000-1000-8000-00805f9b34fb");
+
+    let custom: Uuid = "93700001-1BB7-1599-985B-F5E7DC991483".parse().unwrap();
+    assert_eq!(custom.as_u32(), None);
+    assert_eq!(custom.to_string(), "93700001-1bb7-1599-985b-f5e7dc991483");
+}
+
+#[test]
+fn malformed_uuids_are_rejected() {
+    for malformed in &[
+        "",
+        "2a1",
+        "2a19a",
+        "+a19",
+        "2a1g",
+        "-0002a19-0000-1000-8000-00805f9b34fb",
+        "00002a19-0000-1000-8000-00805f9b34f-",
+        "00002a19-0000-1000-8000-00805f9b-4fb",
+        "00002a1900-00-1000-8000-00805f9b34fb",
+        "00002a19+0000-1000-8000-00805f9b34fb",
+        "+0002a1900001000800000805f9b34fb",
+    ] {
+        match malformed.parse::<Uuid>() {
+            Err(Error::InvalidUuid(_)) => (),
+            other => panic!("{:?} parsed as {:?}", malformed, other),
+        }
+    }
+}
+
+#[test]
+fn paths_round_trip() {
+    let device = Address::new([0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f]);
+    let descriptor = BluezPath::adapter(1)
+        .device(device)
+        .service(0x0a)
+        .characteristic(0x0b)
+        .descriptor(0x0d);
+    let text = "/org/bluez/hci1/dev_0A_0B_0C_0D_0E_0F/service000a/char000b/desc000d";
+    assert_eq!(descriptor.to_string(), text);
+    assert_eq!(text.parse::<BluezPath>().unwrap(), descriptor);
+    assert_eq!(descriptor.kind(), PathKind::Descriptor);
+    assert_eq!(descriptor.device_adress(), Some(device));
+    assert_eq!(
+        descriptor.characteristic_path().to_string(),
+        "/org/bluez/hci1/dev_0A_0B_0C_0D_0E_0F/service000a/char000b"
+    );
+
+    let adapter: BluezPath = "/org/bluez/hci0".parse().unwrap();
+    assert_eq!(adapter, BluezPath::adapter(0));
+    assert_eq!(adapter.kind(), PathKind::Adapter);
+    assert_eq!(
+        descriptor.device_path().unwrap().adapter_path().kind(),
+        PathKind::Adapter
+    );
+}
+
+#[test]
+fn device_paths_do_not_match_by_prefix() {
+    // as strings the first is a prefix of the second
+    assert!("/org/bluez/hci0/dev_0A_0B_0C_0D_0E_AAB"
+        .parse::<BluezPath>()
+        .is_err());
+    let device: BluezPath = "/org/bluez/hci0/dev_0A_0B_0C_0D_0E_AA".parse().unwrap();
+    let other: BluezPath = "/org/bluez/hci0/dev_0A_0B_0C_0D_0E_AB/service0001"
+        .parse()
+        .unwrap();
+    assert_ne!(other.device_path(), Some(device));
+    let service: BluezPath = "/org/bluez/hci0/dev_0A_0B_0C_0D_0E_AA/service0001"
+        .parse()
+        .unwrap();
+    assert_eq!(service.device_path(), Some(device));
+    let other_adapter: BluezPath = "/org/bluez/hci10/dev_0A_0B_0C_0D_0E_AA".parse().unwrap();
+    assert_ne!(other_adapter, device);
+}
+
+#[test]
+fn malformed_paths_are_rejected() {
+    for malformed in &[
+        "",
+        "/org/bluez",
+        "/org/bluez/",
+        "/org/bluez/hci",
+        "/org/bluez/hci+0",
+        "/org/bluez/hci256",
+        "/org/bluez/hci0/",
+        "/org/bluez/hci0/0A_0B_0C_0D_0E_0F",
+        "/org/bluez/hci0/dev_0A_0B_0C_0D_0E_0F/service0a",
+        "/org/bluez/hci0/dev_0A_0B_0C_0D_0E_0F/service+00a",
+        "/org/bluez/hci0/dev_0A_0B_0C_0D_0E_0F/char000a",
+        "/org/bluez/hci0/dev_0A_0B_0C_0D_0E_0F/service000a/char000b/desc000d/x",
+        "/org/bluez/hci0/dev_0A_0B_0C_0D_0E_0F/service000a/",
+    ] {
+        match malformed.parse::<BluezPath>() {
+            Err(Error::InvalidPath(_)) => (),
+            other => panic!("{:?} parsed as {:?}", malformed, other),
+        }
+    }
+}
+
+#[test]
+#[should_panic]
+fn service_needs_a_device() {
+    let _ = BluezPath::adapter(0).service(1);
+}
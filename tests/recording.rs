@@ -0,0 +1,101 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+
+use bluebus::gatt_server::Flag;
+use bluebus::rustbus::client_conn::Timeout;
+use bluebus::test_support::{DbusDaemon, FakeBluez, FakeCharacteristic, FakeDevice, FakeService};
+use bluebus::{Address, Ble, BleBuilder, Error, Event, Uuid};
+
+const DEVICE: Address = Address::new([0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f]);
+const CHARACTERISTIC: Uuid = Uuid::from_u16(0x2a19);
+
+fn device() -> FakeDevice {
+    let characteristic =
+        FakeCharacteristic::new(CHARACTERISTIC, &[Flag::Read, Flag::Write, Flag::Notify])
+            .with_value(vec![42]);
+    FakeDevice::new(DEVICE)
+        .with_service(FakeService::new(Uuid::from_u16(0x180f)).with_characteristic(characteristic))
+}
+
+fn recording_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("bluebus-recording-{}-{}", process::id(), name))
+}
+
+/// the interaction that is recorded and replayed
+fn session(ble: &mut Ble) -> (Vec<u8>, Event) {
+    ble.connect(DEVICE).unwrap();
+    let value = ble.read(DEVICE, CHARACTERISTIC).unwrap();
+    ble.write(DEVICE, CHARACTERISTIC, vec![1, 2, 3]).unwrap();
+    let event = ble
+        .wait_event(Timeout::Duration(Duration::from_secs(5)))
+        .unwrap();
+    (value, event)
+}
+
+fn record(name: &str) -> (PathBuf, (Vec<u8>, Event)) {
+    let bus = DbusDaemon::start().unwrap();
+    let _bluez = FakeBluez::new().with_device(device()).start(&bus).unwrap();
+    let path = recording_path(name);
+    let mut ble = BleBuilder::default()
+        .with_bus_address(bus.address())
+        .record_to(&path)
+        .with_events()
+        .build()
+        .unwrap();
+    (path, session(&mut ble))
+}
+
+#[test]
+fn replay_gives_recorded_results() {
+    let (path, recorded) = record("replay");
+    assert_eq!(recorded, (vec![42], Event::Connected(DEVICE)));
+
+    // no bus and no fake bluez from here on
+    let mut ble = BleBuilder::default()
+        .replay_from(&path)
+        .with_events()
+        .build()
+        .unwrap();
+    assert_eq!(session(&mut ble), recorded);
+
+    // events already parsed are still handed out, after that nothing
+    // more was recorded and waiting does not block
+    assert_eq!(
+        ble.wait_event(Timeout::Duration(Duration::from_secs(60))),
+        Ok(Event::ServicesResolved(DEVICE))
+    );
+    let err = ble
+        .wait_event(Timeout::Duration(Duration::from_secs(60)))
+        .unwrap_err();
+    assert!(matches!(err, Error::ReplayMismatch(_)), "{:?}", err);
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn replay_reports_different_calls() {
+    let (path, _) = record("mismatch");
+    let mut ble = BleBuilder::default()
+        .replay_from(&path)
+        .with_events()
+        .build()
+        .unwrap();
+    ble.connect(DEVICE).unwrap();
+    let err = ble.write(DEVICE, CHARACTERISTIC, vec![9]).unwrap_err();
+    assert!(matches!(err, Error::ReplayMismatch(_)), "{:?}", err);
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn not_a_recording() {
+    let path = recording_path("invalid");
+    fs::write(&path, b"something else").unwrap();
+    match BleBuilder::default().replay_from(&path).build() {
+        Err(Error::InvalidRecording(_)) => (),
+        Err(e) => panic!("unexpected error: {:?}", e),
+        Ok(_) => panic!("replayed something that is not a recording"),
+    }
+    fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,109 @@
+use bluebus::advertising::Advertisement;
+use bluebus::gatt_server::Flag;
+use bluebus::{
+    AdapterInfo, Address, BluezPath, Context, DeviceInfo, GattCharacteristic, GattDescriptor,
+    GattService, Uuid,
+};
+use serde_json::json;
+
+fn device() -> DeviceInfo {
+    DeviceInfo {
+        adress: "0A:0B:0C:0D:0E:0F".parse().unwrap(),
+        name: Some("sensor".to_owned()),
+        alias: "sensor".to_owned(),
+        rssi: Some(-60),
+        tx_power: None,
+        appearance: None,
+        paired: true,
+        trusted: false,
+        connected: false,
+        services_resolved: false,
+        uuids: vec![Uuid::from_u16(0x180f)],
+        mtu: Some(247),
+    }
+}
+
+#[test]
+fn device_info() {
+    let value = serde_json::to_value(device()).unwrap();
+    assert_eq!(value["adress"], json!("0A:0B:0C:0D:0E:0F"));
+    assert_eq!(
+        value["uuids"],
+        json!(["0000180f-0000-1000-8000-00805f9b34fb"])
+    );
+    let back: DeviceInfo = serde_json::from_value(value).unwrap();
+    assert_eq!(back, device());
+}
+
+#[test]
+fn adapter_info() {
+    let adapter = AdapterInfo {
+        numb: 0,
+        adress: Address::new([0, 0, 0, 0, 0, 1]),
+        name: "gateway".to_owned(),
+        alias: "gateway".to_owned(),
+        powered: true,
+        discovering: false,
+    };
+    let json = serde_json::to_string(&adapter).unwrap();
+    assert_eq!(serde_json::from_str::<AdapterInfo>(&json).unwrap(), adapter);
+}
+
+#[test]
+fn gatt_tree() {
+    let device = BluezPath::adapter(0).device(device().adress);
+    let service = GattService {
+        path: device.service(0x0a),
+        uuid: Uuid::from_u16(0x180f),
+        primary: true,
+        characteristics: vec![GattCharacteristic {
+            path: device.service(0x0a).characteristic(0x0b),
+            uuid: Uuid::from_u16(0x2a19),
+            flags: vec![Flag::Read, Flag::Notify],
+            mtu: Some(247),
+            descriptors: vec![GattDescriptor {
+                path: device.service(0x0a).characteristic(0x0b).descriptor(0x0d),
+                uuid: Uuid::from_u16(0x2902),
+            }],
+        }],
+    };
+    let value = serde_json::to_value(&service).unwrap();
+    assert_eq!(
+        value["path"],
+        json!("/org/bluez/hci0/dev_0A_0B_0C_0D_0E_0F/service000a")
+    );
+    assert_eq!(
+        value["characteristics"][0]["flags"],
+        json!(["Read", "Notify"])
+    );
+    let back: GattService = serde_json::from_value(value).unwrap();
+    assert_eq!(back, service);
+}
+
+#[test]
+fn advertisement() {
+    let advertisement = Advertisement::peripheral()
+        .with_service_uuid(Uuid::from_u16(0x180f))
+        .with_local_name("gateway");
+    let json = serde_json::to_string(&advertisement).unwrap();
+    let back: Advertisement = serde_json::from_str(&json).unwrap();
+    assert_eq!(back, advertisement);
+}
+
+#[test]
+fn context() {
+    let context = Context::ReadValue(Uuid::from_u16(0x2a19));
+    let value = serde_json::to_value(&context).unwrap();
+    assert_eq!(
+        value,
+        json!({ "ReadValue": "00002a19-0000-1000-8000-00805f9b34fb" })
+    );
+    assert_eq!(serde_json::from_value::<Context>(value).unwrap(), context);
+}
+
+#[test]
+fn invalid_strings_are_rejected() {
+    assert!(serde_json::from_str::<Address>("\"0A:0B\"").is_err());
+    assert!(serde_json::from_str::<Uuid>("\"not a uuid\"").is_err());
+    assert!(serde_json::from_str::<BluezPath>("\"/org/freedesktop\"").is_err());
+}
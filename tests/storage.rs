@@ -0,0 +1,191 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use bluebus::storage::{AdapterStorage, CachedAttribute, ConnectionParameters, Key};
+use bluebus::test_support::{DbusDaemon, FakeBluez, FakeDevice};
+use bluebus::{Address, AddressType, BleBuilder, Context, Error, Uuid};
+
+const ADAPTER: Address = Address::new([0x00, 0x00, 0x00, 0x00, 0x00, 0x01]);
+const DEVICE: Address = Address::new([0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f]);
+const OTHER: Address = Address::new([0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+const LEGACY: Address = Address::new([0xc4, 0x11, 0x22, 0x33, 0x44, 0x55]);
+
+fn fixtures() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/bluetooth")
+}
+
+/// a copy of the fixtures that tests may change
+fn scratch_root(name: &str) -> PathBuf {
+    fn copy(from: &Path, to: &Path) {
+        fs::create_dir_all(to).unwrap();
+        for entry in fs::read_dir(from).unwrap() {
+            let entry = entry.unwrap();
+            let target = to.join(entry.file_name());
+            if entry.file_type().unwrap().is_dir() {
+                copy(&entry.path(), &target);
+            } else {
+                fs::copy(entry.path(), target).unwrap();
+            }
+        }
+    }
+    let root = env::temp_dir().join(format!("bluebus-storage-{}-{}", process::id(), name));
+    let _ = fs::remove_dir_all(&root);
+    copy(&fixtures(), &root);
+    root
+}
+
+#[test]
+fn lists_and_parses_cache() {
+    let storage = AdapterStorage::new(fixtures(), ADAPTER).unwrap();
+    assert_eq!(storage.cached_devices().unwrap(), vec![OTHER, DEVICE]);
+
+    let cache = storage.attribute_cache(DEVICE).unwrap();
+    assert_eq!(cache.name.as_deref(), Some("fake"));
+    assert_eq!(
+        cache.services(),
+        vec![Uuid::from_u16(0x1801), Uuid::from_u16(0x180f)]
+    );
+    assert_eq!(
+        cache.attributes[4],
+        CachedAttribute::Characteristic {
+            handle: 0x0007,
+            value_handle: 0x0008,
+            properties: 0x12,
+            uuid: Uuid::from_u16(0x2a19),
+        }
+    );
+    assert_eq!(
+        cache.attributes[5],
+        CachedAttribute::Descriptor {
+            handle: 0x0009,
+            uuid: Uuid::from_u16(0x2902),
+        }
+    );
+    assert!(matches!(
+        cache.attributes[6],
+        CachedAttribute::Other { handle: 0x000a, .. }
+    ));
+
+    let missing = Address::new([9; 6]);
+    let err = storage.attribute_cache(missing).unwrap_err();
+    assert_eq!(err, Error::DoesNotExist(Context::AttributeCache));
+    let empty = AdapterStorage::new(fixtures(), missing).unwrap();
+    assert!(empty.cached_devices().unwrap().is_empty());
+}
+
+#[test]
+fn removes_only_regular_cache_files() {
+    let root = scratch_root("remove");
+    let storage = AdapterStorage::new(&root, ADAPTER).unwrap();
+    assert!(storage.remove_attribute_cache(DEVICE).unwrap());
+    assert!(!storage.remove_attribute_cache(DEVICE).unwrap());
+    assert_eq!(storage.cached_devices().unwrap(), vec![OTHER]);
+
+    let dir = storage.dir().join("cache").join(DEVICE.to_string());
+    fs::create_dir(&dir).unwrap();
+    let err = storage.remove_attribute_cache(DEVICE).unwrap_err();
+    assert_eq!(err, Error::InvalidPath(String::new()));
+    assert!(dir.exists());
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn reset_device_removes_device_and_cache() {
+    let bus = DbusDaemon::start().unwrap();
+    let root = scratch_root("reset");
+    let bluez = FakeBluez::new()
+        .with_device(FakeDevice::new(DEVICE))
+        .start(&bus)
+        .unwrap();
+    let mut ble = BleBuilder::default()
+        .with_bus_address(bus.address())
+        .with_storage_root(&root)
+        .build()
+        .unwrap();
+
+    let storage = ble.adapter_storage().unwrap();
+    assert_eq!(storage.dir(), root.join(ADAPTER.to_string()));
+    ble.reset_device(DEVICE).unwrap();
+    assert!(bluez
+        .calls()
+        .iter()
+        .any(|call| call.member == "RemoveDevice"));
+    assert_eq!(storage.cached_devices().unwrap(), vec![OTHER]);
+
+    // bluez no longer knows it, the cache of the other device still goes
+    ble.reset_device(OTHER).unwrap();
+    assert!(storage.cached_devices().unwrap().is_empty());
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn parses_bonds() {
+    let storage = AdapterStorage::new(fixtures(), ADAPTER).unwrap();
+    assert_eq!(storage.bonded_devices().unwrap(), vec![DEVICE, LEGACY]);
+
+    let bond = storage.bond(DEVICE).unwrap();
+    assert_eq!(bond.device.kind(), AddressType::Public);
+    assert_eq!(bond.name.as_deref(), Some("fake"));
+    assert!(bond.trusted);
+    let ltk = bond.long_term_key.unwrap();
+    assert_eq!(ltk.key.to_string(), "0F0E0D0C0B0A09080706050403020100");
+    assert_eq!((ltk.authenticated, ltk.enc_size), (2, 16));
+    assert!(bond.peripheral_long_term_key.is_some());
+    let irk: Key = "00112233445566778899AABBCCDDEEFF".parse().unwrap();
+    assert_eq!(bond.identity_resolving_key, Some(irk));
+    assert_eq!(
+        bond.connection_parameters,
+        Some(ConnectionParameters {
+            min_interval: 6,
+            max_interval: 9,
+            latency: 44,
+            timeout: 216,
+        })
+    );
+    assert!(bond.link_key.is_none());
+
+    let legacy = storage.bond(LEGACY).unwrap();
+    assert_eq!(legacy.device.kind(), AddressType::Random);
+    let ltk = legacy.peripheral_long_term_key.unwrap();
+    assert_eq!((ltk.ediv, ltk.rand), (0x1234, 0x1234_5678_9abc_def0));
+    assert_eq!(legacy.local_signature_key.unwrap().counter, 3);
+    assert_eq!(legacy.link_key.unwrap().kind, 4);
+
+    let err = storage.bond(OTHER).unwrap_err();
+    assert_eq!(err, Error::DoesNotExist(Context::BondInfo));
+}
+
+#[test]
+fn writes_bonds_to_another_root() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let root = env::temp_dir().join(format!("bluebus-storage-{}-migrate", process::id()));
+    let _ = fs::remove_dir_all(&root);
+    let old = AdapterStorage::new(fixtures(), ADAPTER).unwrap();
+    let new_adapter = Address::new([0, 0, 0, 0, 0, 2]);
+    let new = AdapterStorage::new(&root, new_adapter).unwrap();
+
+    for bond in old.bonds().unwrap() {
+        new.write_bond(&bond).unwrap();
+    }
+    assert_eq!(new.bonds().unwrap(), old.bonds().unwrap());
+    let info = new.dir().join(DEVICE.to_string()).join("info");
+    let mode = fs::metadata(&info).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    // entries we do not parse survive
+    assert!(fs::read_to_string(&info)
+        .unwrap()
+        .contains("SupportedTechnologies=LE;"));
+
+    let mut bond = new.bond(LEGACY).unwrap();
+    bond.trusted = true;
+    bond.local_signature_key = None;
+    new.write_bond(&bond).unwrap();
+    let written = new.bond(LEGACY).unwrap();
+    assert!(written.trusted);
+    assert!(written.local_signature_key.is_none());
+    assert_eq!(written.device.kind(), AddressType::Random);
+    fs::remove_dir_all(root).unwrap();
+}
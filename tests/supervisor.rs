@@ -0,0 +1,114 @@
+use std::time::Duration;
+
+use bluebus::rustbus::client_conn::Timeout;
+use bluebus::test_support::{DbusDaemon, FakeBluez, FakeDevice, FakeError, RunningFakeBluez};
+use bluebus::{Address, Backoff, Ble, BleBuilder, ConnectionState, ConnectionSupervisor};
+
+const DEVICE: Address = Address::new([0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f]);
+
+fn setup(bluez: FakeBluez) -> (DbusDaemon, RunningFakeBluez, Ble) {
+    let bus = DbusDaemon::start().unwrap();
+    let bluez = bluez.start(&bus).unwrap();
+    let ble = BleBuilder::default()
+        .with_bus_address(bus.address())
+        .with_events()
+        .build()
+        .unwrap();
+    (bus, bluez, ble)
+}
+
+fn backoff() -> Backoff {
+    Backoff {
+        initial: Duration::from_millis(100),
+        max: Duration::from_millis(400),
+        factor: 2,
+    }
+}
+
+/// pass events to the supervisor until it reports this state
+fn wait_for(supervisor: &mut ConnectionSupervisor, ble: &mut Ble, state: ConnectionState) {
+    loop {
+        let event = ble
+            .wait_event(Timeout::Duration(Duration::from_secs(5)))
+            .unwrap();
+        if supervisor.handle_event(&event) == Some(state.clone()) {
+            return;
+        }
+    }
+}
+
+#[test]
+fn backoff_grows_until_max_attempts() {
+    let device = FakeDevice::new(DEVICE).fail_on("Connect", FakeError::failed("Page Timeout"));
+    let (_bus, _bluez, mut ble) = setup(FakeBluez::new().with_device(device));
+    let mut supervisor = ConnectionSupervisor::new(DEVICE)
+        .with_backoff(backoff())
+        .with_max_attempts(4);
+
+    let mut delays = Vec::new();
+    for attempt in 1..=3 {
+        let changes = supervisor.poll(&mut ble).unwrap();
+        assert_eq!(changes, vec![ConnectionState::Connecting(attempt)]);
+        let delay = supervisor.time_until_next_attempt().unwrap();
+        delays.push(delay);
+        // not due yet, nothing happens
+        assert!(supervisor.poll(&mut ble).unwrap().is_empty());
+        std::thread::sleep(delay);
+    }
+    let expected = [100, 200, 400];
+    for (delay, expected) in delays.iter().zip(&expected) {
+        let expected = Duration::from_millis(*expected);
+        assert!(*delay <= expected && *delay > expected / 2, "{:?}", delays);
+    }
+
+    let changes = supervisor.poll(&mut ble).unwrap();
+    assert_eq!(
+        changes,
+        vec![ConnectionState::Connecting(4), ConnectionState::GaveUp]
+    );
+    assert_eq!(supervisor.time_until_next_attempt(), None);
+}
+
+#[test]
+fn reconnects_after_disconnect() {
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new().with_device(FakeDevice::new(DEVICE)));
+    let mut supervisor = ConnectionSupervisor::new(DEVICE).with_backoff(backoff());
+
+    let changes = supervisor.poll(&mut ble).unwrap();
+    assert_eq!(
+        changes,
+        vec![ConnectionState::Connecting(1), ConnectionState::Connected]
+    );
+    assert!(bluez.is_connected(DEVICE));
+
+    bluez.disconnect(DEVICE);
+    wait_for(&mut supervisor, &mut ble, ConnectionState::Disconnected);
+    let changes = supervisor.poll(&mut ble).unwrap();
+    assert_eq!(
+        changes,
+        vec![ConnectionState::Connecting(1), ConnectionState::Connected]
+    );
+    assert!(bluez.is_connected(DEVICE));
+}
+
+#[test]
+fn discovery_stops_when_connected_by_event() {
+    let (_bus, bluez, mut ble) = setup(FakeBluez::new());
+    let mut supervisor = ConnectionSupervisor::new(DEVICE)
+        .with_backoff(backoff())
+        .discover_when_unknown(true);
+
+    // bluez does not know the device yet
+    supervisor.poll(&mut ble).unwrap();
+    let calls = bluez.calls();
+    assert!(calls.iter().any(|call| call.member == "StartDiscovery"));
+
+    // it shows up and connects by itself
+    bluez.add_device(FakeDevice::new(DEVICE));
+    bluez.connect(DEVICE);
+    wait_for(&mut supervisor, &mut ble, ConnectionState::Connected);
+    supervisor.poll(&mut ble).unwrap();
+    let calls = bluez.calls();
+    assert!(calls.iter().any(|call| call.member == "StopDiscovery"));
+    assert_eq!(supervisor.state(), &ConnectionState::Connected);
+}